@@ -0,0 +1,224 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, State,
+    },
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use crate::audit::AuditEntry;
+use crate::disks::DiskCache;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Events kept for a slow client before it is told it missed some
+const ACTIVITY_BACKLOG: usize = 256;
+/// Warn when the disk of an upload has less than this share of its space left
+const LOW_SPACE_PERCENT: u64 = 5;
+/// At most one low-space warning per interval
+const LOW_SPACE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Something that happened on the server, as sent on `/api/ws/events`
+///
+/// Paths are logical paths; `user` is who did it.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum Activity {
+    FileUploaded { path: String, size: Option<u64>, user: String },
+    FolderCreated { path: String, user: String },
+    FileDeleted { path: String, user: String },
+    /// Put back from the trash to `path`
+    FileRestored { path: String, user: String },
+    FileRenamed { path: String, dest: String, user: String },
+    FileMoved { path: String, dest: String, user: String },
+    FileCopied { path: String, dest: String, user: String },
+    /// The archive `dest` was written with what is below `path`
+    ArchiveCreated { path: String, dest: String, user: String },
+    /// The archive at `path` was unpacked into the folder `dest`
+    ArchiveExtracted { path: String, dest: String, user: String },
+    /// A chunked upload received another chunk
+    JobProgress { job: String, kind: &'static str, path: String, done: u64, total: u64, user: String },
+    /// The disk holding `path` is nearly full
+    QuotaWarning { path: String, free: u64, total: u64 },
+}
+
+impl Activity {
+    /// The event for a successful audited operation, if it is one clients care about
+    fn from_audit(entry: &AuditEntry) -> Option<Self> {
+        if entry.result != "ok" {
+            return None;
+        }
+        let (path, user) = (entry.path.clone(), entry.user.clone());
+        let dest = || entry.dest.clone().unwrap_or_default();
+        Some(match entry.op.as_str() {
+            "upload" => Self::FileUploaded { path, size: entry.size, user },
+            "create_folder" => Self::FolderCreated { path, user },
+            "delete" => Self::FileDeleted { path, user },
+            "restore" => Self::FileRestored { path, user },
+            "rename" => Self::FileRenamed { path, dest: dest(), user },
+            "move" => Self::FileMoved { path, dest: dest(), user },
+            "copy" => Self::FileCopied { path, dest: dest(), user },
+            "archive" => Self::ArchiveCreated { path, dest: dest(), user },
+            "extract" => Self::ArchiveExtracted { path, dest: dest(), user },
+            _ => return None,
+        })
+    }
+
+    /// Logical paths the event is about
+    fn paths(&self) -> impl Iterator<Item = &str> {
+        let (path, dest) = match self {
+            Self::FileRenamed { path, dest, .. }
+            | Self::FileMoved { path, dest, .. }
+            | Self::FileCopied { path, dest, .. }
+            | Self::ArchiveCreated { path, dest, .. }
+            | Self::ArchiveExtracted { path, dest, .. } => (path, Some(dest)),
+            Self::FileUploaded { path, .. }
+            | Self::FolderCreated { path, .. }
+            | Self::FileDeleted { path, .. }
+            | Self::FileRestored { path, .. }
+            | Self::JobProgress { path, .. }
+            | Self::QuotaWarning { path, .. } => (path, None),
+        };
+        std::iter::once(path.as_str()).chain(dest.map(String::as_str))
+    }
+}
+
+/// Whether `path` is `prefix` or below it
+fn within(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty() || path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Broadcast channel for [`Activity`]
+///
+/// Publishing never waits: a client that falls more than `ACTIVITY_BACKLOG` events behind
+/// skips ahead and is told how many it missed.
+#[derive(Clone)]
+pub struct ActivityHub {
+    tx: broadcast::Sender<Arc<Activity>>,
+    last_low_space: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ActivityHub {
+    pub fn new() -> Self {
+        Self {
+            tx: broadcast::channel(ACTIVITY_BACKLOG).0,
+            last_low_space: Arc::default(),
+        }
+    }
+
+    pub fn publish(&self, activity: Activity) {
+        // Nobody listening is not an error
+        let _ = self.tx.send(Arc::new(activity));
+    }
+
+    /// Announce a recorded operation (called by the audit log)
+    pub fn publish_audit(&self, entry: &AuditEntry) {
+        if let Some(activity) = Activity::from_audit(entry) {
+            self.publish(activity);
+        }
+    }
+
+    /// Warn connected clients when the disk under `actual` is nearly full
+    ///
+    /// Disk figures are read in the background, after the upload has been answered.
+    pub fn check_free_space(&self, disks: Arc<DiskCache>, actual: PathBuf, logical: String) {
+        let hub = self.clone();
+        tokio::spawn(async move {
+            let Some(disk) = disks.disk_of(&actual).await else { return };
+            let (free, total) = (disk.free, disk.total);
+            if free.saturating_mul(100) >= total.saturating_mul(LOW_SPACE_PERCENT) {
+                return;
+            }
+            {
+                let mut last = hub.last_low_space.lock().unwrap();
+                if last.is_some_and(|at| at.elapsed() < LOW_SPACE_INTERVAL) {
+                    return;
+                }
+                *last = Some(Instant::now());
+            }
+            hub.publish(Activity::QuotaWarning { path: logical, free, total });
+        });
+    }
+}
+
+/// Message a client sends on `/api/ws/events`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    /// Only receive events under `path` (`/` or `null` for everything)
+    Subscribe { path: Option<String> },
+}
+
+/// Message the server sends besides the activity events themselves
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Notice<'a> {
+    /// Events dropped because the client read too slowly
+    Missed { count: u64 },
+    Subscribed { path: &'a str },
+}
+
+/// WebSocket announcing server activity to the UI
+///
+/// Authenticated by the middleware like every other API route; browsers send the
+/// session cookie with the upgrade request.
+#[utoipa::path(
+    get, path = "/api/ws/events", tag = "system",
+    responses((
+        status = 101,
+        description = "WebSocket of JSON text messages tagged by `type`: `fileUploaded`, `folderCreated`, \
+            `fileDeleted`, `fileRenamed`, `fileMoved`, `fileCopied`, `jobProgress`, `quotaWarning`, and \
+            `missed` (`count` events dropped for a slow reader). Send `{\"type\":\"subscribe\",\"path\":\"/dir\"}` \
+            to only receive events under a path.",
+    )),
+)]
+pub async fn ws_events(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| run_events(socket, state, user))
+}
+
+async fn run_events(mut socket: WebSocket, state: AppState, user: AuthUser) {
+    let mut activity = state.activity.tx.subscribe();
+    let mut prefix = String::from("/");
+    loop {
+        let outgoing = tokio::select! {
+            received = activity.recv() => match received {
+                Ok(event) => {
+                    let visible = event.paths().all(|p| user.can_see(p) && !state.hidden.matches(p));
+                    if !visible || !event.paths().any(|p| within(p, &prefix)) {
+                        continue;
+                    }
+                    serde_json::to_string(&*event)
+                }
+                Err(broadcast::error::RecvError::Lagged(count)) => serde_json::to_string(&Notice::Missed { count }),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(ClientMessage::Subscribe { path }) => {
+                        prefix = format!("/{}", path.as_deref().unwrap_or("").trim_matches('/'));
+                        serde_json::to_string(&Notice::Subscribed { path: &prefix })
+                    }
+                    // Unknown messages are ignored so newer clients keep working
+                    Err(_) => continue,
+                },
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => break,
+            },
+            _ = state.shutdown.cancelled() => break,
+        };
+        let Ok(text) = outgoing else { continue };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
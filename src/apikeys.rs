@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Extension, Path as AxumPath, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use uuid::Uuid;
+use crate::auth::require_admin;
+use crate::error::ApiError;
+use crate::i18n::Msg;
+use crate::jsonfile::JsonFile;
+use crate::models::*;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Header carrying an API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Prefix of every generated key, also used to recognise keys sent as Bearer tokens
+pub const API_KEY_PREFIX: &str = "filest_";
+
+/// Minimum interval between persisting `last_used` updates (seconds)
+const LAST_USED_SAVE_SECS: i64 = 60;
+
+/// API keys persisted as JSON in the data directory
+pub struct ApiKeyStore {
+    file: JsonFile,
+    keys: Mutex<HashMap<String, ApiKeyRecord>>,
+}
+
+impl ApiKeyStore {
+    /// Load the key store from `<data_dir>/api_keys.json`, starting empty if it doesn't exist
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let file = JsonFile::new(data_dir, "api_keys.json", "API key store");
+        let records: Vec<ApiKeyRecord> = file.load()?;
+        let keys = records.into_iter().map(|r| (r.id.clone(), r)).collect();
+        Ok(Self {
+            file,
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// Write the keys to disk, as a list
+    async fn save(&self, keys: &HashMap<String, ApiKeyRecord>) -> Result<(), String> {
+        let records: Vec<&ApiKeyRecord> = keys.values().collect();
+        self.file.save(&records).await.map_err(|e| e.to_string())
+    }
+
+    /// Find the key matching the presented secret and record its use
+    pub async fn verify(&self, key: &str) -> Option<ApiKeyRecord> {
+        let hash = hash_key(key);
+        let mut keys = self.keys.lock().await;
+        // Compare against every stored hash in constant time
+        let mut found = None;
+        for record in keys.values() {
+            if bool::from(record.key_hash.as_bytes().ct_eq(hash.as_bytes())) {
+                found = Some(record.id.clone());
+            }
+        }
+        let record = keys.get_mut(&found?)?;
+
+        let now = Utc::now().timestamp();
+        let stale = record.last_used.is_none_or(|t| now - t >= LAST_USED_SAVE_SECS);
+        record.last_used = Some(now);
+        let record = record.clone();
+        if stale && let Err(e) = self.save(&keys).await {
+            warn!("Failed to record API key use: {}", e);
+        }
+        Some(record)
+    }
+}
+
+/// SHA-256 of a key, hex encoded
+///
+/// Keys are long random strings, so a fast hash is enough; a slow password hash would
+/// only add latency to every scripted request.
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn key_info(record: &ApiKeyRecord) -> ApiKeyInfo {
+    ApiKeyInfo {
+        id: record.id.clone(),
+        name: record.name.clone(),
+        owner: record.owner.clone(),
+        created_at: record.created_at,
+        last_used: record.last_used,
+        readonly: record.readonly,
+    }
+}
+
+/// Create an API key; the key itself is only returned by this call
+#[utoipa::path(
+    post, path = "/api/keys", tag = "admin", request_body = CreateApiKeyRequest,
+    responses((status = 200, description = "The key, shown only in this response", body = ApiResponse<CreateApiKeyResponse>)),
+)]
+pub async fn create_key(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> impl IntoResponse {
+    if let Some(response) = require_admin(&state, &user) {
+        return response;
+    }
+    let name = req.name.trim();
+    if name.is_empty() {
+        return ApiError::BadRequest(Msg::NameRequired.into()).into_response();
+    }
+
+    let key = format!(
+        "{}{}{}",
+        API_KEY_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+    let record = ApiKeyRecord {
+        id: Uuid::new_v4().simple().to_string()[..12].to_string(),
+        name: name.to_string(),
+        owner: user.username.clone(),
+        key_hash: hash_key(&key),
+        created_at: Utc::now().timestamp(),
+        last_used: None,
+        readonly: req.readonly,
+    };
+
+    let mut keys = state.api_keys.keys.lock().await;
+    keys.insert(record.id.clone(), record.clone());
+    if let Err(e) = state.api_keys.save(&keys).await {
+        keys.remove(&record.id);
+        return ApiError::Io(e).into_response();
+    }
+    info!("API key {:?} ({}) created", record.name, record.id);
+
+    Json(ApiResponse::success(CreateApiKeyResponse {
+        key,
+        info: key_info(&record),
+    })).into_response()
+}
+
+/// List API keys (names and usage only)
+#[utoipa::path(
+    get, path = "/api/keys", tag = "admin",
+    responses((status = 200, description = "API keys without their secrets", body = ApiResponse<ApiKeysResponse>)),
+)]
+pub async fn list_keys(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    if let Some(response) = require_admin(&state, &user) {
+        return response;
+    }
+    let keys = state.api_keys.keys.lock().await;
+    let mut list: Vec<ApiKeyInfo> = keys.values().map(key_info).collect();
+    list.sort_by_key(|k| k.created_at);
+
+    Json(ApiResponse::success(ApiKeysResponse { keys: list })).into_response()
+}
+
+/// Revoke an API key
+#[utoipa::path(
+    delete, path = "/api/keys/{id}", tag = "admin", params(("id" = String, Path, description = "Key ID")),
+    responses((status = 200, description = "API key revoked", body = ApiResponse<OperationResponse>)),
+)]
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    AxumPath(id): AxumPath<String>,
+) -> impl IntoResponse {
+    if let Some(response) = require_admin(&state, &user) {
+        return response;
+    }
+    let mut keys = state.api_keys.keys.lock().await;
+    let Some(removed) = keys.remove(&id) else {
+        return ApiError::NotFound(Msg::ApiKeyNotFound.into()).into_response();
+    };
+    if let Err(e) = state.api_keys.save(&keys).await {
+        keys.insert(id, removed);
+        return ApiError::Io(e).into_response();
+    }
+    info!("API key {:?} ({}) revoked", removed.name, removed.id);
+
+    Json(ApiResponse::success(OperationResponse {
+        message: Msg::ApiKeyRevoked.into(),
+        new_path: None,
+    })).into_response()
+}
@@ -0,0 +1,1004 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Extension, FromRequest, Request, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Form, Json,
+};
+use bytes::{BufMut, Bytes};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::stream;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use crate::audit::AuditEntry;
+use crate::disks::{self, SpaceWatch};
+use crate::error::ApiError;
+use crate::handlers::{
+    check_not_internal, check_patterns, content_disposition, safe_path, sizes_changed, source_size, TempPath,
+};
+use crate::i18n::Msg;
+use crate::jobs::{report_roots, WalkProgress};
+use crate::logging::record_path;
+use crate::models::{
+    ApiResponse, ArchiveFormat, BatchDownloadForm, BatchDownloadRequest, CreateArchiveRequest, CreateArchiveResult,
+    JobStarted,
+};
+use crate::paths::{existing_name, is_internal_artifact, INTERNAL_FILE_PREFIX};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Formats `/api/download?format=` can produce, as reported by `/api/capabilities`
+pub const FORMATS: [&str; 2] = ["zip", "tar.gz"];
+
+/// Bytes of a file read and sent at a time
+const BLOCK_BYTES: u64 = 64 * 1024;
+/// Blocks ready ahead of the response; the walk waits while the queue is full
+const QUEUE_LEN: usize = 16;
+/// Most paths one batch download takes
+const MAX_BATCH_PATHS: usize = 1000;
+
+pub(crate) const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP64_END: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const END: u32 = 0x0605_4b50;
+/// The CRC follows the data in a data descriptor (bit 3); names are UTF-8 (bit 11)
+const FLAG_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+const VERSION_DEFAULT: u16 = 20;
+/// Version 4.5, needed to read ZIP64 fields
+const VERSION_ZIP64: u16 = 45;
+/// Made on Unix, so the external attributes carry a file mode
+const MADE_BY_UNIX: u16 = 3 << 8;
+const EXTRA_ZIP64: u16 = 0x0001;
+/// Extended timestamp: the modification time in UTC, which DOS times lack
+const EXTRA_TIMESTAMP: u16 = 0x5455;
+/// A 32-bit size or offset too large for its field is stored as all ones, with the value in
+/// the ZIP64 extra field
+const OVERFLOW: u64 = u32::MAX as u64;
+const DIR_ATTRIBUTES: u32 = (0o040755 << 16) | 0x10;
+const FILE_ATTRIBUTES: u32 = 0o100644 << 16;
+
+pub(crate) const TAR_BLOCK: u64 = 512;
+const TAR_NAME_LEN: usize = 100;
+/// Largest number an 11-digit octal field holds, 8GB as a size; PAX headers carry larger
+const TAR_OCTAL_MAX: u64 = 0o777_7777_7777;
+
+/// An archive format written front to back, one entry after another, so it can be sent
+/// while it is built
+trait ArchiveWriter: Send + Sync {
+    /// The entry of a folder; `name` ends in `/`
+    fn folder(&mut self, name: String, modified: Option<SystemTime>) -> io::Result<Vec<u8>>;
+    /// The header of the next file; exactly `size` bytes of data follow it
+    fn file(&mut self, name: String, size: u64, modified: Option<SystemTime>) -> io::Result<Vec<u8>>;
+    /// A block of the file's data as it goes in the archive
+    fn data(&mut self, block: Vec<u8>) -> io::Result<Vec<u8>>;
+    /// What ends the file just written
+    fn end_file(&mut self) -> io::Result<Vec<u8>>;
+    /// What closes the archive
+    fn finish(&mut self) -> io::Result<Vec<u8>>;
+}
+
+/// A file or folder in the archive, kept for the central directory
+struct Entry {
+    name: String,
+    dir: bool,
+    size: u64,
+    crc: u32,
+    /// Where its local header starts
+    offset: u64,
+    time: u16,
+    date: u16,
+    /// Unix seconds, if they fit the extended timestamp
+    mtime: Option<u32>,
+}
+
+impl Entry {
+    fn flags(&self) -> u16 {
+        if self.dir { FLAG_UTF8 } else { FLAG_UTF8 | FLAG_DESCRIPTOR }
+    }
+
+    fn put_timestamp(&self, extra: &mut Vec<u8>) {
+        if let Some(mtime) = self.mtime {
+            extra.put_u16_le(EXTRA_TIMESTAMP);
+            extra.put_u16_le(5);
+            extra.put_u8(1);
+            extra.put_u32_le(mtime);
+        }
+    }
+}
+
+/// MS-DOS time and date of a modification time, in the server's time zone
+///
+/// DOS dates run from 1980 to 2107; times outside are clamped.
+fn dos_time(modified: Option<SystemTime>) -> (u16, u16) {
+    const EARLIEST: (u16, u16) = (0, (1 << 5) | 1);
+    let Some(modified) = modified else {
+        return EARLIEST;
+    };
+    let t = DateTime::<Local>::from(modified);
+    match t.year() {
+        ..1980 => EARLIEST,
+        2108.. => (0xbf7d, 0xff9f),
+        year => (
+            ((t.hour() << 11) | (t.minute() << 5) | (t.second() / 2)) as u16,
+            ((((year - 1980) as u32) << 9) | (t.month() << 5) | t.day()) as u16,
+        ),
+    }
+}
+
+/// A ZIP archive written front to back, so it can be sent while it is built
+///
+/// Files are stored uncompressed; their CRC goes in a data descriptor after the data, as
+/// it is only known once the file has been read. ZIP64 fields are used where sizes,
+/// offsets or the entry count outgrow the classic format.
+#[derive(Default)]
+struct ZipEncoder {
+    /// Bytes of archive produced so far
+    offset: u64,
+    entries: Vec<Entry>,
+    /// CRC of the file being written
+    crc: crc32fast::Hasher,
+}
+
+impl ZipEncoder {
+    /// The local header of the next entry; a file's `size` bytes of data follow it
+    fn start(&mut self, name: String, dir: bool, size: u64, modified: Option<SystemTime>) -> Vec<u8> {
+        let (time, date) = dos_time(modified);
+        let mtime = modified
+            .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .and_then(|d| u32::try_from(d.as_secs()).ok());
+        let entry = Entry { name, dir, size, crc: 0, offset: self.offset, time, date, mtime };
+        let zip64 = size >= OVERFLOW;
+        let mut extra = Vec::new();
+        if zip64 {
+            extra.put_u16_le(EXTRA_ZIP64);
+            extra.put_u16_le(16);
+            extra.put_u64_le(size);
+            extra.put_u64_le(size);
+        }
+        entry.put_timestamp(&mut extra);
+
+        let mut header = Vec::with_capacity(30 + entry.name.len() + extra.len());
+        header.put_u32_le(LOCAL_HEADER);
+        header.put_u16_le(if zip64 { VERSION_ZIP64 } else { VERSION_DEFAULT });
+        header.put_u16_le(entry.flags());
+        header.put_u16_le(0);
+        header.put_u16_le(time);
+        header.put_u16_le(date);
+        header.put_u32_le(0);
+        header.put_u32_le(size.min(OVERFLOW) as u32);
+        header.put_u32_le(size.min(OVERFLOW) as u32);
+        header.put_u16_le(entry.name.len() as u16);
+        header.put_u16_le(extra.len() as u16);
+        header.put_slice(entry.name.as_bytes());
+        header.put_slice(&extra);
+        self.offset += header.len() as u64 + size;
+        self.entries.push(entry);
+        header
+    }
+}
+
+impl ArchiveWriter for ZipEncoder {
+    fn folder(&mut self, name: String, modified: Option<SystemTime>) -> io::Result<Vec<u8>> {
+        Ok(self.start(name, true, 0, modified))
+    }
+
+    fn file(&mut self, name: String, size: u64, modified: Option<SystemTime>) -> io::Result<Vec<u8>> {
+        self.crc = crc32fast::Hasher::new();
+        Ok(self.start(name, false, size, modified))
+    }
+
+    fn data(&mut self, block: Vec<u8>) -> io::Result<Vec<u8>> {
+        self.crc.update(&block);
+        Ok(block)
+    }
+
+    /// The data descriptor, with the CRC now known
+    fn end_file(&mut self) -> io::Result<Vec<u8>> {
+        let crc = std::mem::take(&mut self.crc).finalize();
+        let Some(entry) = self.entries.last_mut() else {
+            return Ok(Vec::new());
+        };
+        entry.crc = crc;
+        let mut descriptor = Vec::with_capacity(24);
+        descriptor.put_u32_le(DATA_DESCRIPTOR);
+        descriptor.put_u32_le(crc);
+        if entry.size >= OVERFLOW {
+            descriptor.put_u64_le(entry.size);
+            descriptor.put_u64_le(entry.size);
+        } else {
+            descriptor.put_u32_le(entry.size as u32);
+            descriptor.put_u32_le(entry.size as u32);
+        }
+        self.offset += descriptor.len() as u64;
+        Ok(descriptor)
+    }
+
+    /// The central directory and end records
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let start = self.offset;
+        let mut tail = Vec::new();
+        for entry in &self.entries {
+            let mut extra = Vec::new();
+            let mut zip64 = Vec::new();
+            if entry.size >= OVERFLOW {
+                zip64.put_u64_le(entry.size);
+                zip64.put_u64_le(entry.size);
+            }
+            if entry.offset >= OVERFLOW {
+                zip64.put_u64_le(entry.offset);
+            }
+            if !zip64.is_empty() {
+                extra.put_u16_le(EXTRA_ZIP64);
+                extra.put_u16_le(zip64.len() as u16);
+                extra.put_slice(&zip64);
+            }
+            entry.put_timestamp(&mut extra);
+
+            tail.put_u32_le(CENTRAL_HEADER);
+            tail.put_u16_le(MADE_BY_UNIX | VERSION_ZIP64);
+            tail.put_u16_le(if zip64.is_empty() { VERSION_DEFAULT } else { VERSION_ZIP64 });
+            tail.put_u16_le(entry.flags());
+            tail.put_u16_le(0);
+            tail.put_u16_le(entry.time);
+            tail.put_u16_le(entry.date);
+            tail.put_u32_le(entry.crc);
+            tail.put_u32_le(entry.size.min(OVERFLOW) as u32);
+            tail.put_u32_le(entry.size.min(OVERFLOW) as u32);
+            tail.put_u16_le(entry.name.len() as u16);
+            tail.put_u16_le(extra.len() as u16);
+            tail.put_u16_le(0);
+            tail.put_u16_le(0);
+            tail.put_u16_le(0);
+            tail.put_u32_le(if entry.dir { DIR_ATTRIBUTES } else { FILE_ATTRIBUTES });
+            tail.put_u32_le(entry.offset.min(OVERFLOW) as u32);
+            tail.put_slice(entry.name.as_bytes());
+            tail.put_slice(&extra);
+        }
+        let size = tail.len() as u64;
+        let count = self.entries.len() as u64;
+
+        if count >= u16::MAX as u64 || size >= OVERFLOW || start >= OVERFLOW {
+            let zip64_end = start + size;
+            tail.put_u32_le(ZIP64_END);
+            tail.put_u64_le(44);
+            tail.put_u16_le(MADE_BY_UNIX | VERSION_ZIP64);
+            tail.put_u16_le(VERSION_ZIP64);
+            tail.put_u32_le(0);
+            tail.put_u32_le(0);
+            tail.put_u64_le(count);
+            tail.put_u64_le(count);
+            tail.put_u64_le(size);
+            tail.put_u64_le(start);
+
+            tail.put_u32_le(ZIP64_LOCATOR);
+            tail.put_u32_le(0);
+            tail.put_u64_le(zip64_end);
+            tail.put_u32_le(1);
+        }
+        tail.put_u32_le(END);
+        tail.put_u16_le(0);
+        tail.put_u16_le(0);
+        tail.put_u16_le(count.min(u16::MAX as u64) as u16);
+        tail.put_u16_le(count.min(u16::MAX as u64) as u16);
+        tail.put_u32_le(size.min(OVERFLOW) as u32);
+        tail.put_u32_le(start.min(OVERFLOW) as u32);
+        tail.put_u16_le(0);
+        self.offset += tail.len() as u64;
+        Ok(tail)
+    }
+}
+
+/// Write `value` in octal, zero-padded to fill `field` but its closing NUL
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// Zero bytes that fill `len` bytes out to a whole tar block
+fn tar_padding(len: u64) -> Vec<u8> {
+    vec![0; ((TAR_BLOCK - len % TAR_BLOCK) % TAR_BLOCK) as usize]
+}
+
+/// A PAX extended header record, `<length> <key>=<value>\n`, whose length counts itself
+fn pax_record(key: &str, value: &str) -> String {
+    let base = key.len() + value.len() + 3;
+    let mut len = base;
+    while base + len.to_string().len() != len {
+        len = base + len.to_string().len();
+    }
+    format!("{} {}={}\n", len, key, value)
+}
+
+/// A POSIX ustar header block; `name` is cut at 100 bytes, a PAX header before it carrying
+/// the rest
+fn tar_header(name: &str, size: u64, mtime: u64, mode: u64, kind: u8) -> Vec<u8> {
+    let mut header = vec![0; TAR_BLOCK as usize];
+    let name = &name.as_bytes()[..name.len().min(TAR_NAME_LEN)];
+    header[..name.len()].copy_from_slice(name);
+    put_octal(&mut header[100..108], mode);
+    put_octal(&mut header[108..116], 0);
+    put_octal(&mut header[116..124], 0);
+    put_octal(&mut header[124..136], size);
+    put_octal(&mut header[136..148], mtime);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is summed with its own field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+    put_octal(&mut header[148..155], checksum);
+    header
+}
+
+/// A tar archive in the POSIX format
+///
+/// Names over 100 bytes and files over 8GB get a PAX header with the full value. Files
+/// are padded to whole blocks and the archive ends with two empty blocks.
+#[derive(Default)]
+struct TarEncoder {
+    /// Data bytes of the current file, to pad it out
+    written: u64,
+}
+
+impl TarEncoder {
+    fn entry(&mut self, name: &str, size: u64, modified: Option<SystemTime>, dir: bool) -> Vec<u8> {
+        let mtime = modified
+            .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs().min(TAR_OCTAL_MAX));
+        let mut records = String::new();
+        if name.len() > TAR_NAME_LEN {
+            records.push_str(&pax_record("path", name));
+        }
+        if size > TAR_OCTAL_MAX {
+            records.push_str(&pax_record("size", &size.to_string()));
+        }
+        let mut header = Vec::new();
+        if !records.is_empty() {
+            header.extend(tar_header(name, records.len() as u64, mtime, 0o644, b'x'));
+            header.extend(records.as_bytes());
+            header.extend(tar_padding(records.len() as u64));
+        }
+        let (mode, kind) = if dir { (0o755, b'5') } else { (0o644, b'0') };
+        let size = if size > TAR_OCTAL_MAX { 0 } else { size };
+        header.extend(tar_header(name, size, mtime, mode, kind));
+        self.written = 0;
+        header
+    }
+}
+
+impl ArchiveWriter for TarEncoder {
+    fn folder(&mut self, name: String, modified: Option<SystemTime>) -> io::Result<Vec<u8>> {
+        Ok(self.entry(&name, 0, modified, true))
+    }
+
+    fn file(&mut self, name: String, size: u64, modified: Option<SystemTime>) -> io::Result<Vec<u8>> {
+        Ok(self.entry(&name, size, modified, false))
+    }
+
+    fn data(&mut self, block: Vec<u8>) -> io::Result<Vec<u8>> {
+        self.written += block.len() as u64;
+        Ok(block)
+    }
+
+    fn end_file(&mut self) -> io::Result<Vec<u8>> {
+        Ok(tar_padding(self.written))
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Ok(vec![0; 2 * TAR_BLOCK as usize])
+    }
+}
+
+/// Another format compressed with gzip as it is written
+///
+/// Uses the fastest level: the archive is compressed on the server while the client
+/// waits, and most of a large tree is often already compressed media.
+struct Gzip<W> {
+    inner: W,
+    gz: GzEncoder<Vec<u8>>,
+}
+
+impl<W> Gzip<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, gz: GzEncoder::new(Vec::new(), Compression::fast()) }
+    }
+
+    /// Compress `bytes`, returning what the compressor has output so far
+    fn compress(&mut self, bytes: io::Result<Vec<u8>>) -> io::Result<Vec<u8>> {
+        self.gz.write_all(&bytes?)?;
+        Ok(std::mem::take(self.gz.get_mut()))
+    }
+}
+
+impl<W: ArchiveWriter> ArchiveWriter for Gzip<W> {
+    fn folder(&mut self, name: String, modified: Option<SystemTime>) -> io::Result<Vec<u8>> {
+        let bytes = self.inner.folder(name, modified);
+        self.compress(bytes)
+    }
+
+    fn file(&mut self, name: String, size: u64, modified: Option<SystemTime>) -> io::Result<Vec<u8>> {
+        let bytes = self.inner.file(name, size, modified);
+        self.compress(bytes)
+    }
+
+    fn data(&mut self, block: Vec<u8>) -> io::Result<Vec<u8>> {
+        let bytes = self.inner.data(block);
+        self.compress(bytes)
+    }
+
+    fn end_file(&mut self) -> io::Result<Vec<u8>> {
+        let bytes = self.inner.end_file();
+        self.compress(bytes)
+    }
+
+    /// The end of the inner archive, then the rest of the compressed stream and its trailer
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let tail = self.inner.finish()?;
+        self.gz.write_all(&tail)?;
+        self.gz.try_finish()?;
+        Ok(std::mem::take(self.gz.get_mut()))
+    }
+}
+
+/// The walk behind one archive, sending it block by block to the response or file
+struct Pack {
+    state: AppState,
+    user: AuthUser,
+    writer: Box<dyn ArchiveWriter>,
+    out: mpsc::Sender<io::Result<Bytes>>,
+    /// Counts the files packed when the archive is written by a job
+    progress: Option<Arc<WalkProgress>>,
+}
+
+impl Pack {
+    /// Pass bytes on to the response; fails once the client is gone
+    async fn send(&self, bytes: Vec<u8>) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.out.send(Ok(bytes.into())).await.map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Add a folder and everything in it the user can see, under `name`
+    async fn folder(&mut self, logical: &Path, actual: &Path, name: &str) -> io::Result<()> {
+        let modified = fs::metadata(actual).await.and_then(|m| m.modified()).ok();
+        let header = self.writer.folder(format!("{}/", name), modified)?;
+        self.send(header).await?;
+        if let Some(progress) = &self.progress {
+            progress.add(1, 0);
+        }
+        let Ok(mut entries) = fs::read_dir(actual).await else { return Ok(()) };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let entry_logical = logical.join(entry.file_name());
+            let path = self.state.mounts.logical_path(&entry_logical);
+            // Protected files can't be downloaded, so they can't be packed either
+            if self.state.hidden.matches(&path)
+                || self.state.protected.matches(&path)
+                || is_internal_artifact(&path, &entry.path())
+                || self.state.mounts.refuses_link(&entry.path())
+            {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(entry.path()).await else { continue };
+            let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            if metadata.is_dir() {
+                if self.user.can_see(&path) {
+                    Box::pin(self.folder(&entry_logical, &entry.path(), &entry_name)).await?;
+                }
+            } else if self.user.check(&path).is_ok() {
+                self.file(&entry.path(), entry_name).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a file under `name`; one that can't be opened is left out
+    ///
+    /// Its size is fixed in the header before it is read, so a file that shrinks while
+    /// being read fails the archive rather than corrupting it.
+    async fn file(&mut self, actual: &Path, name: String) -> io::Result<()> {
+        let Ok(file) = fs::File::open(actual).await else { return Ok(()) };
+        let Ok(metadata) = file.metadata().await else { return Ok(()) };
+        let size = metadata.len();
+        if let Some(progress) = &self.progress {
+            progress.set_current(Path::new(&name));
+        }
+        let header = self.writer.file(name, size, metadata.modified().ok())?;
+        self.send(header).await?;
+        let mut file = file.take(size);
+        let mut left = size;
+        while left > 0 {
+            let mut block = vec![0; BLOCK_BYTES.min(left) as usize];
+            let read = file.read(&mut block).await?;
+            if read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, Msg::FileChangedWhileArchiving.with(&[&actual.display()])));
+            }
+            block.truncate(read);
+            left -= read as u64;
+            let data = self.writer.data(block)?;
+            self.send(data).await?;
+        }
+        let end = self.writer.end_file()?;
+        self.send(end).await?;
+        if let Some(progress) = &self.progress {
+            progress.add(1, size);
+        }
+        Ok(())
+    }
+
+    /// Add each root in turn, then close the archive
+    async fn run(&mut self, roots: &[(PathBuf, PathBuf, String)]) -> io::Result<()> {
+        for (logical, actual, name) in roots {
+            if actual.is_dir() {
+                self.folder(logical, actual, name).await?;
+            } else {
+                self.file(actual, name.clone()).await?;
+            }
+        }
+        let tail = self.writer.finish()?;
+        self.send(tail).await
+    }
+}
+
+/// A file or folder to pack
+struct Root {
+    /// Logical path as shown to users
+    path: String,
+    logical: PathBuf,
+    actual: PathBuf,
+}
+
+/// What `user_path` stands for in an archive, checked as a download would be: the file or
+/// folder itself, or each mount for the virtual root of several
+fn roots(state: &AppState, user: &AuthUser, user_path: &str) -> Result<Vec<Root>, ApiError> {
+    if state.mounts.is_virtual_root(user_path) {
+        let (_, roots) = report_roots(state, user, user_path)?;
+        return Ok(roots
+            .into_iter()
+            .map(|(logical, actual)| Root { path: state.mounts.logical_path(&logical), logical, actual })
+            .collect());
+    }
+    let paths = safe_path(&state.mounts, user_path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    check_patterns(state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    Ok(vec![Root { path: logical, logical: paths.logical, actual: paths.actual }])
+}
+
+/// The folder holding every one of `paths`, which entry names are given relative to
+fn common_parent<'a>(paths: impl Iterator<Item = &'a str>) -> String {
+    let mut common: Option<Vec<&str>> = None;
+    for path in paths {
+        let mut parent: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        parent.pop();
+        common = Some(match common {
+            None => parent,
+            Some(common) => common.into_iter().zip(parent).take_while(|(a, b)| a == b).map(|(a, _)| a).collect(),
+        });
+    }
+    format!("/{}", common.unwrap_or_default().join("/"))
+}
+
+/// Several paths to pack together, each checked as a download would be, with paths inside
+/// another selected folder packed once
+fn selection(state: &AppState, user: &AuthUser, paths: &[String]) -> Result<Vec<Root>, ApiError> {
+    if paths.is_empty() || paths.len() > MAX_BATCH_PATHS {
+        return Err(ApiError::BadRequest(Msg::InvalidBatchPaths.with(&[&MAX_BATCH_PATHS])));
+    }
+    let mut found: Vec<Root> = Vec::new();
+    for path in paths {
+        found.extend(roots(state, user, path)?);
+    }
+    // Parents sort before what they hold, so keeping a path only when no kept one covers it
+    // drops nested and repeated paths
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut kept: Vec<Root> = Vec::with_capacity(found.len());
+    for root in found {
+        let covered = kept.iter().any(|outer| {
+            outer.path == root.path
+                || outer.path == "/"
+                || root.path.strip_prefix(outer.path.as_str()).is_some_and(|rest| rest.starts_with('/'))
+        });
+        if !covered {
+            kept.push(root);
+        }
+    }
+    Ok(kept)
+}
+
+/// `roots` as the archive names them: by their path below their common parent, which is
+/// returned with them, along with the archive's own name without extension
+fn named_roots(roots: Vec<Root>) -> (String, Vec<(PathBuf, PathBuf, String)>, String) {
+    let parent = common_parent(roots.iter().map(|root| root.path.as_str()));
+    let roots: Vec<(PathBuf, PathBuf, String)> = roots
+        .into_iter()
+        .map(|root| {
+            let name = root.path.strip_prefix(parent.as_str()).unwrap_or(&root.path).trim_start_matches('/');
+            // A mount root has no name of its own below `/`
+            let name = match name {
+                "" => root.actual.file_name().map_or_else(|| "download".to_string(), |n| n.to_string_lossy().to_string()),
+                name => name.to_string(),
+            };
+            (root.logical, root.actual, name)
+        })
+        .collect();
+    let archive_name = match roots.as_slice() {
+        [(_, _, name)] => name.clone(),
+        _ => parent.rsplit('/').next().filter(|n| !n.is_empty()).unwrap_or("download").to_string(),
+    };
+    (parent, roots, archive_name)
+}
+
+/// The writer for `format`, with the file extension and content type of what it writes
+fn encoder(format: ArchiveFormat) -> (Box<dyn ArchiveWriter>, &'static str, &'static str) {
+    match format {
+        ArchiveFormat::Zip => (Box::new(ZipEncoder::default()), "zip", "application/zip"),
+        ArchiveFormat::TarGz => (Box::new(Gzip::new(TarEncoder::default())), "tar.gz", "application/gzip"),
+    }
+}
+
+/// Walk `roots` into an archive in a spawned task, returning the queue its blocks arrive on
+///
+/// Dropping the queue ends the walk at its next send. A read error midway is sent as the
+/// last item.
+fn spawn_pack(
+    state: AppState,
+    user: AuthUser,
+    roots: Vec<(PathBuf, PathBuf, String)>,
+    writer: Box<dyn ArchiveWriter>,
+    progress: Option<Arc<WalkProgress>>,
+    label: String,
+) -> mpsc::Receiver<io::Result<Bytes>> {
+    let (out, queue) = mpsc::channel(QUEUE_LEN);
+    let mut pack = Pack { state, user, writer, out, progress };
+    tokio::spawn(async move {
+        if let Err(e) = pack.run(&roots).await
+            && e.kind() != io::ErrorKind::BrokenPipe
+        {
+            tracing::warn!("Packing {} failed: {}", label, e);
+            let _ = pack.out.send(Err(e)).await;
+        }
+    });
+    queue
+}
+
+/// Stream `roots` as one archive, each named by its path below their common parent
+///
+/// Nothing is buffered beyond a few blocks: the tree is walked and each file read as the
+/// client takes the data, and the walk stops when the client disconnects. A read error
+/// midway ends the response early, so the client sees a failed download instead of a
+/// damaged archive.
+fn stream_archive(state: AppState, user: AuthUser, roots: Vec<Root>, format: ArchiveFormat) -> Response {
+    let (parent, roots, archive_name) = named_roots(roots);
+    let (writer, extension, content_type) = encoder(format);
+    let label = format!("{} download below {}", extension, parent);
+    let queue = spawn_pack(state, user, roots, writer, None, label);
+
+    let blocks = stream::unfold(queue, |mut queue| async move { queue.recv().await.map(|block| (block, queue)) });
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition("attachment", &format!("{}.{}", archive_name, extension))),
+        ],
+        Body::from_stream(blocks),
+    )
+        .into_response()
+}
+
+/// A folder, or a single file, as an archive streamed while it is built
+///
+/// The archive holds what the user could download one file at a time; hidden, protected
+/// and inaccessible entries are left out. The virtual root of several mounts packs each
+/// mount as a top-level folder.
+pub async fn archive_response(
+    state: AppState,
+    user: AuthUser,
+    user_path: &str,
+    format: ArchiveFormat,
+) -> Result<Response, ApiError> {
+    let roots = roots(&state, &user, user_path)?;
+    Ok(stream_archive(state, user, roots, format))
+}
+
+/// Download several files and folders as one ZIP archive, streamed while it is built
+///
+/// Entries keep their paths below the folder holding all of them, so a selection from one
+/// folder packs as its names, and paths from different folders keep the folders that
+/// tell them apart. Every path is checked like `/api/download` before anything is sent;
+/// paths inside another selected folder are packed once. The body is JSON, or from a
+/// browser form a `paths` field holding the JSON array, which lets a page post it and
+/// leave the download to the browser.
+#[utoipa::path(
+    post, path = "/api/download/batch", tag = "files", request_body = BatchDownloadRequest,
+    responses(
+        (status = 200, description = "ZIP archive, without `Content-Length`", content_type = "application/zip"),
+        (status = 400, description = "No paths, or more than 1000", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn download_batch(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+    let paths = if is_form {
+        let Form(form) = Form::<BatchDownloadForm>::from_request(request, &state)
+            .await
+            .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+        serde_json::from_str::<Vec<String>>(&form.paths).map_err(|e| ApiError::BadRequest(e.to_string()))?
+    } else {
+        let Json(req) = Json::<BatchDownloadRequest>::from_request(request, &state)
+            .await
+            .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+        req.paths
+    };
+    let kept = selection(&state, &user, &paths)?;
+    let rate = state.max_download_rate;
+    Ok(crate::throttle::limit(stream_archive(state, user, kept, ArchiveFormat::Zip), rate))
+}
+
+/// Write the archive `queue` delivers to `target`, returning its size
+///
+/// It is written under a temporary name beside `target` and renamed once complete, so a
+/// failed or cancelled job leaves nothing behind.
+async fn write_archive(
+    state: &AppState,
+    mut queue: mpsc::Receiver<io::Result<Bytes>>,
+    target: &Path,
+    progress: &WalkProgress,
+) -> Result<u64, ApiError> {
+    let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    };
+    let aside = dir.join(format!("{}archive_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let staged = TempPath::file(&aside);
+    let write_failed = |e| ApiError::io(Msg::WriteFileFailed, e);
+    let mut file = fs::File::create(&aside).await.map_err(|e| ApiError::io(Msg::CreateFileFailed, e))?;
+    let mut watch = SpaceWatch::new(dir, state.min_free_space);
+    let mut size = 0;
+    loop {
+        // Dropping the queue on cancel ends the walk
+        let block = tokio::select! {
+            _ = progress.cancel.cancelled() => return Err(ApiError::Cancelled(Msg::JobCancelled.into())),
+            block = queue.recv() => block,
+        };
+        let Some(block) = block else { break };
+        let block = block.map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+        file.write_all(&block).await.map_err(write_failed)?;
+        size += block.len() as u64;
+        watch.wrote(block.len() as u64).await?;
+    }
+    file.flush().await.map_err(write_failed)?;
+    drop(file);
+    // Someone may have taken the name while the archive was written
+    if existing_name(dir, name).await.is_some() {
+        return Err(ApiError::AlreadyExists(Msg::DestinationExists.into()));
+    }
+    fs::rename(&aside, target).await.map_err(write_failed)?;
+    staged.keep();
+    Ok(size)
+}
+
+/// Pack files and folders into a ZIP or tar.gz archive written on the server, as a
+/// background job
+///
+/// The paths are checked and named as `/api/download/batch` does it, and the archive is
+/// written into `destination` as `name`, by default the name a download of the same
+/// paths gets. The answer is a `JobStarted` whose token follows the job at
+/// `/api/jobs/{token}` or its `events`, counting the entries packed and the bytes read
+/// against their total, and cancels it. The archive appears under its name only once
+/// complete.
+#[utoipa::path(
+    post, path = "/api/archive", tag = "files", request_body = CreateArchiveRequest,
+    responses(
+        (status = 200, description = "The job writing the archive; its `result` is a `CreateArchiveResult`", body = ApiResponse<JobStarted>),
+        (status = 400, description = "No paths, or more than 1000", body = crate::openapi::ErrorBody),
+        (status = 409, description = "`name` is taken in `destination`", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn create_archive(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<CreateArchiveRequest>,
+) -> Result<Json<ApiResponse<JobStarted>>, ApiError> {
+    let roots = selection(&state, &user, &req.paths)?;
+    let mut total = 0;
+    for root in &roots {
+        total += source_size(&state, &root.logical, &root.actual).await;
+    }
+    let (parent, roots, archive_name) = named_roots(roots);
+    let (writer, extension, _) = encoder(req.format.unwrap_or(ArchiveFormat::Zip));
+    let name = match &req.name {
+        Some(name) => state.name_rules.prepare(name)?,
+        None => state.name_rules.prepare(&format!("{}.{}", archive_name, extension))?,
+    };
+
+    let dest = safe_path(&state.mounts, &req.destination)?;
+    if !dest.actual.is_dir() {
+        return Err(ApiError::NotFound(Msg::DirNotFound.into()));
+    }
+    let target = dest.actual.join(&name);
+    let logical = state.mounts.logical_path(&dest.logical.join(&name));
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &target)?;
+    state.locks.check(&logical, false, &user, &headers)?;
+    if existing_name(&dest.actual, &name).await.is_some() {
+        return Err(ApiError::AlreadyExists(Msg::DestinationExists.into()));
+    }
+    // Files are stored as they are, so the archive is at most about their size
+    disks::ensure_space(&dest.actual, total, state.min_free_space).await?;
+
+    let work = {
+        let (state, user, logical) = (state.clone(), user.clone(), logical.clone());
+        move |progress: Arc<WalkProgress>| async move {
+            progress.set_total(total);
+            let label = format!("{} archive {}", extension, logical);
+            let queue = spawn_pack(state.clone(), user.clone(), roots, writer, Some(progress.clone()), label);
+            let result = write_archive(&state, queue, &target, &progress).await;
+            state.audit.record(
+                AuditEntry::new("archive", &user.username, addr.ip(), &parent)
+                    .dest(&logical)
+                    .size(*result.as_ref().unwrap_or(&0))
+                    .result(&result),
+            );
+            sizes_changed(&state, &target).await;
+            crate::jobs::outcome(result.map(|size| CreateArchiveResult {
+                message: Msg::ArchiveCreated.into(),
+                new_path: logical,
+                size,
+            }))
+        }
+    };
+    let job = state.reports.start(&user.username, "archive", logical, work).await;
+    Ok(Json(ApiResponse::success(JobStarted { message: Msg::JobStarted.into(), job })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Duration;
+
+    const MODIFIED: u64 = 1_700_000_000;
+
+    /// A folder, files of several sizes and names past a tar header's 100 bytes
+    fn sample() -> Vec<(String, Option<Vec<u8>>)> {
+        let long = format!("docs/{}.txt", "n".repeat(150));
+        let big: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        vec![
+            ("docs/".into(), None),
+            ("docs/a.txt".into(), Some(b"hello".to_vec())),
+            ("empty.txt".into(), Some(Vec::new())),
+            (long, Some(big)),
+            ("ünïcode 文件.txt".into(), Some(b"utf-8".to_vec())),
+        ]
+    }
+
+    /// What `writer` produces for `entries`, each file's data handed over in blocks as a
+    /// walk reads them
+    fn build(mut writer: Box<dyn ArchiveWriter>, entries: &[(String, Option<Vec<u8>>)]) -> Vec<u8> {
+        let modified = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(MODIFIED));
+        let mut out = Vec::new();
+        for (name, data) in entries {
+            let Some(data) = data else {
+                out.extend(writer.folder(name.clone(), modified).unwrap());
+                continue;
+            };
+            out.extend(writer.file(name.clone(), data.len() as u64, modified).unwrap());
+            for block in data.chunks(BLOCK_BYTES as usize) {
+                out.extend(writer.data(block.to_vec()).unwrap());
+            }
+            out.extend(writer.end_file().unwrap());
+        }
+        out.extend(writer.finish().unwrap());
+        out
+    }
+
+    #[test]
+    fn zips_read_back_with_the_zip_crate() {
+        let entries = sample();
+        let mut zip = zip::ZipArchive::new(io::Cursor::new(build(encoder(ArchiveFormat::Zip).0, &entries))).unwrap();
+        assert_eq!(zip.len(), entries.len());
+        let local = DateTime::<Local>::from(SystemTime::UNIX_EPOCH + Duration::from_secs(MODIFIED));
+        for (i, (name, data)) in entries.iter().enumerate() {
+            // Reading the whole entry checks its CRC
+            let mut file = zip.by_index(i).unwrap();
+            assert_eq!(file.name(), name);
+            assert_eq!(file.is_dir(), data.is_none(), "{name}");
+            assert_eq!(file.unix_mode(), Some(if data.is_some() { 0o100644 } else { 0o040755 }), "{name}");
+            let mut read = Vec::new();
+            file.read_to_end(&mut read).unwrap();
+            assert_eq!(&read, data.as_ref().unwrap_or(&Vec::new()), "{name}");
+
+            let dos = file.last_modified().unwrap();
+            let date = (dos.year() as i32, dos.month() as u32, dos.day() as u32);
+            assert_eq!(date, (local.year(), local.month(), local.day()), "{name}");
+            assert_eq!((dos.hour() as u32, dos.minute() as u32), (local.hour(), local.minute()), "{name}");
+            let mtime = file.extra_data_fields().find_map(|field| match field {
+                zip::ExtraField::ExtendedTimestamp(ts) => ts.mod_time(),
+                _ => None,
+            });
+            assert_eq!(mtime, Some(MODIFIED as u32), "{name}");
+        }
+    }
+
+    #[test]
+    fn zips_past_65535_entries_use_zip64() {
+        let entries: Vec<_> = (0..70_000).map(|i| (format!("{}/", i), None)).collect();
+        let bytes = build(encoder(ArchiveFormat::Zip).0, &entries);
+        let zip = zip::ZipArchive::new(io::Cursor::new(bytes)).unwrap();
+        assert_eq!(zip.len(), 70_000);
+        assert_eq!(zip.name_for_index(69_999), Some("69999/"));
+    }
+
+    #[test]
+    fn tar_gz_reads_back_with_the_tar_crate() {
+        let entries = sample();
+        let bytes = build(encoder(ArchiveFormat::TarGz).0, &entries);
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(&bytes[..]));
+        let mut read_back = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            let header = entry.header();
+            assert_eq!(header.mtime().unwrap(), MODIFIED, "{name}");
+            let data = match header.entry_type() {
+                tar::EntryType::Directory => {
+                    assert_eq!(header.mode().unwrap(), 0o755);
+                    None
+                }
+                kind => {
+                    assert_eq!((kind, header.mode().unwrap()), (tar::EntryType::Regular, 0o644), "{name}");
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data).unwrap();
+                    Some(data)
+                }
+            };
+            read_back.push((name, data));
+        }
+        assert!(read_back == entries);
+    }
+
+    #[test]
+    fn archives_unpack_with_standard_extractors() {
+        let entries = sample();
+        let zip = build(encoder(ArchiveFormat::Zip).0, &entries);
+        let tar_gz = build(encoder(ArchiveFormat::TarGz).0, &entries);
+        let zip_dir = tempfile::tempdir().unwrap();
+        zip::ZipArchive::new(io::Cursor::new(zip)).unwrap().extract(zip_dir.path()).unwrap();
+        let tar_dir = tempfile::tempdir().unwrap();
+        tar::Archive::new(flate2::read::GzDecoder::new(&tar_gz[..])).unpack(tar_dir.path()).unwrap();
+        for dir in [zip_dir.path(), tar_dir.path()] {
+            for (name, data) in &entries {
+                match data {
+                    Some(data) => assert_eq!(&std::fs::read(dir.join(name)).unwrap(), data, "{name}"),
+                    None => assert!(dir.join(name).is_dir(), "{name}"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get_service, MethodRouter},
+    Router,
+};
+use std::path::Path;
+use tower_http::services::{ServeDir, ServeFile};
+use crate::AppState;
+
+/// Cache policy for file names carrying a content hash (`app.3f9a2c1b.js`, `index-BzX3k9aQ.css`)
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Everything else, including index.html, is revalidated on each load
+const REVALIDATE: &str = "no-cache";
+
+/// Serve a custom frontend from `--static-dir`
+///
+/// Used as the router's fallback, so explicit routes (`/api/*`, `/s/*`) always win.
+/// Unknown paths get `index.html` for client-side routing; `ServeDir` rejects `..`
+/// components, so nothing outside the directory can be reached.
+pub fn static_router(dir: &Path) -> Router<AppState> {
+    let files = ServeDir::new(dir).fallback(ServeFile::new(dir.join("index.html")));
+    Router::new()
+        .fallback_service(files)
+        .layer(middleware::from_fn(cache_headers))
+}
+
+/// Serve the custom frontend's `index.html` on a single route
+///
+/// Needed for the trailing-slash form of `--base-path`, which the nested fallback doesn't match.
+pub fn index_route(dir: &Path) -> MethodRouter<AppState> {
+    get_service(ServeFile::new(dir.join("index.html"))).layer(middleware::from_fn(cache_headers))
+}
+
+/// Whether the file name contains a build hash segment
+fn is_hashed_asset(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let Some((stem, _ext)) = name.rsplit_once('.') else {
+        return false;
+    };
+    stem.split(['.', '-', '_']).any(|part| {
+        part.len() >= 8
+            && part.bytes().all(|b| b.is_ascii_alphanumeric())
+            && part.bytes().any(|b| b.is_ascii_digit())
+            && part.bytes().any(|b| b.is_ascii_alphabetic())
+    })
+}
+
+/// Set `Cache-Control` on static responses
+async fn cache_headers(request: Request, next: Next) -> Response {
+    let hashed = is_hashed_asset(request.uri().path());
+    let mut response = next.run(request).await;
+    if !(response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED) {
+        return response;
+    }
+    // A missing hashed file falls back to index.html, which must not be cached for long
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+    let policy = if hashed && !is_html { IMMUTABLE } else { REVALIDATE };
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static(policy));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+    use axum::body::{to_bytes, Body};
+
+    #[tokio::test]
+    async fn the_static_dir_cannot_be_left() {
+        let outside = tempfile::tempdir().unwrap();
+        let dir = outside.path().join("web");
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>index</html>").unwrap();
+        std::fs::write(dir.join("assets/app.js"), "app").unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        let app = TestApp::with(|c| c.static_dir = Some(dir.clone()));
+        let get = async |uri: &str| {
+            let response = app.send(Request::get(uri).body(Body::empty()).unwrap()).await;
+            let status = response.status();
+            (status, to_bytes(response.into_body(), usize::MAX).await.unwrap())
+        };
+
+        assert_eq!(get("/assets/app.js").await, (StatusCode::OK, "app".into()));
+        for uri in [
+            "/../secret.txt",
+            "/assets/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/%2E%2E%2Fsecret.txt",
+            "/assets/..%2f..%2fsecret.txt",
+            "/..%5csecret.txt",
+            "/assets/%2e%2e%5c%2e%2e%5csecret.txt",
+        ] {
+            let (status, body) = get(uri).await;
+            assert_ne!(body, "secret", "{uri}");
+            assert!(status == StatusCode::NOT_FOUND || body == "<html>index</html>", "{uri}: {status}");
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use axum::{
+    extract::{Extension, Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+use crate::activity::ActivityHub;
+use crate::auth::require_admin;
+use crate::error::ApiError;
+use crate::i18n::Msg;
+use crate::models::{ApiResponse, AuditQuery, AuditResponse};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Rotate the audit file once it grows beyond this size
+const AUDIT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+/// Number of rotated files kept (`audit.log.1` .. `audit.log.N`)
+const AUDIT_KEEP_FILES: u32 = 5;
+/// Default and maximum number of entries returned by the query endpoint
+const AUDIT_DEFAULT_LIMIT: usize = 100;
+const AUDIT_MAX_LIMIT: usize = 1000;
+
+/// One audited operation, stored as a JSON line
+#[derive(Serialize, Deserialize, Clone, utoipa::ToSchema)]
+pub struct AuditEntry {
+    /// RFC 3339 UTC timestamp
+    pub time: String,
+    pub user: String,
+    pub ip: String,
+    pub op: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// "ok" or the error message
+    pub result: String,
+}
+
+impl AuditEntry {
+    pub fn new(op: &str, user: &str, ip: IpAddr, path: impl Into<String>) -> Self {
+        Self {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            user: user.to_string(),
+            ip: ip.to_string(),
+            op: op.to_string(),
+            path: path.into(),
+            dest: None,
+            size: None,
+            result: "ok".to_string(),
+        }
+    }
+
+    pub fn dest(mut self, dest: impl Into<String>) -> Self {
+        self.dest = Some(dest.into());
+        self
+    }
+
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn result<T, E: Display>(mut self, result: &Result<T, E>) -> Self {
+        if let Err(e) = result {
+            self.result = e.to_string();
+        }
+        self
+    }
+
+    /// Whether the entry touches the logical path or anything below it
+    fn touches(&self, prefix: &str) -> bool {
+        let prefix = format!("/{}", prefix.trim_matches('/'));
+        let within = |p: &str| {
+            prefix == "/"
+                || p == prefix
+                || p.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with('/'))
+        };
+        within(&self.path) || self.dest.as_deref().is_some_and(within)
+    }
+}
+
+/// Messages handled by the writer task, in order
+enum AuditMessage {
+    Entry(AuditEntry),
+    /// Answered once every earlier entry has been written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Append-only audit log written by a background task
+///
+/// Handlers only push entries onto a channel, so audit I/O never delays a request.
+pub struct AuditLog {
+    file: PathBuf,
+    tx: mpsc::UnboundedSender<AuditMessage>,
+    /// Successful operations are also announced to connected UIs
+    activity: ActivityHub,
+}
+
+impl AuditLog {
+    /// Start the writer task appending to `<data_dir>/audit.log`
+    pub fn start(data_dir: &Path, activity: ActivityHub) -> Self {
+        let file = data_dir.join("audit.log");
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_entries(file.clone(), rx));
+        Self { file, tx, activity }
+    }
+
+    /// Queue an entry for writing
+    pub fn record(&self, entry: AuditEntry) {
+        crate::logging::record_path(&entry.path);
+        self.activity.publish_audit(&entry);
+        if self.tx.send(AuditMessage::Entry(entry)).is_err() {
+            warn!("Audit writer stopped; entry dropped");
+        }
+    }
+
+    /// Wait until every queued entry is on disk (used at shutdown)
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send(AuditMessage::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+
+    /// Current file followed by rotated files, newest first
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.file.clone()];
+        files.extend((1..=AUDIT_KEEP_FILES).map(|n| rotated(&self.file, n)));
+        files
+    }
+}
+
+fn rotated(file: &Path, n: u32) -> PathBuf {
+    file.with_extension(format!("log.{}", n))
+}
+
+/// Writer task: append JSON lines, rotating the file when it gets too large
+async fn write_entries(file: PathBuf, mut rx: mpsc::UnboundedReceiver<AuditMessage>) {
+    while let Some(message) = rx.recv().await {
+        match message {
+            AuditMessage::Entry(entry) => {
+                if let Err(e) = append(&file, &entry).await {
+                    warn!("Failed to write audit log: {}", e);
+                }
+            }
+            AuditMessage::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+async fn append(file: &Path, entry: &AuditEntry) -> std::io::Result<()> {
+    if fs::metadata(file).await.is_ok_and(|m| m.len() >= AUDIT_MAX_BYTES) {
+        for n in (1..AUDIT_KEEP_FILES).rev() {
+            let _ = fs::rename(rotated(file, n), rotated(file, n + 1)).await;
+        }
+        fs::rename(file, rotated(file, 1)).await?;
+    }
+
+    let mut line = serde_json::to_string(entry).map_err(std::io::Error::other)?;
+    line.push('\n');
+    let mut out = fs::OpenOptions::new().create(true).append(true).open(file).await?;
+    out.write_all(line.as_bytes()).await
+}
+
+fn parse_since(since: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(secs) = since.parse::<i64>() {
+        return DateTime::from_timestamp(secs, 0).ok_or_else(|| Msg::InvalidSince.with(&[&since]));
+    }
+    DateTime::parse_from_rfc3339(since)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| Msg::InvalidSince.with(&[&since]))
+}
+
+/// Query the audit log, newest first (administrator only)
+#[utoipa::path(
+    get, path = "/api/audit", tag = "admin", params(AuditQuery),
+    responses((status = 200, description = "Matching entries, newest first", body = ApiResponse<AuditResponse>)),
+)]
+pub async fn query_audit(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    if let Some(response) = require_admin(&state, &user) {
+        return response;
+    }
+    let since = match query.since.as_deref().map(parse_since).transpose() {
+        Ok(s) => s,
+        Err(e) => return ApiError::BadRequest(e).into_response(),
+    };
+    let limit = query.limit.unwrap_or(AUDIT_DEFAULT_LIMIT).min(AUDIT_MAX_LIMIT);
+
+    let mut entries = Vec::new();
+    'files: for file in state.audit.files() {
+        let Ok(content) = fs::read_to_string(&file).await else {
+            continue;
+        };
+        for line in content.lines().rev() {
+            // A partially written last line is simply skipped
+            let Ok(entry) = serde_json::from_str::<AuditEntry>(line) else {
+                continue;
+            };
+            if let Some(since) = since
+                && DateTime::parse_from_rfc3339(&entry.time).is_ok_and(|t| t < since)
+            {
+                // Files are in chronological order, nothing older can match
+                break 'files;
+            }
+            if query.op.as_deref().is_some_and(|op| op != entry.op)
+                || query.path.as_deref().is_some_and(|p| !entry.touches(p))
+            {
+                continue;
+            }
+            entries.push(entry);
+            if entries.len() >= limit {
+                break 'files;
+            }
+        }
+    }
+
+    Json(ApiResponse::success(AuditResponse { entries })).into_response()
+}
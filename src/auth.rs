@@ -1,13 +1,263 @@
 use axum::{
     body::Body,
-    extract::State,
+    extract::{ConnectInfo, State},
     http::{header, Request, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+    Json,
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
 };
 use base64::{engine::general_purpose::STANDARD, Engine};
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use crate::apikeys::{API_KEY_HEADER, API_KEY_PREFIX};
+use crate::error::ApiError;
+use crate::i18n::Msg;
+use crate::models::{ApiResponse, AuthFailure};
+use crate::users::AuthUser;
 use crate::AppState;
-/// HTTP Basic Authentication middleware
+
+/// Verify a username/password pair against the configured credentials
+///
+/// The command-line user is checked first, then the users file. When a
+/// password hash is configured it takes precedence; the plaintext password
+/// is only compared as a fallback when no hash is set.
+pub fn authenticate(state: &AppState, username: &str, password: &str) -> Option<AuthUser> {
+    let valid = if username == state.username {
+        match &state.password_hash {
+            Some(hash) => verify_password_hash(hash, password),
+            None => bool::from(password.as_bytes().ct_eq(state.password.as_bytes())),
+        }
+    } else {
+        let entry = state.users.get(username)?;
+        match (&entry.password_hash, &entry.password) {
+            (Some(hash), _) => verify_password_hash(hash, password),
+            (None, Some(plain)) => bool::from(password.as_bytes().ct_eq(plain.as_bytes())),
+            (None, None) => false,
+        }
+    };
+    if valid { lookup_user(state, username) } else { None }
+}
+
+/// Look up a known user (for token/session authentication) with their path rules
+pub fn lookup_user(state: &AppState, username: &str) -> Option<AuthUser> {
+    if username == state.username {
+        return Some(AuthUser {
+            username: username.to_string(),
+            rules: None,
+            readonly: false,
+        });
+    }
+    state.users.get(username).map(|entry| AuthUser {
+        username: username.to_string(),
+        rules: entry.rules.clone(),
+        readonly: false,
+    })
+}
+
+/// Check that a configured password hash is a supported bcrypt or argon2 PHC string
+pub fn validate_password_hash(hash: &str) -> Result<(), String> {
+    if is_bcrypt_hash(hash) {
+        bcrypt::HashParts::from_str(hash)
+            .map(|_| ())
+            .map_err(|e| format!("Invalid bcrypt hash: {}", e))
+    } else {
+        PasswordHash::new(hash)
+            .map(|_| ())
+            .map_err(|e| format!("Invalid argon2 PHC hash: {}", e))
+    }
+}
+
+/// Hash a password as an argon2id PHC string
+pub fn hash_password(password: &str) -> Result<String, String> {
+    // A v4 UUID supplies 16 random bytes, the recommended salt length
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes())
+        .map_err(|e| format!("Failed to generate salt: {}", e))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| format!("Failed to hash password: {}", e))
+}
+
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|p| hash.starts_with(p))
+}
+
+/// Verify a password against a bcrypt or argon2 PHC hash
+pub fn verify_password_hash(hash: &str, password: &str) -> bool {
+    if is_bcrypt_hash(hash) {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Decode a Basic credentials string into (username, password)
+fn decode_basic(credentials: &str) -> Option<(String, String)> {
+    let decoded = STANDARD.decode(credentials).ok()?;
+    let credential_str = String::from_utf8(decoded).ok()?;
+    let (username, password) = credential_str.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Result of looking up a bearer token or session
+enum TokenCheck {
+    Valid(String),
+    Expired,
+    Unknown,
+}
+
+/// Look up a bearer token, dropping it from the store once expired
+async fn check_token(state: &AppState, token: &str) -> TokenCheck {
+    let (expired, username) = {
+        let tokens = state.auth_tokens.read().await;
+        match tokens.get(token) {
+            Some(t) => (t.expires_at <= std::time::Instant::now(), t.username.clone()),
+            None => return TokenCheck::Unknown,
+        }
+    };
+
+    if expired {
+        state.auth_tokens.write().await.remove(token);
+        TokenCheck::Expired
+    } else {
+        TokenCheck::Valid(username)
+    }
+}
+
+/// Client IP of a request: the socket peer, or the client behind a trusted proxy (`proxy::resolve_client`)
+pub fn client_ip<B>(request: &Request<B>) -> IpAddr {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ci| ci.0.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// Seconds remaining on an active lockout for this IP, if any
+pub async fn lockout_remaining(state: &AppState, ip: IpAddr) -> Option<u64> {
+    let failures = state.auth_failures.read().await;
+    let locked_until = failures.get(&ip)?.locked_until?;
+    let now = Instant::now();
+    (locked_until > now).then(|| (locked_until - now).as_secs().max(1))
+}
+
+/// Record a failed authentication attempt, locking the IP out after too many failures
+pub async fn record_failure(state: &AppState, ip: IpAddr, username: &str) {
+    let window = Duration::from_secs(state.auth_lockout_secs);
+    let now = Instant::now();
+    let mut failures = state.auth_failures.write().await;
+
+    // Prune stale entries so the map can't grow unbounded
+    failures.retain(|_, f| {
+        f.locked_until.is_some_and(|t| t > now) || now.duration_since(f.window_start) < window
+    });
+
+    let entry = failures.entry(ip).or_insert(AuthFailure {
+        count: 0,
+        window_start: now,
+        locked_until: None,
+    });
+    if now.duration_since(entry.window_start) >= window {
+        entry.count = 0;
+        entry.window_start = now;
+        entry.locked_until = None;
+    }
+    entry.count += 1;
+
+    if entry.count >= state.auth_max_failures && entry.locked_until.is_none() {
+        entry.locked_until = Some(now + window);
+        warn!(
+            "Locking out {} for {}s after {} failed logins (last username: {:?})",
+            ip, state.auth_lockout_secs, entry.count, username
+        );
+    }
+}
+
+/// Clear the failure counter for an IP after a successful login
+pub async fn reset_failures(state: &AppState, ip: IpAddr) {
+    state.auth_failures.write().await.remove(&ip);
+}
+
+/// Build a 429 response for a locked-out client
+pub fn too_many_attempts(retry_after: u64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(ApiResponse::<()>::error_with_code(
+            "TOO_MANY_ATTEMPTS",
+            Msg::TooManyAttempts.with(&[&retry_after]),
+        )),
+    )
+        .into_response()
+}
+
+/// Name of the browser session cookie
+pub const SESSION_COOKIE: &str = "filest_session";
+
+/// Extract the session id from the Cookie header
+pub fn session_cookie(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Build the Set-Cookie value for a session (an empty id clears the cookie)
+pub fn session_set_cookie(state: &AppState, session_id: &str) -> String {
+    let max_age = if session_id.is_empty() { 0 } else { state.session_max_secs };
+    let secure = if state.tls_enabled { "; Secure" } else { "" };
+    let path = if state.base_path.is_empty() { "/" } else { &state.base_path };
+    format!(
+        "{}={}; Path={}; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        SESSION_COOKIE, session_id, path, max_age, secure
+    )
+}
+
+/// Validate a session against its idle and absolute expiry, refreshing its idle timer
+///
+/// Sessions live only in memory, so restarting with a changed password invalidates them all.
+async fn check_session(state: &AppState, session_id: &str) -> TokenCheck {
+    let now = Instant::now();
+    let mut sessions = state.sessions.write().await;
+    let Some(session) = sessions.get_mut(session_id) else {
+        return TokenCheck::Unknown;
+    };
+
+    let idle_expired = now.duration_since(session.last_seen) > Duration::from_secs(state.session_idle_secs);
+    let max_expired = now.duration_since(session.created_at) > Duration::from_secs(state.session_max_secs);
+    if idle_expired || max_expired {
+        sessions.remove(session_id);
+        return TokenCheck::Expired;
+    }
+
+    session.last_seen = now;
+    TokenCheck::Valid(session.username.clone())
+}
+
+/// Attach the authenticated user to the request and its log span, then continue
+async fn proceed(mut request: Request<Body>, user: AuthUser, next: Next) -> Response {
+    tracing::Span::current().record("user", user.username.as_str());
+    request.extensions_mut().insert(user);
+    next.run(request).await
+}
+
+/// HTTP Basic / Bearer token authentication middleware
 pub async fn auth_middleware(
     State(state): State<AppState>,
     request: Request<Body>,
@@ -17,24 +267,96 @@ pub async fn auth_middleware(
     let auth_header = request
         .headers()
         .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok());
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string());
+
+    // API keys: X-Api-Key header, or a Bearer token carrying the key prefix
+    let api_key = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| {
+            auth_header
+                .as_deref()
+                .and_then(|a| a.strip_prefix("Bearer "))
+                .filter(|t| t.trim().starts_with(API_KEY_PREFIX))
+        })
+        .map(|k| k.trim().to_string());
 
     // Track whether client attempted authentication
-    let has_auth_header = auth_header.is_some();
+    let has_auth_header = auth_header.is_some() || api_key.is_some();
+
+    // Refuse locked-out clients before checking anything
+    let ip = client_ip(&request);
+    if has_auth_header && let Some(retry_after) = lockout_remaining(&state, ip).await {
+        return Ok(too_many_attempts(retry_after));
+    }
+
+    if let Some(key) = api_key {
+        // Like unknown login tokens below, not counted towards the lockout
+        let Some(record) = state.api_keys.verify(&key).await else {
+            return Ok(unauthorized("API_KEY_INVALID", Msg::ApiKeyInvalid));
+        };
+        let Some(mut user) = lookup_user(&state, &record.owner) else {
+            return Ok(unauthorized("API_KEY_INVALID", Msg::ApiKeyInvalid));
+        };
+        user.readonly = record.readonly;
+        // WebDAV clients list folders with PROPFIND
+        if record.readonly && !matches!(request.method().as_str(), "GET" | "HEAD" | "OPTIONS" | "PROPFIND") {
+            return Ok(ApiError::ReadOnly(Msg::ApiKeyReadOnly.into()).into_response());
+        }
+        return Ok(proceed(request, user, next).await);
+    }
 
-    match auth_header {
+    match auth_header.as_deref() {
         Some(auth) if auth.starts_with("Basic ") => {
             let credentials = auth.trim_start_matches("Basic ");
 
-            // Decode Base64
-            if let Ok(decoded) = STANDARD.decode(credentials) {
-                if let Ok(credential_str) = String::from_utf8(decoded) {
-                    // Split username and password
-                    if let Some((username, password)) = credential_str.split_once(':') {
-                        // Verify credentials
-                        if username == state.username && password == state.password {
-                            return Ok(next.run(request).await);
+            // Verify credentials
+            match decode_basic(credentials) {
+                Some((username, password)) => match authenticate(&state, &username, &password) {
+                    Some(user) => {
+                        return Ok(proceed(request, user, next).await);
+                    }
+                    None => record_failure(&state, ip, &username).await,
+                },
+                None => record_failure(&state, ip, "").await,
+            }
+        }
+        Some(auth) if auth.starts_with("Bearer ") => {
+            let token = auth.trim_start_matches("Bearer ").trim();
+
+            match check_token(&state, token).await {
+                TokenCheck::Valid(username) => {
+                    // The user may have been removed from the users file since login
+                    let Some(user) = lookup_user(&state, &username) else {
+                        return Ok(unauthorized("TOKEN_INVALID", Msg::TokenInvalid));
+                    };
+                    return Ok(proceed(request, user, next).await);
+                }
+                // Expired or revoked tokens get a distinct code so the UI can prompt re-login
+                TokenCheck::Expired => {
+                    return Ok(unauthorized("TOKEN_EXPIRED", Msg::TokenExpired));
+                }
+                // Not counted towards the lockout: tokens are random and can't be guessed,
+                // and a client still sending one that was revoked would lock out its IP
+                TokenCheck::Unknown => {
+                    return Ok(unauthorized("TOKEN_INVALID", Msg::TokenInvalid));
+                }
+            }
+        }
+        None => {
+            // No Authorization header: fall back to the browser session cookie
+            if let Some(session_id) = session_cookie(request.headers()) {
+                match check_session(&state, &session_id).await {
+                    TokenCheck::Valid(username) => {
+                        if let Some(user) = lookup_user(&state, &username) {
+                            return Ok(proceed(request, user, next).await);
                         }
+                        return Ok(unauthorized("SESSION_EXPIRED", Msg::SessionExpired));
+                    }
+                    TokenCheck::Expired | TokenCheck::Unknown => {
+                        return Ok(unauthorized("SESSION_EXPIRED", Msg::SessionExpired));
                     }
                 }
             }
@@ -47,7 +369,7 @@ pub async fn auth_middleware(
     // This prevents browser from showing built-in auth dialog when frontend handles auth
     let mut response = Response::builder()
         .status(StatusCode::UNAUTHORIZED);
-    
+
     if !has_auth_header {
         // No auth header provided - include WWW-Authenticate for proper HTTP semantics
         response = response.header(
@@ -61,4 +383,38 @@ pub async fn auth_middleware(
     Ok(response
         .body(Body::from("Unauthorized"))
         .unwrap())
-}
\ No newline at end of file
+}
+
+/// Build a 403 JSON response for a path the user may not access
+pub fn forbidden(message: impl Into<String>) -> Response {
+    ApiError::AccessDenied(message.into()).into_response()
+}
+
+/// Refuse the request unless it comes from the administrator (the primary `--user` account)
+pub fn require_admin(state: &AppState, user: &AuthUser) -> Option<Response> {
+    (user.username != state.username).then(|| forbidden(Msg::AdminOnly))
+}
+
+/// Build a 401 JSON response carrying a machine-readable code
+fn unauthorized(code: &'static str, message: Msg) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(ApiResponse::<()>::error_with_code(code, message)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[tokio::test]
+    async fn plaintext_passwords_must_match_exactly() {
+        let app = TestApp::with(|_| {});
+        assert!(authenticate(&app.state, "admin", "admin123").is_some());
+        for wrong in ["", "admin12", "admin1234", "ADMIN123"] {
+            assert!(authenticate(&app.state, "admin", wrong).is_none(), "{wrong:?}");
+        }
+    }
+}
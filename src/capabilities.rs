@@ -0,0 +1,142 @@
+use axum::{
+    extract::{Extension, State},
+    Json,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use crate::archive;
+use crate::limits::UploadLimits;
+use crate::models::{ApiResponse, CapabilitiesResponse, UserRole};
+use crate::ratelimit::ClientLimits;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// How files can be searched for, as `searchModes`: by name or tags with `/api/search`, by
+/// content with `/api/search/content`
+const SEARCH_MODES: [&str; 3] = ["name", "tags", "content"];
+
+/// What `/api/capabilities` tells clients about the server, assembled once at startup
+///
+/// main.rs builds it where the configuration is turned into `AppState`, and each optional
+/// feature registers itself there with [`Capabilities::feature`], so the document can't
+/// drift from what the server actually does.
+pub struct Capabilities(CapabilitiesResponse);
+
+impl Capabilities {
+    pub fn new(upload_limits: &UploadLimits, client_limits: &ClientLimits) -> Self {
+        let (max_conns_per_ip, rate_limit) = client_limits.info();
+        Self(CapabilitiesResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            username: String::new(),
+            role: UserRole::default(),
+            read_only: false,
+            upload: upload_limits.capabilities(),
+            max_conns_per_ip,
+            rate_limit,
+            search_modes: SEARCH_MODES.iter().map(|mode| mode.to_string()).collect(),
+            archive_formats: archive::FORMATS.iter().map(|format| format.to_string()).collect(),
+            features: BTreeMap::new(),
+        })
+    }
+
+    /// Register an optional feature: `false` when off, `true` or its settings when on
+    pub fn feature(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.0.features.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// The document as seen by `user`
+    fn for_user(&self, user: &AuthUser, admin: &str) -> CapabilitiesResponse {
+        CapabilitiesResponse {
+            username: user.username.clone(),
+            role: if user.username == admin { UserRole::Admin } else { UserRole::User },
+            read_only: user.readonly,
+            ..self.0.clone()
+        }
+    }
+}
+
+/// What this server supports and allows, so clients adapt to its configuration instead of
+/// hardcoding it
+///
+/// Upload limits say when to switch to chunked upload. `features` lists the optional
+/// features by name; a client should treat a missing name as unsupported. `role` and
+/// `readOnly` describe the caller.
+#[utoipa::path(
+    get, path = "/api/capabilities", tag = "system",
+    responses((status = 200, description = "Version, limits, features and the caller's role", body = ApiResponse<CapabilitiesResponse>)),
+)]
+pub async fn capabilities(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> Json<ApiResponse<CapabilitiesResponse>> {
+    Json(ApiResponse::success(state.capabilities.for_user(&user, &state.username)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::MIN_REQUEST_BODY;
+    use crate::ratelimit::parse_rate;
+    use crate::testing::{admin, TestApp};
+    use axum::{body::Body, http::{Method, StatusCode}};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn the_document_keeps_its_field_names() {
+        let upload_limits = UploadLimits::new(MIN_REQUEST_BODY, Duration::from_secs(120));
+        let client_limits = ClientLimits::new(Some(8), Some(parse_rate("100/10s").unwrap()));
+        let capabilities = Capabilities::new(&upload_limits, &client_limits)
+            .feature("webdav", false)
+            .feature("clipboard", json!({ "idleSecs": 60 }));
+        let user = AuthUser { username: "bob".to_string(), rules: None, readonly: true };
+
+        let document = serde_json::to_value(capabilities.for_user(&user, "admin")).unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "username": "bob",
+                "role": "user",
+                "readOnly": true,
+                "maxRequestBody": 65536,
+                "maxFileSize": 65536,
+                "maxChunkSize": 49152,
+                "chunkSize": 49152,
+                "chunkedThreshold": 49152,
+                "uploadIdleSecs": 120,
+                "maxConnsPerIp": 8,
+                "rateLimit": { "requests": 100, "windowSecs": 10 },
+                "searchModes": ["name", "tags", "content"],
+                "archiveFormats": ["zip", "tar.gz"],
+                "features": { "clipboard": { "idleSecs": 60 }, "webdav": false },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn the_server_lists_every_feature() {
+        let app = TestApp::with(|_| {});
+        let (status, body) = app.json(admin(Method::GET, "/api/capabilities").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!((&body["username"], &body["role"]), (&json!("admin"), &json!("admin")));
+
+        let features: Vec<&str> = body["features"].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(
+            features,
+            [
+                "activity", "apiDocs", "apiKeys", "append", "archive", "archiveBrowse", "clipboard", "content",
+                "contentPatch", "contentSave", "contentSearch", "deleteConfirmation", "downloadRate", "events",
+                "extract", "image", "locks", "manifest", "markdown", "mediaInfo", "meta", "raw", "search",
+                "searchIndex", "shares", "stream", "tail", "trash", "undo", "uploadHook", "webdav",
+            ]
+        );
+        // Settings of features that have some
+        assert_eq!(body["features"]["raw"], json!({ "autoindex": false }));
+        assert_eq!(body["features"]["searchIndex"], false);
+        assert_eq!(body["features"]["uploadHook"], false);
+        let trash: Vec<&str> = body["features"]["trash"].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(trash, ["maxSize", "retentionDays"]);
+    }
+}
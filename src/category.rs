@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Broad kind of a file, from its extension
+///
+/// Shown as `category` in listings and used by `/api/stats?group_by=category`, so the
+/// two always agree.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCategory {
+    Document,
+    Image,
+    Video,
+    Audio,
+    Archive,
+    Code,
+    Executable,
+    Other,
+}
+
+impl FileCategory {
+    pub fn of(name: &str) -> Self {
+        let Some((_, ext)) = name.rsplit_once('.') else { return Self::Other };
+        match ext.to_ascii_lowercase().as_str() {
+            "pdf" | "doc" | "docx" | "xls" | "xlsx" | "ppt" | "pptx" | "odt" | "ods" | "odp" | "rtf" | "txt"
+            | "md" | "csv" | "epub" => Self::Document,
+            "jpg" | "jpeg" | "png" | "gif" | "svg" | "webp" | "bmp" | "ico" | "tif" | "tiff" | "heic" | "avif" => {
+                Self::Image
+            }
+            "mp4" | "avi" | "mkv" | "mov" | "webm" | "wmv" | "flv" | "m4v" | "mpg" | "mpeg" => Self::Video,
+            "mp3" | "wav" | "flac" | "aac" | "ogg" | "m4a" | "opus" | "wma" => Self::Audio,
+            "zip" | "rar" | "7z" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "iso" | "dmg" => Self::Archive,
+            "html" | "css" | "js" | "ts" | "tsx" | "jsx" | "vue" | "py" | "java" | "go" | "rs" | "c" | "h" | "cpp"
+            | "json" | "xml" | "yaml" | "yml" | "toml" | "sql" | "sh" | "bat" | "ps1" => Self::Code,
+            "exe" | "msi" | "dll" | "so" | "deb" | "rpm" | "apk" | "appimage" => Self::Executable,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Document => "document",
+            Self::Image => "image",
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::Archive => "archive",
+            Self::Code => "code",
+            Self::Executable => "executable",
+            Self::Other => "other",
+        }
+    }
+}
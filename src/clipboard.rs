@@ -0,0 +1,287 @@
+use axum::{
+    extract::{ConnectInfo, Extension, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, copy_item, move_item, safe_path};
+use crate::i18n::Msg;
+use crate::jobs::{Outcome, ReportRequest, WalkProgress};
+use crate::models::{
+    ApiResponse, ClipboardOp, ClipboardRequest, ClipboardResponse, CopyRequest, JobStatus, MoveRequest, PasteItem,
+    PasteRequest, PasteResponse, PasteResult,
+};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Most paths one clipboard holds
+const MAX_CLIPBOARD_PATHS: usize = 1000;
+/// How often idle clipboards are dropped
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Clipboard {
+    op: ClipboardOp,
+    /// Logical paths, in the order they were given
+    paths: Vec<String>,
+    last_used: Instant,
+}
+
+/// Each user's clipboard, held in memory until pasted (for a cut), replaced or idle too long
+pub struct ClipboardStore {
+    idle: Duration,
+    clipboards: Mutex<HashMap<String, Clipboard>>,
+}
+
+impl ClipboardStore {
+    pub fn new(idle: Duration) -> Self {
+        Self { idle, clipboards: Mutex::new(HashMap::new()) }
+    }
+
+    /// The clipboards still in use, with idle ones dropped
+    fn live(&self) -> std::sync::MutexGuard<'_, HashMap<String, Clipboard>> {
+        let mut clipboards = self.clipboards.lock().unwrap_or_else(|e| e.into_inner());
+        clipboards.retain(|_, clipboard| clipboard.last_used.elapsed() < self.idle);
+        clipboards
+    }
+
+    fn response(&self, clipboard: Option<&Clipboard>) -> ClipboardResponse {
+        match clipboard {
+            Some(clipboard) => {
+                let left = self.idle.saturating_sub(clipboard.last_used.elapsed());
+                ClipboardResponse {
+                    op: Some(clipboard.op),
+                    paths: clipboard.paths.clone(),
+                    expires_at: Some(Utc::now().timestamp() + left.as_secs() as i64),
+                }
+            }
+            None => ClipboardResponse { op: None, paths: Vec::new(), expires_at: None },
+        }
+    }
+
+    fn set(&self, username: &str, op: ClipboardOp, paths: Vec<String>) -> ClipboardResponse {
+        let mut clipboards = self.live();
+        if paths.is_empty() {
+            clipboards.remove(username);
+            return self.response(None);
+        }
+        let clipboard = Clipboard { op, paths, last_used: Instant::now() };
+        let response = self.response(Some(&clipboard));
+        clipboards.insert(username.to_string(), clipboard);
+        response
+    }
+
+    /// The user's clipboard, which counts as using it
+    fn get(&self, username: &str) -> ClipboardResponse {
+        let mut clipboards = self.live();
+        let clipboard = clipboards.get_mut(username);
+        if let Some(clipboard) = clipboard {
+            clipboard.last_used = Instant::now();
+            return self.response(Some(clipboard));
+        }
+        self.response(None)
+    }
+
+    /// What to paste; a cut is taken off the clipboard so it can only be pasted once
+    fn for_paste(&self, username: &str) -> Option<(ClipboardOp, Vec<String>)> {
+        let mut clipboards = self.live();
+        let clipboard = clipboards.get_mut(username)?;
+        if clipboard.op == ClipboardOp::Cut {
+            return clipboards.remove(username).map(|c| (c.op, c.paths));
+        }
+        clipboard.last_used = Instant::now();
+        Some((clipboard.op, clipboard.paths.clone()))
+    }
+}
+
+/// Drop idle clipboards every `REAP_INTERVAL` until shutdown
+pub fn start_reaper(clipboards: Arc<ClipboardStore>, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => drop(clipboards.live()),
+            }
+        }
+    });
+}
+
+/// The logical form of a path put on the clipboard, once it is known to be usable
+fn checked_path(state: &AppState, user: &AuthUser, op: ClipboardOp, path: &str) -> Result<String, ApiError> {
+    let paths = safe_path(&state.mounts, path)?;
+    if op == ClipboardOp::Cut && paths.is_root() {
+        return Err(ApiError::AccessDenied(Msg::CannotMoveRoot.into()));
+    }
+    let logical = state.mounts.logical_path(&paths.logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::SourceNotFound.into()));
+    }
+    Ok(logical)
+}
+
+/// Put files and folders on the caller's clipboard, replacing what was there
+///
+/// Every path must exist and be accessible, or nothing is stored. An empty `paths`
+/// clears the clipboard.
+#[utoipa::path(
+    post, path = "/api/clipboard", tag = "files", request_body = ClipboardRequest,
+    responses(
+        (status = 200, description = "The stored clipboard", body = ApiResponse<ClipboardResponse>),
+        (status = 400, description = "More than 1000 paths", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn set_clipboard(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<ClipboardRequest>,
+) -> Result<Json<ApiResponse<ClipboardResponse>>, ApiError> {
+    if req.paths.len() > MAX_CLIPBOARD_PATHS {
+        return Err(ApiError::BadRequest(Msg::TooManyClipboardPaths.with(&[&MAX_CLIPBOARD_PATHS])));
+    }
+    let mut paths: Vec<String> = Vec::with_capacity(req.paths.len());
+    for path in &req.paths {
+        let logical = checked_path(&state, &user, req.op, path)?;
+        if !paths.contains(&logical) {
+            paths.push(logical);
+        }
+    }
+    Ok(Json(ApiResponse::success(state.clipboards.set(&user.username, req.op, paths))))
+}
+
+/// The caller's clipboard, so a reconnecting client can restore its selection
+///
+/// An empty clipboard has no `op` and no `paths`. Reading it counts as use and keeps it
+/// from expiring.
+#[utoipa::path(
+    get, path = "/api/clipboard", tag = "files",
+    responses((status = 200, description = "The clipboard", body = ApiResponse<ClipboardResponse>)),
+)]
+pub async fn get_clipboard(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> Json<ApiResponse<ClipboardResponse>> {
+    Json(ApiResponse::success(state.clipboards.get(&user.username)))
+}
+
+/// A paste request, run item by item in the background
+struct Paste {
+    user: AuthUser,
+    addr: SocketAddr,
+    /// Passed on to each copy or move, for the lock token
+    headers: HeaderMap,
+    op: ClipboardOp,
+    paths: Vec<String>,
+    destination: String,
+}
+
+/// Copy or move each clipboard item into the destination, noting how it went
+async fn paste_items(state: AppState, paste: Paste, progress: Arc<WalkProgress>) -> PasteResult {
+    let Paste { user, addr, headers, op, paths, destination } = paste;
+    let mut items = Vec::with_capacity(paths.len());
+    for source in paths {
+        let result = match op {
+            ClipboardOp::Copy => {
+                let req = CopyRequest { source: source.clone(), destination: destination.clone(), background: false };
+                copy_item(&state, &user, addr, &headers, &req).await.map(|r| (r.new_path, r.skipped))
+            }
+            ClipboardOp::Cut => {
+                let req = MoveRequest { source: source.clone(), destination: destination.clone(), background: false };
+                move_item(&state, &user, addr, &headers, &req)
+                    .await
+                    .map(|r| (r.new_path.unwrap_or_default(), Vec::new()))
+            }
+        };
+        items.push(match result {
+            Ok((new_path, skipped)) => PasteItem {
+                source,
+                success: true,
+                new_path: Some(new_path),
+                skipped,
+                code: None,
+                error: None,
+            },
+            Err(e) => PasteItem {
+                source,
+                success: false,
+                new_path: None,
+                skipped: Vec::new(),
+                code: Some(e.code()),
+                error: Some(e.message().to_string()),
+            },
+        });
+        progress.add(1, 0);
+    }
+    let succeeded = items.iter().filter(|item| item.success).count();
+    PasteResult { op, destination, failed: items.len() - succeeded, succeeded, items }
+}
+
+/// Paste the clipboard into a folder: copies for `copy`, moves for `cut`
+///
+/// Each item is copied or moved like `/api/copy` or `/api/move` would, and gets its own
+/// result; an item deleted or moved since it was put on the clipboard fails with
+/// `NOT_FOUND` without stopping the others. A cut clipboard is cleared when pasted, a
+/// copied one can be pasted again. Pastes that take over two seconds continue in the
+/// background: the response is then `computing` with a `job` token for `/api/jobs`,
+/// whose `result` holds the item results once done.
+#[utoipa::path(
+    post, path = "/api/paste", tag = "files", request_body = PasteRequest,
+    responses(
+        (status = 200, description = "Item results, or the job pasting them", body = ApiResponse<PasteResponse>),
+        (status = 400, description = "The clipboard is empty", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn paste(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<PasteRequest>,
+) -> Result<Json<ApiResponse<PasteResponse>>, ApiError> {
+    let dest_dir = safe_path(&state.mounts, &req.destination)?;
+    let destination = state.mounts.logical_path(&dest_dir.logical);
+    user.check(&destination).map_err(ApiError::AccessDenied)?;
+    if !dest_dir.actual.is_dir() {
+        return Err(ApiError::NotFound(Msg::DirNotFound.into()));
+    }
+    let (op, paths) = state
+        .clipboards
+        .for_paste(&user.username)
+        .ok_or_else(|| ApiError::BadRequest(Msg::ClipboardEmpty.into()))?;
+
+    // Every paste is a job of its own, never answered from the report cache
+    let key = Uuid::new_v4().simple().to_string();
+    let request = ReportRequest::new("paste", &key, &user.username, destination.clone(), &[]).await;
+    let paste = Paste { user: user.clone(), addr, headers, op, paths, destination };
+    let walk = {
+        let state = state.clone();
+        move |progress| async move {
+            let result = paste_items(state, paste, progress).await;
+            serde_json::to_value(result).unwrap_or_default()
+        }
+    };
+    let response = match state.reports.run(&user.username, request, walk).await {
+        Outcome::Done(result) => PasteResponse {
+            status: JobStatus::Done,
+            job: None,
+            entries: None,
+            result: Some((*result).clone()),
+        },
+        Outcome::Running { token, progress } => PasteResponse {
+            status: JobStatus::Computing,
+            job: Some(token),
+            entries: Some(progress.entries()),
+            result: None,
+        },
+    };
+    Ok(Json(ApiResponse::success(response)))
+}
@@ -0,0 +1,390 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::i18n::Lang;
+use crate::mounts::{Mounts, NoFollow};
+use crate::paths::NameForm;
+
+/// Log output format
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with request IDs
+    Json,
+}
+
+/// Effective configuration, merged with precedence CLI flags > config file > defaults
+///
+/// Keys mirror the long CLI flags with `-` replaced by `_`, e.g. `--token-ttl-secs`
+/// becomes `token_ttl_secs`; `--cors-origin` becomes the `cors_origins` list.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub root: PathBuf,
+    /// Named roots as `name=path`; when set, `root` is ignored
+    pub mounts: Vec<String>,
+    pub port: u16,
+    pub bind: String,
+    /// URL prefix when served behind a reverse proxy, e.g. "/filest"
+    pub base_path: String,
+    pub user: String,
+    pub password: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    pub token_ttl_secs: u64,
+    pub auth_max_failures: u32,
+    pub auth_lockout_secs: u64,
+    pub session_idle_secs: u64,
+    pub session_max_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_redirect_port: Option<u16>,
+    pub cors_origins: Vec<String>,
+    pub cors_allow_any: bool,
+    pub data_dir: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub static_dir: Option<PathBuf>,
+    pub hide: Vec<String>,
+    pub protect: Vec<String>,
+    /// Size in bytes, or a string such as "10GB"
+    #[serde(deserialize_with = "deserialize_size", skip_serializing_if = "Option::is_none")]
+    pub confirm_delete_over: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirm_delete_entries: Option<u64>,
+    /// Upload request body limit; bytes or a string such as "500MB"
+    #[serde(deserialize_with = "deserialize_bytes")]
+    pub max_request_body: u64,
+    pub shutdown_grace_secs: u64,
+    /// Create and remove a probe file in each root on `/api/health`
+    pub health_write_probe: bool,
+    /// Serve `/api/openapi.json` (and Swagger UI) without authentication
+    pub public_api_docs: bool,
+    /// Serve the files over WebDAV at `/dav`
+    pub webdav: bool,
+    /// List folders requested under `/raw` instead of refusing them
+    pub raw_autoindex: bool,
+    pub log_format: LogFormat,
+    /// Longest accepted name for new files and folders, in bytes
+    pub max_filename_bytes: usize,
+    /// Refuse new names starting with `.`
+    pub deny_dot_files: bool,
+    /// Unicode normalization applied to new names
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalize_names: Option<NameForm>,
+    /// Symlinks refused instead of followed; all are followed when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_follow_symlinks: Option<NoFollow>,
+    /// Longest `/api/info` spends walking a folder before reporting a partial size
+    pub dir_size_timeout_secs: u64,
+    /// Entries `/api/info` counts before reporting a partial size
+    pub dir_size_max_entries: u64,
+    /// Background walks (`/api/info?exact=true`, reports) running at once
+    pub dir_size_jobs: usize,
+    /// Most bytes `/api/tail` reads back from the end of a file, and reads per poll when following; bytes or a string such as "1MB"
+    #[serde(deserialize_with = "deserialize_bytes")]
+    pub tail_max_bytes: u64,
+    /// Search walks (`/api/search`) running at once; others wait for a slot
+    pub search_jobs: usize,
+    /// Images `/api/image` decodes and resizes at once; others wait for a slot
+    pub image_jobs: usize,
+    /// Longest a search may walk before returning what it found, whatever `timeout_secs` asks for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_timeout_secs: Option<u64>,
+    /// Most results one `/api/search` response gives; `nextToken` continues from there
+    pub search_max_results: usize,
+    /// Files larger than this are skipped by `/api/search/content`; bytes or a string such as "10MB"
+    #[serde(deserialize_with = "deserialize_bytes")]
+    pub content_search_max_bytes: u64,
+    /// Keep an index of file names under `data_dir` and answer name searches from it
+    pub index: bool,
+    /// Entries a report such as `/api/usage` walks before stopping
+    pub report_max_entries: u64,
+    /// Files larger than this are listed by `/api/manifest` without a hash; bytes or a string such as "1GB"
+    #[serde(deserialize_with = "deserialize_bytes")]
+    pub manifest_hash_max_bytes: u64,
+    /// Free space every write must leave on its disk; bytes or a string such as "1GB", 0 to disable
+    #[serde(deserialize_with = "deserialize_bytes")]
+    pub min_free_space: u64,
+    /// Upload requests writing to disk at once; others wait briefly, then get a 429
+    pub max_upload_streams: usize,
+    /// Unfinished chunked upload sessions at once
+    pub max_upload_sessions: usize,
+    /// Seconds an upload may go without receiving data before it is aborted
+    pub upload_idle_secs: u64,
+    /// Seconds a clipboard is kept without being set, read or pasted
+    pub clipboard_idle_secs: u64,
+    /// Move deleted files and folders to the trash instead of removing them
+    pub trash: bool,
+    /// Days trashed items are kept before they are purged, 0 to keep them until purged by hand
+    pub trash_retention_days: u64,
+    /// Bytes the trash may hold before its oldest items are purged; bytes or a string such as "50GB"
+    #[serde(deserialize_with = "deserialize_size", skip_serializing_if = "Option::is_none")]
+    pub trash_max_size: Option<u64>,
+    /// Command run on each upload before it is stored, such as "clamdscan --no-summary {file}"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_hook: Option<String>,
+    /// Whether a failing upload hook rejects the upload or only warns
+    pub upload_hook_mode: crate::hooks::HookMode,
+    /// Seconds an upload hook may run before it is killed and counts as failed
+    pub upload_hook_timeout_secs: u64,
+    /// Upload hook commands running at once
+    pub upload_hook_jobs: usize,
+    /// API requests one client address may have in progress at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_conns_per_ip: Option<usize>,
+    /// API requests one client address may start per window, such as "100/10s"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<crate::ratelimit::RateLimit>,
+    /// Bytes per second each download is sent at most; bytes or a string such as "10MB"
+    #[serde(deserialize_with = "deserialize_size", skip_serializing_if = "Option::is_none")]
+    pub max_download_rate: Option<u64>,
+    /// Reverse proxies, as addresses or CIDR ranges, whose `X-Forwarded-For` and `X-Real-IP` are believed
+    pub trusted_proxies: Vec<crate::proxy::IpRange>,
+    /// Message language for clients that send no usable `Accept-Language`
+    pub default_lang: Lang,
+    /// Keys that don't match any option (reported as warnings)
+    #[serde(flatten, skip_serializing)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("./files"),
+            mounts: Vec::new(),
+            port: 3000,
+            bind: "0.0.0.0".to_string(),
+            base_path: "/".to_string(),
+            user: "admin".to_string(),
+            password: "admin123".to_string(),
+            password_hash: None,
+            token_ttl_secs: 86400,
+            auth_max_failures: 10,
+            auth_lockout_secs: 300,
+            session_idle_secs: 1800,
+            session_max_secs: 86400,
+            users_file: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_redirect_port: None,
+            cors_origins: Vec::new(),
+            cors_allow_any: false,
+            data_dir: PathBuf::from("./filest_data"),
+            static_dir: None,
+            hide: Vec::new(),
+            protect: Vec::new(),
+            confirm_delete_over: None,
+            confirm_delete_entries: None,
+            max_request_body: 10 * 1024 * 1024 * 1024,
+            shutdown_grace_secs: 30,
+            health_write_probe: true,
+            public_api_docs: false,
+            webdav: true,
+            raw_autoindex: false,
+            log_format: LogFormat::Text,
+            max_filename_bytes: crate::paths::DEFAULT_MAX_FILENAME_BYTES,
+            deny_dot_files: false,
+            normalize_names: None,
+            no_follow_symlinks: None,
+            dir_size_timeout_secs: 3,
+            dir_size_max_entries: 200_000,
+            dir_size_jobs: 2,
+            tail_max_bytes: 1024 * 1024,
+            search_jobs: 4,
+            image_jobs: 2,
+            search_timeout_secs: None,
+            search_max_results: 1000,
+            content_search_max_bytes: 10 * 1024 * 1024,
+            index: false,
+            report_max_entries: 1_000_000,
+            manifest_hash_max_bytes: 1024 * 1024 * 1024,
+            min_free_space: 1024 * 1024 * 1024,
+            max_upload_streams: 32,
+            max_upload_sessions: 100,
+            upload_idle_secs: 120,
+            clipboard_idle_secs: 3600,
+            trash: true,
+            trash_retention_days: 30,
+            trash_max_size: None,
+            upload_hook: None,
+            upload_hook_mode: crate::hooks::HookMode::Reject,
+            upload_hook_timeout_secs: 60,
+            upload_hook_jobs: 2,
+            max_conns_per_ip: None,
+            rate_limit: None,
+            max_download_rate: None,
+            trusted_proxies: Vec::new(),
+            default_lang: Lang::ZhCn,
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Read a TOML config file; missing keys keep their defaults
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {:?}: {}", path, e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid config file {:?}: {}", path, e))
+    }
+
+    /// Check values that serde can't, naming the offending key
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port == 0 {
+            return Err("port: must be between 1 and 65535".to_string());
+        }
+        if self.tls_redirect_port == Some(0) {
+            return Err("tls_redirect_port: must be between 1 and 65535".to_string());
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err("tls_cert and tls_key must be set together".to_string());
+        }
+        if self.tls_redirect_port.is_some() && self.tls_cert.is_none() {
+            return Err("tls_redirect_port: requires tls_cert and tls_key".to_string());
+        }
+        if self.max_request_body < crate::limits::MIN_REQUEST_BODY {
+            return Err(format!(
+                "max_request_body: must be at least {}",
+                crate::handlers::format_size(crate::limits::MIN_REQUEST_BODY)
+            ));
+        }
+        if self.max_filename_bytes == 0 {
+            return Err("max_filename_bytes: must be at least 1".to_string());
+        }
+        if self.dir_size_jobs == 0 {
+            return Err("dir_size_jobs: must be at least 1".to_string());
+        }
+        if self.tail_max_bytes == 0 {
+            return Err("tail_max_bytes: must be at least 1".to_string());
+        }
+        if self.content_search_max_bytes == 0 {
+            return Err("content_search_max_bytes: must be at least 1".to_string());
+        }
+        if self.search_jobs == 0 {
+            return Err("search_jobs: must be at least 1".to_string());
+        }
+        if self.image_jobs == 0 {
+            return Err("image_jobs: must be at least 1".to_string());
+        }
+        if self.search_timeout_secs == Some(0) {
+            return Err("search_timeout_secs: must be at least 1".to_string());
+        }
+        if self.search_max_results == 0 {
+            return Err("search_max_results: must be at least 1".to_string());
+        }
+        if self.max_upload_streams == 0 {
+            return Err("max_upload_streams: must be at least 1".to_string());
+        }
+        if self.max_upload_sessions == 0 {
+            return Err("max_upload_sessions: must be at least 1".to_string());
+        }
+        if self.upload_idle_secs == 0 {
+            return Err("upload_idle_secs: must be at least 1".to_string());
+        }
+        if self.clipboard_idle_secs == 0 {
+            return Err("clipboard_idle_secs: must be at least 1".to_string());
+        }
+        if self.upload_hook.as_deref().is_some_and(|hook| hook.trim().is_empty()) {
+            return Err("upload_hook: must not be empty".to_string());
+        }
+        if self.upload_hook_timeout_secs == 0 {
+            return Err("upload_hook_timeout_secs: must be at least 1".to_string());
+        }
+        if self.upload_hook_jobs == 0 {
+            return Err("upload_hook_jobs: must be at least 1".to_string());
+        }
+        if self.max_conns_per_ip == Some(0) {
+            return Err("max_conns_per_ip: must be at least 1".to_string());
+        }
+        if self.max_download_rate == Some(0) {
+            return Err("max_download_rate: must be at least 1".to_string());
+        }
+        if self.trash_max_size == Some(0) {
+            return Err("trash_max_size: must be at least 1".to_string());
+        }
+        if self.cors_allow_any && !self.cors_origins.is_empty() {
+            return Err("cors_allow_any: cannot be combined with cors_origins".to_string());
+        }
+        for (key, path) in [
+            ("users_file", &self.users_file),
+            ("tls_cert", &self.tls_cert),
+            ("tls_key", &self.tls_key),
+        ] {
+            if let Some(path) = path
+                && !path.is_file()
+            {
+                return Err(format!("{}: file not found: {:?}", key, path));
+            }
+        }
+        if !self.base_path.starts_with('/')
+            || self.base_path.contains("//")
+            || self.base_path.split('/').any(|c| c == "." || c == "..")
+            || self.base_path.contains(|c: char| matches!(c, '?' | '#' | '"' | '<' | '>' | '\\' | '{' | '}') || c.is_whitespace())
+        {
+            return Err(format!("base_path: invalid prefix {:?}", self.base_path));
+        }
+        if let Some(dir) = &self.static_dir
+            && !dir.join("index.html").is_file()
+        {
+            return Err(format!("static_dir: index.html not found in {:?}", dir));
+        }
+        let mut names = Vec::new();
+        for spec in &self.mounts {
+            let (name, path) = Mounts::parse_spec(spec).map_err(|e| format!("mounts: {}", e))?;
+            if names.contains(&name) {
+                return Err(format!("mounts: duplicate mount name {:?}", name));
+            }
+            if !path.is_dir() {
+                return Err(format!("mounts: directory not found: {:?}", path));
+            }
+            names.push(name);
+        }
+        if let Some(hash) = &self.password_hash {
+            crate::auth::validate_password_hash(hash).map_err(|e| format!("password_hash: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// The base path without a trailing slash ("" when served at the root)
+    pub fn base_prefix(&self) -> String {
+        self.base_path.trim_end_matches('/').to_string()
+    }
+
+    /// Render the configuration as TOML, with the plaintext password redacted
+    pub fn to_toml(&self) -> Result<String, String> {
+        let mut table = toml::Table::try_from(self).map_err(|e| e.to_string())?;
+        if self.password_hash.is_some() {
+            table.remove("password");
+        } else {
+            table.insert("password".to_string(), toml::Value::String("<redacted>".to_string()));
+        }
+        toml::to_string_pretty(&table).map_err(|e| e.to_string())
+    }
+}
+
+/// Accept either a byte count or a size string such as "500MB"
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    deserialize_bytes(deserializer).map(Some)
+}
+
+/// Required form of `deserialize_size`
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::Text(text) => crate::handlers::parse_size(&text).map_err(serde::de::Error::custom),
+    }
+}
@@ -0,0 +1,681 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Extension, FromRequest, Multipart, Query, Request, State},
+    http::{header, HeaderMap},
+    Json,
+};
+use chardetng::EncodingDetector;
+use chrono::{DateTime, SecondsFormat, Utc};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use uuid::Uuid;
+use crate::audit::AuditEntry;
+use crate::hooks;
+use crate::disks::ensure_space;
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, etag, format_size, get_file_info, safe_path, sizes_changed, TempPath};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::{ApiResponse, ContentQuery, FileInfo, SaveContentRequest, SavedContent, TextContent};
+use crate::paths::INTERNAL_FILE_PREFIX;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Size of the buffer used to move the staged body into the file
+const COPY_BUFFER_BYTES: usize = 256 * 1024;
+/// Largest file `GET /api/content` returns
+pub(crate) const MAX_TEXT_BYTES: u64 = 2 * 1024 * 1024;
+
+/// One lock per file being patched or saved, so writes to the same file take turns
+///
+/// Entries are dropped once nobody holds or waits for them.
+#[derive(Default)]
+pub struct PathLocks {
+    locks: std::sync::Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+}
+
+impl PathLocks {
+    pub async fn lock(&self, path: &Path) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(path.to_path_buf()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+/// A `Content-Range: bytes start-end/total` header as `(start, end, total)`, `end` inclusive
+fn parse_content_range(headers: &HeaderMap) -> Result<(u64, u64, u64), ApiError> {
+    let invalid = || ApiError::BadRequest(Msg::InvalidContentRange.into());
+    let value = headers
+        .get(header::CONTENT_RANGE)
+        .ok_or_else(|| ApiError::BadRequest(Msg::ContentRangeRequired.into()))?
+        .to_str()
+        .map_err(|_| invalid())?;
+    let (range, total) = value
+        .trim()
+        .strip_prefix("bytes ")
+        .and_then(|rest| rest.split_once('/'))
+        .ok_or_else(invalid)?;
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let parse = |n: &str| n.trim().parse::<u64>().map_err(|_| invalid());
+    let (start, end, total) = (parse(start)?, parse(end)?, parse(total)?);
+    if start > end || end >= total {
+        return Err(ApiError::RangeNotSatisfiable(Msg::RangeOutsideFile.with(&[&value])));
+    }
+    Ok((start, end, total))
+}
+
+/// Stream the body into `staging`, returning its length and SHA-256
+///
+/// Stops as soon as the body runs past `expected` bytes.
+async fn stage_body(body: Body, staging: &Path, expected: u64) -> Result<(u64, String), ApiError> {
+    let mut file = fs::File::create(staging)
+        .await
+        .map_err(|e| ApiError::io(Msg::CreateFileFailed, e))?;
+    let mut stream = body.into_data_stream();
+    let mut hasher = Sha256::new();
+    let mut received: u64 = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(format!("{}: {}", Msg::ReadUploadFailed, e)))?;
+        received += chunk.len() as u64;
+        if received > expected {
+            return Err(ApiError::BadRequest(Msg::ContentLengthMismatch.with(&[&expected])));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    }
+    file.flush().await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    Ok((received, format!("{:x}", hasher.finalize())))
+}
+
+/// Copy the staged region into `target` at `start`, extending the file to `total` first
+async fn write_at(staging: &Path, target: &Path, start: u64, total: u64) -> std::io::Result<()> {
+    let mut source = fs::File::open(staging).await?;
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(target).await?;
+    if file.metadata().await?.len() < total {
+        file.set_len(total).await?;
+    }
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut buffer = vec![0; COPY_BUFFER_BYTES];
+    loop {
+        let read = source.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read]).await?;
+    }
+    file.sync_all().await
+}
+
+/// Make a change to a copy of `target` and pass the result through `--upload-hook` before
+/// it replaces the file, returning the hook's warning in warn mode
+///
+/// The hook has to see the whole file as it will be, not just the bytes changed. Only
+/// used with a hook set; without one, patches and appends go straight into the file.
+async fn replace_checked(
+    state: &AppState,
+    target: &Path,
+    logical: &str,
+    user: &str,
+    ip: IpAddr,
+    change: impl AsyncFnOnce(&Path) -> std::io::Result<()>,
+) -> Result<Option<String>, ApiError> {
+    let dir = target.parent().ok_or_else(|| ApiError::InvalidPath(Msg::InvalidPath.into()))?;
+    let copy = dir.join(format!("{}hook_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let staged = TempPath::file(&copy);
+    let write = async {
+        if fs::metadata(target).await.is_ok() {
+            fs::copy(target, &copy).await?;
+        }
+        change(&copy).await
+    };
+    write.await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    let warning = hooks::check_upload(state, &copy, logical, user, ip).await?;
+    fs::rename(&copy, target).await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    staged.keep();
+    Ok(warning)
+}
+
+/// Overwrite one byte range of a file, for sync clients that know what changed
+///
+/// The body replaces bytes `start` to `end` given by `Content-Range: bytes start-end/total`.
+/// A file shorter than `total` is extended (or created) first; a longer one keeps its
+/// length. The body is staged next to the file and checked against the range length and
+/// the optional `X-Content-Sha256` before the file is touched, so a short or corrupt
+/// body changes nothing. Patches to the same file are applied one at a time. With
+/// `--upload-hook` set, the patch goes on a copy of the file, which replaces it once the
+/// hook passes it, so a rejected patch leaves the file as it was.
+#[utoipa::path(
+    patch, path = "/api/content", tag = "upload", params(ContentQuery),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "The patched file, with its new size and modification time", body = ApiResponse<FileInfo>),
+        (status = 400, description = "Missing or malformed `Content-Range`, or a body that doesn't match it", body = crate::openapi::ErrorBody),
+        (status = 413, description = "`total` over the file size limit", body = crate::openapi::ErrorBody),
+        (status = 416, description = "`RANGE_NOT_SATISFIABLE`: the range doesn't fit in `total`", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn patch_content(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<ContentQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<ApiResponse<FileInfo>>, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    if paths.is_root() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    state.locks.check(&logical, false, &user, &headers)?;
+    if paths.actual.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let (Some(dir), Some(name)) = (paths.actual.parent(), paths.actual.file_name()) else {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    };
+    if !paths.actual.exists() {
+        state.name_rules.check(&name.to_string_lossy())?;
+    }
+
+    let (start, end, total) = parse_content_range(&headers)?;
+    if total > state.upload_limits.max_file_size {
+        return Err(ApiError::PayloadTooLarge(Msg::FileTooLarge.into()));
+    }
+    let expected = end - start + 1;
+    let declared = crate::limits::declared_length(&headers);
+    if declared.is_some_and(|length| length != expected) {
+        return Err(ApiError::BadRequest(Msg::ContentLengthMismatch.with(&[&expected])));
+    }
+    let checksum = headers
+        .get("x-content-sha256")
+        .map(|v| v.to_str().map(str::to_ascii_lowercase).map_err(|_| ApiError::BadRequest(Msg::ChecksumMismatch.into())))
+        .transpose()?;
+
+    // The body is staged in full before the file grows to `total`, or a copy of it does
+    let current = fs::metadata(&paths.actual).await.map_or(0, |m| m.len());
+    let mut required = expected.saturating_add(total.saturating_sub(current));
+    if state.upload_hook.is_some() {
+        required = required.saturating_add(current);
+    }
+    ensure_space(dir, required, state.min_free_space).await?;
+
+    let _slot = state.upload_slots.stream().await?;
+    let staging = dir.join(format!("{}patch_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let _staged = TempPath::file(&staging);
+    let (received, sha256) = stage_body(body, &staging, expected).await?;
+    if received != expected {
+        return Err(ApiError::BadRequest(Msg::ContentLengthMismatch.with(&[&expected])));
+    }
+    if checksum.is_some_and(|checksum| checksum != sha256) {
+        return Err(ApiError::BadRequest(Msg::ChecksumMismatch.into()));
+    }
+
+    let result = {
+        let _lock = state.content_locks.lock(&paths.actual).await;
+        let patch = async |file: &Path| write_at(&staging, file, start, total).await;
+        if state.upload_hook.is_some() {
+            replace_checked(&state, &paths.actual, &logical, &user.username, addr.ip(), patch).await.map(|_| ())
+        } else {
+            patch(&paths.actual).await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))
+        }
+    };
+    let mut entry = AuditEntry::new("patch", &user.username, addr.ip(), &logical).result(&result);
+    if result.is_ok() {
+        entry = entry.size(received);
+    }
+    state.audit.record(entry);
+    sizes_changed(&state, &paths.actual).await;
+    result?;
+
+    let info = get_file_info(&state.mounts, &paths.logical, &paths.actual, None)
+        .await
+        .map_err(ApiError::Io)?;
+    Ok(Json(ApiResponse::success(info)))
+}
+
+/// Stream `data` into `staging`, returning its length
+///
+/// Stops with 413 as soon as it runs past `limit` bytes.
+async fn stage_stream<E: std::fmt::Display>(
+    mut data: impl Stream<Item = Result<Bytes, E>> + Unpin,
+    staging: &Path,
+    limit: u64,
+) -> Result<u64, ApiError> {
+    let mut file = fs::File::create(staging)
+        .await
+        .map_err(|e| ApiError::io(Msg::CreateFileFailed, e))?;
+    let mut received: u64 = 0;
+    while let Some(chunk) = data.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(format!("{}: {}", Msg::ReadUploadFailed, e)))?;
+        received += chunk.len() as u64;
+        if received > limit {
+            return Err(ApiError::PayloadTooLarge(Msg::FileTooLarge.into()));
+        }
+        file.write_all(&chunk).await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    }
+    file.flush().await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    Ok(received)
+}
+
+/// Add the staged bytes to the end of `target`, which must exist
+async fn append_from(staging: &Path, target: &Path) -> std::io::Result<()> {
+    let mut source = fs::File::open(staging).await?;
+    let mut file = fs::OpenOptions::new().append(true).open(target).await?;
+    tokio::io::copy(&mut source, &mut file).await?;
+    file.sync_all().await
+}
+
+/// Add data to the end of an existing file, for log collectors and other incremental writers
+///
+/// The data is the raw body, or the first part of a `multipart/form-data` body. It is
+/// staged next to the file first, so an interrupted request appends nothing, and appends
+/// to the same file are applied one at a time, each in one piece. The file may not grow
+/// past the file size limit. With `--upload-hook` set, the data is added to a copy of the
+/// file, which replaces it once the hook passes it, as for `PATCH`.
+#[utoipa::path(
+    post, path = "/api/append", tag = "upload", params(ContentQuery),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "The file after the append, with its new size and modification time", body = ApiResponse<FileInfo>),
+        (status = 400, description = "Not a file, or a multipart body without parts", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No such file", body = crate::openapi::ErrorBody),
+        (status = 413, description = "The file would grow past the file size limit", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn append_content(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<ContentQuery>,
+    request: Request,
+) -> Result<Json<ApiResponse<FileInfo>>, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    state.locks.check(&logical, false, &user, request.headers())?;
+    let metadata = fs::metadata(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::FileNotFound, e))?;
+    if !metadata.is_file() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let Some(dir) = paths.actual.parent() else {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    };
+
+    let max_file_size = state.upload_limits.max_file_size;
+    let limit = max_file_size.saturating_sub(metadata.len());
+    // Counts multipart framing too, so only a bound for the data
+    if let Some(length) = crate::limits::declared_length(request.headers()) {
+        if length > limit {
+            return Err(ApiError::PayloadTooLarge(Msg::FileTooLarge.into()));
+        }
+        // Staged, then appended
+        ensure_space(dir, length.saturating_mul(2), state.min_free_space).await?;
+    }
+    let is_multipart = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+
+    let _slot = state.upload_slots.stream().await?;
+    let staging = dir.join(format!("{}append_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let _staged = TempPath::file(&staging);
+    let received = if is_multipart {
+        let mut multipart = Multipart::from_request(request, &state)
+            .await
+            .map_err(|e| ApiError::BadRequest(e.body_text()))?;
+        let field = multipart
+            .next_field()
+            .await
+            .map_err(|e| ApiError::BadRequest(format!("{}: {}", Msg::ReadUploadFailed, e)))?
+            .ok_or_else(|| ApiError::BadRequest(Msg::NoAppendData.into()))?;
+        stage_stream(field, &staging, limit).await?
+    } else {
+        stage_stream(request.into_body().into_data_stream(), &staging, limit).await?
+    };
+    // With a hook, the copy the data is added to needs room for the whole file
+    let copy = if state.upload_hook.is_some() { metadata.len() } else { 0 };
+    ensure_space(dir, received.saturating_add(copy), state.min_free_space).await?;
+
+    let result = {
+        let _lock = state.content_locks.lock(&paths.actual).await;
+        // Another append may have got there first
+        let current = fs::metadata(&paths.actual).await.map_or(0, |m| m.len());
+        if current.saturating_add(received) > max_file_size {
+            return Err(ApiError::PayloadTooLarge(Msg::FileTooLarge.into()));
+        }
+        let append = async |file: &Path| append_from(&staging, file).await;
+        if state.upload_hook.is_some() {
+            replace_checked(&state, &paths.actual, &logical, &user.username, addr.ip(), append).await.map(|_| ())
+        } else {
+            append(&paths.actual).await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))
+        }
+    };
+    let mut entry = AuditEntry::new("append", &user.username, addr.ip(), &logical).result(&result);
+    if result.is_ok() {
+        entry = entry.size(received);
+    }
+    state.audit.record(entry);
+    sizes_changed(&state, &paths.actual).await;
+    result?;
+
+    let info = get_file_info(&state.mounts, &paths.logical, &paths.actual, None)
+        .await
+        .map_err(ApiError::Io)?;
+    Ok(Json(ApiResponse::success(info)))
+}
+
+/// The encoding of `bytes`, and the length of the byte order mark it starts with
+///
+/// A BOM decides; otherwise valid UTF-8 is UTF-8, and anything else is guessed from its
+/// byte patterns, which tells legacy encodings such as GBK, Big5, Shift_JIS and
+/// Windows-1252 apart.
+fn detect_encoding(bytes: &[u8]) -> (&'static Encoding, usize) {
+    if let Some((encoding, bom)) = Encoding::for_bom(bytes) {
+        return (encoding, bom);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (UTF_8, 0);
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    (detector.guess(None, true), 0)
+}
+
+/// A text file's content as a string, for viewing and editing it in the browser
+///
+/// Files over 2MB get 413, and binary files (a NUL byte near the start) 415
+/// `UNSUPPORTED_TYPE`. The encoding comes from a byte order mark, else UTF-8 when the
+/// bytes are valid UTF-8, else a guess from the content; `encoding` and `bom` say which,
+/// so an editor can save the file the way it was.
+#[utoipa::path(
+    get, path = "/api/content", tag = "files", params(ContentQuery),
+    responses(
+        (status = 200, description = "The decoded text", body = ApiResponse<TextContent>),
+        (status = 400, description = "Not a file", body = crate::openapi::ErrorBody),
+        (status = 413, description = "Over 2MB", body = crate::openapi::ErrorBody),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: a binary file", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn get_content(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ContentQuery>,
+) -> Result<Json<ApiResponse<TextContent>>, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    let metadata = fs::metadata(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::FileNotFound, e))?;
+    if !metadata.is_file() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let too_large = || ApiError::PayloadTooLarge(Msg::TextTooLarge.with(&[&format_size(MAX_TEXT_BYTES)]));
+    if metadata.len() > MAX_TEXT_BYTES {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    fs::File::open(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?
+        // The file may have grown since
+        .take(MAX_TEXT_BYTES + 1)
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+    if bytes.len() as u64 > MAX_TEXT_BYTES {
+        return Err(too_large());
+    }
+
+    let (encoding, bom) = detect_encoding(&bytes);
+    // UTF-16 text is full of NULs, but it has a BOM
+    if bom == 0 && crate::tail::looks_binary(&bytes) {
+        return Err(ApiError::UnsupportedType(Msg::NotATextFile.into()));
+    }
+    let (content, _) = encoding.decode_without_bom_handling(&bytes[bom..]);
+    let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+    Ok(Json(ApiResponse::success(TextContent {
+        content: content.into_owned(),
+        encoding: encoding.name().to_string(),
+        bom: bom > 0,
+        size: bytes.len() as u64,
+        modified_at: modified.map(|m| m.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        etag: etag(metadata.len(), modified),
+    })))
+}
+
+/// `content` as bytes in `encoding`, after a byte order mark when `bom` is set
+///
+/// encoding_rs only decodes UTF-16, so that is encoded here. A BOM is only written for
+/// UTF-8 and UTF-16, the encodings that have one.
+fn encode_text(content: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>, ApiError> {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let le = encoding == UTF_16LE;
+        let mut bytes = Vec::with_capacity(2 + content.len() * 2);
+        if bom {
+            bytes.extend_from_slice(if le { &[0xFF, 0xFE] } else { &[0xFE, 0xFF] });
+        }
+        for unit in content.encode_utf16() {
+            bytes.extend_from_slice(&if le { unit.to_le_bytes() } else { unit.to_be_bytes() });
+        }
+        return Ok(bytes);
+    }
+    // `replacement` can only decode, and would silently write UTF-8
+    if encoding.output_encoding() != encoding {
+        return Err(ApiError::BadRequest(Msg::UnknownEncoding.with(&[&encoding.name()])));
+    }
+    let mut bytes = Vec::with_capacity(3 + content.len());
+    if bom && encoding == UTF_8 {
+        bytes.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    let (encoded, _, unmappable) = encoding.encode(content);
+    if unmappable {
+        return Err(ApiError::BadRequest(Msg::NotEncodable.with(&[&encoding.name()])));
+    }
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
+
+/// The ETag of the file at `path` now, or `None` when there is no file
+async fn current_etag(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).await.ok()?;
+    Some(etag(metadata.len(), metadata.modified().ok().map(DateTime::<Utc>::from)))
+}
+
+/// Save text edited in the browser, refusing to overwrite changes made since it was opened
+///
+/// `etag` is the one `GET /api/content` returned; if the file has been changed or removed
+/// since, nothing is written and the answer is 412 `PRECONDITION_FAILED`, so the editor
+/// can reload and merge. Without `etag` the file is created, and 409 `ALREADY_EXISTS`
+/// comes back if it exists. The text is saved in `encoding` (UTF-8 by default), with a
+/// BOM when `bom` is set, so passing back what `GET` reported keeps the file as it was.
+/// The new content is written to a temporary file next to the file, which then replaces
+/// it in one rename, so readers never see half a save; a replaced file keeps its
+/// permissions. The response's `etag` is the one for the next save.
+#[utoipa::path(
+    put, path = "/api/content", tag = "upload", request_body = SaveContentRequest,
+    responses(
+        (status = 200, description = "Saved: the new size, modification time and ETag", body = ApiResponse<SavedContent>),
+        (status = 400, description = "Not a file, an unknown encoding, or text the encoding can't represent", body = crate::openapi::ErrorBody),
+        (status = 409, description = "`ALREADY_EXISTS`: no `etag` and the file exists", body = crate::openapi::ErrorBody),
+        (status = 412, description = "`PRECONDITION_FAILED`: the file changed since `etag`", body = crate::openapi::ErrorBody),
+        (status = 413, description = "Over 2MB once encoded", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn put_content(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<SaveContentRequest>,
+) -> Result<Json<ApiResponse<SavedContent>>, ApiError> {
+    let paths = safe_path(&state.mounts, &req.path)?;
+    if paths.is_root() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    state.locks.check(&logical, false, &user, &headers)?;
+    if paths.actual.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let (Some(dir), Some(name)) = (paths.actual.parent(), paths.actual.file_name()) else {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    };
+    let existing = fs::metadata(&paths.actual).await.ok();
+    if existing.is_none() {
+        state.name_rules.check(&name.to_string_lossy())?;
+    }
+
+    let encoding = match &req.encoding {
+        Some(label) => Encoding::for_label(label.trim().as_bytes())
+            .ok_or_else(|| ApiError::BadRequest(Msg::UnknownEncoding.with(&[label])))?,
+        None => UTF_8,
+    };
+    let bytes = encode_text(&req.content, encoding, req.bom)?;
+    let size = bytes.len() as u64;
+    if size > MAX_TEXT_BYTES.min(state.upload_limits.max_file_size) {
+        return Err(ApiError::PayloadTooLarge(Msg::FileTooLarge.into()));
+    }
+    ensure_space(dir, size, state.min_free_space).await?;
+
+    let _slot = state.upload_slots.stream().await?;
+    let staging = dir.join(format!("{}save_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let staged = TempPath::file(&staging);
+    let write = async {
+        let mut file = fs::File::create(&staging).await?;
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        if let Some(existing) = &existing {
+            fs::set_permissions(&staging, existing.permissions()).await?;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    write.await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    let warning = hooks::check_upload(&state, &staging, &logical, &user.username, addr.ip()).await?;
+
+    let result = {
+        let _lock = state.content_locks.lock(&paths.actual).await;
+        let current = current_etag(&paths.actual).await;
+        match (&req.etag, current) {
+            (Some(expected), Some(current)) if *expected == current => Ok(()),
+            (Some(_), _) => Err(ApiError::PreconditionFailed(Msg::ContentChanged.into())),
+            (None, Some(_)) => Err(ApiError::AlreadyExists(Msg::ContentExists.into())),
+            (None, None) => Ok(()),
+        }?;
+        fs::rename(&staging, &paths.actual).await
+    };
+    let mut entry = AuditEntry::new("save", &user.username, addr.ip(), &logical).result(&result);
+    if result.is_ok() {
+        staged.keep();
+        entry = entry.size(size);
+    }
+    state.audit.record(entry);
+    sizes_changed(&state, &paths.actual).await;
+    result.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+
+    let metadata = fs::metadata(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::FileNotFound, e))?;
+    let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+    Ok(Json(ApiResponse::success(SavedContent {
+        size: metadata.len(),
+        modified_at: modified.map(|m| m.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        etag: etag(metadata.len(), modified),
+        warning,
+    })))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testing::{admin, scanner, TestApp};
+    use axum::http::{Method, StatusCode};
+
+    fn patch(path: &str, range: &str, body: &'static str) -> axum::http::Request<Body> {
+        admin(Method::PATCH, &format!("/api/content?path={}", path))
+            .header(header::CONTENT_RANGE, range)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    /// Names left in the root besides `expected`, such as staging files
+    fn leftovers(app: &TestApp, expected: &[&str]) -> Vec<String> {
+        let names = std::fs::read_dir(app.root()).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap());
+        names.filter(|name| !expected.contains(&name.as_str())).collect()
+    }
+
+    #[tokio::test]
+    async fn the_upload_hook_sees_the_patched_file() {
+        let scripts = tempfile::tempdir().unwrap();
+        let app = TestApp::with(|c| c.upload_hook = Some(scanner(scripts.path())));
+        app.write("notes.txt", "EIC-- world");
+
+        let (status, body) = app.json(patch("/notes.txt", "bytes 6-10/11", "WORLD")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(app.read("notes.txt"), b"EIC-- WORLD");
+
+        // Neither the old bytes nor the patch hold the signature; the file after it does
+        let (status, body) = app.json(patch("/notes.txt", "bytes 3-4/11", "AR")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "REJECTED_BY_HOOK");
+        assert_eq!(app.read("notes.txt"), b"EIC-- WORLD");
+
+        // Nor is a new file created from a rejected patch
+        let (status, _) = app.json(patch("/new.txt", "bytes 0-4/5", "EICAR")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!app.path("new.txt").exists());
+        assert_eq!(leftovers(&app, &["notes.txt"]), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn the_upload_hook_sees_the_file_appended_to() {
+        let scripts = tempfile::tempdir().unwrap();
+        let app = TestApp::with(|c| c.upload_hook = Some(scanner(scripts.path())));
+        app.write("app.log", "line 1\nEIC");
+        let append = |body: &'static str| {
+            admin(Method::POST, "/api/append?path=/app.log").body(Body::from(body)).unwrap()
+        };
+
+        let (status, body) = app.json(append("-- ok\n")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["size"], 16);
+        assert_eq!(app.read("app.log"), b"line 1\nEIC-- ok\n");
+
+        app.write("app.log", "line 1\nEIC");
+        let (status, body) = app.json(append("AR\n")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "REJECTED_BY_HOOK");
+        assert_eq!(app.read("app.log"), b"line 1\nEIC");
+        assert_eq!(leftovers(&app, &["app.log"]), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,619 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Extension, Path as AxumPath, State},
+    http::{header, HeaderMap, HeaderName, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use percent_encoding::percent_decode_str;
+use std::net::SocketAddr;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+use uuid::Uuid;
+use crate::audit::AuditEntry;
+use crate::disks::{ensure_space, SpaceWatch};
+use crate::error::ApiError;
+use crate::hashes::{content_tags, file_sha256};
+use crate::handlers::{
+    check_not_internal, check_patterns, check_patterns_tree, check_transfer_space, copy_dir, copy_file_keeping_metadata, etag, file_response,
+    format_size, get_file_info, is_within_folder, mount_entries, move_path, safe_path, sizes_changed, tree_stats,
+    tagged_hash, SafePathResult, TempPath, TreeStats, WalkCaps, HTTP_DATE,
+};
+use crate::hooks;
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::FileInfo;
+use crate::paths::{existing_name, is_internal_artifact, url_path, INTERNAL_FILE_PREFIX};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Where the WebDAV tree is served, below `--base-path`
+pub const DAV_PREFIX: &str = "/dav";
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL, MOVE, COPY";
+
+/// WebDAV (RFC 4918, class 1) access to the mounts, so they can be mapped as a network drive
+///
+/// Paths below `/dav` are the logical paths the API uses, with the same authentication,
+/// access rules and audit log. Read-only API keys can list and download. Locks aren't
+/// supported, so macOS Finder mounts the share read-only.
+pub async fn dav(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    path: Option<AxumPath<String>>,
+    method: Method,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let path = format!("/{}", path.map(|AxumPath(p)| p).unwrap_or_default());
+    let method = method.as_str();
+    // The virtual root of several mounts can only be listed
+    if state.mounts.is_virtual_root(&path) && !matches!(method, "OPTIONS" | "PROPFIND") {
+        return Ok(method_not_allowed());
+    }
+    match method {
+        "OPTIONS" => Ok(options()),
+        "PROPFIND" => propfind(&state, &user, &path, &headers).await,
+        "GET" | "HEAD" => get(&state, &user, &path, &headers).await,
+        "PUT" => put(&state, &user, addr, &path, &headers, body).await,
+        "MKCOL" => mkcol(&state, &user, addr, &path, &headers).await,
+        "DELETE" => delete(&state, &user, addr, &path, &headers).await,
+        "MOVE" | "COPY" => transfer(&state, &user, addr, &path, &headers, method == "MOVE").await,
+        _ => Ok(method_not_allowed()),
+    }
+}
+
+fn method_not_allowed() -> Response {
+    (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()
+}
+
+fn options() -> Response {
+    (
+        [
+            (header::ALLOW, ALLOW),
+            (HeaderName::from_static("dav"), "1"),
+            // Lets Office open documents in place
+            (HeaderName::from_static("ms-author-via"), "DAV"),
+        ],
+    )
+        .into_response()
+}
+
+/// Split a request path into its parent folder and last segment
+fn split_parent(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// The href of a logical path
+fn href(base_path: &str, logical: &str, is_dir: bool) -> String {
+    url_path(&format!("{}{}", base_path, DAV_PREFIX), logical, is_dir)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn push_prop(xml: &mut String, name: &str, value: &str) {
+    xml.push_str(&format!("<D:{name}>{}</D:{name}>", escape(value)));
+}
+
+/// One `<D:response>` of a PROPFIND, from the same details `/api/files` lists
+fn push_response(xml: &mut String, base_path: &str, info: &FileInfo) {
+    let is_dir = info.file_type == "folder";
+    xml.push_str(&format!("<D:response><D:href>{}</D:href><D:propstat><D:prop>", href(base_path, &info.path, is_dir)));
+    push_prop(xml, "displayname", &info.name);
+    let modified = info
+        .modified_at
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|m| m.with_timezone(&Utc));
+    if let Some(modified) = modified {
+        push_prop(xml, "getlastmodified", &modified.format(HTTP_DATE).to_string());
+    }
+    if let Some(created) = &info.created_at {
+        push_prop(xml, "creationdate", created);
+    }
+    if is_dir {
+        xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        xml.push_str("<D:resourcetype/>");
+        push_prop(xml, "getcontentlength", &info.size.to_string());
+        push_prop(xml, "getcontenttype", mime_guess::from_path(&info.name).first_or_octet_stream().as_ref());
+        // Same validator as the `ETag` of GET, which clients compare
+        push_prop(xml, "getetag", &etag(info.size, modified));
+    }
+    xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+}
+
+/// List a resource and, unless `Depth: 0`, its children
+///
+/// The request body is ignored: every property is returned. `Depth: infinity` is
+/// answered as 1, as most servers do.
+async fn propfind(state: &AppState, user: &AuthUser, path: &str, headers: &HeaderMap) -> Result<Response, ApiError> {
+    let with_children = headers.get("depth").is_none_or(|depth| depth != "0");
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+
+    if state.mounts.is_virtual_root(path) {
+        xml.push_str(&format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname/>\
+             <D:resourcetype><D:collection/></D:resourcetype></D:prop>\
+             <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+            href(&state.base_path, "/", true)
+        ));
+        if with_children {
+            for info in mount_entries(state, user, None).await {
+                push_response(&mut xml, &state.base_path, &info);
+            }
+        }
+    } else {
+        let paths = safe_path(&state.mounts, path)?;
+        let logical = state.mounts.logical_path(&paths.logical);
+        record_path(&logical);
+        user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+        check_patterns(state, &logical, false)?;
+
+        let info = get_file_info(&state.mounts, &paths.logical, &paths.actual, None)
+            .await
+            .map_err(|_| ApiError::NotFound(Msg::FileNotFound.into()))?;
+        push_response(&mut xml, &state.base_path, &info);
+        if with_children && info.file_type == "folder" {
+            let mut entries = fs::read_dir(&paths.actual).await.map_err(|e| ApiError::io(Msg::ReadDirFailed, e))?;
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let logical_file = paths.logical.join(entry.file_name());
+                if let Ok(info) = get_file_info(&state.mounts, &logical_file, &entry.path(), None).await
+                    && user.can_see(&info.path)
+                    && !state.hidden.matches(&info.path)
+                    && !is_internal_artifact(&info.path, &entry.path())
+                {
+                    push_response(&mut xml, &state.base_path, &info);
+                }
+            }
+        }
+    }
+
+    xml.push_str("</D:multistatus>\n");
+    Ok((StatusCode::MULTI_STATUS, [(header::CONTENT_TYPE, "application/xml; charset=utf-8")], xml).into_response())
+}
+
+/// Download a file, with the same checks and range support as `/api/download`
+async fn get(state: &AppState, user: &AuthUser, path: &str, headers: &HeaderMap) -> Result<Response, ApiError> {
+    let paths = safe_path(&state.mounts, path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    check_patterns(state, &logical, true)?;
+
+    if paths.actual.is_dir() {
+        return Ok(method_not_allowed());
+    }
+    let response = file_response(&paths.actual, headers, "inline").await;
+    Ok(crate::throttle::limit(response, state.max_download_rate))
+}
+
+/// Stream a request body into `file`, refusing more than `limit` bytes or a write into
+/// `--min-free-space`
+async fn write_body(body: Body, mut file: fs::File, limit: u64, mut space: SpaceWatch) -> Result<u64, ApiError> {
+    let mut stream = body.into_data_stream();
+    let mut total_size: u64 = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(format!("{}: {}", Msg::ReadUploadFailed, e)))?;
+        total_size += chunk.len() as u64;
+        if total_size > limit {
+            return Err(ApiError::PayloadTooLarge(Msg::FileTooLarge.into()));
+        }
+        file.write_all(&chunk).await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+        space.wrote(chunk.len() as u64).await?;
+    }
+    file.sync_all().await.map_err(|e| ApiError::io(Msg::SyncFileFailed, e))?;
+    Ok(total_size)
+}
+
+/// Create or replace a file
+///
+/// The body is written to a temporary file next to the target and renamed over it, so
+/// an interrupted upload leaves the previous content in place. When both the stored file
+/// and the body hash to a SHA-256 listed in `If-None-Match`, the stored file is left
+/// untouched and the answer is 204 with `X-Upload-Skipped: true`.
+async fn put(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    path: &str,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let (parent, name) = split_parent(path);
+    if name.is_empty() {
+        return Ok(method_not_allowed());
+    }
+    let dir = safe_path(&state.mounts, parent)?;
+    if !dir.actual.is_dir() {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    let name = state.name_rules.prepare(name)?;
+    // Overwrite the stored file rather than adding a look-alike with another normalization
+    let name = match existing_name(&dir.actual, &name).await {
+        Some(stored) => stored.to_string_lossy().into_owned(),
+        None => name,
+    };
+    let target = dir.actual.join(&name);
+    let logical = state.mounts.logical_path(&dir.logical.join(&name));
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &logical, true)?;
+    check_not_internal(&logical, &target)?;
+    state.locks.check(&logical, false, user, headers)?;
+
+    if target.is_dir() {
+        return Ok(method_not_allowed());
+    }
+    let existed = target.exists();
+    let stored = tagged_hash(state, &target, &content_tags(headers)).await;
+
+    if let Some(length) = crate::limits::declared_length(headers) {
+        ensure_space(&dir.actual, length, state.min_free_space).await?;
+    }
+
+    let _slot = state.upload_slots.stream().await?;
+    let temp = dir.actual.join(format!("{}dav_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let file = fs::File::create(&temp).await.map_err(|e| ApiError::io(Msg::CreateFileFailed, e))?;
+    let partial = TempPath::file(&temp);
+    let space = SpaceWatch::new(&temp, state.min_free_space);
+    let result = write_body(body, file, state.upload_limits.max_file_size, space).await;
+    if result.is_ok() && stored.is_some() && file_sha256(&temp).await.ok() == stored {
+        return Ok((StatusCode::NO_CONTENT, [("x-upload-skipped", "true")]).into_response());
+    }
+    let result = match result {
+        // WebDAV responses carry no warning; a hook failure in warn mode is only audited
+        Ok(size) => match hooks::check_upload(state, &temp, &logical, &user.username, addr.ip()).await {
+            Ok(_) => fs::rename(&temp, &target).await.map(|()| size).map_err(|e| ApiError::io(Msg::WriteFileFailed, e)),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    if result.is_ok() {
+        partial.keep();
+    }
+
+    let mut entry = AuditEntry::new("upload", &user.username, addr.ip(), &logical).result(&result);
+    if let Ok(size) = result {
+        entry = entry.size(size);
+    }
+    state.audit.record(entry);
+    sizes_changed(state, &target).await;
+    result?;
+
+    state.activity.check_free_space(state.disks.clone(), dir.actual, state.mounts.logical_path(&dir.logical));
+    Ok(if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED }.into_response())
+}
+
+/// Create a folder; its parent must exist
+async fn mkcol(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    // A body would describe the new collection's contents, which isn't supported
+    if headers.get(header::CONTENT_LENGTH).is_some_and(|len| len != "0") || headers.contains_key(header::TRANSFER_ENCODING) {
+        return Ok(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+    }
+    let (parent, name) = split_parent(path);
+    if name.is_empty() {
+        return Ok(method_not_allowed());
+    }
+    let dir = safe_path(&state.mounts, parent)?;
+    if !dir.actual.is_dir() {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    let name = state.name_rules.prepare(name)?;
+    let folder = dir.actual.join(&name);
+    let logical = state.mounts.logical_path(&dir.logical.join(&name));
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &logical, true)?;
+    check_not_internal(&logical, &folder)?;
+    state.locks.check(&logical, false, user, headers)?;
+
+    if existing_name(&dir.actual, &name).await.is_some() {
+        return Ok(method_not_allowed());
+    }
+
+    let result = fs::create_dir(&folder).await;
+    state.audit.record(AuditEntry::new("create_folder", &user.username, addr.ip(), &logical).result(&result));
+    sizes_changed(state, &folder).await;
+
+    result.map_err(|e| ApiError::io(Msg::CreateFailed, e))?;
+    Ok(StatusCode::CREATED.into_response())
+}
+
+/// Delete a file or folder
+///
+/// Clients can't confirm a large delete, so folders over `--confirm-delete-over` or
+/// `--confirm-delete-entries` are refused.
+async fn delete(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    let paths = safe_path(&state.mounts, path)?;
+    if paths.is_root() {
+        return Err(ApiError::AccessDenied(Msg::CannotDeleteRoot.into()));
+    }
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    check_patterns_tree(state, &paths.logical, &paths.actual).await?;
+    check_not_internal(&logical, &paths.actual)?;
+    state.locks.check(&logical, true, user, headers)?;
+
+    if paths.actual.is_dir() && (state.confirm_delete_bytes.is_some() || state.confirm_delete_entries.is_some()) {
+        let caps = WalkCaps {
+            max_bytes: state.confirm_delete_bytes.unwrap_or(u64::MAX),
+            max_entries: state.confirm_delete_entries.unwrap_or(u64::MAX),
+            ..WalkCaps::NONE
+        };
+        let mut stats = TreeStats::default();
+        tree_stats(state, &paths.logical, &paths.actual, caps, &mut stats).await;
+        if stats.truncated {
+            let size_formatted = format_size(stats.size);
+            return Err(ApiError::AccessDenied(Msg::FolderTooLarge.with(&[&stats.entries, &size_formatted])));
+        }
+    }
+
+    let mut entry = AuditEntry::new("delete", &user.username, addr.ip(), &logical);
+    if let Ok(metadata) = fs::metadata(&paths.actual).await
+        && !metadata.is_dir()
+    {
+        entry = entry.size(metadata.len());
+    }
+    let result = remove(state, user, &paths).await;
+    state.audit.record(entry.result(&result));
+    sizes_changed(state, &paths.actual).await;
+
+    if let Some(id) = result? {
+        state.undo.trashed(&user.username, logical.clone(), id);
+    }
+    state.meta.removed(&logical).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Delete a checked path as `/api/delete` does: into the trash, returning its id, unless
+/// the trash is off
+async fn remove(state: &AppState, user: &AuthUser, paths: &SafePathResult) -> Result<Option<String>, ApiError> {
+    if state.trash.enabled() {
+        return state.trash.put(state, user, paths, None).await.map(Some);
+    }
+    let removed = if paths.actual.is_dir() {
+        fs::remove_dir_all(&paths.actual).await
+    } else {
+        fs::remove_file(&paths.actual).await
+    };
+    removed.map(|()| None).map_err(|e| ApiError::io(Msg::DeleteFailed, e))
+}
+
+/// The request path a `Destination` header names; it may be a full URL
+fn destination(state: &AppState, headers: &HeaderMap) -> Result<String, ApiError> {
+    let invalid = || ApiError::BadRequest(Msg::InvalidDestination.into());
+    let value = headers.get("destination").and_then(|v| v.to_str().ok()).ok_or_else(invalid)?;
+    let path = match value.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+        None => value,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let rest = path
+        .strip_prefix(&format!("{}{}", state.base_path, DAV_PREFIX))
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        .ok_or_else(invalid)?;
+    let rest = percent_decode_str(rest).decode_utf8().map_err(|_| invalid())?;
+    Ok(format!("/{}", rest.trim_start_matches('/')))
+}
+
+/// Move or copy to the `Destination` header, replacing what is there unless `Overwrite: F`
+async fn transfer(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    path: &str,
+    headers: &HeaderMap,
+    is_move: bool,
+) -> Result<Response, ApiError> {
+    let source = safe_path(&state.mounts, path)?;
+    if is_move && source.is_root() {
+        return Err(ApiError::AccessDenied(Msg::CannotMoveRoot.into()));
+    }
+    let source_logical = state.mounts.logical_path(&source.logical);
+    record_path(&source_logical);
+    user.check(&source_logical).map_err(ApiError::AccessDenied)?;
+
+    if !source.actual.exists() {
+        return Err(ApiError::NotFound(Msg::SourceNotFound.into()));
+    }
+    // Copying out of a protected path would bypass the download restriction
+    check_patterns_tree(state, &source.logical, &source.actual).await?;
+    check_not_internal(&source_logical, &source.actual)?;
+
+    let destination = destination(state, headers)?;
+    let (parent, name) = split_parent(&destination);
+    if name.is_empty() {
+        return Err(ApiError::AccessDenied(Msg::MoveIntoSelf.into()));
+    }
+    let dir = safe_path(&state.mounts, parent)?;
+    if !dir.actual.is_dir() {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    let name = state.name_rules.prepare(name)?;
+    let dest_actual = dir.actual.join(&name);
+    let dest_logical = state.mounts.logical_path(&dir.logical.join(&name));
+    user.check(&dest_logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &dest_logical, true)?;
+    check_not_internal(&dest_logical, &dest_actual)?;
+    if is_move {
+        state.locks.check(&source_logical, true, user, headers)?;
+    }
+    // Replacing a folder takes everything in it too
+    state.locks.check(&dest_logical, true, user, headers)?;
+
+    // Onto itself, into itself, or replacing a folder it is in
+    if source.actual.starts_with(&dest_actual) || is_within_folder(&dir.actual, &source.actual).await {
+        return Err(ApiError::AccessDenied(Msg::MoveIntoSelf.into()));
+    }
+    check_transfer_space(state, &source.logical, &source.actual, &dir.actual, is_move).await?;
+
+    let replaced = match existing_name(&dir.actual, &name).await {
+        Some(_) if headers.get("overwrite").is_some_and(|v| v == "F") => {
+            return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+        }
+        Some(stored) => {
+            let existing = SafePathResult {
+                root: dir.root.clone(),
+                logical: dir.logical.join(&stored),
+                actual: dir.actual.join(&stored),
+            };
+            check_patterns_tree(state, &existing.logical, &existing.actual).await?;
+            // What is replaced can be restored from the trash like any other delete
+            let result = remove(state, user, &existing).await;
+            state.audit.record(AuditEntry::new("delete", &user.username, addr.ip(), &dest_logical).result(&result));
+            result?;
+            state.meta.removed(&dest_logical).await;
+            true
+        }
+        None => false,
+    };
+
+    let result = if is_move {
+        move_path(&source.actual, &dest_actual, None).await
+    } else if source.actual.is_dir() {
+        // WebDAV has no partial success for a copy, so what was left out is only logged
+        copy_dir(&source.actual, &dest_actual, Some(&dir.root), None).await.map(|skipped| {
+            for entry in skipped {
+                warn!("COPY {} left out {}: {}", source_logical, entry.path, entry.reason);
+            }
+        })
+    } else {
+        copy_file_keeping_metadata(&source.actual, &dest_actual).await
+    };
+    let op = if is_move { "move" } else { "copy" };
+    state.audit.record(
+        AuditEntry::new(op, &user.username, addr.ip(), &source_logical).dest(&dest_logical).result(&result),
+    );
+    if is_move {
+        sizes_changed(state, &source.actual).await;
+    }
+    sizes_changed(state, &dest_actual).await;
+
+    let failed = if is_move { Msg::MoveFailed } else { Msg::CopyFailed };
+    result.map_err(|e| ApiError::io(failed, e))?;
+    if is_move {
+        state.meta.moved(&source_logical, &dest_logical).await;
+    }
+    Ok(if replaced { StatusCode::NO_CONTENT } else { StatusCode::CREATED }.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{admin, TestApp};
+
+    async fn dav_request(app: &TestApp, method: &str, uri: &str, headers: &[(&str, &str)]) -> (StatusCode, HeaderMap, String) {
+        let mut request = admin(Method::from_bytes(method.as_bytes()).unwrap(), uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = app.send(request.body(Body::empty()).unwrap()).await;
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn hrefs(xml: &str) -> Vec<&str> {
+        xml.split("<D:href>").skip(1).map(|part| part.split("</D:href>").next().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn propfind_lists_children_unless_depth_is_zero() {
+        let app = TestApp::with(|_| {});
+        app.write("docs/a.txt", "aaa");
+        app.write("docs/sub/b.txt", "b");
+
+        let (status, headers, xml) = dav_request(&app, "PROPFIND", "/dav/docs/", &[("depth", "0")]).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert!(headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("application/xml"));
+        assert_eq!(hrefs(&xml), ["/dav/docs/"]);
+
+        let (status, _, xml) = dav_request(&app, "PROPFIND", "/dav/docs/", &[("depth", "1")]).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        let mut listed = hrefs(&xml);
+        listed.sort();
+        assert_eq!(listed, ["/dav/docs/", "/dav/docs/a.txt", "/dav/docs/sub/"]);
+        assert!(xml.contains("<D:getcontentlength>3</D:getcontentlength>"), "{xml}");
+
+        // Infinity is answered as 1
+        let (_, _, xml) = dav_request(&app, "PROPFIND", "/dav/docs/", &[("depth", "infinity")]).await;
+        assert!(!xml.contains("b.txt"), "{xml}");
+        let (status, _, _) = dav_request(&app, "PROPFIND", "/dav/missing", &[("depth", "0")]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn move_and_copy_honour_overwrite() {
+        let app = TestApp::with(|_| {});
+        app.write("a.txt", "a");
+        app.write("b.txt", "b");
+
+        let (status, _, _) = dav_request(&app, "COPY", "/dav/a.txt", &[("destination", "/dav/c.txt")]).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(app.read("c.txt"), b"a");
+
+        let refuse = [("destination", "http://localhost/dav/b.txt"), ("overwrite", "F")];
+        for method in ["MOVE", "COPY"] {
+            let (status, _, _) = dav_request(&app, method, "/dav/a.txt", &refuse).await;
+            assert_eq!(status, StatusCode::PRECONDITION_FAILED, "{method}");
+        }
+        assert_eq!((app.read("a.txt"), app.read("b.txt")), (b"a".to_vec(), b"b".to_vec()));
+
+        let replace = [("destination", "/dav/b.txt"), ("overwrite", "T")];
+        let (status, _, _) = dav_request(&app, "MOVE", "/dav/a.txt", &replace).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(app.read("b.txt"), b"a");
+        assert!(!app.path("a.txt").exists());
+
+        // Overwrite defaults to T
+        let (status, _, _) = dav_request(&app, "COPY", "/dav/c.txt", &[("destination", "/dav/b.txt")]).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _, _) = dav_request(&app, "MOVE", "/dav/c.txt", &[("destination", "/dav/none/c.txt")]).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _, _) = dav_request(&app, "MOVE", "/dav/c.txt", &[]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn locking_is_not_offered() {
+        let app = TestApp::with(|_| {});
+        app.write("a.txt", "a");
+
+        let (status, headers, _) = dav_request(&app, "OPTIONS", "/dav/", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["dav"], "1");
+        for method in ["LOCK", "UNLOCK"] {
+            let (status, headers, _) = dav_request(&app, method, "/dav/a.txt", &[]).await;
+            assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED, "{method}");
+            assert!(!headers[header::ALLOW].to_str().unwrap().contains("LOCK"));
+        }
+    }
+}
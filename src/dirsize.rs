@@ -0,0 +1,175 @@
+use axum::{
+    extract::{Extension, Query, State},
+    Json,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, Semaphore};
+use uuid::Uuid;
+use crate::error::ApiError;
+use crate::handlers::{format_size, tree_stats, TreeStats, WalkCaps};
+use crate::i18n::Msg;
+use crate::models::{ApiResponse, JobStatus, SizeJobQuery, SizeJobResponse};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// How long a computed folder size is reused
+const CACHE_TTL: Duration = Duration::from_secs(300);
+/// Finished background jobs can be polled for this long
+const JOB_TTL: Duration = Duration::from_secs(600);
+
+/// Size of a folder's contents from a walk
+#[derive(Clone, Copy)]
+pub struct DirSize {
+    pub size: u64,
+    pub entries: u64,
+    /// The walk stopped at the limits, so the figures are lower bounds
+    pub estimate: bool,
+    at: Instant,
+}
+
+impl DirSize {
+    fn from_stats(stats: &TreeStats) -> Self {
+        Self { size: stats.size, entries: stats.entries, estimate: stats.truncated, at: Instant::now() }
+    }
+}
+
+/// A full-size computation requested with `/api/info?exact=true`
+struct SizeJob {
+    /// Who asked; only they can poll it
+    owner: String,
+    /// Logical path, as reported back
+    path: String,
+    actual: PathBuf,
+    result: Option<DirSize>,
+    created: Instant,
+}
+
+/// Folder sizes shared by `/api/info` and listings, plus the background jobs filling them
+///
+/// Sizes are keyed by the folder's path on disk. Changes made through filest drop the
+/// sizes of every folder above them; changes made directly on disk show up once the
+/// entry expires after `CACHE_TTL`.
+pub struct DirSizes {
+    cache: RwLock<HashMap<PathBuf, DirSize>>,
+    jobs: RwLock<HashMap<String, SizeJob>>,
+    /// Caps concurrent full walks (`--dir-size-jobs`), shared with the report jobs
+    walks: Arc<Semaphore>,
+    /// Limits of the inline walk done by `/api/info`
+    max_duration: Duration,
+    max_entries: u64,
+}
+
+impl DirSizes {
+    pub fn new(max_duration: Duration, max_entries: u64, walks: Arc<Semaphore>) -> Self {
+        Self {
+            cache: RwLock::default(),
+            jobs: RwLock::default(),
+            walks,
+            max_duration,
+            max_entries,
+        }
+    }
+
+    /// A recent size for `actual`, exact or estimated
+    pub async fn cached(&self, actual: &Path) -> Option<DirSize> {
+        self.cache.read().await.get(actual).filter(|s| s.at.elapsed() < CACHE_TTL).copied()
+    }
+
+    /// Drop the sizes of `actual` and every folder above it
+    pub async fn invalidate(&self, actual: &Path) {
+        self.cache.write().await.retain(|dir, _| !actual.starts_with(dir));
+    }
+
+    async fn store(&self, actual: &Path, size: DirSize) {
+        let mut cache = self.cache.write().await;
+        cache.retain(|_, s| s.at.elapsed() < CACHE_TTL);
+        // An estimate never replaces a fresh exact size
+        if size.estimate && cache.get(actual).is_some_and(|s| !s.estimate) {
+            return;
+        }
+        cache.insert(actual.to_path_buf(), size);
+    }
+
+    /// The folder's size from the cache, or from a walk stopped at the configured limits
+    pub async fn bounded(&self, state: &AppState, logical: &Path, actual: &Path) -> DirSize {
+        if let Some(size) = self.cached(actual).await {
+            return size;
+        }
+        let caps = WalkCaps {
+            max_entries: self.max_entries,
+            deadline: Some(Instant::now() + self.max_duration),
+            ..WalkCaps::NONE
+        };
+        let mut stats = TreeStats::default();
+        tree_stats(state, logical, actual, caps, &mut stats).await;
+        let size = DirSize::from_stats(&stats);
+        self.store(actual, size).await;
+        size
+    }
+
+    /// Start a full walk of `actual` in the background, or join the one already running
+    ///
+    /// Returns the token to poll.
+    pub async fn start_job(&self, state: &AppState, owner: &str, path: String, logical: &Path, actual: &Path) -> String {
+        let mut jobs = self.jobs.write().await;
+        jobs.retain(|_, job| job.result.is_none() || job.created.elapsed() < JOB_TTL);
+        if let Some((token, _)) = jobs
+            .iter()
+            .find(|(_, job)| job.result.is_none() && job.owner == owner && job.actual == actual)
+        {
+            return token.clone();
+        }
+
+        let token = Uuid::new_v4().simple().to_string();
+        jobs.insert(token.clone(), SizeJob {
+            owner: owner.to_string(),
+            path,
+            actual: actual.to_path_buf(),
+            result: None,
+            created: Instant::now(),
+        });
+
+        let (state, token_, logical, actual) = (state.clone(), token.clone(), logical.to_path_buf(), actual.to_path_buf());
+        tokio::spawn(async move {
+            let sizes = &state.dir_sizes;
+            // Queued jobs wait here, so no more than `--dir-size-jobs` walks run at once
+            let Ok(_permit) = sizes.walks.clone().acquire_owned().await else { return };
+            let mut stats = TreeStats::default();
+            tree_stats(&state, &logical, &actual, WalkCaps::NONE, &mut stats).await;
+            let size = DirSize::from_stats(&stats);
+            sizes.store(&actual, size).await;
+            if let Some(job) = sizes.jobs.write().await.get_mut(&token_) {
+                job.result = Some(size);
+            }
+        });
+        token
+    }
+}
+
+/// Poll a folder-size job started by `/api/info?exact=true`
+#[utoipa::path(
+    get, path = "/api/info/size", tag = "files", params(SizeJobQuery),
+    responses((status = 200, description = "Job status; `size` is set once `done`", body = ApiResponse<SizeJobResponse>)),
+)]
+pub async fn size_job(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<SizeJobQuery>,
+) -> Result<Json<ApiResponse<SizeJobResponse>>, ApiError> {
+    let jobs = state.dir_sizes.jobs.read().await;
+    let job = jobs
+        .get(&query.token)
+        .filter(|job| job.owner == user.username)
+        .ok_or_else(|| ApiError::NotFound(Msg::SizeJobNotFound.into()))?;
+    Ok(Json(ApiResponse::success(SizeJobResponse {
+        token: query.token,
+        status: if job.result.is_some() { JobStatus::Done } else { JobStatus::Computing },
+        path: job.path.clone(),
+        size: job.result.map(|r| r.size),
+        size_formatted: job.result.map(|r| format_size(r.size)),
+        entries: job.result.map(|r| r.entries),
+    })))
+}
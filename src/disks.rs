@@ -0,0 +1,220 @@
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::Disks;
+use tokio::sync::Mutex;
+use crate::error::ApiError;
+use crate::handlers::format_size;
+use crate::i18n::Msg;
+
+/// How long a disk scan is reused; the UI polls `/api/disk` after every change
+const SCAN_TTL: Duration = Duration::from_secs(5);
+
+/// A mounted filesystem, as last scanned
+#[derive(Clone, Debug)]
+pub struct DiskEntry {
+    pub mount_point: PathBuf,
+    pub file_system: String,
+    pub total: u64,
+    pub free: u64,
+}
+
+/// The system's disks, rescanned at most every `SCAN_TTL`
+///
+/// Enumerating disks reads every mount (and can stall on a hung network share), so
+/// `/api/disk`, `/api/health` and the low-space check share one recent scan.
+#[derive(Default)]
+pub struct DiskCache {
+    scan: Mutex<Option<(Instant, Arc<Vec<DiskEntry>>)>>,
+}
+
+impl DiskCache {
+    /// A scan no older than `SCAN_TTL`
+    ///
+    /// The lock is held while rescanning, so concurrent callers wait for one scan
+    /// instead of starting their own.
+    pub async fn list(&self) -> Arc<Vec<DiskEntry>> {
+        let mut scan = self.scan.lock().await;
+        if let Some((at, disks)) = scan.as_ref()
+            && at.elapsed() < SCAN_TTL
+        {
+            return disks.clone();
+        }
+        let disks = tokio::task::spawn_blocking(|| {
+            Disks::new_with_refreshed_list()
+                .list()
+                .iter()
+                .map(|d| DiskEntry {
+                    mount_point: d.mount_point().to_path_buf(),
+                    file_system: d.file_system().to_string_lossy().into_owned(),
+                    total: d.total_space(),
+                    free: d.available_space(),
+                })
+                .collect()
+        })
+        .await
+        .map(Arc::new)
+        .unwrap_or_default();
+        *scan = Some((Instant::now(), Arc::clone(&disks)));
+        disks
+    }
+
+    /// The disk holding `path`, from a recent scan
+    pub async fn disk_of(&self, path: &Path) -> Option<DiskEntry> {
+        let path = tokio::fs::canonicalize(path).await.unwrap_or_else(|_| path.to_path_buf());
+        select_disk(&self.list().await, &path).cloned()
+    }
+}
+
+/// Split off a Windows drive so `\\?\C:\data` and `C:\` compare by drive letter
+fn split_drive(path: &Path) -> (Option<u8>, PathBuf) {
+    let mut components = path.components().peekable();
+    let drive = match components.peek() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(d) | Prefix::VerbatimDisk(d) => Some(d.to_ascii_uppercase()),
+            _ => None,
+        },
+        _ => None,
+    };
+    if drive.is_some() {
+        components.next();
+    }
+    (drive, components.collect())
+}
+
+/// 路径所在的磁盘（挂载点最长匹配）
+/// `path` must already be canonical. On Windows the drive letters must match and the rest
+/// of the path is compared below the drive's mount point. Components are compared whole,
+/// so `/data` does not hold `/database`.
+pub fn select_disk<'a>(disks: &'a [DiskEntry], path: &Path) -> Option<&'a DiskEntry> {
+    let (drive, rest) = split_drive(path);
+    disks
+        .iter()
+        .filter(|d| {
+            let (disk_drive, mount_point) = split_drive(&d.mount_point);
+            disk_drive == drive && rest.starts_with(&mount_point)
+        })
+        .max_by_key(|d| d.mount_point.components().count())
+}
+
+/// Bytes a write of unknown size may add between free-space checks
+const WATCH_INTERVAL_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Bytes the filesystem holding `path` has free for this process
+///
+/// Asks the filesystem directly rather than the cached scan, so the figure is current. A
+/// path that doesn't exist yet is measured at its nearest existing ancestor. `None` when
+/// the filesystem can't tell.
+pub async fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|dir| dir.exists())?.to_path_buf();
+    tokio::task::spawn_blocking(move || fs4::available_space(existing).ok()).await.ok().flatten()
+}
+
+/// Refuse to write `required` bytes below `path` when that would leave less than `margin`
+/// free (`--min-free-space`)
+pub async fn ensure_space(path: &Path, required: u64, margin: u64) -> Result<(), ApiError> {
+    match available_space(path).await {
+        Some(available) if available < required.saturating_add(margin) => {
+            Err(insufficient_space(required, available, margin))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `INSUFFICIENT_SPACE` for a write of `required` bytes with `available` free
+pub fn insufficient_space(required: u64, available: u64, margin: u64) -> ApiError {
+    ApiError::InsufficientSpace(Msg::InsufficientSpace.with(&[
+        &format_size(required),
+        &required,
+        &format_size(available),
+        &available,
+        &format_size(margin),
+    ]))
+}
+
+/// Stops a write of unknown size before it eats into `--min-free-space`
+///
+/// Free space is looked up every `WATCH_INTERVAL_BYTES` written, so the write ends with
+/// `INSUFFICIENT_SPACE` a little past the margin rather than at a full disk.
+pub struct SpaceWatch {
+    path: PathBuf,
+    margin: u64,
+    unchecked: u64,
+}
+
+impl SpaceWatch {
+    pub fn new(path: &Path, margin: u64) -> Self {
+        Self { path: path.to_path_buf(), margin, unchecked: 0 }
+    }
+
+    /// Count `bytes` more written, failing once the margin is breached
+    pub async fn wrote(&mut self, bytes: u64) -> Result<(), ApiError> {
+        self.unchecked += bytes;
+        if self.margin == 0 || self.unchecked < WATCH_INTERVAL_BYTES {
+            return Ok(());
+        }
+        self.unchecked = 0;
+        match available_space(&self.path).await {
+            Some(available) if available < self.margin => Err(ApiError::InsufficientSpace(
+                Msg::FreeSpaceExhausted.with(&[&format_size(available), &format_size(self.margin)]),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disks(mount_points: &[&str]) -> Vec<DiskEntry> {
+        mount_points
+            .iter()
+            .map(|m| DiskEntry { mount_point: PathBuf::from(m), file_system: "ext4".to_string(), total: 100, free: 50 })
+            .collect()
+    }
+
+    fn selected(disks: &[DiskEntry], path: &str) -> Option<PathBuf> {
+        select_disk(disks, Path::new(path)).map(|d| d.mount_point.clone())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_path_is_on_the_disk_mounted_above_it() {
+        let disks = disks(&["/", "/data"]);
+        assert_eq!(selected(&disks, "/data/files/a.txt"), Some("/data".into()));
+        assert_eq!(selected(&disks, "/data"), Some("/data".into()));
+        assert_eq!(selected(&disks, "/home/user"), Some("/".into()));
+        // Whole components only
+        assert_eq!(selected(&disks, "/database/x"), Some("/".into()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_longest_mount_point_wins() {
+        // Listed in any order
+        let disks = disks(&["/mnt/nas/archive", "/", "/mnt/nas", "/mnt"]);
+        assert_eq!(selected(&disks, "/mnt/nas/archive/2024"), Some("/mnt/nas/archive".into()));
+        assert_eq!(selected(&disks, "/mnt/nas/photos"), Some("/mnt/nas".into()));
+        assert_eq!(selected(&disks, "/mnt/usb"), Some("/mnt".into()));
+        assert_eq!(selected(&disks, "/srv"), Some("/".into()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_disk_without_a_mount_point_above() {
+        assert_eq!(selected(&disks(&["/data", "/mnt/nas"]), "/home/user"), None);
+        assert_eq!(selected(&disks(&["/data"]), "/dat"), None);
+        assert_eq!(selected(&[], "/data"), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drives_are_matched_by_letter() {
+        let disks = disks(&["C:\\", "D:\\", "D:\\mnt\\share"]);
+        assert_eq!(selected(&disks, "\\\\?\\C:\\data"), Some("C:\\".into()));
+        assert_eq!(selected(&disks, "d:\\files"), Some("D:\\".into()));
+        assert_eq!(selected(&disks, "\\\\?\\D:\\mnt\\share\\a"), Some("D:\\mnt\\share".into()));
+        assert_eq!(selected(&disks, "E:\\x"), None);
+    }
+}
@@ -0,0 +1,214 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::fmt;
+use std::io::ErrorKind;
+use crate::models::ApiResponse;
+
+/// Error returned by API handlers
+///
+/// Each variant maps to an HTTP status and a stable `code` string, so clients can tell
+/// failures apart without matching on the message. The body keeps the
+/// `{"success": false, "error": "..."}` shape the embedded UI reads.
+#[derive(Debug)]
+pub enum ApiError {
+    /// Traversal attempts and paths that can't be used for the operation
+    InvalidPath(String),
+    /// A new file or folder name refused by `paths::validate_filename`
+    InvalidFileName(String),
+    /// Other malformed request data
+    BadRequest(String),
+    /// A move or copy target inside the folder being moved or copied
+    InvalidDestination(String),
+    /// Refused by the user's access rules or the hidden/protected patterns
+    AccessDenied(String),
+    /// A write attempted with read-only credentials
+    ReadOnly(String),
+    NotFound(String),
+    /// An upload that sent no data for `--upload-idle-secs`
+    IdleTimeout(String),
+    AlreadyExists(String),
+    /// Upload over the configured size limits
+    PayloadTooLarge(String),
+    /// A byte range outside the file
+    RangeNotSatisfiable(String),
+    /// A write to a path someone else holds a lock on (`/api/lock`)
+    Locked(String),
+    /// A conditional write whose condition no longer holds, such as a stale ETag
+    PreconditionFailed(String),
+    /// A background operation stopped by `/api/jobs/{token}/cancel`
+    Cancelled(String),
+    /// The disk or the user's quota is full
+    QuotaExceeded(String),
+    /// Less free space than a write needs plus `--min-free-space`
+    InsufficientSpace(String),
+    /// A file of a kind the operation doesn't handle
+    UnsupportedType(String),
+    /// A file whose contents couldn't be parsed
+    ParseError(String),
+    /// An upload the `--upload-hook` command refused
+    RejectedByHook(String),
+    /// Every chunked upload session slot is taken (`--max-upload-sessions`)
+    TooManySessions(String),
+    /// No upload stream slot freed up in time (`--max-upload-streams`)
+    TooManyUploads(String),
+    /// Any other filesystem failure
+    Io(String),
+}
+
+impl ApiError {
+    /// Classify a filesystem error, prefixing the message with what was being done
+    pub fn io(context: impl fmt::Display, e: std::io::Error) -> Self {
+        let message = format!("{}: {}", context, e);
+        match e.kind() {
+            ErrorKind::NotFound => Self::NotFound(message),
+            ErrorKind::AlreadyExists => Self::AlreadyExists(message),
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded | ErrorKind::FileTooLarge => {
+                Self::QuotaExceeded(message)
+            }
+            _ => Self::Io(message),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidPath(_) | Self::InvalidFileName(_) | Self::BadRequest(_) | Self::InvalidDestination(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::AccessDenied(_) | Self::ReadOnly(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::IdleTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+            Self::AlreadyExists(_) | Self::Cancelled(_) => StatusCode::CONFLICT,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::Locked(_) => StatusCode::LOCKED,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::QuotaExceeded(_) | Self::InsufficientSpace(_) => StatusCode::INSUFFICIENT_STORAGE,
+            Self::UnsupportedType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::ParseError(_) | Self::RejectedByHook(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManySessions(_) | Self::TooManyUploads(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable machine-readable code, sent as `code` in the response body
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidPath(_) => "INVALID_PATH",
+            Self::InvalidFileName(_) => "INVALID_FILE_NAME",
+            Self::BadRequest(_) => "BAD_REQUEST",
+            Self::InvalidDestination(_) => "INVALID_DESTINATION",
+            Self::AccessDenied(_) => "ACCESS_DENIED",
+            Self::ReadOnly(_) => "READ_ONLY",
+            Self::NotFound(_) => "NOT_FOUND",
+            Self::IdleTimeout(_) => "IDLE_TIMEOUT",
+            Self::AlreadyExists(_) => "ALREADY_EXISTS",
+            Self::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            Self::RangeNotSatisfiable(_) => "RANGE_NOT_SATISFIABLE",
+            Self::Locked(_) => "LOCKED",
+            Self::PreconditionFailed(_) => "PRECONDITION_FAILED",
+            Self::Cancelled(_) => "CANCELLED",
+            Self::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            Self::InsufficientSpace(_) => "INSUFFICIENT_SPACE",
+            Self::UnsupportedType(_) => "UNSUPPORTED_TYPE",
+            Self::ParseError(_) => "PARSE_ERROR",
+            Self::RejectedByHook(_) => "REJECTED_BY_HOOK",
+            Self::TooManySessions(_) => "TOO_MANY_SESSIONS",
+            Self::TooManyUploads(_) => "TOO_MANY_UPLOADS",
+            Self::Io(_) => "IO_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::InvalidPath(m)
+            | Self::InvalidFileName(m)
+            | Self::BadRequest(m)
+            | Self::InvalidDestination(m)
+            | Self::AccessDenied(m)
+            | Self::ReadOnly(m)
+            | Self::NotFound(m)
+            | Self::IdleTimeout(m)
+            | Self::AlreadyExists(m)
+            | Self::PayloadTooLarge(m)
+            | Self::RangeNotSatisfiable(m)
+            | Self::Locked(m)
+            | Self::PreconditionFailed(m)
+            | Self::Cancelled(m)
+            | Self::QuotaExceeded(m)
+            | Self::InsufficientSpace(m)
+            | Self::UnsupportedType(m)
+            | Self::ParseError(m)
+            | Self::RejectedByHook(m)
+            | Self::TooManySessions(m)
+            | Self::TooManyUploads(m)
+            | Self::Io(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ApiResponse::<()>::error_with_code(self.code(), self.message());
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn variants_keep_their_status_and_code() {
+        let cases = [
+            (ApiError::InvalidPath("m".into()), 400, "INVALID_PATH"),
+            (ApiError::InvalidFileName("m".into()), 400, "INVALID_FILE_NAME"),
+            (ApiError::BadRequest("m".into()), 400, "BAD_REQUEST"),
+            (ApiError::InvalidDestination("m".into()), 400, "INVALID_DESTINATION"),
+            (ApiError::AccessDenied("m".into()), 403, "ACCESS_DENIED"),
+            (ApiError::ReadOnly("m".into()), 403, "READ_ONLY"),
+            (ApiError::NotFound("m".into()), 404, "NOT_FOUND"),
+            (ApiError::IdleTimeout("m".into()), 408, "IDLE_TIMEOUT"),
+            (ApiError::AlreadyExists("m".into()), 409, "ALREADY_EXISTS"),
+            (ApiError::PayloadTooLarge("m".into()), 413, "PAYLOAD_TOO_LARGE"),
+            (ApiError::RangeNotSatisfiable("m".into()), 416, "RANGE_NOT_SATISFIABLE"),
+            (ApiError::Locked("m".into()), 423, "LOCKED"),
+            (ApiError::PreconditionFailed("m".into()), 412, "PRECONDITION_FAILED"),
+            (ApiError::Cancelled("m".into()), 409, "CANCELLED"),
+            (ApiError::QuotaExceeded("m".into()), 507, "QUOTA_EXCEEDED"),
+            (ApiError::InsufficientSpace("m".into()), 507, "INSUFFICIENT_SPACE"),
+            (ApiError::UnsupportedType("m".into()), 415, "UNSUPPORTED_TYPE"),
+            (ApiError::ParseError("m".into()), 422, "PARSE_ERROR"),
+            (ApiError::RejectedByHook("m".into()), 422, "REJECTED_BY_HOOK"),
+            (ApiError::TooManySessions("m".into()), 429, "TOO_MANY_SESSIONS"),
+            (ApiError::TooManyUploads("m".into()), 429, "TOO_MANY_UPLOADS"),
+            (ApiError::Io("m".into()), 500, "IO_ERROR"),
+        ];
+        for (error, status, code) in cases {
+            let response = error.into_response();
+            assert_eq!(response.status().as_u16(), status, "{code}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["success"], false, "{code}");
+            assert_eq!(body["code"], code);
+            assert_eq!(body["error"], "m", "{code}");
+        }
+    }
+
+    #[test]
+    fn io_errors_are_classified_by_kind() {
+        let io = |kind| ApiError::io("ctx", std::io::Error::from(kind));
+        assert!(matches!(io(ErrorKind::NotFound), ApiError::NotFound(_)));
+        assert!(matches!(io(ErrorKind::AlreadyExists), ApiError::AlreadyExists(_)));
+        assert!(matches!(io(ErrorKind::StorageFull), ApiError::QuotaExceeded(_)));
+        assert!(matches!(io(ErrorKind::PermissionDenied), ApiError::Io(_)));
+    }
+}
@@ -0,0 +1,247 @@
+use axum::{
+    extract::{Extension, Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{stream, Stream, StreamExt};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{timeout, Instant};
+use utoipa::ToSchema;
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, safe_path};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::PathQuery;
+use crate::paths::INTERNAL_FILE_PREFIX;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Quiet period that ends a batch of changes
+const DEBOUNCE: Duration = Duration::from_millis(300);
+/// Longest a batch is held back while changes keep arriving (e.g. a large copy)
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(2);
+/// Batches kept for a slow client before it is told to reload
+const BATCH_BACKLOG: usize = 64;
+
+/// What happened to an entry of the watched folder
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+/// A change in the watched folder, by entry name
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Change {
+    kind: ChangeKind,
+    name: OsString,
+    /// Previous name of a renamed entry
+    from: Option<OsString>,
+}
+
+/// A change as sent to the client
+#[derive(Serialize, ToSchema)]
+pub struct ChangeEvent {
+    pub kind: ChangeKind,
+    /// Logical path, as in `/api/files`
+    pub path: String,
+    /// Previous path of a renamed entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+}
+
+type Batch = Arc<Vec<Change>>;
+
+/// Filesystem watchers shared by all clients watching the same folder
+///
+/// Each entry lives as long as some `/api/events` stream holds it; the last stream to
+/// go away drops the watcher, which ends its debounce task.
+#[derive(Clone, Default)]
+pub struct Watchers {
+    dirs: Arc<Mutex<HashMap<PathBuf, Weak<DirWatch>>>>,
+}
+
+struct DirWatch {
+    _watcher: RecommendedWatcher,
+    batches: broadcast::Sender<Batch>,
+}
+
+impl Watchers {
+    /// Join the watcher for `dir`, starting one if nobody watches it yet
+    fn subscribe(&self, dir: &Path) -> notify::Result<(Arc<DirWatch>, broadcast::Receiver<Batch>)> {
+        let mut dirs = self.dirs.lock().unwrap();
+        dirs.retain(|_, watch| watch.strong_count() > 0);
+        if let Some(watch) = dirs.get(dir).and_then(Weak::upgrade) {
+            let batches = watch.batches.subscribe();
+            return Ok((watch, batches));
+        }
+
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                let _ = raw_tx.send(event);
+            }
+        })?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        let (batches, receiver) = broadcast::channel(BATCH_BACKLOG);
+        tokio::spawn(debounce(dir.to_path_buf(), raw_rx, batches.clone()));
+
+        let watch = Arc::new(DirWatch { _watcher: watcher, batches });
+        dirs.insert(dir.to_path_buf(), Arc::downgrade(&watch));
+        Ok((watch, receiver))
+    }
+}
+
+/// Group raw events into batches, sent after `DEBOUNCE` without changes
+async fn debounce(dir: PathBuf, mut raw: mpsc::UnboundedReceiver<notify::Event>, batches: broadcast::Sender<Batch>) {
+    while let Some(first) = raw.recv().await {
+        let mut batch = Vec::new();
+        collect(&dir, first, &mut batch);
+        let deadline = Instant::now() + MAX_BATCH_DELAY;
+        loop {
+            let wait = DEBOUNCE.min(deadline.saturating_duration_since(Instant::now()));
+            match timeout(wait, raw.recv()).await {
+                Ok(Some(event)) => collect(&dir, event, &mut batch),
+                // The watcher is gone, and with it every subscriber
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+        if !batch.is_empty() {
+            let _ = batches.send(Arc::new(batch));
+        }
+    }
+}
+
+/// Add a raw event to the batch, dropping repeats and filest's own temporary files
+fn collect(dir: &Path, event: notify::Event, batch: &mut Vec<Change>) {
+    let name = |path: &PathBuf| {
+        path.parent()
+            .filter(|parent| *parent == dir)
+            .and(path.file_name())
+            .filter(|name| !name.to_string_lossy().starts_with(INTERNAL_FILE_PREFIX))
+            .map(|name| name.to_os_string())
+    };
+    let kind = match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            let [from, to] = event.paths.as_slice() else { return };
+            // The backend also reported both halves of the rename on their own
+            let (from, to) = (name(from), name(to));
+            batch.retain(|c| {
+                !(c.kind == ChangeKind::Deleted && Some(&c.name) == from.as_ref()
+                    || c.kind == ChangeKind::Created && Some(&c.name) == to.as_ref())
+            });
+            let change = match (from, to) {
+                (Some(from), Some(to)) => Change { kind: ChangeKind::Renamed, name: to, from: Some(from) },
+                // Moved in or out of the folder
+                (None, Some(to)) => Change { kind: ChangeKind::Created, name: to, from: None },
+                (Some(from), None) => Change { kind: ChangeKind::Deleted, name: from, from: None },
+                (None, None) => return,
+            };
+            batch.push(change);
+            return;
+        }
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => ChangeKind::Created,
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => ChangeKind::Deleted,
+        EventKind::Modify(_) => ChangeKind::Modified,
+        _ => return,
+    };
+    for name in event.paths.iter().filter_map(name) {
+        // A file that was just created is reported once, not once per write
+        let seen = batch.iter().any(|c| {
+            c.name == name
+                && (c.kind == kind || kind == ChangeKind::Modified && c.kind == ChangeKind::Created)
+        });
+        if !seen {
+            batch.push(Change { kind, name, from: None });
+        }
+    }
+}
+
+/// Follow changes to the entries of a folder as server-sent events
+///
+/// Each `change` event carries a JSON array of changes collected over a short quiet
+/// period. A `resync` event means changes were missed and the listing should be reloaded.
+#[utoipa::path(
+    get, path = "/api/events", tag = "files", params(PathQuery),
+    responses((
+        status = 200,
+        description = "`text/event-stream` of `change` events, each a JSON array of changes",
+        content_type = "text/event-stream",
+        body = Vec<ChangeEvent>,
+    )),
+)]
+pub async fn watch_events(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<PathQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let user_path = query.path.unwrap_or_else(|| "/".to_string());
+    let paths = safe_path(&state.mounts, &user_path)?;
+
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, false)?;
+    check_not_internal(&logical, &paths.actual)?;
+
+    if !paths.actual.is_dir() {
+        return Err(ApiError::NotFound(Msg::DirNotFound.into()));
+    }
+
+    let (watch, batches) = state
+        .watchers
+        .subscribe(&paths.actual)
+        .map_err(|e| ApiError::Io(format!("{}: {}", Msg::WatchFailed, e)))?;
+
+    // Translate names into logical paths, leaving out what the user can't see
+    let dir = paths.logical;
+    // Ends the stream at shutdown instead of letting it hold up the grace period
+    let shutdown = state.shutdown.clone().cancelled_owned();
+    let to_event = move |change: &Change| {
+        let path = state.mounts.logical_path(&dir.join(&change.name));
+        let from = change.from.as_ref().map(|from| state.mounts.logical_path(&dir.join(from)));
+        let visible = |p: &str| user.can_see(p) && !state.hidden.matches(p);
+        (visible(&path) || from.as_deref().is_some_and(visible)).then_some(ChangeEvent {
+            kind: change.kind,
+            path,
+            from,
+        })
+    };
+
+    let events = stream::unfold((watch, batches), move |(watch, mut batches)| {
+        let to_event = to_event.clone();
+        async move {
+            loop {
+                let event = match batches.recv().await {
+                    Ok(batch) => {
+                        let changes: Vec<ChangeEvent> = batch.iter().filter_map(&to_event).collect();
+                        if changes.is_empty() {
+                            continue;
+                        }
+                        Event::default().event("change").json_data(changes).ok()?
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => Event::default().event("resync").data(""),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                };
+                return Some((Ok(event), (watch, batches)));
+            }
+        }
+    })
+    .take_until(shutdown);
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
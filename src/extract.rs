@@ -0,0 +1,1338 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Extension, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read, Seek};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use futures::stream;
+use tokio::fs;
+use tokio::sync::mpsc;
+use unicode_normalization::UnicodeNormalization;
+use tar::EntryType;
+use uuid::Uuid;
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::{ExtraField, ZipArchive};
+use crate::archive::{LOCAL_HEADER, TAR_BLOCK};
+use crate::audit::AuditEntry;
+use crate::disks;
+use crate::error::ApiError;
+use crate::hooks;
+use crate::handlers::{
+    check_not_internal, check_patterns, content_disposition, safe_path, sandbox_inline, sizes_changed, SafePathResult,
+    TempPath,
+};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::{
+    ApiResponse, ArchiveEntry, ArchiveEntryQuery, ArchiveListResponse, ArchiveQuery, Disposition, ExtractConflict,
+    ExtractRequest, ExtractResult, SkippedEntry,
+};
+use crate::paths::INTERNAL_FILE_PREFIX;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Formats `/api/extract` reads, as reported by `/api/capabilities`
+pub const FORMATS: [&str; 3] = ["zip", "tar", "tar.gz"];
+
+/// Most entries an archive may hold
+const MAX_ENTRIES: u64 = 100_000;
+/// Blocks of an entry queued ahead of the client
+const QUEUE_LEN: usize = 8;
+/// Bytes decompressed and sent at a time by `/api/archive/get`
+const BLOCK_BYTES: usize = 64 * 1024;
+const MODE_TYPE: u32 = 0o170000;
+const MODE_DIR: u32 = 0o040000;
+const MODE_SYMLINK: u32 = 0o120000;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// What an archive entry is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EntryKind {
+    File,
+    Folder,
+    Symlink,
+    /// A hard link, device or other entry that is never extracted
+    Other,
+}
+
+/// A file or folder in an archive, whatever its format
+#[derive(Clone)]
+struct Entry {
+    /// Path in the archive as stored
+    name: String,
+    kind: EntryKind,
+    size: u64,
+    /// Bytes it takes in the archive, for formats that compress entries one by one
+    compressed: Option<u64>,
+    modified: Option<SystemTime>,
+    /// Why the format can't read its data, such as encryption
+    problem: Option<String>,
+}
+
+impl Entry {
+    fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Folder
+    }
+
+    /// Why the entry can't be extracted or read, if it can't
+    fn unreadable(&self) -> Option<String> {
+        match self.kind {
+            EntryKind::Symlink => Some(Msg::ArchiveSymlinkEntry.into()),
+            EntryKind::Other => Some(Msg::ArchiveSpecialEntry.into()),
+            _ => self.problem.clone(),
+        }
+    }
+}
+
+/// An archive format read one entry after another
+///
+/// ZIP could seek to any entry, but a compressed tar has to be read from the start, so
+/// extraction goes through entries in archive order and reads each one's data before
+/// asking for the next.
+trait ArchiveReader: Send {
+    /// The next entry, or `None` after the last
+    fn next_entry(&mut self) -> Result<Option<Entry>, ApiError>;
+    /// The data of the entry `next_entry` returned last, never longer than its size and
+    /// checked as far as the format allows; damaged data fails with `InvalidData`
+    fn data(&mut self) -> io::Result<Box<dyn Read + Send + '_>>;
+}
+
+/// Open the archive at `path` as the format its first bytes show
+///
+/// A tar, possibly gzipped, starts with a header block; anything else is tried as ZIP,
+/// whose directory is at the end.
+fn open_reader(path: &Path) -> Result<Box<dyn ArchiveReader>, ApiError> {
+    let open = || std::fs::File::open(path).map_err(|e| ApiError::io(Msg::OpenFileFailed, e));
+    let mut head = Vec::with_capacity(TAR_BLOCK as usize);
+    open()?
+        .take(TAR_BLOCK)
+        .read_to_end(&mut head)
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+    if head.starts_with(&GZIP_MAGIC) {
+        let mut block = Vec::with_capacity(TAR_BLOCK as usize);
+        let decoder = MultiGzDecoder::new(io::BufReader::new(open()?));
+        decoder.take(TAR_BLOCK).read_to_end(&mut block).map_err(read_error)?;
+        if !is_tar_header(&block) {
+            return Err(ApiError::UnsupportedType(Msg::UnsupportedArchive.into()));
+        }
+        let source = TarSource::Gzip(MultiGzDecoder::new(io::BufReader::new(open()?)));
+        return Ok(Box::new(TarReader::new(source)));
+    }
+    if is_tar_header(&head) {
+        return Ok(Box::new(TarReader::new(TarSource::Plain(io::BufReader::new(open()?)))));
+    }
+    match ZipArchive::new(io::BufReader::new(open()?)) {
+        Ok(archive) => Ok(Box::new(ZipReader::new(archive)?)),
+        // A ZIP may have something else in front, such as a self-extractor, so only one
+        // starting with a local header is known to be damaged rather than no ZIP at all
+        Err(e) if head.starts_with(&LOCAL_HEADER.to_le_bytes()) => Err(zip_error(e)),
+        Err(_) => Err(ApiError::UnsupportedType(Msg::UnsupportedArchive.into())),
+    }
+}
+
+/// All entries of an archive, at most `MAX_ENTRIES`
+fn read_entries(reader: &mut dyn ArchiveReader) -> Result<Vec<Entry>, ApiError> {
+    let mut entries = Vec::new();
+    while let Some(entry) = reader.next_entry()? {
+        if entries.len() as u64 == MAX_ENTRIES {
+            return Err(ApiError::PayloadTooLarge(Msg::TooManyArchiveEntries.with(&[&MAX_ENTRIES])));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// An archive read through in order, knowing which entry of its listing comes next
+struct Cursor {
+    reader: Box<dyn ArchiveReader>,
+    next: usize,
+}
+
+impl Cursor {
+    fn open(path: &Path) -> Result<Self, ApiError> {
+        Ok(Self { reader: open_reader(path)?, next: 0 })
+    }
+
+    /// Move on to entry `index` of the listing, so that `reader.data()` reads it
+    fn seek(&mut self, index: usize) -> Result<(), ApiError> {
+        while self.next <= index {
+            // The archive changed since it was listed
+            self.reader.next_entry()?.ok_or_else(damaged)?;
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+fn damaged() -> ApiError {
+    ApiError::ParseError(Msg::InvalidArchive.into())
+}
+
+/// A read error, telling damaged or cut short data from failing to read the file
+fn read_error(e: io::Error) -> ApiError {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof => damaged(),
+        _ => ApiError::io(Msg::ReadFileFailed, e),
+    }
+}
+
+fn zip_error(e: ZipError) -> ApiError {
+    match e {
+        ZipError::Io(e) => read_error(e),
+        _ => damaged(),
+    }
+}
+
+/// A DOS date and time in the server's time zone, as archivers without the extended
+/// timestamp store them
+fn dos_time(time: u16, date: u16) -> Option<SystemTime> {
+    let day = NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, ((date >> 5) & 0xf) as u32, (date & 0x1f) as u32)?;
+    let time = day.and_hms_opt((time >> 11) as u32, ((time >> 5) & 0x3f) as u32, ((time & 0x1f) * 2) as u32)?;
+    Local.from_local_datetime(&time).earliest().map(SystemTime::from)
+}
+
+/// A ZIP entry as its central directory header describes it
+fn zip_entry(file: &ZipFile<'_, io::BufReader<std::fs::File>>) -> Entry {
+    let kind = match file.unix_mode().map(|mode| mode & MODE_TYPE) {
+        Some(MODE_SYMLINK) => EntryKind::Symlink,
+        Some(MODE_DIR) => EntryKind::Folder,
+        _ if file.name().ends_with(['/', '\\']) => EntryKind::Folder,
+        _ => EntryKind::File,
+    };
+    let method = file.compression();
+    let problem = if file.encrypted() {
+        Some(Msg::EncryptedZipEntry.into())
+    } else if kind == EntryKind::File && !zip::SUPPORTED_COMPRESSION_METHODS.contains(&method) {
+        Some(Msg::UnsupportedZipMethod.with(&[&method]))
+    } else {
+        None
+    };
+    let timestamp = file.extra_data_fields().find_map(|field| match field {
+        ExtraField::ExtendedTimestamp(timestamp) => timestamp.mod_time(),
+        _ => None,
+    });
+    let modified = match timestamp {
+        Some(secs) => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)),
+        None => file.last_modified().and_then(|time| dos_time(time.timepart(), time.datepart())),
+    };
+    Entry {
+        // Names that aren't UTF-8 are in the old IBM code page, which the crate decodes
+        name: file.name().to_string(),
+        kind,
+        size: file.size(),
+        compressed: Some(file.compressed_size()),
+        modified,
+        problem,
+    }
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Msg::DamagedArchiveEntry.to_string())
+}
+
+/// An entry's data, decompressed and checked against the size and CRC the directory gives
+///
+/// Never reads past that size, so an archive can't unpack to more than it declares. Data
+/// that doesn't match fails with `InvalidData`, at the latest with its last bytes.
+struct ZipData<R> {
+    data: io::Take<R>,
+    crc: crc32fast::Hasher,
+    read: u64,
+    size: u64,
+    expected_crc: u32,
+}
+
+impl<R: Read> Read for ZipData<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.data.read(buf) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Err(e),
+            // The decoder's errors all mean the data is damaged
+            Err(_) => return Err(invalid_data()),
+        };
+        self.read += read as u64;
+        self.crc.update(&buf[..read]);
+        // Checked with the last bytes, before they are handed out, so a streamed entry
+        // that doesn't match ends short of its `Content-Length`; the crate only checks
+        // once asked for more
+        let complete = self.read == self.size;
+        let wrong_crc = complete && (read > 0 || self.size == 0) && self.crc.clone().finalize() != self.expected_crc;
+        if self.read > self.size || wrong_crc || (read == 0 && !complete) {
+            return Err(invalid_data());
+        }
+        Ok(read)
+    }
+}
+
+/// A ZIP archive, its entries in the order of the central directory
+struct ZipReader {
+    archive: ZipArchive<io::BufReader<std::fs::File>>,
+    /// Index of the entry after the one `next_entry` returned last
+    next: usize,
+}
+
+impl ZipReader {
+    fn new(archive: ZipArchive<io::BufReader<std::fs::File>>) -> Result<Self, ApiError> {
+        if archive.len() as u64 > MAX_ENTRIES {
+            return Err(ApiError::PayloadTooLarge(Msg::TooManyArchiveEntries.with(&[&MAX_ENTRIES])));
+        }
+        Ok(Self { archive, next: 0 })
+    }
+}
+
+impl ArchiveReader for ZipReader {
+    fn next_entry(&mut self) -> Result<Option<Entry>, ApiError> {
+        if self.next == self.archive.len() {
+            return Ok(None);
+        }
+        let entry = zip_entry(&self.archive.by_index_raw(self.next).map_err(zip_error)?);
+        self.next += 1;
+        Ok(Some(entry))
+    }
+
+    fn data(&mut self) -> io::Result<Box<dyn Read + Send + '_>> {
+        let index = self.next.checked_sub(1).ok_or_else(invalid_data)?;
+        let file = self.archive.by_index(index).map_err(|e| match e {
+            ZipError::Io(e) => e,
+            _ => invalid_data(),
+        })?;
+        let (size, expected_crc) = (file.size(), file.crc32());
+        Ok(Box::new(ZipData { data: file.take(size + 1), crc: crc32fast::Hasher::new(), read: 0, size, expected_crc }))
+    }
+}
+
+/// Whether `block` starts a tar archive: a header whose checksum matches, or the empty
+/// block that ends an archive with no entries
+fn is_tar_header(block: &[u8]) -> bool {
+    if block.len() != TAR_BLOCK as usize {
+        return false;
+    }
+    if block.iter().all(|&b| b == 0) {
+        return true;
+    }
+    let header = tar::Header::from_byte_slice(block);
+    let mut expected = header.clone();
+    expected.set_cksum();
+    header.cksum().is_ok_and(|sum| expected.cksum().is_ok_and(|expected| expected == sum))
+}
+
+/// A tar error; the crate reports malformed headers as `Other`
+fn tar_error(e: io::Error) -> ApiError {
+    match e.kind() {
+        io::ErrorKind::Other => damaged(),
+        _ => read_error(e),
+    }
+}
+
+/// A tar archive's bytes, as stored or gunzipped while read
+enum TarSource {
+    Plain(io::BufReader<std::fs::File>),
+    Gzip(MultiGzDecoder<io::BufReader<std::fs::File>>),
+}
+
+impl Read for TarSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.read(buf),
+            Self::Gzip(gz) => gz.read(buf),
+        }
+    }
+}
+
+impl TarSource {
+    /// Pass over `len` bytes: seeking in a plain archive, decompressing them in a gzipped one
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        match self {
+            Self::Plain(file) => {
+                let len = i64::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                file.seek_relative(len)?;
+                // Seeking past the end succeeds, so an archive cut short only shows here
+                if file.stream_position()? > file.get_ref().metadata()?.len() {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+            Self::Gzip(gz) => {
+                if io::copy(&mut gz.take(len), &mut io::sink())? < len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A tar archive in the POSIX, GNU or old format, read front to back
+///
+/// Each header, with the PAX and GNU long name headers in front of it, is read by a
+/// `tar::Archive` started where the entry before ends. The data is read from the source
+/// here, so it can be handed out after that archive is gone. Headers are checked by
+/// their checksum; the data has none.
+struct TarReader {
+    source: TarSource,
+    /// Data of the current entry not read yet
+    remaining: u64,
+    /// Padding after it, up to the next block
+    padding: u64,
+}
+
+impl TarReader {
+    fn new(source: TarSource) -> Self {
+        Self { source, remaining: 0, padding: 0 }
+    }
+}
+
+/// Bytes after `len` bytes of data up to a whole tar block
+fn tar_padding(len: u64) -> u64 {
+    (TAR_BLOCK - len % TAR_BLOCK) % TAR_BLOCK
+}
+
+impl ArchiveReader for TarReader {
+    fn next_entry(&mut self) -> Result<Option<Entry>, ApiError> {
+        let skip = self.remaining.checked_add(self.padding).ok_or_else(damaged)?;
+        self.source.skip(skip).map_err(read_error)?;
+        (self.remaining, self.padding) = (0, 0);
+        let mut archive = tar::Archive::new(&mut self.source);
+        let mut entries = archive.entries().map_err(tar_error)?;
+        // The end is marked by empty blocks, though some archivers leave them out
+        let mut entry = loop {
+            let Some(entry) = entries.next() else {
+                return Ok(None);
+            };
+            let entry = entry.map_err(tar_error)?;
+            // Global PAX values say nothing extraction uses
+            if !entry.header().entry_type().is_pax_global_extensions() {
+                break entry;
+            }
+        };
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let kind = match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous if name.ends_with('/') => EntryKind::Folder,
+            EntryType::Regular | EntryType::Continuous => EntryKind::File,
+            EntryType::Directory => EntryKind::Folder,
+            EntryType::Symlink => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        // Seconds, possibly with a fraction; times before 1970 are left out
+        let pax_mtime = entry.pax_extensions().ok().flatten().and_then(|mut extensions| {
+            extensions.find_map(|extension| {
+                let extension = extension.ok()?;
+                if extension.key().ok()? != "mtime" {
+                    return None;
+                }
+                extension.value().ok()?.split('.').next()?.parse().ok()
+            })
+        });
+        let mtime = pax_mtime.or(entry.header().mtime().ok());
+        self.remaining = entry.size();
+        self.padding = tar_padding(self.remaining);
+        Ok(Some(Entry {
+            name,
+            kind,
+            // Links, devices and folders have no data to extract whatever their size says
+            size: if kind == EntryKind::File { self.remaining } else { 0 },
+            compressed: None,
+            modified: mtime.map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            problem: None,
+        }))
+    }
+
+    fn data(&mut self) -> io::Result<Box<dyn Read + Send + '_>> {
+        Ok(Box::new(TarData { reader: self }))
+    }
+}
+
+/// The data of a tar entry, exactly as long as its header says
+struct TarData<'a> {
+    reader: &'a mut TarReader,
+}
+
+impl Read for TarData<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.reader.remaining.min(usize::MAX as u64) as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = match self.reader.source.read(&mut buf[..len]) {
+            Ok(0) => return Err(invalid_data()),
+            Ok(read) => read,
+            // Corrupt gzip data
+            Err(e) if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput) => {
+                return Err(invalid_data());
+            }
+            Err(e) => return Err(e),
+        };
+        self.reader.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Decompress the data of the entry `reader` is at into `target`
+fn write_entry(reader: &mut dyn ArchiveReader, target: &Path) -> io::Result<()> {
+    let mut data = reader.data()?;
+    let mut out = std::fs::File::create(target)?;
+    io::copy(&mut data, &mut out)?;
+    Ok(())
+}
+
+/// The names along an entry's path, or `None` for one that could lead outside the folder
+/// it is extracted into: an absolute path, a Windows drive or a `..`
+fn entry_names(name: &str) -> Option<Vec<&str>> {
+    let absolute = name.starts_with(['/', '\\']) || name.as_bytes().get(1) == Some(&b':');
+    // Archives made on Windows may separate with backslashes
+    let names: Vec<&str> = name.split(['/', '\\']).filter(|n| !n.is_empty() && *n != ".").collect();
+    if absolute || names.is_empty() || names.contains(&"..") {
+        return None;
+    }
+    Some(names)
+}
+
+/// What extracting an archive has done so far
+struct Extraction {
+    /// The archive, where the last file written left it
+    cursor: Option<Cursor>,
+    conflict: ExtractConflict,
+    files: u64,
+    folders: u64,
+    size: u64,
+    skipped: Vec<SkippedEntry>,
+    /// The names in each folder written to, by their NFC form, read once and kept up to
+    /// date, so a large archive doesn't rescan a folder for every entry
+    names: HashMap<PathBuf, HashMap<String, OsString>>,
+    /// With an upload hook, the files that passed it, held aside until every file has so
+    /// that a rejected one leaves nothing of the archive behind
+    held: Option<Vec<Written>>,
+    /// Folders this extraction created, outermost first
+    created: Vec<PathBuf>,
+    user: String,
+    ip: IpAddr,
+}
+
+/// A file entry written aside, to be moved to `target`
+struct Written {
+    staged: TempPath,
+    aside: PathBuf,
+    target: PathBuf,
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+impl Extraction {
+    /// The names in `dir` by their NFC form
+    async fn names_in(&mut self, dir: &Path) -> &mut HashMap<String, OsString> {
+        if !self.names.contains_key(dir) {
+            let mut names = HashMap::new();
+            if let Ok(mut entries) = fs::read_dir(dir).await {
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    let stored = entry.file_name();
+                    if let Some(key) = stored.to_str().map(|s| s.nfc().collect()) {
+                        names.insert(key, stored);
+                    }
+                }
+            }
+            self.names.insert(dir.to_path_buf(), names);
+        }
+        self.names.entry(dir.to_path_buf()).or_default()
+    }
+
+    /// The entry of `dir` named `name` up to normalization, as `paths::existing_name`
+    /// finds it; files held back count as there
+    async fn existing(&mut self, dir: &Path, name: &OsStr) -> Option<OsString> {
+        if fs::symlink_metadata(dir.join(name)).await.is_ok() {
+            return Some(name.to_os_string());
+        }
+        let key: String = name.to_str()?.nfc().collect();
+        self.names_in(dir).await.get(&key).cloned()
+    }
+
+    /// `fs::create_dir_all`, noting the folders it creates
+    async fn create_dirs(&mut self, dir: &Path) -> io::Result<()> {
+        let missing: Vec<PathBuf> = dir.ancestors().take_while(|dir| !dir.exists()).map(Path::to_path_buf).collect();
+        fs::create_dir_all(dir).await?;
+        self.created.extend(missing.into_iter().rev());
+        Ok(())
+    }
+
+    /// Move a written file into place
+    async fn place(&mut self, file: Written) -> Result<(), ApiError> {
+        fs::rename(&file.aside, &file.target).await.map_err(|e| ApiError::io(Msg::ExtractFailed, e))?;
+        file.staged.keep();
+        if let Some(modified) = file.modified {
+            let _ = filetime::set_file_mtime(&file.target, filetime::FileTime::from_system_time(modified));
+        }
+        self.files += 1;
+        self.size += file.size;
+        Ok(())
+    }
+
+    /// Undo an extraction that failed while files were held back for the upload hook:
+    /// they are removed as they are dropped, and then the folders created for them
+    async fn discard(&mut self) {
+        let Some(held) = self.held.take() else {
+            return;
+        };
+        drop(held);
+        for dir in self.created.drain(..).rev() {
+            let _ = fs::remove_dir(dir).await;
+        }
+    }
+
+    /// A name like `name` that is free in `dir`, numbered as copies are: `report (1).pdf`
+    async fn free_name(&mut self, dir: &Path, name: &str) -> String {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+            _ => (name, None),
+        };
+        let mut counter = 1;
+        loop {
+            let candidate = match ext {
+                Some(ext) => format!("{} ({}).{}", stem, counter, ext),
+                None => format!("{} ({})", stem, counter),
+            };
+            if self.existing(dir, OsStr::new(&candidate)).await.is_none() {
+                return candidate;
+            }
+            counter += 1;
+        }
+    }
+
+    fn skip(&mut self, entry: &Entry, reason: impl Into<String>) {
+        self.skipped.push(SkippedEntry { path: entry.name.clone(), reason: reason.into() });
+    }
+
+    /// Create a folder entry, or merge it into the folder already there
+    async fn folder(&mut self, entry: &Entry, paths: &SafePathResult) {
+        if paths.actual.is_dir() {
+            return;
+        }
+        match self.create_dirs(&paths.actual).await {
+            Ok(()) => self.folders += 1,
+            Err(_) if paths.actual.exists() => self.skip(entry, Msg::ArchiveEntryExists),
+            Err(e) => self.skip(entry, format!("{}: {}", Msg::CreateDirFailed, e)),
+        }
+    }
+
+    /// Write a file entry, going by `conflict` when its name is taken
+    ///
+    /// It is written aside first and moved into place once complete, so a damaged entry
+    /// leaves nothing behind and `overwrite` keeps the old file until then. With an
+    /// upload hook, the file is passed through it and held aside. Entries that can't be
+    /// extracted are skipped; only failing to write to the destination, such as a full
+    /// disk, or the hook rejecting a file stops the extraction.
+    async fn file(
+        &mut self,
+        state: &AppState,
+        index: usize,
+        entry: &Entry,
+        logical: &str,
+        paths: &SafePathResult,
+    ) -> Result<(), ApiError> {
+        let (Some(parent), Some(name)) = (paths.actual.parent(), paths.actual.file_name()) else {
+            self.skip(entry, Msg::UnsafeArchiveEntry);
+            return Ok(());
+        };
+        if let Err(e) = self.create_dirs(parent).await {
+            self.skip(entry, format!("{}: {}", Msg::CreateDirFailed, e));
+            return Ok(());
+        }
+        let target = match (self.existing(parent, name).await, self.conflict) {
+            (None, _) => paths.actual.clone(),
+            (Some(stored), ExtractConflict::Overwrite) if !parent.join(&stored).is_dir() => parent.join(stored),
+            (Some(_), ExtractConflict::Rename) => {
+                let name = self.free_name(parent, &name.to_string_lossy()).await;
+                if let Err(e) = state.name_rules.check(&name) {
+                    self.skip(entry, e.message());
+                    return Ok(());
+                }
+                parent.join(name)
+            }
+            // A folder is never replaced by a file
+            (Some(_), _) => {
+                self.skip(entry, Msg::ArchiveEntryExists);
+                return Ok(());
+            }
+        };
+
+        let aside = parent.join(format!("{}extract_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+        let staged = TempPath::file(&aside);
+        let mut cursor = self.cursor.take().ok_or_else(|| ApiError::Io(Msg::ExtractFailed.into()))?;
+        let (cursor, written) = {
+            let aside = aside.clone();
+            tokio::task::spawn_blocking(move || {
+                let written = cursor.seek(index).map(|()| write_entry(cursor.reader.as_mut(), &aside));
+                (cursor, written)
+            })
+            .await
+            .map_err(|e| ApiError::Io(e.to_string()))?
+        };
+        self.cursor = Some(cursor);
+        match written? {
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                self.skip(entry, e.to_string());
+                return Ok(());
+            }
+            written => written.map_err(|e| ApiError::io(Msg::ExtractFailed, e))?,
+        }
+        let name = target.file_name().unwrap_or_default().to_os_string();
+        if self.held.is_some() {
+            // Checked under the name it gets, which `rename` may have changed
+            let dir = logical.rsplit_once('/').map_or("", |(dir, _)| dir);
+            let logical = format!("{}/{}", dir, name.to_string_lossy());
+            hooks::check_upload(state, &aside, &logical, &self.user, self.ip).await?;
+        }
+        self.names_in(parent).await.insert(name.to_string_lossy().nfc().collect(), name);
+        let file = Written { staged, aside, target, modified: entry.modified, size: entry.size };
+        match &mut self.held {
+            Some(held) => held.push(file),
+            None => self.place(file).await?,
+        }
+        Ok(())
+    }
+}
+
+/// Where an entry goes, checked as an upload there would be, or why it is left out
+fn entry_target(
+    state: &AppState,
+    user: &AuthUser,
+    headers: &HeaderMap,
+    dest: &SafePathResult,
+    dest_logical: &str,
+    entry: &Entry,
+) -> Result<(String, SafePathResult), String> {
+    if let Some(reason) = entry.unreadable() {
+        return Err(reason);
+    }
+    let names = entry_names(&entry.name).ok_or_else(|| Msg::UnsafeArchiveEntry.to_string())?;
+    let mut logical = dest_logical.trim_end_matches('/').to_string();
+    for name in names {
+        let name = state.name_rules.prepare(name).map_err(|e| e.message().to_string())?;
+        logical.push('/');
+        logical.push_str(&name);
+    }
+    let paths = safe_path(&state.mounts, &logical).map_err(|e| e.message().to_string())?;
+    // Below the virtual root of several mounts, a first name could pick another mount
+    if paths.root != dest.root || !paths.logical.starts_with(&dest.logical) {
+        return Err(Msg::UnsafeArchiveEntry.into());
+    }
+    let refused = |e: ApiError| e.message().to_string();
+    user.check(&logical)?;
+    check_patterns(state, &logical, true).map_err(refused)?;
+    check_not_internal(&logical, &paths.actual).map_err(refused)?;
+    state.locks.check(&logical, false, user, headers).map_err(refused)?;
+    Ok((logical, paths))
+}
+
+/// The archive at `user_path`, checked as a download of it would be, and its entries
+async fn open_archive(
+    state: &AppState,
+    user: &AuthUser,
+    user_path: &str,
+) -> Result<(SafePathResult, String, Vec<Entry>), ApiError> {
+    let archive = safe_path(&state.mounts, user_path)?;
+    let logical = state.mounts.logical_path(&archive.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &logical, true)?;
+    check_not_internal(&logical, &archive.actual)?;
+    let metadata = fs::metadata(&archive.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::FileNotFound, e))?;
+    if !metadata.is_file() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let path = archive.actual.clone();
+    let entries = tokio::task::spawn_blocking(move || read_entries(open_reader(&path)?.as_mut()))
+    .await
+    .map_err(|e| ApiError::Io(e.to_string()))??;
+    Ok((archive, logical, entries))
+}
+
+/// The entries of a ZIP, tar or tar.gz archive, without extracting it
+///
+/// Names are as the archive stores them, which is what `/api/archive/get` takes; they
+/// aren't checked the way extracting them would be. `readable` is false for entries
+/// that can't be read: symlinks, hard links and devices, encrypted ZIP entries and ZIP
+/// compression methods other than stored and deflate. A tar.gz is decompressed through
+/// to list it.
+#[utoipa::path(
+    get, path = "/api/archive/list", tag = "files", params(ArchiveQuery),
+    responses(
+        (status = 200, description = "The entries in the order the archive lists them", body = ApiResponse<ArchiveListResponse>),
+        (status = 413, description = "More than 100000 entries", body = crate::openapi::ErrorBody),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: not a ZIP, tar or tar.gz archive", body = crate::openapi::ErrorBody),
+        (status = 422, description = "`PARSE_ERROR`: a damaged archive", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn list_archive(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ArchiveQuery>,
+) -> Result<Json<ApiResponse<ArchiveListResponse>>, ApiError> {
+    let (_, _, entries) = open_archive(&state, &user, &query.path).await?;
+    let (mut files, mut folders, mut size) = (0, 0, 0);
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            match entry.kind {
+                EntryKind::Folder => folders += 1,
+                EntryKind::File => {
+                    files += 1;
+                    size += entry.size;
+                }
+                _ => {}
+            }
+            let file_type = match entry.kind {
+                EntryKind::File => "file",
+                EntryKind::Folder => "folder",
+                EntryKind::Symlink => "symlink",
+                EntryKind::Other => "other",
+            };
+            ArchiveEntry {
+                readable: entry.kind == EntryKind::File && entry.unreadable().is_none(),
+                file_type: file_type.to_string(),
+                size: entry.size,
+                compressed_size: entry.compressed,
+                modified: entry.modified.map(|m| DateTime::<Utc>::from(m).to_rfc3339_opts(SecondsFormat::Secs, true)),
+                name: entry.name,
+            }
+        })
+        .collect();
+    Ok(Json(ApiResponse::success(ArchiveListResponse { entries, files, folders, size })))
+}
+
+/// One file from a ZIP, tar or tar.gz archive, decompressed while it is sent
+///
+/// `entry` is the name as `/api/archive/list` gives it; of several entries by that name,
+/// as a tar may hold, the last. ZIP data is checked against the CRC in the archive as it
+/// streams; damaged data ends the response early, so the client sees a failed download
+/// rather than wrong content. Sent at most at `--max-download-rate`;
+/// `disposition=inline` works as for `/api/download`.
+#[utoipa::path(
+    get, path = "/api/archive/get", tag = "files", params(ArchiveEntryQuery),
+    responses(
+        (status = 200, description = "The entry's content", content_type = "application/octet-stream"),
+        (status = 400, description = "A folder entry", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No such entry", body = crate::openapi::ErrorBody),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: not a supported archive, or an entry that can't be read", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn get_archive_entry(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ArchiveEntryQuery>,
+) -> Result<Response, ApiError> {
+    let rate = crate::throttle::download_rate(state.max_download_rate, None)?;
+    let (archive, _, entries) = open_archive(&state, &user, &query.path).await?;
+    let index = entries
+        .iter()
+        .rposition(|entry| entry.name == query.entry)
+        .ok_or_else(|| ApiError::NotFound(Msg::ArchiveEntryNotFound.into()))?;
+    let entry = &entries[index];
+    if entry.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    if let Some(reason) = entry.unreadable() {
+        return Err(ApiError::UnsupportedType(reason));
+    }
+    let path = archive.actual.clone();
+    let mut cursor = tokio::task::spawn_blocking(move || {
+        let mut cursor = Cursor::open(&path)?;
+        cursor.seek(index)?;
+        Ok::<_, ApiError>(cursor)
+    })
+    .await
+    .map_err(|e| ApiError::Io(e.to_string()))??;
+
+    // Read on a blocking thread; a full queue holds it back and a dropped one stops it
+    let (out, queue) = mpsc::channel::<io::Result<Bytes>>(QUEUE_LEN);
+    tokio::task::spawn_blocking(move || {
+        let mut data = match cursor.reader.data() {
+            Ok(data) => data,
+            Err(e) => {
+                let _ = out.blocking_send(Err(e));
+                return;
+            }
+        };
+        let mut block = vec![0; BLOCK_BYTES];
+        loop {
+            let sent = match data.read(&mut block) {
+                Ok(0) => break,
+                Ok(read) => out.blocking_send(Ok(Bytes::copy_from_slice(&block[..read]))),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let _ = out.blocking_send(Err(e));
+                    break;
+                }
+            };
+            if sent.is_err() {
+                break;
+            }
+        }
+    });
+    let blocks = stream::unfold(queue, |mut queue| async move { queue.recv().await.map(|block| (block, queue)) });
+
+    let name = entry.name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let disposition = query.disposition.unwrap_or_default();
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, mime_guess::from_path(name).first_or_octet_stream().to_string()),
+            (header::CONTENT_LENGTH, entry.size.to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(disposition.as_str(), name)),
+        ],
+        Body::from_stream(blocks),
+    )
+        .into_response();
+    if disposition == Disposition::Inline {
+        sandbox_inline(&mut response);
+    }
+    Ok(crate::throttle::limit(response, rate))
+}
+
+/// Unpack a ZIP, tar or tar.gz archive on the server into a folder
+///
+/// The format is told by the file's content, not its name. Entries are read from the
+/// archive one at a time and decompressed straight to disk. Every entry's path is checked
+/// before anything is written: one that is absolute or climbs out with `..` ("zip
+/// slip"), a symlink, hard link or device, an encrypted entry, or a name the server
+/// refuses is left out and listed in `skipped`, as is one the caller couldn't upload to.
+/// Each file is checked against the size in the archive, and the CRC for ZIP, and never
+/// written past that size. Folders are merged into existing ones; `conflict` says what
+/// happens to files already there, and by default nothing is extracted if there are any.
+/// Timestamps are kept, file modes are not. With `--upload-hook`, every file is passed
+/// through it before any is moved into place; if it rejects one, nothing of the archive
+/// is left in the destination.
+#[utoipa::path(
+    post, path = "/api/extract", tag = "files", request_body = ExtractRequest,
+    responses(
+        (status = 200, description = "Extracted; entries left out are listed in `skipped`", body = ApiResponse<ExtractResult>),
+        (status = 409, description = "With `conflict: fail`, a file already in the destination", body = crate::openapi::ErrorBody),
+        (status = 413, description = "More than 100000 entries", body = crate::openapi::ErrorBody),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: not a ZIP, tar or tar.gz archive", body = crate::openapi::ErrorBody),
+        (status = 422, description = "`PARSE_ERROR`: a damaged archive; `REJECTED_BY_HOOK`: the upload hook refused a file", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn extract(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<ExtractRequest>,
+) -> Result<Json<ApiResponse<ExtractResult>>, ApiError> {
+    let (archive, archive_logical, entries) = open_archive(&state, &user, &req.path).await?;
+
+    let dest = safe_path(&state.mounts, &req.destination)?;
+    let dest_logical = state.mounts.logical_path(&dest.logical);
+    user.check(&dest_logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &dest_logical, true)?;
+    check_not_internal(&dest_logical, &dest.actual)?;
+    if dest.actual.exists() && !dest.actual.is_dir() {
+        return Err(ApiError::InvalidDestination(Msg::NotADirectory.into()));
+    }
+
+    let mut extraction = Extraction {
+        cursor: None,
+        conflict: req.conflict,
+        files: 0,
+        folders: 0,
+        size: 0,
+        skipped: Vec::new(),
+        names: HashMap::new(),
+        held: state.upload_hook.is_some().then(Vec::new),
+        created: Vec::new(),
+        user: user.username.clone(),
+        ip: addr.ip(),
+    };
+    let mut targets = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.into_iter().enumerate() {
+        // `./`, which tar puts first when packing a folder's contents, is the destination
+        if entry.is_dir() && entry.name.split(['/', '\\']).all(|name| name.is_empty() || name == ".") {
+            continue;
+        }
+        match entry_target(&state, &user, &headers, &dest, &dest_logical, &entry) {
+            Ok((logical, paths)) => targets.push((index, entry, logical, paths)),
+            Err(reason) => extraction.skip(&entry, reason),
+        }
+    }
+    if req.conflict == ExtractConflict::Fail {
+        for (_, entry, logical, paths) in &targets {
+            if !entry.is_dir()
+                && let (Some(parent), Some(name)) = (paths.actual.parent(), paths.actual.file_name())
+                && extraction.existing(parent, name).await.is_some()
+            {
+                return Err(ApiError::AlreadyExists(Msg::ExtractTargetExists.with(&[logical])));
+            }
+        }
+    }
+    let total: u64 = targets.iter().filter(|(_, entry, ..)| !entry.is_dir()).map(|(_, entry, ..)| entry.size).sum();
+    disks::ensure_space(&dest.actual, total, state.min_free_space).await?;
+
+    // Read through again for the data; a tar.gz can only be read from the start
+    let path = archive.actual.clone();
+    extraction.cursor = Some(
+        tokio::task::spawn_blocking(move || Cursor::open(&path))
+            .await
+            .map_err(|e| ApiError::Io(e.to_string()))??,
+    );
+    let mut result = extraction.create_dirs(&dest.actual).await.map_err(|e| ApiError::io(Msg::CreateDirFailed, e));
+    if result.is_ok() {
+        for (index, entry, logical, paths) in &targets {
+            if entry.is_dir() {
+                extraction.folder(entry, paths).await;
+            } else if let Err(e) = extraction.file(&state, *index, entry, logical, paths).await {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    if result.is_err() {
+        extraction.discard().await;
+    } else if let Some(held) = extraction.held.take() {
+        for file in held {
+            if let Err(e) = extraction.place(file).await {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    state.audit.record(
+        AuditEntry::new("extract", &user.username, addr.ip(), &archive_logical)
+            .dest(&dest_logical)
+            .size(extraction.size)
+            .result(&result),
+    );
+    sizes_changed(&state, &dest.actual).await;
+    result?;
+
+    let Extraction { files, folders, size, skipped, .. } = extraction;
+    let message = if skipped.is_empty() {
+        Msg::Extracted.with(&[&files])
+    } else {
+        Msg::ExtractedWithSkipped.with(&[&files, &skipped.len()])
+    };
+    Ok(Json(ApiResponse::success(ExtractResult {
+        message,
+        destination: dest_logical,
+        files,
+        folders,
+        size,
+        skipped,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    /// What reading an entry's data gave: the bytes, or the kind of error it failed with
+    type Data = Result<Vec<u8>, io::ErrorKind>;
+
+    /// Every entry of the archive at `path`, with the data of its files
+    fn read_all(path: &Path) -> Result<Vec<(String, EntryKind, Data)>, ApiError> {
+        let mut reader = open_reader(path)?;
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry()? {
+            let mut data = Vec::new();
+            let read = match entry.kind {
+                EntryKind::File => reader.data().and_then(|mut r| r.read_to_end(&mut data)).map_err(|e| e.kind()),
+                _ => Ok(0),
+            };
+            entries.push((entry.name, entry.kind, read.map(|_| data)));
+        }
+        Ok(entries)
+    }
+
+    fn write(dir: &tempfile::TempDir, name: &str, bytes: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// A ZIP made by the `zip` crate: a stored file, a deflated one, a folder and a symlink
+    fn sample_zip(zip64_end: bool) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let deflated = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("a.txt", stored).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.add_directory("docs/", stored).unwrap();
+        zip.start_file("docs/b.txt", deflated.large_file(zip64_end)).unwrap();
+        zip.write_all(&b"compressible ".repeat(500)).unwrap();
+        zip.add_symlink("link", "/etc/passwd", stored).unwrap();
+        if zip64_end {
+            zip.set_zip64_comment(Some(""));
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn sample_entries() -> Vec<(String, EntryKind, Data)> {
+        vec![
+            ("a.txt".into(), EntryKind::File, Ok(b"hello".to_vec())),
+            ("docs/".into(), EntryKind::Folder, Ok(Vec::new())),
+            ("docs/b.txt".into(), EntryKind::File, Ok(b"compressible ".repeat(500))),
+            ("link".into(), EntryKind::Symlink, Ok(Vec::new())),
+        ]
+    }
+
+    #[test]
+    fn zip_entries_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(&dir, "a.zip", &sample_zip(false));
+        assert_eq!(read_all(&path).unwrap(), sample_entries());
+    }
+
+    #[test]
+    fn zip64_end_records_are_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut zip = sample_zip(true);
+        // Mark the classic end record's count, size and offset as too large, as archives
+        // with over 65535 entries or past 4GB do, so only the ZIP64 record gives them
+        let end = zip.len() - 22;
+        assert_eq!(&zip[end..end + 4], b"PK\x05\x06");
+        zip[end + 8..end + 12].copy_from_slice(&[0xff; 4]);
+        zip[end + 12..end + 20].copy_from_slice(&[0xff; 8]);
+        let path = write(&dir, "a.zip", &zip);
+        assert_eq!(read_all(&path).unwrap(), sample_entries());
+
+        // A locator that doesn't point at a ZIP64 end record
+        let locator = end - 20;
+        zip[locator + 8..locator + 16].copy_from_slice(&0u64.to_le_bytes());
+        let path = write(&dir, "b.zip", &zip);
+        assert!(matches!(read_all(&path), Err(ApiError::ParseError(_))));
+    }
+
+    #[test]
+    fn truncated_or_damaged_zips_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip = sample_zip(false);
+        // Cut short, it is a damaged ZIP; without the local header in front, no ZIP at all
+        for len in [zip.len() - 1, zip.len() / 2, 10] {
+            let path = write(&dir, "cut.zip", &zip[..len]);
+            assert!(matches!(read_all(&path), Err(ApiError::ParseError(_))), "{len}");
+            let path = write(&dir, "cut.zip", &zip[4..len]);
+            assert!(matches!(read_all(&path), Err(ApiError::UnsupportedType(_))), "{len}");
+        }
+        // Data cut out of the middle leaves the directory pointing past the end
+        let path = write(&dir, "short.zip", &[&zip[..40], &zip[200..]].concat());
+        assert!(matches!(read_all(&path), Err(ApiError::ParseError(_))));
+
+        // Damaged data fails when read, and only that entry
+        let mut damaged = zip.clone();
+        let at = damaged.windows(5).position(|w| w == b"hello").unwrap();
+        damaged[at] = b'j';
+        let at = damaged.windows(5).position(|w| w == b"b.txt").unwrap() + 5 + 20 + 10;
+        damaged[at] ^= 0xff;
+        let path = write(&dir, "damaged.zip", &damaged);
+        let entries = read_all(&path).unwrap();
+        assert_eq!(entries[0].2, Err(io::ErrorKind::InvalidData));
+        assert_eq!(entries[1].2, Ok(Vec::new()));
+        assert_eq!(entries[2].2, Err(io::ErrorKind::InvalidData));
+    }
+
+    /// A tar made by the `tar` crate with a file, a folder, both kinds of link, a name too
+    /// long for the header and one given in a PAX header
+    fn sample_tar() -> Vec<u8> {
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut append = |kind: tar::EntryType, name: &str, data: &[u8], link: Option<&str>| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(1_700_000_000);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            tar.append_data(&mut header, name, data).unwrap();
+        };
+        append(tar::EntryType::Regular, "a.txt", b"hello", None);
+        append(tar::EntryType::Directory, "docs/", b"", None);
+        append(tar::EntryType::Symlink, "docs/link", b"", Some("/etc/passwd"));
+        append(tar::EntryType::Link, "docs/hard", b"", Some("a.txt"));
+        append(tar::EntryType::Regular, &format!("docs/{}.txt", "n".repeat(150)), &[7; 1000], None);
+        tar.append_pax_extensions([("path", "docs/ünïcode.txt".as_bytes())]).unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_size(3);
+        header.set_path("plain.txt").unwrap();
+        header.set_cksum();
+        tar.append(&header, &b"pax"[..]).unwrap();
+        tar.into_inner().unwrap()
+    }
+
+    fn sample_tar_entries() -> Vec<(String, EntryKind, Data)> {
+        vec![
+            ("a.txt".into(), EntryKind::File, Ok(b"hello".to_vec())),
+            ("docs/".into(), EntryKind::Folder, Ok(Vec::new())),
+            ("docs/link".into(), EntryKind::Symlink, Ok(Vec::new())),
+            ("docs/hard".into(), EntryKind::Other, Ok(Vec::new())),
+            (format!("docs/{}.txt", "n".repeat(150)), EntryKind::File, Ok(vec![7; 1000])),
+            ("docs/ünïcode.txt".into(), EntryKind::File, Ok(b"pax".to_vec())),
+        ]
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(bytes).unwrap();
+        gz.finish().unwrap()
+    }
+
+    #[test]
+    fn tar_entries_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = sample_tar();
+        let path = write(&dir, "a.tar", &tar);
+        assert_eq!(read_all(&path).unwrap(), sample_tar_entries());
+        let path = write(&dir, "a.tar.gz", &gzip(&tar));
+        assert_eq!(read_all(&path).unwrap(), sample_tar_entries());
+        // Without the closing empty blocks
+        let path = write(&dir, "open.tar", &tar[..tar.len() - 2 * TAR_BLOCK as usize]);
+        assert_eq!(read_all(&path).unwrap(), sample_tar_entries());
+    }
+
+    #[test]
+    fn links_and_special_entries_are_never_extracted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut zip = read_all(&write(&dir, "a.zip", &sample_zip(false))).unwrap();
+        let mut tar = read_all(&write(&dir, "a.tar", &sample_tar())).unwrap();
+        zip.append(&mut tar);
+        for (name, kind, _) in zip {
+            let entry = Entry { name, kind, size: 0, compressed: None, modified: None, problem: None };
+            let link = matches!(kind, EntryKind::Symlink | EntryKind::Other);
+            assert_eq!(entry.unreadable().is_some(), link, "{}", entry.name);
+        }
+    }
+
+    #[test]
+    fn truncated_tars_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = sample_tar();
+        let gz = gzip(&tar);
+        // Cut inside the data of the last file: the entries before it read, its data fails
+        // and the archive can't be listed
+        let cut = tar.len() - 2 * TAR_BLOCK as usize - TAR_BLOCK as usize + 2;
+        for path in [write(&dir, "cut.tar", &tar[..cut]), write(&dir, "cut.tar.gz", &gzip(&tar[..cut]))] {
+            let mut reader = open_reader(&path).unwrap();
+            for _ in 0..6 {
+                reader.next_entry().unwrap().unwrap();
+            }
+            let mut data = Vec::new();
+            let read = reader.data().unwrap().read_to_end(&mut data);
+            assert_eq!(read.map_err(|e| e.kind()).unwrap_err(), io::ErrorKind::InvalidData, "{path:?}");
+            assert!(matches!(reader.next_entry(), Err(ApiError::ParseError(_))), "{path:?}");
+            assert!(matches!(read_all(&path), Err(ApiError::ParseError(_))), "{path:?}");
+        }
+        // A compressed stream cut short
+        for len in [gz.len() - 30, gz.len() / 2, 20] {
+            let path = write(&dir, "short.tar.gz", &gz[..len]);
+            assert!(matches!(read_all(&path), Err(ApiError::ParseError(_))), "{len}");
+        }
+        // Cut inside a header
+        let path = write(&dir, "header.tar", &tar[..TAR_BLOCK as usize + 100]);
+        let mut reader = open_reader(&path).unwrap();
+        assert!(reader.next_entry().unwrap().is_some());
+        assert!(matches!(reader.next_entry(), Err(ApiError::ParseError(_))));
+        // A size past anything a file could hold
+        let mut huge = tar::Builder::new(Vec::new());
+        huge.append_pax_extensions([("size", u64::MAX.to_string().as_bytes())]).unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_path("huge.bin").unwrap();
+        header.set_size(0);
+        header.set_cksum();
+        huge.append(&header, io::empty()).unwrap();
+        let path = write(&dir, "huge.tar", &huge.into_inner().unwrap());
+        assert!(matches!(read_all(&path), Err(ApiError::ParseError(_))));
+        // A header whose checksum doesn't match
+        let mut damaged = tar.clone();
+        damaged[TAR_BLOCK as usize * 2 + 10] ^= 1;
+        let path = write(&dir, "damaged.tar", &damaged);
+        assert!(matches!(read_all(&path), Err(ApiError::ParseError(_))));
+    }
+
+    #[test]
+    fn entries_leading_outside_are_refused() {
+        for name in ["../x", "a/../../x", "a/..", "/etc/passwd", "\\x", "C:\\x", "c:x", "a\\..\\..\\x", "", "./"] {
+            assert_eq!(entry_names(name), None, "{name}");
+        }
+        assert_eq!(entry_names("a/./b/"), Some(vec!["a", "b"]));
+        assert_eq!(entry_names("a\\b.txt"), Some(vec!["a", "b.txt"]));
+        assert_eq!(entry_names("..a/b.."), Some(vec!["..a", "b.."]));
+    }
+
+    #[test]
+    fn entries_leading_outside_are_listed_as_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        zip.start_file("../evil.txt", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"x").unwrap();
+        let path = write(&dir, "a.zip", &zip.finish().unwrap().into_inner());
+
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..12].copy_from_slice(b"../../evil.x");
+        header.set_size(1);
+        header.set_cksum();
+        tar.append(&header, &b"x"[..]).unwrap();
+        let tar_path = write(&dir, "a.tar", &tar.into_inner().unwrap());
+
+        for (path, name) in [(path, "../evil.txt"), (tar_path, "../../evil.x")] {
+            let entries = read_all(&path).unwrap();
+            assert_eq!(entries, [(name.to_string(), EntryKind::File, Ok(b"x".to_vec()))]);
+            assert_eq!(entry_names(name), None);
+        }
+    }
+
+    fn extract_request(path: &str, destination: &str, conflict: &str) -> axum::http::Request<Body> {
+        let body = serde_json::json!({ "path": path, "destination": destination, "conflict": conflict });
+        crate::testing::admin(axum::http::Method::POST, "/api/extract")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_file_the_upload_hook_rejects_leaves_nothing_extracted() {
+        let scripts = tempfile::tempdir().unwrap();
+        let app = crate::testing::TestApp::with(|c| c.upload_hook = Some(crate::testing::scanner(scripts.path())));
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, data) in [("keep.txt", "new"), ("docs/a.txt", "fine"), ("docs/deep/b.txt", "EICAR"), ("z.txt", "ok")] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data.as_bytes()).unwrap();
+        }
+        app.write("a.zip", zip.finish().unwrap().into_inner());
+        app.write("keep.txt", "old");
+
+        for destination in ["/", "/out/new"] {
+            let (status, body) = app.json(extract_request("/a.zip", destination, "overwrite")).await;
+            assert_eq!(status, axum::http::StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+            assert_eq!(body["code"], "REJECTED_BY_HOOK");
+        }
+        assert_eq!(app.read("keep.txt"), b"old");
+        let mut names: Vec<_> = std::fs::read_dir(app.root()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["a.zip", "keep.txt"]);
+
+        // Without the signature, everything lands
+        app.write("a.zip", {
+            let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+            zip.start_file("docs/a.txt", SimpleFileOptions::default()).unwrap();
+            zip.write_all(b"fine").unwrap();
+            zip.finish().unwrap().into_inner()
+        });
+        let (status, body) = app.json(extract_request("/a.zip", "/out", "fail")).await;
+        assert_eq!(status, axum::http::StatusCode::OK, "{body}");
+        assert_eq!(body["files"], 1);
+        assert_eq!(app.read("out/docs/a.txt"), b"fine");
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{
+    extract::{Extension, Query, State},
+    Json,
+};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::sync::Mutex;
+use crate::error::ApiError;
+use crate::handlers::safe_path;
+use crate::i18n::Msg;
+use crate::jsonfile::JsonFile;
+use crate::models::*;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Longest accepted favorite label, in characters
+const MAX_LABEL_CHARS: usize = 200;
+
+/// Favorite folders and files per user, persisted as JSON in the data directory
+///
+/// Each user's list is kept in display order. The lock is held across every
+/// load-modify-save cycle, so concurrent changes can't overwrite each other.
+pub struct FavoriteStore {
+    file: JsonFile,
+    favorites: Mutex<HashMap<String, Vec<FavoriteRecord>>>,
+}
+
+impl FavoriteStore {
+    /// Load the store from `<data_dir>/favorites.json`, starting empty if it doesn't exist
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let file = JsonFile::new(data_dir, "favorites.json", "favorites store");
+        let favorites = file.load()?;
+        Ok(Self {
+            file,
+            favorites: Mutex::new(favorites),
+        })
+    }
+
+    /// Apply `change` to a copy of a user's list and persist it, keeping the old list if
+    /// either fails
+    async fn update<T>(
+        &self,
+        username: &str,
+        change: impl FnOnce(&mut Vec<FavoriteRecord>) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let mut favorites = self.favorites.lock().await;
+        let previous = favorites.get(username).cloned().unwrap_or_default();
+        let mut list = previous.clone();
+        let result = change(&mut list)?;
+        set_list(&mut favorites, username, list);
+        if let Err(e) = self.file.save(&*favorites).await {
+            set_list(&mut favorites, username, previous);
+            return Err(e);
+        }
+        Ok(result)
+    }
+}
+
+/// Replace a user's list, leaving no entry for an empty one
+fn set_list(favorites: &mut HashMap<String, Vec<FavoriteRecord>>, username: &str, list: Vec<FavoriteRecord>) {
+    if list.is_empty() {
+        favorites.remove(username);
+    } else {
+        favorites.insert(username.to_string(), list);
+    }
+}
+
+/// A favorite as listed, checking that its target is still there
+fn favorite_info(state: &AppState, user: &AuthUser, position: usize, record: &FavoriteRecord) -> FavoriteInfo {
+    let exists = user.can_see(&record.path)
+        && safe_path(&state.mounts, &record.path).is_ok_and(|p| p.actual.exists());
+    FavoriteInfo {
+        path: record.path.clone(),
+        label: record.label.clone(),
+        position,
+        exists,
+        created_at: record.created_at,
+    }
+}
+
+/// The user's favorites in their chosen order
+///
+/// Favorites whose target was removed or became inaccessible are kept and listed with
+/// `exists: false`, so the user can decide what to do with them.
+#[utoipa::path(
+    get, path = "/api/favorites", tag = "files",
+    responses((status = 200, description = "Favorites in display order", body = ApiResponse<FavoritesResponse>)),
+)]
+pub async fn list_favorites(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> Json<ApiResponse<FavoritesResponse>> {
+    let favorites = state.favorites.favorites.lock().await;
+    let list = favorites
+        .get(&user.username)
+        .map(|list| {
+            list.iter()
+                .enumerate()
+                .map(|(position, record)| favorite_info(&state, &user, position, record))
+                .collect()
+        })
+        .unwrap_or_default();
+    Json(ApiResponse::success(FavoritesResponse { favorites: list }))
+}
+
+/// Add a favorite, or change the label or position of an existing one
+///
+/// Without a `position` a new favorite goes last and an existing one stays where it is;
+/// positions past the end mean last.
+#[utoipa::path(
+    post, path = "/api/favorites", tag = "files", request_body = AddFavoriteRequest,
+    responses((status = 200, description = "The stored favorite", body = ApiResponse<FavoriteInfo>)),
+)]
+pub async fn add_favorite(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<AddFavoriteRequest>,
+) -> Result<Json<ApiResponse<FavoriteInfo>>, ApiError> {
+    let paths = safe_path(&state.mounts, &req.path)?;
+    let path = state.mounts.logical_path(&paths.logical);
+    user.check_visible(&path).map_err(ApiError::AccessDenied)?;
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    let label = req.label.as_deref().map(str::trim).filter(|l| !l.is_empty());
+    if label.is_some_and(|l| l.chars().count() > MAX_LABEL_CHARS) {
+        return Err(ApiError::BadRequest(Msg::LabelTooLong.with(&[&MAX_LABEL_CHARS])));
+    }
+
+    let (position, record) = state
+        .favorites
+        .update(&user.username, |list| {
+            let existing = list.iter().position(|f| f.path == path);
+            let mut record = match existing {
+                Some(index) => list.remove(index),
+                None => FavoriteRecord {
+                    path: path.clone(),
+                    label: String::new(),
+                    created_at: Utc::now().timestamp(),
+                },
+            };
+            record.label = match label {
+                Some(label) => label.to_string(),
+                None if record.label.is_empty() => default_label(&path),
+                None => record.label,
+            };
+            let position = req.position.or(existing).unwrap_or(list.len()).min(list.len());
+            list.insert(position, record.clone());
+            Ok((position, record))
+        })
+        .await?;
+    Ok(Json(ApiResponse::success(favorite_info(&state, &user, position, &record))))
+}
+
+/// The last component of a path, or `/` for the root
+fn default_label(path: &str) -> String {
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => "/".to_string(),
+    }
+}
+
+/// Remove a favorite by path
+#[utoipa::path(
+    delete, path = "/api/favorites", tag = "files", params(FavoriteQuery),
+    responses((status = 200, description = "Favorite removed", body = ApiResponse<OperationResponse>)),
+)]
+pub async fn remove_favorite(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<FavoriteQuery>,
+) -> Result<Json<ApiResponse<OperationResponse>>, ApiError> {
+    // Match on the stored form, which may be gone from disk and so can't go through safe_path
+    let path = format!("/{}", query.path.trim_matches('/'));
+    state
+        .favorites
+        .update(&user.username, |list| {
+            let index = list
+                .iter()
+                .position(|f| f.path == path)
+                .ok_or_else(|| ApiError::NotFound(Msg::FavoriteNotFound.into()))?;
+            list.remove(index);
+            Ok(())
+        })
+        .await?;
+    Ok(Json(ApiResponse::success(OperationResponse {
+        message: Msg::FavoriteRemoved.into(),
+        new_path: None,
+    })))
+}
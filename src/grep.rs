@@ -0,0 +1,243 @@
+use axum::{
+    extract::{Extension, Query, State},
+    Json,
+};
+use futures::{stream, StreamExt};
+use regex::{Regex, RegexBuilder};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use crate::error::ApiError;
+use crate::handlers::SearchBudget;
+use crate::i18n::Msg;
+use crate::jobs::report_roots;
+use crate::models::{ApiResponse, ContentMatch, ContentSearchFile, ContentSearchQuery, ContentSearchResponse};
+use crate::paths::is_internal_artifact;
+use crate::tail::looks_binary;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Files one search reads and searches at a time
+const SCAN_CONCURRENCY: usize = 4;
+/// Matching lines given per file
+const MAX_MATCHES_PER_FILE: usize = 20;
+/// Characters of a long line kept around a match
+const SNIPPET_CHARS: usize = 200;
+/// Of those, how many come before the match
+const SNIPPET_BEFORE: usize = 60;
+/// Files with matches one search returns
+pub(crate) const MAX_RESULTS: usize = 100;
+/// Largest compiled regular expression, so a pathological one is refused instead of built
+pub(crate) const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// A file the walk found to search
+struct Candidate {
+    path: String,
+    actual: PathBuf,
+    size: u64,
+}
+
+/// `line` cut to `SNIPPET_CHARS` characters from a little before `start`, with `…` where
+/// it was cut
+fn snippet(line: &str, start: usize) -> String {
+    if line.chars().count() <= SNIPPET_CHARS {
+        return line.to_string();
+    }
+    let from = line[..start].char_indices().rev().take(SNIPPET_BEFORE).last().map_or(start, |(i, _)| i);
+    let to = line[from..].char_indices().nth(SNIPPET_CHARS).map_or(line.len(), |(i, _)| from + i);
+    let before = if from > 0 { "…" } else { "" };
+    let after = if to < line.len() { "…" } else { "" };
+    format!("{}{}{}", before, &line[from..to], after)
+}
+
+/// The lines of a file `regex` matches, and whether there were more than
+/// `MAX_MATCHES_PER_FILE`; `None` for a binary file or one that can't be read
+///
+/// Lines are matched one at a time, so a match never spans lines. Text that isn't UTF-8
+/// is matched as far as it reads as UTF-8.
+fn scan(actual: &Path, max_bytes: u64, regex: &Regex) -> Option<(Vec<ContentMatch>, bool)> {
+    let mut bytes = Vec::new();
+    // The file may have grown since the walk saw its size
+    std::fs::File::open(actual).ok()?.take(max_bytes).read_to_end(&mut bytes).ok()?;
+    if looks_binary(&bytes) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    let mut matches = Vec::new();
+    if !regex.is_match(&text) {
+        return Some((matches, false));
+    }
+    for (index, line) in text.lines().enumerate() {
+        let Some(found) = regex.find(line) else { continue };
+        if matches.len() == MAX_MATCHES_PER_FILE {
+            return Some((matches, true));
+        }
+        matches.push(ContentMatch {
+            line: index as u64 + 1,
+            column: line[..found.start()].chars().count() as u64 + 1,
+            text: snippet(line, found.start()),
+        });
+    }
+    Some((matches, false))
+}
+
+/// The walk behind one content search, and what it found
+struct Search<'a> {
+    state: &'a AppState,
+    user: &'a AuthUser,
+    regex: Regex,
+    budget: SearchBudget,
+    /// The resolved folders being walked, so a symlink back up the tree isn't followed forever
+    ancestors: Vec<PathBuf>,
+    results: Vec<ContentSearchFile>,
+    files_scanned: u64,
+    files_skipped: u64,
+}
+
+impl Search<'_> {
+    /// Whether to stop: enough files found, the time is up or the server is stopping
+    fn done(&mut self) -> bool {
+        self.results.len() >= MAX_RESULTS || self.budget.exhausted()
+    }
+
+    /// Search the files of a folder, then its subfolders
+    ///
+    /// Like `/api/search`, paths follow the logical chain and skip what the user can't
+    /// see; files are also left out where they couldn't be downloaded, protected ones
+    /// included.
+    async fn folder(&mut self, logical: &Path, actual: &Path) {
+        if self.done() {
+            return;
+        }
+        let resolved = fs::canonicalize(actual).await.unwrap_or_else(|_| actual.to_path_buf());
+        if self.ancestors.contains(&resolved) {
+            return;
+        }
+        let Ok(mut entries) = fs::read_dir(actual).await else { return };
+        self.ancestors.push(resolved);
+        self.budget.dirs_scanned += 1;
+
+        let (state, user) = (self.state, self.user);
+        let mut files = Vec::new();
+        let mut folders = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if self.budget.exhausted() {
+                break;
+            }
+            let entry_logical = logical.join(entry.file_name());
+            let path = state.mounts.logical_path(&entry_logical);
+            if state.hidden.matches(&path)
+                || is_internal_artifact(&path, &entry.path())
+                || state.mounts.refuses_link(&entry.path())
+            {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(entry.path()).await else { continue };
+            if metadata.is_dir() {
+                if user.can_see(&path) {
+                    folders.push((entry_logical, entry.path()));
+                }
+                continue;
+            }
+            if user.check(&path).is_err() || state.protected.matches(&path) {
+                continue;
+            }
+            if metadata.len() > state.content_search_max_bytes {
+                self.files_skipped += 1;
+                continue;
+            }
+            files.push(Candidate { path, actual: entry.path(), size: metadata.len() });
+        }
+        self.files(files).await;
+        for (logical, actual) in folders {
+            if self.done() {
+                break;
+            }
+            Box::pin(self.folder(&logical, &actual)).await;
+        }
+        self.ancestors.pop();
+    }
+
+    /// Search files `SCAN_CONCURRENCY` at a time, keeping their order in the results
+    async fn files(&mut self, files: Vec<Candidate>) {
+        let (regex, max_bytes) = (self.regex.clone(), self.state.content_search_max_bytes);
+        let mut scans = stream::iter(files)
+            .map(|file| {
+                let (actual, regex) = (file.actual.clone(), regex.clone());
+                async move {
+                    let found = tokio::task::spawn_blocking(move || scan(&actual, max_bytes, &regex)).await;
+                    (file, found.ok().flatten())
+                }
+            })
+            .buffered(SCAN_CONCURRENCY);
+        while let Some((file, found)) = scans.next().await {
+            let Some((matches, more_matches)) = found else {
+                self.files_skipped += 1;
+                continue;
+            };
+            self.files_scanned += 1;
+            if !matches.is_empty() {
+                self.results.push(ContentSearchFile { path: file.path, size: file.size, matches, more_matches });
+            }
+            if self.done() {
+                break;
+            }
+        }
+    }
+}
+
+/// Search inside text files for a string or regular expression
+///
+/// Walks from `path` as `/api/search` does, within the same `--search-jobs` slots and
+/// time limits, and reads files a few at a time. Binary files (a NUL in the first 8000
+/// bytes) and files over `--content-search-max-bytes` are skipped and counted in
+/// `filesSkipped`. Each file with a match gives its first 20 matching lines, with line
+/// numbers and the line cut around the match; at most 100 files are returned. Case is
+/// ignored unless `case_sensitive=true`.
+#[utoipa::path(
+    get, path = "/api/search/content", tag = "files", params(ContentSearchQuery),
+    responses(
+        (status = 200, description = "Files with matching lines. A search that ran out of `timeout_secs` returns what it found with `timedOut: true`", body = ApiResponse<ContentSearchResponse>),
+        (status = 400, description = "An empty `query`, or an invalid regular expression", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn search_content(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ContentSearchQuery>,
+) -> Result<Json<ApiResponse<ContentSearchResponse>>, ApiError> {
+    if query.query.is_empty() {
+        return Err(ApiError::BadRequest(Msg::EmptySearchText.into()));
+    }
+    let pattern = if query.regex { query.query.clone() } else { regex::escape(&query.query) };
+    let regex = RegexBuilder::new(&pattern)
+        .case_insensitive(!query.case_sensitive)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| ApiError::BadRequest(Msg::InvalidSearchRegex.with(&[&e])))?;
+    let (base, roots) = report_roots(&state, &user, query.path.as_deref().unwrap_or("/"))?;
+
+    let mut search = Search {
+        state: &state,
+        user: &user,
+        regex,
+        budget: SearchBudget::new(&state, base, query.timeout_secs),
+        ancestors: Vec::new(),
+        results: Vec::new(),
+        files_scanned: 0,
+        files_skipped: 0,
+    };
+    if let Some(_permit) = search.budget.permit(&state.search_permits).await {
+        for (logical, actual) in &roots {
+            search.folder(logical, actual).await;
+        }
+    }
+    search.budget.finished = true;
+
+    Ok(Json(ApiResponse::success(ContentSearchResponse {
+        results: search.results,
+        timed_out: search.budget.timed_out,
+        files_scanned: search.files_scanned,
+        files_skipped: search.files_skipped,
+    })))
+}
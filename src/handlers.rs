@@ -1,21 +1,50 @@
 use axum::{
     body::Body,
-    extract::{Multipart, Query, State},
-    http::{header, StatusCode},
+    extract::{multipart::Field, ConnectInfo, Extension, Multipart, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use chrono::{DateTime, Local};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Semaphore, SemaphorePermit};
 use tokio_util::io::ReaderStream;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
+use futures::{stream, StreamExt, TryStreamExt};
+use crate::activity::Activity;
+use crate::audit::AuditEntry;
+use crate::category::FileCategory;
+use crate::disks;
+use crate::error::ApiError;
+use crate::hashes::{content_tags, file_sha256};
+use crate::hooks;
+use crate::i18n::Msg;
+use crate::jobs::WalkProgress;
+use crate::logging::record_path;
 use crate::models::*;
+use crate::mounts::Mounts;
+use crate::paths::{
+    check_no_prefix, existing_name, is_internal_artifact, natural_cmp, search_key, upload_temp_dir, NameMatcher,
+    NameRules, INTERNAL_FILE_PREFIX, PATH_SEPARATORS,
+};
+use crate::patterns::PathPatterns;
+use crate::render;
+use crate::users::AuthUser;
 use crate::AppState;
 // ========== 辅助函数 ==========
 /// 格式化文件大小
-fn format_size(bytes: u64) -> String {
+pub(crate) fn format_size(bytes: u64) -> String {
     const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
     if bytes == 0 {
         return "0 B".to_string();
@@ -25,28 +54,58 @@ fn format_size(bytes: u64) -> String {
     let i = i.min(UNITS.len() - 1);
     format!("{:.2} {}", bytes as f64 / k.powi(i as i32), UNITS[i])
 }
+/// Parse a human-readable size such as "500MB", "10GB" or "1024" (bytes)
+pub(crate) fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("Invalid size: {:?}", text))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        "T" | "TB" | "TIB" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size unit: {:?}", text)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
 /// Result of safe_path containing both logical and actual paths
-struct SafePathResult {
+pub(crate) struct SafePathResult {
+    /// Root directory of the mount the path belongs to
+    pub(crate) root: PathBuf,
     /// The logical path (as user requested, relative to root)
-    logical: PathBuf,
+    pub(crate) logical: PathBuf,
     /// The actual path on disk (symlinks resolved)
-    actual: PathBuf,
+    pub(crate) actual: PathBuf,
+}
+
+impl SafePathResult {
+    /// Whether the path is the root of its mount
+    pub(crate) fn is_root(&self) -> bool {
+        self.logical == self.root
+    }
 }
 
 /// Safe path check to prevent path traversal attacks
-fn safe_path(root: &Path, user_path: &str) -> Result<SafePathResult, String> {
-    // Normalize user path: remove leading slashes and handle .. components
-    let normalized = user_path.trim_start_matches('/');
+/// The first path segment selects the mount when several are configured.
+pub(crate) fn safe_path(mounts: &Mounts, user_path: &str) -> Result<SafePathResult, ApiError> {
+    check_no_prefix(user_path)?;
+    let (mount, rest) = mounts.select(user_path)?;
+    let root = mount.root.as_path();
+
+    // Normalize user path: remove leading separators and handle .. components
+    let normalized = rest.trim_start_matches(PATH_SEPARATORS);
     
     // Build path without following symlinks first for security check
     let mut logical_path = root.to_path_buf();
-    for component in normalized.split('/') {
+    for component in normalized.split(PATH_SEPARATORS) {
         match component {
             "" | "." => continue,
             ".." => {
                 // Don't allow going above root
                 if logical_path == root {
-                    return Err("Access denied: Invalid path".to_string());
+                    return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
                 }
                 logical_path.pop();
             }
@@ -58,7 +117,16 @@ fn safe_path(root: &Path, user_path: &str) -> Result<SafePathResult, String> {
     
     // Verify logical path is under root
     if !logical_path.starts_with(root) {
-        return Err("Access denied: Invalid path".to_string());
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    }
+
+    // With --no-follow-symlinks, no refused link may appear anywhere along the way
+    let mut walked = root.to_path_buf();
+    for component in logical_path.strip_prefix(root).unwrap_or(Path::new("")).components() {
+        walked.push(component);
+        if mounts.refuses_link(&walked) {
+            return Err(ApiError::AccessDenied(Msg::SymlinkNotFollowed.with(&[&mounts.logical_path(&walked)])));
+        }
     }
     
     // Now get the actual path (following symlinks) for file operations
@@ -70,12 +138,13 @@ fn safe_path(root: &Path, user_path: &str) -> Result<SafePathResult, String> {
     };
     
     Ok(SafePathResult {
+        root: root.to_path_buf(),
         logical: logical_path,
         actual: actual_path,
     })
 }
 /// 获取相对路径
-fn relative_path(root: &Path, full_path: &Path) -> String {
+pub(crate) fn relative_path(root: &Path, full_path: &Path) -> String {
     match full_path.strip_prefix(root) {
         Ok(rel) => {
             let rel_str = rel.to_string_lossy().replace('\\', "/");
@@ -88,449 +157,1750 @@ fn relative_path(root: &Path, full_path: &Path) -> String {
         Err(_) => "/".to_string(),
     }
 }
+/// Format of the display strings `modified` / `created`
+const DISPLAY_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
 /// 格式化时间
-fn format_time(time: std::time::SystemTime) -> String {
-    let datetime: DateTime<Local> = time.into();
-    datetime.format("%Y-%m-%d %H:%M").to_string()
+/// Returns the display string, in `tz` or the server's local time, and the RFC 3339 UTC
+/// form. A time the platform doesn't record (e.g. `created()` on some Linux filesystems)
+/// is "-" and `None`.
+fn format_time(time: std::io::Result<SystemTime>, tz: Option<Tz>) -> (String, Option<String>) {
+    let Ok(time) = time else {
+        return ("-".to_string(), None);
+    };
+    let utc: DateTime<Utc> = time.into();
+    let display = match tz {
+        Some(tz) => utc.with_timezone(&tz).format(DISPLAY_TIME_FORMAT).to_string(),
+        None => utc.with_timezone(&Local).format(DISPLAY_TIME_FORMAT).to_string(),
+    };
+    (display, Some(utc.to_rfc3339_opts(SecondsFormat::Millis, true)))
+}
+/// Parse the `tz` query parameter (an IANA name such as `Asia/Shanghai`)
+fn parse_tz(tz: Option<&str>) -> Result<Option<Tz>, ApiError> {
+    tz.map(|name| {
+        name.parse::<Tz>()
+            .map_err(|_| ApiError::BadRequest(Msg::InvalidTimezone.with(&[&name])))
+    })
+    .transpose()
 }
 /// 获取文件信息
-async fn get_file_info(root: &Path, path: &Path) -> Result<FileInfo, String> {
-    let metadata = fs::metadata(path)
+/// `logical` is the path reported to the client, `actual` the file whose metadata is read
+pub(crate) async fn get_file_info(
+    mounts: &Mounts,
+    logical: &Path,
+    actual: &Path,
+    tz: Option<Tz>,
+) -> Result<FileInfo, String> {
+    // A link --no-follow-symlinks refuses is described as the link itself
+    let mut metadata = fs::symlink_metadata(actual)
         .await
-        .map_err(|e| format!("Failed to get metadata: {}", e))?;
+        .map_err(|e| format!("{}: {}", Msg::MetadataFailed, e))?;
+    let symlink = metadata.file_type().is_symlink();
+    if symlink && !mounts.refuses_link(actual) {
+        metadata = fs::metadata(actual)
+            .await
+            .map_err(|e| format!("{}: {}", Msg::MetadataFailed, e))?;
+    }
 
-    let name = path
+    let name = logical
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_default();
 
     let file_type = if metadata.is_dir() { "folder" } else { "file" }.to_string();
     let size = metadata.len();
-
-    let modified = metadata
-        .modified()
-        .map(format_time)
-        .unwrap_or_else(|_| "-".to_string());
-
-    let created = metadata
-        .created()
-        .map(format_time)
-        .unwrap_or_else(|_| "-".to_string());
+    let (modified, modified_at) = format_time(metadata.modified(), tz);
+    let (created, created_at) = format_time(metadata.created(), tz);
+    let category = (!metadata.is_dir()).then(|| FileCategory::of(&name));
 
     Ok(FileInfo {
         name,
-        path: relative_path(root, path),
+        path: mounts.logical_path(logical),
         file_type,
         size,
         size_formatted: format_size(size),
         modified,
         created,
+        modified_at,
+        created_at,
+        category,
+        dir_size: None,
+        dir_size_is_estimate: None,
+        tags: None,
+        symlink: symlink.then_some(true),
     })
 }
 
-/// Get file info using a logical base path for consistent path reporting
-/// This is used when listing directory contents where the directory may be a symlink
-async fn get_file_info_with_logical_base(root: &Path, logical_dir: &Path, actual_file: &Path) -> Result<FileInfo, String> {
-    let metadata = fs::metadata(actual_file)
-        .await
-        .map_err(|e| format!("Failed to get metadata: {}", e))?;
+/// Apply the `--hide` / `--protect` patterns to a logical path, returning the refusal if any
+/// Hidden paths behave as if they don't exist; protected paths are refused when `protect` is set.
+pub(crate) fn check_patterns(state: &AppState, logical: &str, protect: bool) -> Result<(), ApiError> {
+    if state.hidden.matches(logical) {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    if protect && state.protected.matches(logical) {
+        return Err(ApiError::AccessDenied(Msg::ProtectedPath.with(&[&logical])));
+    }
+    Ok(())
+}
 
-    let name = actual_file
-        .file_name()
-        .map(|n| n.to_string_lossy().to_string())
-        .unwrap_or_default();
+/// Refuse filest's own temporary files, which the file operations leave alone
+pub(crate) fn check_not_internal(logical: &str, actual: &Path) -> Result<(), ApiError> {
+    if is_internal_artifact(logical, actual) {
+        return Err(ApiError::AccessDenied(Msg::InternalFile.with(&[&logical])));
+    }
+    Ok(())
+}
 
-    let file_type = if metadata.is_dir() { "folder" } else { "file" }.to_string();
-    let size = metadata.len();
+/// Like `check_patterns` with `protect`, but also refuses folders containing hidden or protected entries
+pub(crate) async fn check_patterns_tree(state: &AppState, logical: &Path, actual: &Path) -> Result<(), ApiError> {
+    let rel = state.mounts.logical_path(logical);
+    check_patterns(state, &rel, true)?;
+    if actual.is_dir() && tree_has_restricted(state, logical, actual).await {
+        return Err(ApiError::AccessDenied(Msg::FolderContainsProtected.with(&[&rel])));
+    }
+    Ok(())
+}
 
-    let modified = metadata
-        .modified()
-        .map(format_time)
-        .unwrap_or_else(|_| "-".to_string());
+/// Whether any entry below the directory matches the hidden or protected patterns
+async fn tree_has_restricted(state: &AppState, logical_dir: &Path, actual_dir: &Path) -> bool {
+    if state.hidden.is_empty() && state.protected.is_empty() {
+        return false;
+    }
+    if let Ok(mut entries) = fs::read_dir(actual_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let logical = logical_dir.join(entry.file_name());
+            let rel = state.mounts.logical_path(&logical);
+            if state.hidden.matches(&rel) || state.protected.matches(&rel) {
+                return true;
+            }
+            // Don't follow symlinked directories
+            if entry.file_type().await.is_ok_and(|t| t.is_dir())
+                && Box::pin(tree_has_restricted(state, &logical, &entry.path())).await
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
 
-    let created = metadata
-        .created()
-        .map(format_time)
-        .unwrap_or_else(|_| "-".to_string());
+/// Forget cached folder sizes and reports covering a path changed through filest, and
+/// queue it for the search index
+pub(crate) async fn sizes_changed(state: &AppState, actual: &Path) {
+    state.dir_sizes.invalidate(actual).await;
+    state.reports.invalidate(actual).await;
+    if let Some(index) = &state.index {
+        index.changed(&state.mounts, actual);
+    }
+}
 
-    // Build the logical path by combining logical_dir with the file name
-    let logical_file_path = logical_dir.join(&name);
-    
-    Ok(FileInfo {
-        name,
-        path: relative_path(root, &logical_file_path),
-        file_type,
-        size,
-        size_formatted: format_size(size),
-        modified,
-        created,
-    })
+/// Totals gathered while walking a directory tree
+#[derive(Default)]
+pub(crate) struct TreeStats {
+    pub size: u64,
+    pub entries: u64,
+    /// The walk stopped early because a cap was exceeded
+    pub truncated: bool,
+}
+
+/// Where a tree walk gives up, leaving `TreeStats::truncated` set
+#[derive(Clone, Copy)]
+pub(crate) struct WalkCaps {
+    pub max_bytes: u64,
+    pub max_entries: u64,
+    pub deadline: Option<Instant>,
+    /// Levels counted: 1 is the directory's own entries; deeper entries are skipped
+    /// without setting `truncated`
+    pub max_depth: u32,
+}
+
+impl WalkCaps {
+    /// Walk the whole tree
+    pub const NONE: Self = Self { max_bytes: u64::MAX, max_entries: u64::MAX, deadline: None, max_depth: u32::MAX };
 }
 
-/// 递归获取目录大小
-async fn get_dir_size(path: &Path) -> u64 {
-    let mut size = 0u64;
+/// Sum sizes and count entries below a directory, skipping hidden paths
+/// Stops as soon as one of `caps` is exceeded.
+pub(crate) async fn tree_stats(
+    state: &AppState,
+    logical: &Path,
+    path: &Path,
+    caps: WalkCaps,
+    stats: &mut TreeStats,
+) {
+    walk_tree(state, logical, path, caps, stats, &mut |_, _| {}).await;
+}
 
+/// Walk a directory tree like `tree_stats`, calling `visit` with the logical path and
+/// metadata of every entry counted
+pub(crate) async fn walk_tree(
+    state: &AppState,
+    logical: &Path,
+    path: &Path,
+    caps: WalkCaps,
+    stats: &mut TreeStats,
+    visit: &mut (dyn FnMut(&Path, &std::fs::Metadata) + Send),
+) {
     if let Ok(mut entries) = fs::read_dir(path).await {
         while let Ok(Some(entry)) = entries.next_entry().await {
             let entry_path = entry.path();
+            let entry_logical = logical.join(entry.file_name());
+            let rel = state.mounts.logical_path(&entry_logical);
+            if state.hidden.matches(&rel) || is_internal_artifact(&rel, &entry_path) || state.mounts.refuses_link(&entry_path) {
+                continue;
+            }
             if let Ok(metadata) = fs::metadata(&entry_path).await {
+                stats.entries += 1;
+                visit(&entry_logical, &metadata);
                 if metadata.is_dir() {
-                    size += Box::pin(get_dir_size(&entry_path)).await;
+                    if caps.max_depth > 1 {
+                        let caps = WalkCaps { max_depth: caps.max_depth - 1, ..caps };
+                        Box::pin(walk_tree(state, &entry_logical, &entry_path, caps, stats, visit)).await;
+                    }
                 } else {
-                    size += metadata.len();
+                    stats.size += metadata.len();
                 }
             }
+            if stats.size > caps.max_bytes
+                || stats.entries > caps.max_entries
+                || caps.deadline.is_some_and(|d| Instant::now() >= d)
+            {
+                stats.truncated = true;
+            }
+            if stats.truncated {
+                return;
+            }
         }
     }
-
-    size
 }
-/// 递归复制目录
-async fn copy_dir(src: &Path, dest: &Path) -> Result<(), String> {
-    fs::create_dir_all(dest)
+/// Give a copy the modification time and, on unix, the permissions of its source
+async fn keep_metadata(dest: &Path, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+    #[cfg(unix)]
+    fs::set_permissions(dest, metadata.permissions()).await?;
+    let mtime = filetime::FileTime::from_last_modification_time(metadata);
+    let dest = dest.to_path_buf();
+    tokio::task::spawn_blocking(move || filetime::set_file_mtime(dest, mtime))
         .await
-        .map_err(|e| format!("Failed to create directory: {}", e))?;
+        .map_err(std::io::Error::other)?
+}
 
-    let mut entries = fs::read_dir(src)
+/// Latest client-supplied modification time accepted, ahead of the server's clock
+const MAX_MTIME_AHEAD: chrono::Duration = chrono::Duration::days(1);
+
+/// Give an uploaded file the modification time its client sent, in epoch milliseconds
+///
+/// An unusable time (before 1970, more than a day ahead, or refused by the filesystem) is
+/// ignored with a warning rather than failing the upload. Returns the file's resulting
+/// `modifiedAt`, so clients can see whether the time took, and the warning if any.
+pub(crate) async fn apply_client_mtime(path: &Path, millis: Option<i64>) -> (Option<String>, Option<String>) {
+    let warning = match millis {
+        None => None,
+        Some(ms) if ms < 0 || ms > (Utc::now() + MAX_MTIME_AHEAD).timestamp_millis() => {
+            Some(Msg::LastModifiedIgnored.with(&[&ms]))
+        }
+        Some(ms) => {
+            let mtime = filetime::FileTime::from_unix_time(ms / 1000, (ms % 1000) as u32 * 1_000_000);
+            let target = path.to_path_buf();
+            match tokio::task::spawn_blocking(move || filetime::set_file_mtime(target, mtime)).await {
+                Ok(Ok(())) => None,
+                _ => Some(Msg::LastModifiedIgnored.with(&[&ms])),
+            }
+        }
+    };
+    let modified_at = match fs::metadata(path).await {
+        Ok(metadata) => format_time(metadata.modified(), None).1,
+        Err(_) => None,
+    };
+    (modified_at, warning)
+}
+
+/// Copy a file along with its modification time and permissions
+pub(crate) async fn copy_file_keeping_metadata(src: &Path, dest: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(src).await?;
+    fs::copy(src, dest).await?;
+    keep_metadata(dest, &metadata).await
+}
+
+/// Whether a symlink at `link` pointing to `target` resolves below `root`, judged from
+/// the path alone since the target may not have been copied yet
+fn link_within(link: &Path, target: &Path, root: &Path) -> bool {
+    let mut resolved = link.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in target.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            std::path::Component::CurDir => {}
+            // Root and prefix components replace the path, as in `PathBuf::push`
+            component => resolved.push(component),
+        }
+    }
+    resolved.starts_with(root)
+}
+
+/// An entry left out of a copy, by its path below the copied folder (`.` for the folder itself)
+fn skipped_entry(rel: &Path, error: std::io::Error) -> SkippedEntry {
+    let path = rel.to_string_lossy().replace('\\', "/");
+    SkippedEntry {
+        path: if path.is_empty() { ".".to_string() } else { path },
+        reason: error.to_string(),
+    }
+}
+
+/// Stops a copy whose job was cancelled before its next entry
+fn check_cancelled(progress: Option<&WalkProgress>) -> std::io::Result<()> {
+    match progress {
+        Some(progress) if progress.is_cancelled() => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, Msg::JobCancelled.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Note an entry left out of a copy, counting it as an error in `progress`
+fn skip(skipped: &mut Vec<SkippedEntry>, progress: Option<&WalkProgress>, rel: &Path, error: std::io::Error) {
+    // Stopping for a cancel isn't a failure of the entry
+    if let Some(progress) = progress
+        && !progress.is_cancelled()
+    {
+        progress.error();
+    }
+    skipped.push(skipped_entry(rel, error));
+}
+
+#[cfg(unix)]
+async fn symlink(target: &Path, link: &Path, _is_dir: bool) -> std::io::Result<()> {
+    fs::symlink(target, link).await
+}
+
+#[cfg(windows)]
+async fn symlink(target: &Path, link: &Path, is_dir: bool) -> std::io::Result<()> {
+    if is_dir {
+        fs::symlink_dir(target, link).await
+    } else {
+        fs::symlink_file(target, link).await
+    }
+}
+
+/// Copy one entry of a folder being copied, adding what it couldn't copy below it to `skipped`
+async fn copy_entry(
+    src: &Path,
+    dest: &Path,
+    rel: &Path,
+    links_within: Option<&Path>,
+    skipped: &mut Vec<SkippedEntry>,
+    progress: Option<&WalkProgress>,
+) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(src).await?;
+    if let Some(progress) = progress {
+        progress.set_current(rel);
+        progress.add(1, if metadata.is_file() { metadata.len() } else { 0 });
+    }
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(src).await?;
+        if let Some(root) = links_within
+            && !link_within(dest, &target, root)
+        {
+            return Err(std::io::Error::other(Msg::SymlinkOutsideRoot.to_string()));
+        }
+        let is_dir = fs::metadata(src).await.is_ok_and(|m| m.is_dir());
+        return symlink(&target, dest, is_dir).await;
+    }
+    if !metadata.is_dir() {
+        fs::copy(src, dest).await?;
+        return keep_metadata(dest, &metadata).await;
+    }
+
+    fs::create_dir(dest).await?;
+    let mut entries = fs::read_dir(src).await?;
+    loop {
+        check_cancelled(progress)?;
+        let entry = match entries.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(e) => {
+                skip(skipped, progress, rel, e);
+                break;
+            }
+        };
+        let entry_rel = rel.join(entry.file_name());
+        if let Err(e) = Box::pin(copy_entry(
+            &entry.path(),
+            &dest.join(entry.file_name()),
+            &entry_rel,
+            links_within,
+            skipped,
+            progress,
+        ))
         .await
-        .map_err(|e| format!("Failed to read directory: {}", e))?;
+        {
+            skip(skipped, progress, &entry_rel, e);
+        }
+    }
+    // Last, since writing the entries changed it
+    keep_metadata(dest, &metadata).await
+}
+
+/// 递归复制目录
+/// Files keep their modification time and, on unix, their permissions. Symlinks are
+/// recreated as symlinks rather than followed; with `links_within`, ones resolving outside
+/// that folder are left out. Entries that can't be copied don't stop the copy: they are
+/// returned by their path below `src`. Only failing to create `dest` itself is an error.
+/// Entries and bytes copied, and entries left out, are counted in `progress`; once its
+/// job is cancelled the copy stops with `Interrupted`, leaving what it copied for the
+/// caller to remove.
+pub(crate) async fn copy_dir(
+    src: &Path,
+    dest: &Path,
+    links_within: Option<&Path>,
+    progress: Option<&WalkProgress>,
+) -> std::io::Result<Vec<SkippedEntry>> {
+    let mut skipped = Vec::new();
+    let mut entries = fs::read_dir(src).await?;
+    let metadata = fs::metadata(src).await?;
+    fs::create_dir_all(dest).await?;
+    loop {
+        check_cancelled(progress)?;
+        let entry = match entries.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(e) => {
+                skip(&mut skipped, progress, Path::new(""), e);
+                break;
+            }
+        };
+        let rel = PathBuf::from(entry.file_name());
+        let dest = dest.join(entry.file_name());
+        if let Err(e) = copy_entry(&entry.path(), &dest, &rel, links_within, &mut skipped, progress).await {
+            skip(&mut skipped, progress, &rel, e);
+        }
+    }
+    if let Err(e) = keep_metadata(dest, &metadata).await {
+        skip(&mut skipped, progress, Path::new(""), e);
+    }
+    Ok(skipped)
+}
+/// Entries of the virtual root: one folder per mount the user can see
+pub(crate) async fn mount_entries(state: &AppState, user: &AuthUser, tz: Option<Tz>) -> Vec<FileInfo> {
+    let mut entries = Vec::new();
+    for mount in state.mounts.list() {
+        let logical = format!("/{}", mount.name);
+        if !user.can_see(&logical) || state.hidden.matches(&logical) {
+            continue;
+        }
+        if let Ok(mut info) = get_file_info(&state.mounts, &mount.root, &mount.root, tz).await {
+            info.name = mount.name.clone();
+            entries.push(info);
+        }
+    }
+    entries
+}
+/// Most failed paths listed by a delete
+pub(crate) const MAX_DELETE_FAILURES: usize = 50;
+/// Attempts at removing an entry that is briefly in use
+const REMOVE_ATTEMPTS: u32 = 3;
+/// Most paths in one `/api/delete/batch`
+const MAX_BATCH_DELETE: usize = 1000;
 
-    while let Ok(Some(entry)) = entries.next_entry().await {
-        let src_path = entry.path();
-        let dest_path = dest.join(entry.file_name());
+/// What a recursive delete removed and what it left behind
+#[derive(Default)]
+pub(crate) struct RemoveOutcome {
+    pub removed: u64,
+    pub failed: u64,
+    /// The first `MAX_DELETE_FAILURES` failures by actual path
+    pub failures: Vec<(PathBuf, std::io::Error)>,
+    /// Stopped early by the cancellation token
+    pub cancelled: bool,
+    /// Counts removed entries and their bytes, and failures, as they happen
+    pub progress: Option<Arc<WalkProgress>>,
+}
 
-        if src_path.is_dir() {
-            Box::pin(copy_dir(&src_path, &dest_path)).await?;
-        } else {
-            fs::copy(&src_path, &dest_path)
-                .await
-                .map_err(|e| format!("Failed to copy file: {}", e))?;
+impl RemoveOutcome {
+    fn fail(&mut self, path: &Path, error: std::io::Error) {
+        self.failed += 1;
+        if let Some(progress) = &self.progress {
+            progress.error();
+        }
+        if self.failures.len() < MAX_DELETE_FAILURES {
+            self.failures.push((path.to_path_buf(), error));
         }
     }
+}
 
-    Ok(())
+/// Errors from another process holding the entry open for a moment
+fn is_transient(e: &std::io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    e.kind() == std::io::ErrorKind::ResourceBusy || (cfg!(windows) && matches!(e.raw_os_error(), Some(32 | 33)))
+}
+
+/// Remove a file or an empty folder, retrying while it is in use
+async fn remove_entry(path: &Path, is_dir: bool) -> std::io::Result<()> {
+    // Windows refuses to remove read-only files
+    #[cfg(windows)]
+    if let Ok(metadata) = fs::symlink_metadata(path).await
+        && metadata.permissions().readonly()
+    {
+        let mut permissions = metadata.permissions();
+        permissions.set_readonly(false);
+        let _ = fs::set_permissions(path, permissions).await;
+    }
+    let mut attempt = 1;
+    loop {
+        let result = if is_dir { fs::remove_dir(path).await } else { fs::remove_file(path).await };
+        match result {
+            Err(e) if attempt < REMOVE_ATTEMPTS && is_transient(&e) => {
+                tokio::time::sleep(std::time::Duration::from_millis(100 * u64::from(attempt))).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
 }
-// ========== API 处理函数 ==========
-/// 获取目录内容
-pub async fn get_files(
-    State(state): State<AppState>,
-    Query(query): Query<PathQuery>,
-) -> impl IntoResponse {
-    let user_path = query.path.unwrap_or_else(|| "/".to_string());
 
-    let paths = match safe_path(&state.root_dir, &user_path) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
+/// Delete a file or a folder tree bottom-up, going on past entries that can't be removed
+///
+/// Unlike `remove_dir_all`, one locked or read-only file doesn't hide what else was left:
+/// every failure is counted, and a folder is only removed once everything in it is.
+/// Symlinks are removed, never followed. Stops between entries once `cancel` fires.
+pub(crate) async fn remove_tree(path: &Path, cancel: &CancellationToken, outcome: &mut RemoveOutcome) {
+    let metadata = match fs::symlink_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) => return outcome.fail(path, e),
     };
+    let is_dir = metadata.is_dir();
+    if is_dir {
+        let failed = outcome.failed;
+        let mut entries = match fs::read_dir(path).await {
+            Ok(entries) => entries,
+            Err(e) => return outcome.fail(path, e),
+        };
+        loop {
+            if cancel.is_cancelled() {
+                outcome.cancelled = true;
+                return;
+            }
+            match entries.next_entry().await {
+                Ok(Some(entry)) => Box::pin(remove_tree(&entry.path(), cancel, outcome)).await,
+                Ok(None) => break,
+                Err(e) => return outcome.fail(path, e),
+            }
+        }
+        // What is left inside was already reported
+        if outcome.failed > failed || outcome.cancelled {
+            return;
+        }
+    }
+    match remove_entry(path, is_dir).await {
+        Ok(()) => {
+            outcome.removed += 1;
+            if let Some(progress) = &outcome.progress {
+                progress.add(1, if metadata.is_file() { metadata.len() } else { 0 });
+            }
+        }
+        Err(e) => outcome.fail(path, e),
+    }
+}
 
-    if !paths.actual.exists() {
-        return Json(ApiResponse::<()>::error("目录不存在")).into_response();
+/// Whether `dir` is `folder` or somewhere below it
+///
+/// Both paths are resolved first, so a destination reached through a symlink into the
+/// folder counts. On unix the ancestors of `dir` are also compared to `folder` by device
+/// and inode, which catches spellings that resolve differently, such as `/Data` and
+/// `/data` on a case-insensitive filesystem.
+pub(crate) async fn is_within_folder(dir: &Path, folder: &Path) -> bool {
+    let dir = fs::canonicalize(dir).await.unwrap_or_else(|_| dir.to_path_buf());
+    let folder = fs::canonicalize(folder).await.unwrap_or_else(|_| folder.to_path_buf());
+    if dir.starts_with(&folder) {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let Ok(target) = fs::metadata(&folder).await else { return false };
+        for ancestor in dir.ancestors() {
+            if let Ok(metadata) = fs::metadata(ancestor).await
+                && metadata.dev() == target.dev()
+                && metadata.ino() == target.ino()
+            {
+                return true;
+            }
+        }
     }
+    false
+}
 
-    if !paths.actual.is_dir() {
-        return Json(ApiResponse::<()>::error("不是有效的目录")).into_response();
+/// 移动文件或目录
+/// Falls back to copy + delete when the destination is on another filesystem
+/// (e.g. a different mount), where a plain rename fails; that copy is counted in `progress`.
+pub(crate) async fn move_path(src: &Path, dest: &Path, progress: Option<&WalkProgress>) -> std::io::Result<()> {
+    match fs::rename(src, dest).await {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() != std::io::ErrorKind::CrossesDevices => return Err(e),
+        Err(_) => {}
     }
 
+    // A rename keeps every entry as it is, so anything left out fails the move
+    let is_dir = src.is_dir();
+    let copied = if is_dir {
+        copy_dir(src, dest, None, progress).await.and_then(|skipped| match skipped.first() {
+            Some(entry) => Err(std::io::Error::other(format!("{}: {}", entry.path, entry.reason))),
+            None => Ok(()),
+        })
+    } else {
+        copy_file_keeping_metadata(src, dest).await
+    };
+    if let Err(e) = copied {
+        // Keep the source and drop the partial copy
+        let _ = if is_dir {
+            fs::remove_dir_all(dest).await
+        } else {
+            fs::remove_file(dest).await
+        };
+        return Err(e);
+    }
+
+    let removed = if is_dir {
+        fs::remove_dir_all(src).await
+    } else {
+        fs::remove_file(src).await
+    };
+    removed.map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", Msg::RemoveSourceFailed, e)))
+}
+/// Entries of a folder the user can see, for listings
+pub(crate) async fn dir_entries(
+    state: &AppState,
+    user: &AuthUser,
+    paths: &SafePathResult,
+    tz: Option<Tz>,
+) -> Result<Vec<FileInfo>, ApiError> {
     let mut files = Vec::new();
 
     match fs::read_dir(&paths.actual).await {
         Ok(mut entries) => {
             while let Ok(Some(entry)) = entries.next_entry().await {
                 // Use logical path for file info to maintain consistent paths
-                if let Ok(info) = get_file_info_with_logical_base(&state.root_dir, &paths.logical, &entry.path()).await {
-                    files.push(info);
+                let logical_file = paths.logical.join(entry.file_name());
+                if let Ok(mut info) = get_file_info(&state.mounts, &logical_file, &entry.path(), tz).await {
+                    // Omit entries the user can't access rather than leaking their names
+                    if user.can_see(&info.path)
+                        && !state.hidden.matches(&info.path)
+                        && !is_internal_artifact(&info.path, &entry.path())
+                    {
+                        // Folder sizes are only known once `/api/info` has computed them
+                        if info.file_type == "folder"
+                            && let Some(dir) = state.dir_sizes.cached(&entry.path()).await
+                        {
+                            info.dir_size = Some(dir.size);
+                            info.dir_size_is_estimate = Some(dir.estimate);
+                        }
+                        files.push(info);
+                    }
                 }
             }
         }
-        Err(e) => return Json(ApiResponse::<()>::error(format!("读取目录失败: {}", e))).into_response(),
+        Err(e) => return Err(ApiError::io(Msg::ReadDirFailed, e)),
+    }
+    Ok(files)
+}
+
+/// Sort a listing by `sort` in `order`, folders first either way
+///
+/// Entries that tie, such as files of one size, are in name order.
+fn sort_entries(files: &mut [FileInfo], sort: ListingSort, order: SortOrder) {
+    // A folder's own size is the filesystem's, not what it holds
+    let size = |file: &FileInfo| if file.file_type == "folder" { file.dir_size.unwrap_or(0) } else { file.size };
+    let extension = |file: &FileInfo| {
+        Path::new(&file.name).extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default()
+    };
+    files.sort_by(|a, b| {
+        let by = match sort {
+            ListingSort::Name => Ordering::Equal,
+            ListingSort::Size => size(a).cmp(&size(b)),
+            // RFC 3339 in UTC, so the text sorts as the time
+            ListingSort::Modified => a.modified_at.cmp(&b.modified_at),
+            ListingSort::Type => extension(a).cmp(&extension(b)),
+        };
+        let by = by.then_with(|| natural_cmp(&a.name, &b.name));
+        let by = if order == SortOrder::Desc { by.reverse() } else { by };
+        (b.file_type == "folder").cmp(&(a.file_type == "folder")).then(by)
+    });
+}
+// ========== API 处理函数 ==========
+/// 获取目录内容
+#[utoipa::path(
+    get, path = "/api/files", tag = "files", params(MetadataQuery),
+    responses((
+        status = 200,
+        description = "Folder listing; the root lists the mounts. `Accept: text/plain` or `text/csv` (or `format=`) gives a table instead",
+        content(
+            (ApiResponse<FilesResponse> = "application/json"),
+            (String = "text/plain"),
+            (String = "text/csv"),
+        ),
+    ), (
+        status = 304,
+        description = "The listing still matches the `ETag` sent in `If-None-Match`",
+    )),
+)]
+pub async fn get_files(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<MetadataQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let response = list_files(&state, &user, query, &headers).await?;
+    Ok(with_listing_etag(&headers, response).await)
+}
+
+async fn list_files(
+    state: &AppState,
+    user: &AuthUser,
+    query: MetadataQuery,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    let user_path = query.path.unwrap_or_else(|| "/".to_string());
+    let tz = parse_tz(query.tz.as_deref())?;
+    let format = render::negotiate(query.format, headers);
+    let sort = query.sort.or(query.order.map(|_| ListingSort::Name));
+    let order = query.order.unwrap_or(SortOrder::Asc);
+
+    // With named mounts the root lists the mounts themselves
+    if state.mounts.is_virtual_root(&user_path) {
+        let mut files = mount_entries(state, user, tz).await;
+        if let Some(sort) = sort {
+            sort_entries(&mut files, sort, order);
+        }
+        if let Some(response) = render::listing(format, &files, false) {
+            return Ok(response);
+        }
+        return Ok(Json(ApiResponse::success(FilesResponse {
+            path: "/".to_string(),
+            files,
+        })).into_response());
+    }
+
+    let paths = safe_path(&state.mounts, &user_path)?;
+
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &logical, false)?;
+    check_not_internal(&logical, &paths.actual)?;
+
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::DirNotFound.into()));
     }
 
+    if !paths.actual.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotADirectory.into()));
+    }
+
+    let mut files = dir_entries(state, user, &paths, tz).await?;
+    if let Some(sort) = sort {
+        sort_entries(&mut files, sort, order);
+    }
+    if query.include_meta {
+        state.meta.fill_tags(&mut files).await;
+    }
+    if let Some(response) = render::listing(format, &files, false) {
+        return Ok(response);
+    }
     // Return the logical path, not the actual (resolved) path
-    Json(ApiResponse::success(FilesResponse {
-        path: relative_path(&state.root_dir, &paths.logical),
+    Ok(Json(ApiResponse::success(FilesResponse {
+        path: state.mounts.logical_path(&paths.logical),
         files,
-    })).into_response()
+    })).into_response())
 }
 /// 创建文件夹
+/// A `name` of several `/`-separated names creates the missing folders of the chain,
+/// each name checked like a single one; a trailing `/` is ignored. Folders that already
+/// exist are reused, so only a chain that exists in full is a conflict.
+#[utoipa::path(
+    post, path = "/api/folder", tag = "files", request_body = CreateFolderRequest,
+    responses((
+        status = 200,
+        description = "Folder created, `newPath` is its logical path and `created` lists the new folders",
+        body = ApiResponse<CreateFolderResponse>,
+    )),
+)]
 pub async fn create_folder(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(req): Json<CreateFolderRequest>,
-) -> impl IntoResponse {
-    let parent = match safe_path(&state.root_dir, &req.path) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
-
-    let folder_path_actual = parent.actual.join(&req.name);
-    let folder_path_logical = parent.logical.join(&req.name);
+) -> Result<Json<ApiResponse<CreateFolderResponse>>, ApiError> {
+    let parent = safe_path(&state.mounts, &req.path)?;
+    let names = folder_names(&state.name_rules, &req.name)?;
 
-    if folder_path_actual.exists() {
-        return Json(ApiResponse::<()>::error("文件夹已存在")).into_response();
+    // Follow the folders that exist, by their stored names, then add the rest
+    let mut folder_path_actual = parent.actual.clone();
+    let mut folder_path_logical = parent.logical.clone();
+    let mut created = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        let stored = if created.is_empty() { existing_name(&folder_path_actual, name).await } else { None };
+        let is_new = stored.is_none();
+        let name = stored.unwrap_or_else(|| name.into());
+        folder_path_actual.push(&name);
+        folder_path_logical.push(&name);
+        // Before telling existing folders apart, so a refusal doesn't reveal them.
+        // Folders kept on the way to the new ones only need to be traversable.
+        let logical = state.mounts.logical_path(&folder_path_logical);
+        let passed = !is_new && i + 1 < names.len();
+        if passed {
+            user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+        } else {
+            user.check(&logical).map_err(ApiError::AccessDenied)?;
+        }
+        check_patterns(&state, &logical, !passed)?;
+        check_not_internal(&logical, &folder_path_actual)?;
+        if !is_new {
+            // A file in the way
+            if !folder_path_actual.is_dir() {
+                return Err(ApiError::AlreadyExists(Msg::NameExists.into()));
+            }
+            continue;
+        }
+        state.locks.check(&logical, false, &user, &headers)?;
+        created.push(logical);
+    }
+    if created.is_empty() {
+        return Err(ApiError::AlreadyExists(Msg::FolderExists.into()));
     }
 
-    match fs::create_dir_all(&folder_path_actual).await {
-        Ok(_) => Json(ApiResponse::success(OperationResponse {
-            message: "文件夹创建成功".to_string(),
-            new_path: Some(relative_path(&state.root_dir, &folder_path_logical)),
-        })).into_response(),
-        Err(e) => Json(ApiResponse::<()>::error(format!("创建失败: {}", e))).into_response(),
+    let logical = state.mounts.logical_path(&folder_path_logical);
+    let result = fs::create_dir_all(&folder_path_actual).await;
+    state.audit.record(AuditEntry::new("create_folder", &user.username, addr.ip(), &logical).result(&result));
+    sizes_changed(&state, &folder_path_actual).await;
+
+    result.map_err(|e| ApiError::io(Msg::CreateFailed, e))?;
+    Ok(Json(ApiResponse::success(CreateFolderResponse {
+        message: Msg::FolderCreated.into(),
+        new_path: logical,
+        created,
+    })))
+}
+/// The prepared names of the folders in `a/b/c`, a trailing slash allowed
+fn folder_names(rules: &NameRules, name: &str) -> Result<Vec<String>, ApiError> {
+    let name = name.strip_suffix('/').unwrap_or(name);
+    name.split('/').map(|name| rules.prepare(name)).collect()
+}
+
+/// 创建空文件
+/// Creates an empty file named `name` in the folder `path`, for instance to start a new
+/// text file in the editor. An existing file or folder of that name (up to Unicode
+/// normalization) is a conflict, never truncated. `lastModified` works as for uploads.
+#[utoipa::path(
+    post, path = "/api/file", tag = "files", request_body = CreateFileRequest,
+    responses(
+        (status = 200, description = "File created, `newPath` is its logical path", body = ApiResponse<CreateFileResponse>),
+        (status = 409, description = "`ALREADY_EXISTS`: the name is taken", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn create_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<CreateFileRequest>,
+) -> Result<Json<ApiResponse<CreateFileResponse>>, ApiError> {
+    let parent = safe_path(&state.mounts, &req.path)?;
+    let name = state.name_rules.prepare(&req.name)?;
+    if !parent.actual.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotADirectory.into()));
     }
+    if existing_name(&parent.actual, &name).await.is_some() {
+        return Err(ApiError::AlreadyExists(Msg::NameExists.into()));
+    }
+    let file_path_actual = parent.actual.join(&name);
+    let logical = state.mounts.logical_path(&parent.logical.join(&name));
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &file_path_actual)?;
+    state.locks.check(&logical, false, &user, &headers)?;
+
+    // `create_new` so a file that appeared since the check is left alone
+    let result = fs::OpenOptions::new().write(true).create_new(true).open(&file_path_actual).await.map(drop);
+    state.audit.record(AuditEntry::new("create_file", &user.username, addr.ip(), &logical).result(&result));
+    result.map_err(|e| ApiError::io(Msg::CreateFailed, e))?;
+    let (modified_at, warning) = apply_client_mtime(&file_path_actual, req.last_modified).await;
+    sizes_changed(&state, &file_path_actual).await;
+    Ok(Json(ApiResponse::success(CreateFileResponse {
+        message: Msg::FileCreated.into(),
+        new_path: logical,
+        modified_at,
+        warning,
+    })))
 }
 /// 上传文件 (streaming)
 /// Uses chunk() to stream file content, avoiding loading entire file into memory
+///
+/// The target folder is the `path` query parameter, or else a `path` field sent before
+/// the files; multipart parts need not arrive in any particular order, so files without
+/// a known folder are refused rather than stored at the root.
+///
+/// `If-None-Match` may list the SHA-256 of files being sent (`"<sha256>", ...`). A file
+/// whose stored counterpart hashes to one of them, and whose received content does too,
+/// is left alone and reported with `skipped: true`; anything else is stored as usual.
+#[utoipa::path(
+    post, path = "/api/upload", tag = "upload", params(UploadQuery),
+    request_body(content = crate::openapi::UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Stored files", body = ApiResponse<UploadResponse>),
+        (status = 413, description = "Over `maxRequestBody`", body = crate::openapi::ErrorBody),
+        (status = 429, description = "`TOO_MANY_UPLOADS`: no upload slot freed up in time", body = crate::openapi::ErrorBody),
+    ),
+)]
 pub async fn upload_files(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> impl IntoResponse {
-    let mut upload_path_actual = state.root_dir.clone();
-    let mut upload_path_logical = state.root_dir.clone();
+) -> Result<Json<ApiResponse<UploadResponse>>, ApiError> {
+    let from_query = query.path.is_some();
+    let skip_tags = content_tags(&headers);
+    let mut upload_dir = query.path.map(|p| safe_path(&state.mounts, &p)).transpose()?;
+    let _slot = state.upload_slots.stream().await?;
     let mut uploaded_files = Vec::new();
+    let idle = state.upload_limits.idle_timeout;
+    // Set by a `lastModified` field, for the file right after it
+    let mut last_modified: Option<String> = None;
+    let mut space_checked = false;
 
-    while let Ok(Some(mut field)) = multipart.next_field().await {
+    loop {
+        let Ok(next) = tokio::time::timeout(idle, multipart.next_field()).await else {
+            return Err(crate::limits::idle_timeout(idle));
+        };
+        let mut field = match next {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            // The body limit can also be hit between files, after earlier ones were stored
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return Err(crate::limits::too_large(state.upload_limits.max_request_body));
+            }
+            Err(e) => return Err(ApiError::BadRequest(format!("{}: {}", Msg::ReadUploadFailed, e))),
+        };
         let name = field.name().unwrap_or("").to_string();
 
         if name == "path" {
+            // `?path=` wins; a field only counts when it leads the files
+            if from_query {
+                continue;
+            }
+            if !uploaded_files.is_empty() {
+                return Err(ApiError::BadRequest(Msg::UploadPathAfterFiles.into()));
+            }
             if let Ok(path_str) = field.text().await {
-                let paths = match safe_path(&state.root_dir, &path_str) {
-                    Ok(p) => p,
-                    Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-                };
-                upload_path_actual = paths.actual;
-                upload_path_logical = paths.logical;
+                upload_dir = Some(safe_path(&state.mounts, &path_str)?);
             }
             continue;
         }
 
+        if name == "lastModified" {
+            last_modified = Some(field.text().await.unwrap_or_default());
+            continue;
+        }
+
         if name == "files" {
             let filename = field
                 .file_name()
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| "unknown".to_string());
+            let filename = state.name_rules.prepare(&filename)?;
 
-            // Ensure upload directory exists
-            if let Err(e) = fs::create_dir_all(&upload_path_actual).await {
-                return Json(ApiResponse::<()>::error(format!("创建目录失败: {}", e))).into_response();
+            let Some(dir) = &upload_dir else {
+                return Err(ApiError::BadRequest(Msg::NoUploadDir.into()));
+            };
+            // Overwrite the stored file rather than adding a look-alike with another normalization
+            let filename = match existing_name(&dir.actual, &filename).await {
+                Some(stored) => stored.to_string_lossy().into_owned(),
+                None => filename,
+            };
+
+            // The request's length covers every file in it, so it's checked once, up front
+            if !space_checked {
+                space_checked = true;
+                if let Some(length) = crate::limits::declared_length(&headers) {
+                    disks::ensure_space(&dir.actual, length, state.min_free_space).await?;
+                }
             }
 
-            let file_path_actual = upload_path_actual.join(&filename);
-            let file_path_logical = upload_path_logical.join(&filename);
+            let file_path_actual = dir.actual.join(&filename);
+            let file_path_logical = dir.logical.join(&filename);
 
-            // Create file for streaming write
-            let mut file = match fs::File::create(&file_path_actual).await {
-                Ok(f) => f,
-                Err(e) => {
-                    return Json(ApiResponse::<()>::error(format!("创建文件失败: {}", e))).into_response();
-                }
+            let logical = state.mounts.logical_path(&file_path_logical);
+            user.check(&logical).map_err(ApiError::AccessDenied)?;
+            check_patterns(&state, &logical, true)?;
+            check_not_internal(&logical, &file_path_actual)?;
+            state.locks.check(&logical, false, &user, &headers)?;
+
+            // Ensure upload directory exists
+            fs::create_dir_all(&dir.actual)
+                .await
+                .map_err(|e| ApiError::io(Msg::CreateDirFailed, e))?;
+
+            let (millis, mut warning) = match last_modified.take().map(|v| v.trim().parse::<i64>().map_err(|_| v)) {
+                Some(Ok(millis)) => (Some(millis), None),
+                Some(Err(v)) => (None, Some(Msg::LastModifiedIgnored.with(&[&v.trim()]))),
+                None => (None, None),
             };
 
-            // Stream chunks to file - read and write in small chunks
-            // This keeps memory usage constant regardless of file size
-            let mut total_size: u64 = 0;
-            loop {
-                match field.chunk().await {
-                    Ok(Some(chunk)) => {
-                        total_size += chunk.len() as u64;
-                        if let Err(e) = file.write_all(&chunk).await {
-                            // Clean up partial file on error
-                            let _ = fs::remove_file(&file_path_actual).await;
-                            return Json(ApiResponse::<()>::error(format!("写入文件失败: {}", e))).into_response();
-                        }
-                    }
-                    Ok(None) => {
-                        // End of field data
-                        break;
-                    }
-                    Err(e) => {
-                        // Clean up partial file on error
-                        let _ = fs::remove_file(&file_path_actual).await;
-                        return Json(ApiResponse::<()>::error(format!("读取上传数据失败: {}", e))).into_response();
+            // A file that may be unchanged is received aside and only moved in if it differs;
+            // with an upload hook every file is, and only moved in once the hook passes it
+            let stored = tagged_hash(&state, &file_path_actual, &skip_tags).await;
+            let aside = stored.is_some() || state.upload_hook.is_some();
+            let write_path = if aside {
+                dir.actual.join(format!("{}upload_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()))
+            } else {
+                file_path_actual.clone()
+            };
+            let _staged = aside.then(|| TempPath::file(&write_path));
+
+            // Create file for streaming write
+            let file = fs::File::create(&write_path)
+                .await
+                .map_err(|e| ApiError::io(Msg::CreateFileFailed, e))?;
+
+            let mut result =
+                stream_field_to_file(&mut field, file, &write_path, None, idle, state.min_free_space).await;
+            if let Some(stored) = &stored
+                && let Ok(size) = result
+                && file_sha256(&write_path).await.is_ok_and(|sha256| &sha256 == stored)
+            {
+                // A skipped file is left alone, its modification time included
+                let (modified_at, _) = apply_client_mtime(&file_path_actual, None).await;
+                uploaded_files.push(UploadedFile { name: filename, size, path: logical, skipped: true, modified_at, warning });
+                continue;
+            }
+            if aside && let Ok(size) = result {
+                result = match hooks::check_upload(&state, &write_path, &logical, &user.username, addr.ip()).await {
+                    Ok(hook_warning) => {
+                        warning = warning.or(hook_warning);
+                        fs::rename(&write_path, &file_path_actual)
+                            .await
+                            .map(|()| size)
+                            .map_err(|e| ApiError::io(Msg::WriteFileFailed, e))
                     }
-                }
+                    Err(e) => Err(e),
+                };
             }
-
-            // Ensure all data is flushed to disk
-            if let Err(e) = file.sync_all().await {
-                let _ = fs::remove_file(&file_path_actual).await;
-                return Json(ApiResponse::<()>::error(format!("同步文件失败: {}", e))).into_response();
+            let mut entry = AuditEntry::new("upload", &user.username, addr.ip(), &logical).result(&result);
+            if let Ok(size) = result {
+                entry = entry.size(size);
             }
+            state.audit.record(entry);
+            sizes_changed(&state, &file_path_actual).await;
+            let total_size = result?;
+            let (modified_at, mtime_warning) = apply_client_mtime(&file_path_actual, millis).await;
+            warning = warning.or(mtime_warning);
 
             uploaded_files.push(UploadedFile {
                 name: filename,
                 size: total_size,
-                path: relative_path(&state.root_dir, &file_path_logical),
+                path: state.mounts.logical_path(&file_path_logical),
+                skipped: false,
+                modified_at,
+                warning,
             });
         }
     }
 
-    Json(ApiResponse::success(UploadResponse {
+    if let Some(dir) = upload_dir.filter(|_| !uploaded_files.is_empty()) {
+        state.activity.check_free_space(state.disks.clone(), dir.actual, state.mounts.logical_path(&dir.logical));
+    }
+
+    Ok(Json(ApiResponse::success(UploadResponse {
         files: uploaded_files,
-    })).into_response()
+    })))
+}
+/// SHA-256 of the file stored at `path`, when it's one of the `If-None-Match` `tags`
+///
+/// Such an upload may be a copy of what's stored; it's received aside and skipped only if
+/// its own hash turns out the same. A stored file that can't be read counts as different.
+pub(crate) async fn tagged_hash(state: &AppState, path: &Path, tags: &[String]) -> Option<String> {
+    if tags.is_empty() {
+        return None;
+    }
+    let metadata = fs::metadata(path).await.ok().filter(|m| m.is_file())?;
+    let sha256 = state.hashes.sha256(path, &metadata).await.ok()?;
+    tags.contains(&sha256).then_some(sha256)
 }
-/// 下载文件 (streaming)
-/// Uses ReaderStream to stream file content, avoiding loading entire file into memory
-pub async fn download_file(
-    State(state): State<AppState>,
-    Query(query): Query<PathQuery>,
-) -> Response {
-    let user_path = query.path.unwrap_or_default();
 
-    let paths = match safe_path(&state.root_dir, &user_path) {
-        Ok(p) => p,
-        Err(e) => {
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(e))
-                .unwrap();
+/// A file or directory that is removed on drop unless `keep()` is called
+///
+/// Covers the cases where an upload future is dropped mid-way (client disconnect, or
+/// shutdown after the grace period), which skip the explicit error cleanup.
+pub(crate) struct TempPath {
+    path: PathBuf,
+    is_dir: bool,
+    keep: bool,
+}
+
+impl TempPath {
+    pub(crate) fn file(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), is_dir: false, keep: false }
+    }
+
+    pub(crate) fn dir(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), is_dir: true, keep: false }
+    }
+
+    pub(crate) fn keep(mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        let _ = if self.is_dir {
+            std::fs::remove_dir_all(&self.path)
+        } else {
+            std::fs::remove_file(&self.path)
+        };
+    }
+}
+
+/// Stream a multipart field into an open file
+/// Reads and writes in small chunks so memory usage stays constant regardless of file size.
+/// The partial file is removed on error, including when `limit` bytes are exceeded, no
+/// data arrives for `idle` or the disk gets down to `margin` bytes free; a slow upload
+/// that keeps sending is never cut off.
+pub(crate) async fn stream_field_to_file(
+    field: &mut Field<'_>,
+    mut file: fs::File,
+    path: &Path,
+    limit: Option<u64>,
+    idle: std::time::Duration,
+    margin: u64,
+) -> Result<u64, ApiError> {
+    let partial = TempPath::file(path);
+    let mut space = disks::SpaceWatch::new(path, margin);
+    let mut total_size: u64 = 0;
+    let result = loop {
+        let Ok(next) = tokio::time::timeout(idle, field.chunk()).await else {
+            break Err(crate::limits::idle_timeout(idle));
+        };
+        match next {
+            Ok(Some(chunk)) => {
+                total_size += chunk.len() as u64;
+                if limit.is_some_and(|max| total_size > max) {
+                    break Err(ApiError::PayloadTooLarge(Msg::FileTooLarge.into()));
+                }
+                if let Err(e) = file.write_all(&chunk).await {
+                    break Err(ApiError::io(Msg::WriteFileFailed, e));
+                }
+                if let Err(e) = space.wrote(chunk.len() as u64).await {
+                    break Err(e);
+                }
+            }
+            // End of field data; ensure all data is flushed to disk
+            Ok(None) => break file.sync_all().await.map_err(|e| ApiError::io(Msg::SyncFileFailed, e)),
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                break Err(ApiError::PayloadTooLarge(Msg::BodyTooLarge.into()));
+            }
+            Err(e) => break Err(ApiError::BadRequest(format!("{}: {}", Msg::ReadUploadFailed, e))),
         }
     };
 
+    // Dropping the guard cleans up the partial file on error
+    drop(file);
+    result.map(|()| {
+        partial.keep();
+        total_size
+    })
+}
+
+/// 下载文件 (streaming)
+/// Uses ReaderStream to stream file content, avoiding loading entire file into memory.
+/// With `format=zip` or `format=tar.gz` a folder (or file) is sent as an archive built on the fly.
+/// With `disposition=inline` the browser shows the file instead of saving it; HTML, SVG
+/// and XML are then sandboxed so their scripts can't act for the user.
+#[utoipa::path(
+    get, path = "/api/download", tag = "files", params(DownloadQuery),
+    responses(
+        (status = 200, description = "File content; supports `Range` and conditional requests. With `format`, an archive without `Content-Length`", content_type = "application/octet-stream"),
+        (status = 206, description = "Requested byte range", content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified"),
+        (status = 416, description = "Range not satisfiable"),
+    ),
+)]
+pub async fn download_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let user_path = query.path.unwrap_or_default();
+    let rate = crate::throttle::download_rate(state.max_download_rate, query.rate.as_deref())?;
+    if let Some(format) = query.format {
+        let response = crate::archive::archive_response(state, user, &user_path, format).await?;
+        return Ok(crate::throttle::limit(response, rate));
+    }
+
+    let paths = safe_path(&state.mounts, &user_path)?;
+
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+
     if !paths.actual.exists() {
-        return Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::from("文件不存在"))
-            .unwrap();
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
     }
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
 
     if paths.actual.is_dir() {
-        return Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::from("不能下载文件夹"))
-            .unwrap();
+        return Err(ApiError::InvalidPath(Msg::CannotDownloadFolder.into()));
+    }
+
+    let disposition = query.disposition.unwrap_or_default();
+    let mut response = file_response(&paths.actual, &headers, disposition.as_str()).await;
+    if disposition == Disposition::Inline {
+        sandbox_inline(&mut response);
+    }
+    Ok(crate::throttle::limit(response, rate))
+}
+
+/// Content types a browser runs scripts in when it shows them
+const ACTIVE_CONTENT_TYPES: [&str; 5] = ["text/html", "application/xhtml+xml", "image/svg+xml", "text/xml", "application/xml"];
+
+/// Keep a file shown inline from acting as a page of this server
+///
+/// Uploaded HTML or SVG opened from `/api/download` would otherwise run its scripts with
+/// the user's session. The browser may not guess a type other than the one sent, and
+/// types that can carry scripts are sandboxed, which gives them an origin of their own.
+pub(crate) fn sandbox_inline(response: &mut Response) {
+    let headers = response.headers_mut();
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    let active = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| ACTIVE_CONTENT_TYPES.iter().any(|t| v.starts_with(t)));
+    if active {
+        headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+    }
+}
+/// Most parts sent for one request; asking for more gets the whole file
+const MAX_BYTE_RANGES: usize = 32;
+
+/// Parse a `Range: bytes=` header value into inclusive (start, end) pairs
+///
+/// Returns `Err(())` when none of the ranges can be satisfied and `Ok(None)` when the
+/// header is absent or malformed (served as a full body). Unsatisfiable ranges among
+/// others are dropped, and overlapping or adjacent ones merged in file order, so asking
+/// for many small or repeated pieces can't multiply the response; past `MAX_BYTE_RANGES`
+/// parts the whole file is sent instead, as HTTP allows.
+fn parse_range(range: Option<&str>, len: u64) -> Result<Option<Vec<(u64, u64)>>, ()> {
+    let Some(spec) = range.and_then(|r| r.trim().strip_prefix("bytes=")) else {
+        return Ok(None);
+    };
+    let mut ranges = Vec::new();
+    for spec in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((start, end)) = spec.split_once('-') else {
+            return Ok(None);
+        };
+        if let Ok(range) = parse_one_range(start.trim(), end.trim(), len) {
+            ranges.push(range);
+        }
+    }
+    if ranges.is_empty() {
+        return Err(());
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    if merged.len() > MAX_BYTE_RANGES {
+        return Ok(None);
+    }
+    Ok(Some(merged))
+}
+
+/// One `start-end`, `start-` or `-suffix` range, `Err(())` when it can't be satisfied
+fn parse_one_range(start: &str, end: &str, len: u64) -> Result<(u64, u64), ()> {
+    let (start, end) = match (start, end) {
+        // bytes=-N: the last N bytes
+        ("", suffix) => {
+            let n: u64 = suffix.parse().map_err(|_| ())?;
+            if n == 0 || len == 0 {
+                return Err(());
+            }
+            (len.saturating_sub(n), len - 1)
+        }
+        (start, "") => (start.parse().map_err(|_| ())?, len.saturating_sub(1)),
+        (start, end) => {
+            let start: u64 = start.parse().map_err(|_| ())?;
+            let end: u64 = end.parse().map_err(|_| ())?;
+            (start, end.min(len.saturating_sub(1)))
+        }
+    };
+    if start >= len || start > end {
+        return Err(());
+    }
+    Ok((start, end))
+}
+
+/// A `multipart/byteranges` body of the ranges, with its length and boundary
+///
+/// Each part seeks a duplicate of the handle just before it is read, so the parts all
+/// come from the file that was opened for the response.
+fn byteranges_body(file: std::fs::File, ranges: Vec<(u64, u64)>, mime: &str, len: u64) -> (u64, String, Body) {
+    let boundary = Uuid::new_v4().simple().to_string();
+    let parts = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let head = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, mime, start, end, len
+            );
+            (head, start, end - start + 1)
+        })
+        .collect::<Vec<_>>();
+    let tail = format!("\r\n--{}--\r\n", boundary);
+    let body_len = parts.iter().map(|(head, _, n)| head.len() as u64 + n).sum::<u64>() + tail.len() as u64;
+
+    let body = stream::iter(parts)
+        .then(move |(head, start, n)| {
+            let file = file.try_clone();
+            async move {
+                let mut file = fs::File::from_std(file?);
+                file.seek(std::io::SeekFrom::Start(start)).await?;
+                let head = stream::once(async move { Ok(bytes::Bytes::from(head)) });
+                Ok::<_, std::io::Error>(head.chain(ReaderStream::new(file.take(n))))
+            }
+        })
+        .try_flatten()
+        .chain(stream::once(async move { Ok(bytes::Bytes::from(tail)) }));
+    (body_len, boundary, Body::from_stream(body))
+}
+/// `Last-Modified` and similar headers are HTTP dates (RFC 1123)
+pub(crate) const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Validator of a file's content, from its size and modification time
+pub(crate) fn etag(size: u64, modified: Option<DateTime<Utc>>) -> String {
+    format!("\"{:x}-{:x}\"", size, modified.map_or(0, |m| m.timestamp_millis()))
+}
+
+/// Whether `If-None-Match` lists `etag`, or `None` without the header
+fn if_none_match(headers: &HeaderMap, etag: &str) -> Option<bool> {
+    let tags = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok())?;
+    Some(tags.split(',').map(|tag| tag.trim().trim_start_matches("W/")).any(|tag| tag == "*" || tag == etag))
+}
+
+/// Whether a conditional request already has this version: `If-None-Match` when sent,
+/// else `If-Modified-Since` (to the second)
+pub(crate) fn not_modified(headers: &HeaderMap, etag: &str, modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(matches) = if_none_match(headers, etag) {
+        return matches;
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+    matches!((since, modified), (Some(since), Some(modified)) if modified.timestamp() <= since.timestamp())
+}
+
+/// A folder listing tagged with an `ETag`, or an empty 304 when the client already has it
+///
+/// The tag is a hash of the rendered listing and its content type, so it moves whenever an
+/// entry is added, removed, renamed, resized or touched, and two views of one folder (other
+/// sorting, format, time zone or tags) never share one. The listing is still built, but a
+/// polling client that already has it gets no body. `no-cache` makes browsers revalidate
+/// rather than reuse a stale listing.
+async fn with_listing_etag(headers: &HeaderMap, response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return ApiError::Io(e.to_string()).into_response(),
+    };
+    let mut hasher = Sha256::new();
+    if let Some(content_type) = parts.headers.get(header::CONTENT_TYPE) {
+        hasher.update(content_type.as_bytes());
     }
+    hasher.update(&bytes);
+    let etag = format!("\"{}\"", &format!("{:x}", hasher.finalize())[..32]);
+    let cache = [(header::ETAG, etag.clone()), (header::CACHE_CONTROL, "no-cache".to_string())];
+    if if_none_match(headers, &etag) == Some(true) {
+        return (StatusCode::NOT_MODIFIED, cache).into_response();
+    }
+    for (name, value) in cache {
+        if let Ok(value) = value.parse() {
+            parts.headers.insert(name, value);
+        }
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Characters RFC 5987 allows unencoded in an extended parameter value (`attr-char`)
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!').remove(b'#').remove(b'$').remove(b'&').remove(b'+').remove(b'-').remove(b'.')
+    .remove(b'^').remove(b'_').remove(b'`').remove(b'|').remove(b'~');
+
+/// A `Content-Disposition` value naming `filename`
+///
+/// `filename` gets an ASCII stand-in, with other characters, quotes and backslashes as
+/// `_`; names that need it also get `filename*` (RFC 6266 / RFC 5987) with the UTF-8 name
+/// percent-encoded, which browsers prefer.
+pub(crate) fn content_disposition(disposition: &str, filename: &str) -> String {
+    let ascii: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    if ascii == filename {
+        return format!("{}; filename=\"{}\"", disposition, ascii);
+    }
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition,
+        ascii,
+        utf8_percent_encode(filename, ATTR_CHAR)
+    )
+}
 
-    let filename = paths.actual
+/// Stream a file as the response body, honoring byte range requests (several ranges as
+/// `multipart/byteranges`) and conditional requests (`ETag` / `Last-Modified`)
+/// Shared by the download endpoint, public share links, WebDAV and `/raw`
+pub(crate) async fn file_response(path: &Path, headers: &HeaderMap, disposition: &str) -> Response {
+    let filename = path
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "download".to_string());
 
     // Get file metadata for Content-Length header
-    let metadata = match fs::metadata(&paths.actual).await {
+    let metadata = match fs::metadata(path).await {
         Ok(m) => m,
         Err(e) => {
             return Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(format!("获取文件信息失败: {}", e)))
+                .body(Body::from(format!("{}: {}", Msg::MetadataFailed, e)))
+                .unwrap();
+        }
+    };
+    let len = metadata.len();
+    let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+    let etag = etag(len, modified);
+    let last_modified = modified.map(|m| m.format(HTTP_DATE).to_string());
+
+    if not_modified(headers, &etag, modified) {
+        let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED).header(header::ETAG, &etag);
+        if let Some(last_modified) = &last_modified {
+            builder = builder.header(header::LAST_MODIFIED, last_modified);
+        }
+        return builder.body(Body::empty()).unwrap();
+    }
+
+    // A range of another version would mix content; `If-Range` asks for the whole file then
+    let if_range = headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok());
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+        .filter(|_| if_range.is_none_or(|v| v == etag || Some(v) == last_modified.as_deref()));
+    let range = match parse_range(range, len) {
+        Ok(r) => r,
+        Err(()) => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .body(Body::empty())
                 .unwrap();
         }
     };
 
     // Open file for streaming
-    let file = match fs::File::open(&paths.actual).await {
+    let mut file = match fs::File::open(path).await {
         Ok(f) => f,
         Err(e) => {
             return Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Body::from(format!("打开文件失败: {}", e)))
+                .body(Body::from(format!("{}: {}", Msg::OpenFileFailed, e)))
                 .unwrap();
         }
     };
 
-    // Create a stream from the file - this reads in chunks, not all at once
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-
-    let mime = mime_guess::from_path(&paths.actual)
+    let mime = mime_guess::from_path(path)
         .first_or_octet_stream()
         .to_string();
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, mime)
-        .header(header::CONTENT_LENGTH, metadata.len())
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", filename),
-        )
-        .body(body)
-        .unwrap()
+    let mut builder = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag)
+        .header(header::CONTENT_DISPOSITION, content_disposition(disposition, &filename));
+
+    if let Some(last_modified) = &last_modified {
+        builder = builder.header(header::LAST_MODIFIED, last_modified);
+    }
+
+    // Create a stream from the file - this reads in chunks, not all at once
+    let body = match range {
+        Some(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
+                return Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(format!("{}: {}", Msg::ReadFileFailed, e)))
+                    .unwrap();
+            }
+            let part_len = end - start + 1;
+            builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, mime)
+                .header(header::CONTENT_LENGTH, part_len)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len));
+            Body::from_stream(ReaderStream::new(file.take(part_len)))
+        }
+        Some(ranges) => {
+            let (body_len, boundary, body) = byteranges_body(file.into_std().await, ranges, &mime, len);
+            builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, format!("multipart/byteranges; boundary={}", boundary))
+                .header(header::CONTENT_LENGTH, body_len);
+            body
+        }
+        None => {
+            builder = builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, mime)
+                .header(header::CONTENT_LENGTH, len);
+            Body::from_stream(ReaderStream::new(file))
+        }
+    };
+
+    builder.body(body).unwrap()
 }
 /// 重命名
+#[utoipa::path(
+    put, path = "/api/rename", tag = "files", request_body = RenameRequest,
+    responses((status = 200, description = "Renamed, `newPath` is the new logical path", body = ApiResponse<OperationResponse>)),
+)]
 pub async fn rename(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(req): Json<RenameRequest>,
-) -> impl IntoResponse {
-    let old_paths = match safe_path(&state.root_dir, &req.path) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
+) -> Result<Json<ApiResponse<OperationResponse>>, ApiError> {
+    let old_paths = safe_path(&state.mounts, &req.path)?;
+    let new_name = state.name_rules.prepare(&req.new_name)?;
+
+    if old_paths.is_root() {
+        return Err(ApiError::AccessDenied(Msg::CannotRenameRoot.into()));
+    }
 
     if !old_paths.actual.exists() {
-        return Json(ApiResponse::<()>::error("文件不存在")).into_response();
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
     }
 
-    let new_path_actual = old_paths.actual.parent().unwrap().join(&req.new_name);
-    let new_path_logical = old_paths.logical.parent().unwrap().join(&req.new_name);
+    let parent_actual = old_paths.actual.parent().unwrap();
+    let new_path_actual = parent_actual.join(&new_name);
+    let new_path_logical = old_paths.logical.parent().unwrap().join(&new_name);
 
-    if new_path_actual.exists() {
-        return Json(ApiResponse::<()>::error("目标名称已存在")).into_response();
+    for logical in [&old_paths.logical, &new_path_logical] {
+        user.check(&state.mounts.logical_path(logical)).map_err(ApiError::AccessDenied)?;
     }
+    check_patterns_tree(&state, &old_paths.logical, &old_paths.actual).await?;
+    check_patterns(&state, &state.mounts.logical_path(&new_path_logical), true)?;
+    check_not_internal(&state.mounts.logical_path(&old_paths.logical), &old_paths.actual)?;
+    check_not_internal(&state.mounts.logical_path(&new_path_logical), &new_path_actual)?;
+    state.locks.check(&state.mounts.logical_path(&old_paths.logical), true, &user, &headers)?;
+    state.locks.check(&state.mounts.logical_path(&new_path_logical), false, &user, &headers)?;
 
-    match fs::rename(&old_paths.actual, &new_path_actual).await {
-        Ok(_) => Json(ApiResponse::success(OperationResponse {
-            message: "重命名成功".to_string(),
-            new_path: Some(relative_path(&state.root_dir, &new_path_logical)),
-        })).into_response(),
-        Err(e) => Json(ApiResponse::<()>::error(format!("重命名失败: {}", e))).into_response(),
+    // Changing only the normalization of the item's own name is a rename, not a conflict
+    let old_name = old_paths.actual.file_name().unwrap_or_default();
+    if let Some(stored) = existing_name(parent_actual, &new_name).await
+        && (stored != old_name || new_name.as_str() == old_name)
+    {
+        return Err(ApiError::AlreadyExists(Msg::NameExists.into()));
     }
+
+    let result = fs::rename(&old_paths.actual, &new_path_actual).await;
+    state.audit.record(
+        AuditEntry::new("rename", &user.username, addr.ip(), state.mounts.logical_path(&old_paths.logical))
+            .dest(state.mounts.logical_path(&new_path_logical))
+            .result(&result),
+    );
+    sizes_changed(&state, &old_paths.actual).await;
+    sizes_changed(&state, &new_path_actual).await;
+
+    result.map_err(|e| ApiError::io(Msg::RenameFailed, e))?;
+    let (old_logical, new_logical) =
+        (state.mounts.logical_path(&old_paths.logical), state.mounts.logical_path(&new_path_logical));
+    state.meta.moved(&old_logical, &new_logical).await;
+    state.undo.moved(&user.username, UndoOp::Rename, old_logical, new_logical.clone());
+    Ok(Json(ApiResponse::success(OperationResponse {
+        message: Msg::Renamed.into(),
+        new_path: Some(new_logical),
+    })))
+}
+/// A move or copy that passed its checks, ready to run
+pub(crate) struct Transfer {
+    source: SafePathResult,
+    dest_actual: PathBuf,
+    dest_logical: PathBuf,
+    /// Root of the destination's mount, which copied symlinks must stay within
+    dest_root: PathBuf,
 }
-/// 移动文件
-pub async fn move_file(
-    State(state): State<AppState>,
-    Json(req): Json<MoveRequest>,
-) -> impl IntoResponse {
-    let source = match safe_path(&state.root_dir, &req.source) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
 
-    let dest_dir = match safe_path(&state.root_dir, &req.destination) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
+/// Check a move of `req.source` into `req.destination`
+async fn check_move(state: &AppState, user: &AuthUser, headers: &HeaderMap, req: &MoveRequest) -> Result<Transfer, ApiError> {
+    let source = safe_path(&state.mounts, &req.source)?;
+
+    let dest_dir = safe_path(&state.mounts, &req.destination)?;
+
+    if source.is_root() {
+        return Err(ApiError::AccessDenied(Msg::CannotMoveRoot.into()));
+    }
 
     if !source.actual.exists() {
-        return Json(ApiResponse::<()>::error("源文件不存在")).into_response();
+        return Err(ApiError::NotFound(Msg::SourceNotFound.into()));
     }
 
     let filename = source.actual.file_name().unwrap();
     let dest_actual = dest_dir.actual.join(filename);
     let dest_logical = dest_dir.logical.join(filename);
 
-    if dest_actual.exists() {
-        return Json(ApiResponse::<()>::error("目标位置已存在同名文件")).into_response();
+    for logical in [&source.logical, &dest_logical] {
+        user.check(&state.mounts.logical_path(logical)).map_err(ApiError::AccessDenied)?;
+    }
+    check_patterns_tree(state, &source.logical, &source.actual).await?;
+    check_patterns(state, &state.mounts.logical_path(&dest_logical), true)?;
+    check_not_internal(&state.mounts.logical_path(&source.logical), &source.actual)?;
+    check_not_internal(&state.mounts.logical_path(&dest_logical), &dest_actual)?;
+    state.locks.check(&state.mounts.logical_path(&source.logical), true, user, headers)?;
+    state.locks.check(&state.mounts.logical_path(&dest_logical), false, user, headers)?;
+
+    if existing_name(&dest_dir.actual, filename).await.is_some() {
+        return Err(ApiError::AlreadyExists(Msg::DestinationExists.into()));
     }
 
     // 检查是否移动到自身子目录
-    if source.actual.is_dir() && dest_actual.starts_with(&source.actual) {
-        return Json(ApiResponse::<()>::error("不能移动到自身子目录")).into_response();
+    if source.actual.is_dir() && is_within_folder(&dest_dir.actual, &source.actual).await {
+        return Err(ApiError::InvalidDestination(Msg::MoveIntoSelf.into()));
+    }
+    check_transfer_space(state, &source.logical, &source.actual, &dest_dir.actual, true).await?;
+    Ok(Transfer { source, dest_actual, dest_logical, dest_root: dest_dir.root })
+}
+
+/// Carry out a checked move
+async fn run_move(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    transfer: Transfer,
+    progress: Option<&WalkProgress>,
+) -> Result<OperationResponse, ApiError> {
+    let Transfer { source, dest_actual, dest_logical, .. } = transfer;
+    if let Some(progress) = progress {
+        progress.set_total(source_size(state, &source.logical, &source.actual).await);
     }
+    let result = move_path(&source.actual, &dest_actual, progress).await;
+    state.audit.record(
+        AuditEntry::new("move", &user.username, addr.ip(), state.mounts.logical_path(&source.logical))
+            .dest(state.mounts.logical_path(&dest_logical))
+            .result(&result),
+    );
+    sizes_changed(state, &source.actual).await;
+    sizes_changed(state, &dest_actual).await;
 
-    match fs::rename(&source.actual, &dest_actual).await {
-        Ok(_) => Json(ApiResponse::success(OperationResponse {
-            message: "移动成功".to_string(),
-            new_path: Some(relative_path(&state.root_dir, &dest_logical)),
-        })).into_response(),
-        Err(e) => Json(ApiResponse::<()>::error(format!("移动失败: {}", e))).into_response(),
+    // move_path drops the partial copy of a cancelled move across filesystems
+    if result.is_err() && progress.is_some_and(WalkProgress::is_cancelled) {
+        return Err(ApiError::Cancelled(Msg::JobCancelled.into()));
     }
+    result.map_err(|e| ApiError::io(Msg::MoveFailed, e))?;
+    let (source_logical, dest_logical) =
+        (state.mounts.logical_path(&source.logical), state.mounts.logical_path(&dest_logical));
+    state.meta.moved(&source_logical, &dest_logical).await;
+    state.undo.moved(&user.username, UndoOp::Move, source_logical, dest_logical.clone());
+    Ok(OperationResponse {
+        message: Msg::Moved.into(),
+        new_path: Some(dest_logical),
+    })
+}
+
+/// Move one path, as `/api/move` does without `background`
+pub(crate) async fn move_item(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    req: &MoveRequest,
+) -> Result<OperationResponse, ApiError> {
+    let transfer = check_move(state, user, headers, req).await?;
+    run_move(state, user, addr, transfer, None).await
 }
-/// 复制文件
-pub async fn copy_file(
-    State(state): State<AppState>,
-    Json(req): Json<CopyRequest>,
-) -> impl IntoResponse {
-    let source = match safe_path(&state.root_dir, &req.source) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
 
-    let dest_dir = match safe_path(&state.root_dir, &req.destination) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
+/// Start `run` as a background job of `kind` on `path`, answering with its token
+async fn start_job<T, Fut>(
+    state: &AppState,
+    user: &AuthUser,
+    kind: &'static str,
+    path: String,
+    run: impl FnOnce(AppState, Arc<WalkProgress>) -> Fut,
+) -> Response
+where
+    T: serde::Serialize,
+    Fut: std::future::Future<Output = Result<T, ApiError>> + Send + 'static,
+{
+    let work = {
+        let state = state.clone();
+        move |progress| {
+            let run = run(state, progress);
+            async move { crate::jobs::outcome(run.await) }
+        }
     };
+    let job = state.reports.start(&user.username, kind, path, work).await;
+    Json(ApiResponse::success(JobStarted { message: Msg::JobStarted.into(), job })).into_response()
+}
+
+/// 移动文件
+/// With `background: true` the checks are made, then the move runs as a job: the answer
+/// is a `JobStarted` with the token to poll at `/api/jobs/{token}`. Only moves across
+/// filesystems, which copy, take long.
+#[utoipa::path(
+    put, path = "/api/move", tag = "files", request_body = MoveRequest,
+    responses((status = 200, description = "Moved into `destination`, or the job moving it", body = ApiResponse<OperationResponse>)),
+)]
+pub async fn move_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<MoveRequest>,
+) -> Result<Response, ApiError> {
+    let transfer = check_move(&state, &user, &headers, &req).await?;
+    if req.background {
+        let path = state.mounts.logical_path(&transfer.source.logical);
+        let owner = user.clone();
+        return Ok(start_job(&state, &user, "move", path, move |state, progress| async move {
+            run_move(&state, &owner, addr, transfer, Some(&progress)).await
+        })
+        .await);
+    }
+    let response = run_move(&state, &user, addr, transfer, None).await?;
+    Ok(Json(ApiResponse::success(response)).into_response())
+}
+
+/// Check a copy of `req.source` into `req.destination`, picking a free name there
+async fn check_copy(state: &AppState, user: &AuthUser, headers: &HeaderMap, req: &CopyRequest) -> Result<Transfer, ApiError> {
+    let source = safe_path(&state.mounts, &req.source)?;
+
+    let dest_dir = safe_path(&state.mounts, &req.destination)?;
+
+    for logical in [&source.logical, &dest_dir.logical] {
+        user.check(&state.mounts.logical_path(logical)).map_err(ApiError::AccessDenied)?;
+    }
 
     if !source.actual.exists() {
-        return Json(ApiResponse::<()>::error("源文件不存在")).into_response();
+        return Err(ApiError::NotFound(Msg::SourceNotFound.into()));
+    }
+    // The copy would keep finding its own output
+    if source.actual.is_dir() && is_within_folder(&dest_dir.actual, &source.actual).await {
+        return Err(ApiError::InvalidDestination(Msg::CopyIntoSelf.into()));
     }
 
     let filename = source.actual.file_name().unwrap().to_string_lossy().to_string();
@@ -538,99 +1908,477 @@ pub async fn copy_file(
     let stem = source.actual.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
 
     // 处理同名文件
-    let mut dest_actual = dest_dir.actual.join(&filename);
-    let mut dest_logical = dest_dir.logical.join(&filename);
+    let mut name = filename;
     let mut counter = 1;
-    while dest_actual.exists() {
-        let new_name = match &ext {
+    while existing_name(&dest_dir.actual, &name).await.is_some() {
+        name = match &ext {
             Some(e) => format!("{} ({}). {}", stem, counter, e),
             None => format!("{} ({})", stem, counter),
         };
-        dest_actual = dest_dir.actual.join(&new_name);
-        dest_logical = dest_dir.logical.join(&new_name);
         counter += 1;
     }
+    // The generated name may push a long name over the limit
+    state.name_rules.check(&name)?;
+    let dest_actual = dest_dir.actual.join(&name);
+    let dest_logical = dest_dir.logical.join(&name);
+
+    // Copying out of a protected path would bypass the download restriction
+    check_patterns_tree(state, &source.logical, &source.actual).await?;
+    check_patterns(state, &state.mounts.logical_path(&dest_logical), true)?;
+    check_not_internal(&state.mounts.logical_path(&source.logical), &source.actual)?;
+    check_not_internal(&state.mounts.logical_path(&dest_logical), &dest_actual)?;
+    state.locks.check(&state.mounts.logical_path(&dest_logical), false, user, headers)?;
+    check_transfer_space(state, &source.logical, &source.actual, &dest_dir.actual, false).await?;
+    Ok(Transfer { source, dest_actual, dest_logical, dest_root: dest_dir.root })
+}
 
+/// Carry out a checked copy
+async fn run_copy(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    transfer: Transfer,
+    progress: Option<&WalkProgress>,
+) -> Result<CopyResponse, ApiError> {
+    let Transfer { source, dest_actual, dest_logical, dest_root } = transfer;
+    if let Some(progress) = progress {
+        progress.set_total(source_size(state, &source.logical, &source.actual).await);
+    }
+    // Symlinks are recreated only while they stay inside the destination's mount
     let result = if source.actual.is_dir() {
-        copy_dir(&source.actual, &dest_actual).await
+        copy_dir(&source.actual, &dest_actual, Some(&dest_root), progress).await
     } else {
-        fs::copy(&source.actual, &dest_actual)
-            .await
-            .map(|_| ())
-            .map_err(|e| format!("复制失败: {}", e))
+        if let (Some(progress), Some(name)) = (progress, source.actual.file_name()) {
+            progress.set_current(Path::new(name));
+        }
+        let copied = copy_file_keeping_metadata(&source.actual, &dest_actual).await;
+        if let (Some(progress), Ok(())) = (progress, &copied) {
+            progress.add(1, fs::metadata(&dest_actual).await.map_or(0, |m| m.len()));
+        }
+        copied.map(|()| Vec::new())
     };
+    let cancelled = progress.is_some_and(WalkProgress::is_cancelled);
+    if cancelled {
+        // The destination is left as it was before the copy
+        let mut outcome = RemoveOutcome::default();
+        remove_tree(&dest_actual, &CancellationToken::new(), &mut outcome).await;
+    }
+    state.audit.record(
+        AuditEntry::new("copy", &user.username, addr.ip(), state.mounts.logical_path(&source.logical))
+            .dest(state.mounts.logical_path(&dest_logical))
+            .result(&result),
+    );
+    sizes_changed(state, &dest_actual).await;
 
-    match result {
-        Ok(_) => Json(ApiResponse::success(OperationResponse {
-            message: "复制成功".to_string(),
-            new_path: Some(relative_path(&state.root_dir, &dest_logical)),
-        })).into_response(),
-        Err(e) => Json(ApiResponse::<()>::error(e)).into_response(),
+    if cancelled {
+        return Err(ApiError::Cancelled(Msg::JobCancelled.into()));
     }
+    let skipped = result.map_err(|e| ApiError::io(Msg::CopyFailed, e))?;
+    let message = if skipped.is_empty() {
+        Msg::Copied.into()
+    } else {
+        Msg::CopiedWithSkipped.with(&[&skipped.len()])
+    };
+    let new_path = state.mounts.logical_path(&dest_logical);
+    state.undo.copied(&user.username, new_path.clone());
+    Ok(CopyResponse { message, new_path, skipped })
 }
-/// 删除文件/文件夹
-pub async fn delete_file(
+
+/// Copy one path, as `/api/copy` does without `background`
+pub(crate) async fn copy_item(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    req: &CopyRequest,
+) -> Result<CopyResponse, ApiError> {
+    let transfer = check_copy(state, user, headers, req).await?;
+    run_copy(state, user, addr, transfer, None).await
+}
+
+/// 复制文件
+/// With `background: true` the checks are made, then the copy runs as a job: the answer
+/// is a `JobStarted` with the token to poll at `/api/jobs/{token}`, which counts the
+/// entries and bytes copied.
+#[utoipa::path(
+    post, path = "/api/copy", tag = "files", request_body = CopyRequest,
+    responses((
+        status = 200,
+        description = "Copied into `destination`; entries of a folder that couldn't be copied are listed in `skipped`. \
+            With `background: true` a `JobStarted` instead",
+        body = ApiResponse<CopyResponse>,
+    )),
+)]
+pub async fn copy_file(
     State(state): State<AppState>,
-    Json(req): Json<DeleteRequest>,
-) -> impl IntoResponse {
-    let paths = match safe_path(&state.root_dir, &req.path) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<CopyRequest>,
+) -> Result<Response, ApiError> {
+    let transfer = check_copy(&state, &user, &headers, &req).await?;
+    if req.background {
+        let path = state.mounts.logical_path(&transfer.source.logical);
+        let owner = user.clone();
+        return Ok(start_job(&state, &user, "copy", path, move |state, progress| async move {
+            run_copy(&state, &owner, addr, transfer, Some(&progress)).await
+        })
+        .await);
+    }
+    let response = run_copy(&state, &user, addr, transfer, None).await?;
+    Ok(Json(ApiResponse::success(response)).into_response())
+}
+/// How deleting one path went
+enum Deletion {
+    /// A folder over the `--confirm-delete-*` thresholds, left alone without `force`
+    Confirm(String, DeleteConfirmation),
+    Done(DeleteResult),
+    /// Some entries couldn't be removed and remain
+    Partial(DeleteResult),
+}
+
+impl Deletion {
+    /// The body `/api/delete` answers with
+    fn into_body(self) -> serde_json::Value {
+        let body = match self {
+            Deletion::Done(result) => serde_json::to_value(ApiResponse::success(result)),
+            // Not a failure: the client repeats the request with `force`
+            Deletion::Confirm(message, confirmation) => {
+                serde_json::to_value(ApiResponse::error_with_data("CONFIRM_REQUIRED", message, confirmation))
+            }
+            // Neither done nor failed: the client learns what is left
+            Deletion::Partial(result) => {
+                serde_json::to_value(ApiResponse::error_with_data("PARTIAL_FAILURE", result.message.clone(), result))
+            }
+        };
+        body.unwrap_or_default()
+    }
+}
+
+/// Check a delete of `path`, or the confirmation a large folder needs without `force`
+///
+/// `force_internal` must only be passed for the admin.
+async fn check_delete(
+    state: &AppState,
+    user: &AuthUser,
+    headers: &HeaderMap,
+    path: &str,
+    force: bool,
+    force_internal: bool,
+) -> Result<Result<SafePathResult, Deletion>, ApiError> {
+    let paths = safe_path(&state.mounts, path)?;
+
+    if paths.is_root() {
+        return Err(ApiError::AccessDenied(Msg::CannotDeleteRoot.into()));
+    }
+
+    user.check(&state.mounts.logical_path(&paths.logical)).map_err(ApiError::AccessDenied)?;
 
     if !paths.actual.exists() {
-        return Json(ApiResponse::<()>::error("文件不存在")).into_response();
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
     }
 
-    let result = if paths.actual.is_dir() {
-        fs::remove_dir_all(&paths.actual).await
-    } else {
-        fs::remove_file(&paths.actual).await
-    };
+    check_patterns_tree(state, &paths.logical, &paths.actual).await?;
+    // Orphaned temporary files can be cleared by the admin on request
+    if !force_internal {
+        check_not_internal(&state.mounts.logical_path(&paths.logical), &paths.actual)?;
+    }
+    state.locks.check(&state.mounts.logical_path(&paths.logical), true, user, headers)?;
 
-    match result {
-        Ok(_) => Json(ApiResponse::success(OperationResponse {
-            message: "删除成功".to_string(),
-            new_path: None,
-        })).into_response(),
-        Err(e) => Json(ApiResponse::<()>::error(format!("删除失败: {}", e))).into_response(),
+    // Large recursive deletes need an explicit `force`; the probe stops at the threshold
+    if paths.actual.is_dir()
+        && !force
+        && (state.confirm_delete_bytes.is_some() || state.confirm_delete_entries.is_some())
+    {
+        let caps = WalkCaps {
+            max_bytes: state.confirm_delete_bytes.unwrap_or(u64::MAX),
+            max_entries: state.confirm_delete_entries.unwrap_or(u64::MAX),
+            ..WalkCaps::NONE
+        };
+        let mut stats = TreeStats::default();
+        tree_stats(state, &paths.logical, &paths.actual, caps, &mut stats).await;
+        if stats.truncated {
+            let size_formatted = format_size(stats.size);
+            return Ok(Err(Deletion::Confirm(
+                Msg::FolderTooLarge.with(&[&stats.entries, &size_formatted]),
+                DeleteConfirmation {
+                    size: stats.size,
+                    size_formatted,
+                    entries: stats.entries,
+                },
+            )));
+        }
+    }
+    Ok(Ok(paths))
+}
+
+/// Carry out a checked delete, moving the item to the trash unless `permanent` or the
+/// trash is off
+async fn run_delete(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    paths: SafePathResult,
+    progress: Option<Arc<WalkProgress>>,
+    permanent: bool,
+) -> Result<Deletion, ApiError> {
+    let logical = state.mounts.logical_path(&paths.logical);
+    let mut entry = AuditEntry::new("delete", &user.username, addr.ip(), &logical);
+    if let Ok(metadata) = fs::metadata(&paths.actual).await
+        && !metadata.is_dir()
+    {
+        entry = entry.size(metadata.len());
+    }
+    if !permanent && state.trash.enabled() {
+        let result = state.trash.put(state, user, &paths, progress.as_deref()).await;
+        state.audit.record(entry.result(&result));
+        sizes_changed(state, &paths.actual).await;
+        let id = result?;
+        state.meta.removed(&logical).await;
+        state.undo.trashed(&user.username, logical, id.clone());
+        return Ok(Deletion::Done(DeleteResult {
+            message: Msg::MovedToTrash.into(),
+            deleted: 1,
+            failed: 0,
+            failures: Vec::new(),
+            trash_id: Some(id),
+        }));
+    }
+    // A job's token is a child of the shutdown token, so it stops at shutdown too
+    let cancel = progress.as_ref().map_or_else(|| state.shutdown.clone(), |p| p.cancel.clone());
+    if let Some(progress) = &progress {
+        progress.set_total(source_size(state, &paths.logical, &paths.actual).await);
+    }
+    let mut outcome = RemoveOutcome { progress, ..RemoveOutcome::default() };
+    remove_tree(&paths.actual, &cancel, &mut outcome).await;
+    sizes_changed(state, &paths.actual).await;
+
+    if outcome.failed == 0 && !outcome.cancelled {
+        state.audit.record(entry);
+        state.meta.removed(&logical).await;
+        return Ok(Deletion::Done(DeleteResult {
+            message: Msg::Deleted.into(),
+            deleted: outcome.removed,
+            failed: 0,
+            failures: Vec::new(),
+            trash_id: None,
+        }));
+    }
+
+    let stopped_by_job = outcome.cancelled && !state.shutdown.is_cancelled();
+    let message = match (outcome.cancelled && outcome.failed == 0, stopped_by_job) {
+        (true, true) => Msg::DeleteCancelled.with(&[&outcome.removed]),
+        (true, false) => Msg::DeleteInterrupted.to_string(),
+        _ => Msg::DeletePartial.with(&[&outcome.removed, &outcome.failed]),
+    };
+    state.audit.record(entry.result(&Err::<(), _>(&message)));
+    if outcome.removed == 0 {
+        return Err(match outcome.failures.into_iter().next() {
+            Some((_, e)) => ApiError::io(Msg::DeleteFailed, e),
+            None if stopped_by_job => ApiError::Cancelled(message),
+            None => ApiError::Io(message),
+        });
+    }
+    let failures = outcome
+        .failures
+        .into_iter()
+        .map(|(actual, e)| DeleteFailure {
+            path: state.mounts.logical_path(&paths.logical.join(actual.strip_prefix(&paths.actual).unwrap_or(&actual))),
+            error: e.to_string(),
+        })
+        .collect();
+    Ok(Deletion::Partial(DeleteResult {
+        message,
+        deleted: outcome.removed,
+        failed: outcome.failed,
+        failures,
+        trash_id: None,
+    }))
+}
+/// 删除文件/文件夹
+/// The item goes to the trash unless `permanent` is set or the server runs with
+/// `--no-trash`; the answer then carries the `trashId` to restore it with.
+/// With `background: true` the checks (and the size check for `force`) are made, then
+/// the delete runs as a job: the answer is a `JobStarted` with the token to poll at
+/// `/api/jobs/{token}`, which counts the entries removed and those that couldn't be.
+#[utoipa::path(
+    delete, path = "/api/delete", tag = "files", request_body = DeleteRequest,
+    responses((
+        status = 200,
+        description = "Deleted, or with `success: false` and code `PARTIAL_FAILURE` the counts and paths of what \
+            couldn't be removed. A large folder without `force` returns code `CONFIRM_REQUIRED` and a `DeleteConfirmation` instead, \
+            and `background: true` a `JobStarted`",
+        body = ApiResponse<DeleteResult>,
+    )),
+)]
+pub async fn delete_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<DeleteRequest>,
+) -> Result<Response, ApiError> {
+    if req.force_internal
+        && let Some(response) = crate::auth::require_admin(&state, &user)
+    {
+        return Ok(response);
+    }
+    let paths = match check_delete(&state, &user, &headers, &req.path, req.force, req.force_internal).await? {
+        Ok(paths) => paths,
+        Err(confirm) => return Ok(Json(confirm.into_body()).into_response()),
+    };
+    // The admin clearing filest's own temporary files has no use for the trash
+    let permanent = req.permanent || req.force_internal;
+    if req.background {
+        let path = state.mounts.logical_path(&paths.logical);
+        let work = {
+            let (state, user) = (state.clone(), user.clone());
+            move |progress| async move {
+                match run_delete(&state, &user, addr, paths, Some(progress), permanent).await {
+                    Ok(deletion) => deletion.into_body(),
+                    Err(e) => crate::jobs::outcome::<()>(Err(e)),
+                }
+            }
+        };
+        let job = state.reports.start(&user.username, "delete", path, work).await;
+        return Ok(Json(ApiResponse::success(JobStarted { message: Msg::JobStarted.into(), job })).into_response());
+    }
+    let deletion = run_delete(&state, &user, addr, paths, None, permanent).await?;
+    Ok(Json(deletion.into_body()).into_response())
+}
+/// 批量删除
+/// Deletes each path as `/api/delete` would, one after another, so selecting many files
+/// takes one request; like there, paths go to the trash unless `permanent` is set. Every
+/// path gets its own result and a failure doesn't stop the others: `code` is the error
+/// code, `CONFIRM_REQUIRED` for a large folder sent without `force`, or
+/// `PARTIAL_FAILURE` with `deleted` and `failed` counts for a folder only partly removed.
+#[utoipa::path(
+    delete, path = "/api/delete/batch", tag = "files", request_body = BatchDeleteRequest,
+    responses(
+        (status = 200, description = "One result per path, in request order", body = ApiResponse<BatchDeleteResult>),
+        (status = 400, description = "No paths, or over 1000", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn delete_batch(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<BatchDeleteRequest>,
+) -> Result<Json<ApiResponse<BatchDeleteResult>>, ApiError> {
+    if req.paths.is_empty() || req.paths.len() > MAX_BATCH_DELETE {
+        return Err(ApiError::BadRequest(Msg::InvalidBatchPaths.with(&[&MAX_BATCH_DELETE])));
     }
+    let mut items = Vec::with_capacity(req.paths.len());
+    for path in req.paths {
+        let deletion = match check_delete(&state, &user, &headers, &path, req.force, false).await {
+            Ok(Ok(paths)) => run_delete(&state, &user, addr, paths, None, req.permanent).await,
+            Ok(Err(confirm)) => Ok(confirm),
+            Err(e) => Err(e),
+        };
+        let (code, error, deleted, failed) = match deletion {
+            Ok(Deletion::Done(result)) => (None, None, result.deleted, 0),
+            Ok(Deletion::Confirm(message, _)) => (Some("CONFIRM_REQUIRED"), Some(message), 0, 0),
+            Ok(Deletion::Partial(result)) => (Some("PARTIAL_FAILURE"), Some(result.message), result.deleted, result.failed),
+            Err(e) => (Some(e.code()), Some(e.message().to_string()), 0, 0),
+        };
+        items.push(BatchDeleteItem { path, success: code.is_none(), deleted, failed, code, error });
+    }
+    let succeeded = items.iter().filter(|item| item.success).count();
+    Ok(Json(ApiResponse::success(BatchDeleteResult { failed: items.len() - succeeded, succeeded, items })))
 }
 /// 获取文件/文件夹信息
+#[utoipa::path(
+    get, path = "/api/info", tag = "files", params(InfoQuery),
+    responses((status = 200, description = "File or folder details", body = ApiResponse<InfoResponse>)),
+)]
 pub async fn get_info(
     State(state): State<AppState>,
-    Query(query): Query<PathQuery>,
-) -> impl IntoResponse {
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<InfoQuery>,
+) -> Result<Json<ApiResponse<InfoResponse>>, ApiError> {
     let user_path = query.path.unwrap_or_default();
+    let tz = parse_tz(query.tz.as_deref())?;
 
-    let paths = match safe_path(&state.root_dir, &user_path) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
+    if state.mounts.is_virtual_root(&user_path) {
+        let mounts = mount_entries(&state, &user, tz).await;
+        let (mut size, mut size_is_estimate) = (0, false);
+        for mount in state.mounts.list() {
+            if mounts.iter().any(|m| m.name == mount.name) {
+                let dir = state.dir_sizes.bounded(&state, &mount.root, &mount.root).await;
+                size += dir.size;
+                size_is_estimate |= dir.estimate;
+            }
+        }
+        return Ok(Json(ApiResponse::success(InfoResponse {
+            info: FileInfoDetail {
+                name: "/".to_string(),
+                path: "/".to_string(),
+                file_type: "folder".to_string(),
+                size,
+                size_formatted: format_size(size),
+                modified: "-".to_string(),
+                created: "-".to_string(),
+                modified_at: None,
+                created_at: None,
+                children: Some(mounts.len()),
+                size_is_estimate,
+                size_status: None,
+                size_job: None,
+            },
+        })));
+    }
+
+    let paths = safe_path(&state.mounts, &user_path)?;
+
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, false)?;
+    check_not_internal(&logical, &paths.actual)?;
 
     if !paths.actual.exists() {
-        return Json(ApiResponse::<()>::error("文件不存在")).into_response();
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
     }
 
-    let info = match get_file_info(&state.root_dir, &paths.logical).await {
-        Ok(i) => i,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
-    };
+    let mut info = get_file_info(&state.mounts, &paths.logical, &paths.logical, tz).await.map_err(ApiError::Io)?;
+    if paths.is_root() && state.mounts.is_multi() {
+        // Mounts are known by their name rather than their directory's
+        info.name = info.path.trim_start_matches('/').to_string();
+    }
 
+    let (mut size_is_estimate, mut size_status, mut size_job) = (false, None, None);
     let (children, size, size_formatted) = if paths.actual.is_dir() {
         let mut count = 0;
         if let Ok(mut entries) = fs::read_dir(&paths.actual).await {
-            while entries.next_entry().await.ok().flatten().is_some() {
-                count += 1;
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let rel = state.mounts.logical_path(&paths.logical.join(entry.file_name()));
+                if !state.hidden.matches(&rel) && !is_internal_artifact(&rel, &entry.path()) {
+                    count += 1;
+                }
             }
         }
-        let dir_size = get_dir_size(&paths.actual).await;
+        let sizes = &state.dir_sizes;
+        let (dir_size, estimate) = match sizes.cached(&paths.actual).await {
+            Some(dir) if !(query.exact && dir.estimate) => (dir.size, dir.estimate),
+            // Full walks run in the background; meanwhile report what is known
+            cached if query.exact => {
+                size_status = Some(JobStatus::Computing);
+                size_job = Some(sizes.start_job(&state, &user.username, logical.clone(), &paths.logical, &paths.actual).await);
+                (cached.map_or(0, |dir| dir.size), true)
+            }
+            _ => {
+                let dir = sizes.bounded(&state, &paths.logical, &paths.actual).await;
+                (dir.size, dir.estimate)
+            }
+        };
+        size_is_estimate = estimate;
         (Some(count), dir_size, format_size(dir_size))
     } else {
         (None, info.size, info.size_formatted.clone())
     };
 
-    Json(ApiResponse::success(InfoResponse {
+    Ok(Json(ApiResponse::success(InfoResponse {
         info: FileInfoDetail {
             name: info.name,
             path: info.path,
@@ -639,27 +2387,40 @@ pub async fn get_info(
             size_formatted,
             modified: info.modified,
             created: info.created,
+            modified_at: info.modified_at,
+            created_at: info.created_at,
             children,
+            size_is_estimate,
+            size_status,
+            size_job,
         },
-    })).into_response()
+    })))
 }
 /// 获取所有文件夹
-pub async fn get_folders(State(state): State<AppState>) -> impl IntoResponse {
+#[utoipa::path(
+    get, path = "/api/folders", tag = "files",
+    responses((status = 200, description = "All folders, for move/copy targets", body = ApiResponse<FoldersResponse>)),
+)]
+pub async fn get_folders(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
     let mut folders = Vec::new();
 
     async fn scan_dir(
-        root: &Path,
+        mounts: &Mounts,
         dir: &Path,
         prefix: &str,
         folders: &mut Vec<FolderItem>,
+        user: &AuthUser,
+        hidden: &PathPatterns,
     ) {
-        let rel_path = relative_path(root, dir);
+        let rel_path = mounts.logical_path(dir);
+        // The last logical segment, so mounts show their name
         let display_name = if rel_path == "/" {
-            "根目录".to_string()
+            Msg::RootFolder.into()
         } else {
-            dir.file_name()
-                .map(|n| n.to_string_lossy().to_string())
-                .unwrap_or_default()
+            rel_path.rsplit('/').next().unwrap_or_default().to_string()
         };
 
         folders.push(FolderItem {
@@ -671,126 +2432,532 @@ pub async fn get_folders(State(state): State<AppState>) -> impl IntoResponse {
             let mut subdirs = Vec::new();
             while let Ok(Some(entry)) = entries.next_entry().await {
                 let path = entry.path();
-                if path.is_dir() {
+                let rel = mounts.logical_path(&path);
+                if path.is_dir()
+                    && user.can_see(&rel)
+                    && !hidden.matches(&rel)
+                    && !is_internal_artifact(&rel, &path)
+                    && !mounts.refuses_link(&path)
+                {
                     subdirs.push(path);
                 }
             }
             subdirs.sort();
 
             for subdir in subdirs {
-                Box::pin(scan_dir(root, &subdir, &format!("{}　", prefix), folders)).await;
+                Box::pin(scan_dir(mounts, &subdir, &format!("{}　", prefix), folders, user, hidden)).await;
             }
         }
     }
 
-    scan_dir(&state.root_dir, &state.root_dir, "", &mut folders).await;
+    if state.mounts.is_multi() {
+        folders.push(FolderItem {
+            path: "/".to_string(),
+            display: Msg::RootFolder.into(),
+        });
+        for mount in state.mounts.list() {
+            let logical = format!("/{}", mount.name);
+            if user.can_see(&logical) && !state.hidden.matches(&logical) {
+                scan_dir(&state.mounts, &mount.root, "　", &mut folders, &user, &state.hidden).await;
+            }
+        }
+    } else {
+        let root = &state.mounts.list()[0].root;
+        scan_dir(&state.mounts, root, "", &mut folders, &user, &state.hidden).await;
+    }
 
     Json(ApiResponse::success(FoldersResponse { folders }))
 }
 /// 获取磁盘信息
+#[utoipa::path(
+    get, path = "/api/disk", tag = "files",
+    responses((status = 200, description = "Disk usage per mount", body = ApiResponse<DiskResponse>)),
+)]
 pub async fn get_disk_info(State(state): State<AppState>) -> impl IntoResponse {
-    use sysinfo::Disks;
-
-    let disks = Disks::new_with_refreshed_list();
-
-    // 查找根目录所在的磁盘
-    let mut total = 500 * 1024 * 1024 * 1024u64; // 默认 500GB
-    let mut free = 400 * 1024 * 1024 * 1024u64;
-
-    for disk in disks.list() {
-        if state.root_dir.starts_with(disk.mount_point()) {
-            total = disk.total_space();
-            free = disk.available_space();
-            break;
-        }
+    // 查找每个挂载所在的磁盘
+    let mut mounts = Vec::new();
+    for mount in state.mounts.list() {
+        // Zeros rather than made-up figures when no disk holds the root
+        let disk = state.disks.disk_of(&mount.root).await;
+        let (total, scanned) = disk.as_ref().map_or((0, 0), |d| (d.total, d.free));
+        // The scan may be a few seconds old; what the write checks will see is asked for fresh
+        let free = match disk {
+            Some(_) => disks::available_space(&mount.root).await.unwrap_or(scanned),
+            None => 0,
+        };
+        let used = total.saturating_sub(free);
+        mounts.push(MountDiskInfo {
+            name: mount.name.clone(),
+            total,
+            used,
+            free,
+            writable: free.saturating_sub(state.min_free_space),
+            used_formatted: format_size(used),
+            unknown: disk.is_none(),
+            mount_point: disk.as_ref().map(|d| d.mount_point.to_string_lossy().into_owned()),
+            file_system: disk.map(|d| d.file_system),
+        });
     }
 
-    let used = total.saturating_sub(free);
-
+    // The top-level figures describe the first mount
+    let first = &mounts[0];
     Json(ApiResponse::success(DiskResponse {
-        total,
-        used,
-        free,
-        used_formatted: format_size(used),
+        total: first.total,
+        used: first.used,
+        free: first.free,
+        writable: first.writable,
+        min_free_space: state.min_free_space,
+        used_formatted: first.used_formatted.clone(),
+        unknown: first.unknown,
+        mount_point: first.mount_point.clone(),
+        file_system: first.file_system.clone(),
+        mounts: if state.mounts.is_multi() { mounts } else { Vec::new() },
     }))
 }
+/// How far a search walk may go, and how far it went
+///
+/// The walk runs in the request's own future, so a client that disconnects drops it at
+/// the next folder read; `finished` tells such an abandoned walk apart in the log.
+pub(crate) struct SearchBudget {
+    path: String,
+    deadline: Option<Instant>,
+    shutdown: CancellationToken,
+    pub(crate) dirs_scanned: u64,
+    pub(crate) timed_out: bool,
+    pub(crate) finished: bool,
+}
+
+impl SearchBudget {
+    /// The budget of a search below `path`: the tighter of the caller's `timeout_secs` and
+    /// the server's cap
+    pub(crate) fn new(state: &AppState, path: String, timeout_secs: Option<u64>) -> Self {
+        let timeout = match (timeout_secs.map(std::time::Duration::from_secs), state.search_timeout) {
+            (Some(asked), Some(cap)) => Some(asked.min(cap)),
+            (asked, cap) => asked.or(cap),
+        };
+        Self {
+            path,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            shutdown: state.shutdown.clone(),
+            dirs_scanned: 0,
+            timed_out: false,
+            finished: false,
+        }
+    }
+
+    /// A search walk slot; waiting for it counts against the time, and `None` means it ran out
+    pub(crate) async fn permit<'a>(&mut self, permits: &'a Semaphore) -> Option<SemaphorePermit<'a>> {
+        let permit = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), permits.acquire()).await.ok(),
+            None => Some(permits.acquire().await),
+        };
+        let permit = permit.and_then(Result::ok);
+        self.timed_out |= permit.is_none();
+        permit
+    }
+
+    /// Whether the walk should stop before its next entry: the time is up or the server is stopping
+    pub(crate) fn exhausted(&mut self) -> bool {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.timed_out = true;
+        }
+        self.timed_out || self.shutdown.is_cancelled()
+    }
+}
+
+impl Drop for SearchBudget {
+    fn drop(&mut self) {
+        if !self.finished {
+            tracing::debug!("Search of {} abandoned after {} folders", self.path, self.dirs_scanned);
+        }
+    }
+}
+
+/// Carries `nextToken` when search results are given as text or CSV
+pub const NEXT_TOKEN_HEADER: &str = "x-next-token";
+
+/// The `nextToken` continuing a search after `path`, the last entry it went through
+///
+/// Searches go through entries in `Path` order: names sorted, and everything in a
+/// folder right after the folder. A later page skips what comes up to `path` in that
+/// order, so nothing has to be kept between requests.
+fn search_token(path: &str) -> String {
+    URL_SAFE_NO_PAD.encode(path)
+}
+
+/// The path a `token` continues after, which is below `base` for the search it came from
+fn search_after(token: &str, base: &str) -> Result<String, ApiError> {
+    URL_SAFE_NO_PAD
+        .decode(token)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .filter(|path| Path::new(path).strip_prefix(base).is_ok_and(|rest| !rest.as_os_str().is_empty()))
+        .ok_or_else(|| ApiError::BadRequest(Msg::InvalidSearchToken.into()))
+}
+
+/// What a name search's results must be: the name per `mode`, then the size, time and
+/// type filters of `/api/search`
+pub(crate) struct SearchFilter {
+    pub(crate) name: NameMatcher,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after: Option<DateTime<Utc>>,
+    modified_before: Option<DateTime<Utc>>,
+    kind: Option<SearchType>,
+}
+
+impl SearchFilter {
+    /// The filters of a search; empty parameters are ignored
+    pub(crate) fn new(query: &SearchQuery) -> Result<Self, ApiError> {
+        let given = |text: &Option<String>| text.clone().filter(|text| !text.is_empty());
+        let size = |text: Option<String>| {
+            text.map(|text| parse_size(&text).map_err(|_| ApiError::BadRequest(Msg::InvalidSearchSize.with(&[&text]))))
+                .transpose()
+        };
+        let time = |text: Option<String>| {
+            text.map(|text| {
+                DateTime::parse_from_rfc3339(&text)
+                    .map(|time| time.with_timezone(&Utc))
+                    .or_else(|_| NaiveDate::parse_from_str(&text, "%Y-%m-%d").map(|day| day.and_time(NaiveTime::MIN).and_utc()))
+                    .map_err(|_| ApiError::BadRequest(Msg::InvalidSearchTime.with(&[&text])))
+            })
+            .transpose()
+        };
+        Ok(Self {
+            name: NameMatcher::new(query.mode.unwrap_or_default(), &query.query)?,
+            min_size: size(given(&query.min_size))?,
+            max_size: size(given(&query.max_size))?,
+            modified_after: time(given(&query.modified_after))?,
+            modified_before: time(given(&query.modified_before))?,
+            kind: query.kind,
+        })
+    }
+
+    /// Whether an entry whose name matched passes the other filters
+    ///
+    /// A size filter leaves out folders, whose size would take a walk, and a time filter
+    /// entries without a modification time.
+    pub(crate) fn accepts(&self, info: &FileInfo) -> bool {
+        let is_dir = info.file_type == "folder";
+        if (self.min_size.is_some() || self.max_size.is_some()) && is_dir
+            || self.min_size.is_some_and(|min| info.size < min)
+            || self.max_size.is_some_and(|max| info.size > max)
+        {
+            return false;
+        }
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            let Some(modified) = info.modified_at.as_deref().and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            else {
+                return false;
+            };
+            if self.modified_after.is_some_and(|after| modified < after)
+                || self.modified_before.is_some_and(|before| modified >= before)
+            {
+                return false;
+            }
+        }
+        match self.kind {
+            None => true,
+            Some(SearchType::Entry(EntryType::File)) => !is_dir,
+            Some(SearchType::Entry(EntryType::Folder)) => is_dir,
+            Some(SearchType::Category(category)) => info.category == Some(category),
+        }
+    }
+}
+
 /// 搜索文件
+#[utoipa::path(
+    get, path = "/api/search", tag = "files", params(SearchQuery),
+    responses((
+        status = 200,
+        description = "Matching files and folders; `Accept: text/plain` or `text/csv` (or `format=`) gives a table instead. \
+            A search that ran out of `timeout_secs` returns what it found with `timedOut: true`",
+        content(
+            (ApiResponse<SearchResponse> = "application/json"),
+            (String = "text/plain"),
+            (String = "text/csv"),
+        ),
+    )),
+)]
 pub async fn search_files(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
     Query(query): Query<SearchQuery>,
-) -> impl IntoResponse {
-    let paths = match safe_path(&state.root_dir, &query.path.unwrap_or_else(|| "/".to_string())) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let filter = SearchFilter::new(&query)?;
+    let user_path = query.path.unwrap_or_else(|| "/".to_string());
+    let format = render::negotiate(query.format, &headers);
+
+    // Directories to search as (logical, actual): every visible mount when searching from the virtual root
+    let (base, dirs) = if state.mounts.is_virtual_root(&user_path) {
+        let dirs = 
+        state.mounts.list().iter()
+            .filter(|m| {
+                let logical = format!("/{}", m.name);
+                user.can_see(&logical) && !state.hidden.matches(&logical)
+            })
+            .map(|m| (m.root.clone(), m.root.clone()))
+            .collect();
+        ("/".to_string(), dirs)
+    } else {
+        let paths = safe_path(&state.mounts, &user_path)?;
+
+        let logical = state.mounts.logical_path(&paths.logical);
+        record_path(&logical);
+        user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+        check_patterns(&state, &logical, false)?;
+        check_not_internal(&logical, &paths.actual)?;
+        (logical, vec![(paths.logical, paths.actual)])
     };
+    let tags: Vec<String> = query
+        .tags
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    let after = query.token.as_deref().map(|token| search_after(token, &base)).transpose()?;
+    let limit = query.limit.unwrap_or(state.search_max_results).clamp(1, state.search_max_results);
 
-    let query_lower = query.query.to_lowercase();
     let mut results = Vec::new();
+    let mut budget = SearchBudget::new(&state, base.clone(), query.timeout_secs);
 
+    /// Walk `actual_dir`, reporting each match under `logical_dir`
+    ///
+    /// Like listings, paths are built from the logical chain rather than from where
+    /// symlinks point, so every result can be passed back to `/api/files` or `/api/download`.
+    /// `ancestors` holds the resolved directories being walked, so a symlink back up the
+    /// tree isn't followed forever. The walk stops early once `budget` is exhausted.
+    ///
+    /// Entries are gone through in name order, and `resume` (relative to `actual_dir`)
+    /// skips those up to and including where an earlier page stopped. `last` is left at
+    /// the last entry gone through.
+    #[allow(clippy::too_many_arguments)]
     async fn search_in_dir(
-        root: &Path,
-        dir: &Path,
-        query: &str,
+        mounts: &Mounts,
+        logical_dir: &Path,
+        actual_dir: &Path,
+        resume: Option<&Path>,
+        ancestors: &mut Vec<PathBuf>,
+        filter: &SearchFilter,
         results: &mut Vec<FileInfo>,
+        last: &mut Option<String>,
         limit: usize,
+        user: &AuthUser,
+        hidden: &PathPatterns,
+        budget: &mut SearchBudget,
     ) {
-        if results.len() >= limit {
+        if results.len() >= limit || budget.exhausted() {
+            return;
+        }
+        let resolved = fs::canonicalize(actual_dir).await.unwrap_or_else(|_| actual_dir.to_path_buf());
+        if ancestors.contains(&resolved) {
             return;
         }
+        ancestors.push(resolved);
 
-        if let Ok(mut entries) = fs::read_dir(dir).await {
-            while let Ok(Some(entry)) = entries.next_entry().await {
-                if results.len() >= limit {
+        if let Ok(mut read) = fs::read_dir(actual_dir).await {
+            budget.dirs_scanned += 1;
+            let mut entries = Vec::new();
+            while let Ok(Some(entry)) = read.next_entry().await {
+                entries.push(entry);
+            }
+            entries.sort_by_key(|entry| entry.file_name());
+            // The entry an earlier page stopped in or at, and where below it
+            let mut resume = resume.and_then(|path| {
+                let mut components = path.components();
+                let name = components.next()?.as_os_str().to_os_string();
+                Some((name, components.as_path().to_path_buf()))
+            });
+
+            for entry in entries {
+                if results.len() >= limit || budget.exhausted() {
                     break;
                 }
 
-                let path = entry.path();
-                let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+                let file_name = entry.file_name();
+                // Gone through by an earlier page: only what is below it may be left
+                let mut below = None;
+                if let Some((name, rest)) = resume.take() {
+                    match file_name.cmp(&name) {
+                        Ordering::Less => {
+                            resume = Some((name, rest));
+                            continue;
+                        }
+                        Ordering::Equal => below = Some(rest),
+                        Ordering::Greater => {}
+                    }
+                }
 
-                if name.contains(query) {
-                    if let Ok(info) = get_file_info(root, &path).await {
+                let logical = logical_dir.join(&file_name);
+                let actual = entry.path();
+                let rel = mounts.logical_path(&logical);
+                if !user.can_see(&rel) || hidden.matches(&rel) || is_internal_artifact(&rel, &actual) {
+                    continue;
+                }
+                if below.is_none() {
+                    // Matched up to case and Unicode normalization; results keep the stored name
+                    let name = search_key(&file_name.to_string_lossy());
+                    if filter.name.matches(&name)
+                        && let Ok(info) = get_file_info(mounts, &logical, &actual, None).await
+                        && filter.accepts(&info)
+                    {
                         results.push(info);
                     }
+                    *last = Some(rel);
                 }
 
-                if path.is_dir() && results.len() < limit {
-                    Box::pin(search_in_dir(root, &path, query, results, limit)).await;
+                if actual.is_dir() && results.len() < limit && !mounts.refuses_link(&actual) {
+                    Box::pin(search_in_dir(
+                        mounts, &logical, &actual, below.as_deref(), ancestors, filter, results, last, limit, user,
+                        hidden, budget,
+                    ))
+                    .await;
                 }
             }
         }
+        ancestors.pop();
     }
 
-    search_in_dir(&state.root_dir, &paths.actual, &query_lower, &mut results, 100).await;
+    // Tagged files are looked up in the metadata store rather than by walking the tree,
+    // and names in the search index once it is built
+    let mut indexed = false;
+    let mut last = None;
+    if tags.is_empty()
+        && let Some(index) = &state.index
+        && let Some(found) = index.search(&state, &user, &base, &filter, after.as_deref(), limit).await
+    {
+        (results, last) = found;
+        indexed = true;
+    } else if tags.is_empty() {
+        if let Some(_permit) = budget.permit(&state.search_permits).await {
+            // Mounts in name order too, so a later page knows which ones are done
+            let mut dirs: Vec<(String, PathBuf, PathBuf)> = dirs
+                .into_iter()
+                .map(|(logical, actual)| (state.mounts.logical_path(&logical), logical, actual))
+                .collect();
+            dirs.sort_by(|a, b| Path::new(&a.0).cmp(Path::new(&b.0)));
+            for (rel, logical, actual) in &dirs {
+                let resume = match &after {
+                    None => None,
+                    Some(after) => match Path::new(after).strip_prefix(rel) {
+                        Ok(rest) => Some(rest),
+                        Err(_) if Path::new(after) < Path::new(rel) => None,
+                        Err(_) => continue,
+                    },
+                };
+                search_in_dir(
+                    &state.mounts, logical, actual, resume, &mut Vec::new(), &filter, &mut results, &mut last, limit,
+                    &user, &state.hidden, &mut budget,
+                )
+                .await;
+            }
+        }
+    } else {
+        (results, last) = crate::meta::search_tagged(&state, &user, &base, &tags, &filter, after.as_deref(), limit).await;
+    }
+    if query.include_meta || !tags.is_empty() {
+        state.meta.fill_tags(&mut results).await;
+    }
+
+    budget.finished = true;
+    // Stopped short of the end; one out of time before its first entry continues where
+    // the given token did
+    let next_token = (results.len() >= limit || budget.timed_out)
+        .then(|| last.or(after))
+        .flatten()
+        .map(|path| search_token(&path));
 
-    Json(ApiResponse::success(SearchResponse { results })).into_response()
+    if let Some(mut response) = render::listing(format, &results, true) {
+        if let Some(token) = next_token.and_then(|token| HeaderValue::from_str(&token).ok()) {
+            response.headers_mut().insert(NEXT_TOKEN_HEADER, token);
+        }
+        return Ok(response);
+    }
+    Ok(Json(ApiResponse::success(SearchResponse {
+        results,
+        timed_out: budget.timed_out,
+        dirs_scanned: budget.dirs_scanned,
+        indexed,
+        next_token,
+    }))
+    .into_response())
 }
 
 // ========== Chunked Upload API ==========
 
+/// How often chunked upload sessions are checked for `--upload-idle-secs`
+const SESSION_REAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
 /// Initialize chunked upload session
+#[utoipa::path(
+    post, path = "/api/upload/init", tag = "upload", request_body = ChunkedUploadInitRequest,
+    responses(
+        (status = 200, description = "Upload session created", body = ApiResponse<ChunkedUploadInitResponse>),
+        (status = 413, description = "`totalSize` over `maxFileSize`", body = crate::openapi::ErrorBody),
+        (status = 429, description = "`TOO_MANY_SESSIONS`: every session slot is taken", body = crate::openapi::ErrorBody),
+    ),
+)]
 pub async fn chunked_upload_init(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(req): Json<ChunkedUploadInitRequest>,
-) -> impl IntoResponse {
+) -> Result<Json<ApiResponse<ChunkedUploadInitResponse>>, ApiError> {
     // Validate upload path
-    let paths = match safe_path(&state.root_dir, &req.path) {
-        Ok(p) => p,
-        Err(e) => return Json(ApiResponse::<()>::error(e)).into_response(),
+    let paths = safe_path(&state.mounts, &req.path)?;
+    let filename = state.name_rules.prepare(&req.filename)?;
+
+    let logical = state.mounts.logical_path(&paths.logical.join(&filename));
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual.join(&filename))?;
+    state.locks.check(&logical, false, &user, &headers)?;
+
+    // The assembled file is bound by the same limit as a plain upload
+    let limits = state.upload_limits;
+    if req.total_size > limits.max_file_size {
+        return Err(crate::limits::too_large(limits.max_file_size));
+    }
+    if req.chunk_size == 0
+        || req.chunk_size > limits.max_chunk_size
+        || u64::from(req.total_chunks) != req.total_size.div_ceil(req.chunk_size).max(1)
+    {
+        return Err(ApiError::BadRequest(
+            Msg::InvalidChunkSize.with(&[&format_size(limits.max_chunk_size)]),
+        ));
+    }
+
+    check_upload_space(&state, &paths.actual, req.total_size).await?;
+
+    // Abandoned sessions give their slots to new ones once the limit is reached
+    let slot = match state.upload_slots.session() {
+        Ok(slot) => slot,
+        Err(e) => {
+            if drop_stale_sessions(&state.upload_sessions, limits.idle_timeout).await == 0 {
+                return Err(e);
+            }
+            state.upload_slots.session()?
+        }
     };
 
     // Generate unique upload ID
     let upload_id = Uuid::new_v4().to_string();
 
     // Create temp directory for chunks
-    let temp_dir = std::env::temp_dir().join("filest_uploads").join(&upload_id);
-    if let Err(e) = fs::create_dir_all(&temp_dir).await {
-        return Json(ApiResponse::<()>::error(format!("Failed to create temp directory: {}", e))).into_response();
-    }
+    let temp_dir = upload_temp_dir(&upload_id);
+    fs::create_dir_all(&temp_dir)
+        .await
+        .map_err(|e| ApiError::io(Msg::CreateDirFailed, e))?;
 
     // Create upload session
     let session = UploadSession {
         upload_id: upload_id.clone(),
-        filename: req.filename.clone(),
+        filename,
         total_size: req.total_size,
         total_chunks: req.total_chunks,
         chunk_size: req.chunk_size,
@@ -798,6 +2965,11 @@ pub async fn chunked_upload_init(
         temp_dir: temp_dir.clone(),
         received_chunks: vec![false; req.total_chunks as usize],
         created_at: std::time::Instant::now(),
+        owner: user.username.clone(),
+        client: addr.ip(),
+        last_modified: req.last_modified,
+        last_activity: Default::default(),
+        slot: std::sync::Arc::new(slot),
     };
 
     // Store session
@@ -806,18 +2978,28 @@ pub async fn chunked_upload_init(
         sessions.insert(upload_id.clone(), session);
     }
 
-    Json(ApiResponse::success(ChunkedUploadInitResponse {
+    Ok(Json(ApiResponse::success(ChunkedUploadInitResponse {
         upload_id,
         chunk_size: req.chunk_size,
-    })).into_response()
+    })))
 }
 
 /// Upload a single chunk
+#[utoipa::path(
+    post, path = "/api/upload/chunk", tag = "upload", params(ChunkUploadQuery),
+    request_body(content = crate::openapi::ChunkForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Chunk stored", body = ApiResponse<ChunkUploadResponse>),
+        (status = 413, description = "Chunk larger than the session's `chunkSize`", body = crate::openapi::ErrorBody),
+        (status = 429, description = "`TOO_MANY_UPLOADS`: no upload slot freed up in time", body = crate::openapi::ErrorBody),
+    ),
+)]
 pub async fn chunked_upload_chunk(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
     Query(query): Query<ChunkUploadQuery>,
     mut multipart: Multipart,
-) -> impl IntoResponse {
+) -> Result<Json<ApiResponse<ChunkUploadResponse>>, ApiError> {
     let upload_id = query.upload_id;
     let chunk_index = query.chunk_index;
 
@@ -826,60 +3008,103 @@ pub async fn chunked_upload_chunk(
         let sessions = state.upload_sessions.read().await;
         match sessions.get(&upload_id) {
             Some(s) => s.clone(),
-            None => return Json(ApiResponse::<()>::error("Upload session not found")).into_response(),
+            None => return Err(ApiError::NotFound(Msg::UploadSessionNotFound.into())),
         }
     };
 
     // Validate chunk index
     if chunk_index >= session.total_chunks {
-        return Json(ApiResponse::<()>::error("Invalid chunk index")).into_response();
+        return Err(ApiError::BadRequest(Msg::InvalidChunkIndex.into()));
     }
 
-    // Get chunk data from multipart
-    let chunk_data = match multipart.next_field().await {
-        Ok(Some(field)) => {
-            match field.bytes().await {
-                Ok(data) => data,
-                Err(e) => return Json(ApiResponse::<()>::error(format!("Failed to read chunk data: {}", e))).into_response(),
+    let _slot = state.upload_slots.stream().await?;
+    // Get chunk data from multipart; the session isn't idle while data keeps arriving
+    session.touch();
+    let idle = state.upload_limits.idle_timeout;
+    let mut field = match tokio::time::timeout(idle, multipart.next_field()).await {
+        Err(_) => return Err(crate::limits::idle_timeout(idle)),
+        Ok(Ok(Some(field))) => field,
+        Ok(Ok(None)) => return Err(ApiError::BadRequest(Msg::NoChunkData.into())),
+        Ok(Err(e)) => return Err(ApiError::BadRequest(format!("{}: {}", Msg::ReadChunkFailed, e))),
+    };
+    let mut chunk_data = Vec::new();
+    loop {
+        match tokio::time::timeout(idle, field.chunk()).await {
+            Err(_) => return Err(crate::limits::idle_timeout(idle)),
+            Ok(Ok(Some(data))) => {
+                session.touch();
+                chunk_data.extend_from_slice(&data);
+                if chunk_data.len() as u64 > session.chunk_size {
+                    return Err(crate::limits::too_large(session.chunk_size));
+                }
             }
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => return Err(ApiError::BadRequest(format!("{}: {}", Msg::ReadChunkFailed, e))),
         }
-        Ok(None) => return Json(ApiResponse::<()>::error("No chunk data provided")).into_response(),
-        Err(e) => return Json(ApiResponse::<()>::error(format!("Failed to get multipart field: {}", e))).into_response(),
-    };
+    }
 
     // Write chunk to temp file
     let chunk_path = session.temp_dir.join(format!("chunk_{:06}", chunk_index));
-    if let Err(e) = fs::write(&chunk_path, &chunk_data).await {
-        return Json(ApiResponse::<()>::error(format!("Failed to write chunk: {}", e))).into_response();
-    }
+    fs::write(&chunk_path, &chunk_data)
+        .await
+        .map_err(|e| ApiError::io(Msg::WriteChunkFailed, e))?;
 
     // Update session
-    {
+    let done = {
         let mut sessions = state.upload_sessions.write().await;
-        if let Some(s) = sessions.get_mut(&upload_id) {
+        sessions.get_mut(&upload_id).map(|s| {
             s.received_chunks[chunk_index as usize] = true;
-        }
+            s.received_chunks.iter().filter(|&&r| r).count() as u64
+        })
+    };
+    if let Some(done) = done {
+        state.activity.publish(Activity::JobProgress {
+            job: upload_id,
+            kind: "upload",
+            path: state.mounts.logical_path(&session.upload_path.join(&session.filename)),
+            done,
+            total: u64::from(session.total_chunks),
+            user: user.username.clone(),
+        });
     }
 
-    Json(ApiResponse::success(ChunkUploadResponse {
+    Ok(Json(ApiResponse::success(ChunkUploadResponse {
         chunk_index,
         received: true,
-    })).into_response()
+    })))
 }
 
 /// Complete chunked upload - merge all chunks
+#[utoipa::path(
+    post, path = "/api/upload/complete", tag = "upload", request_body = ChunkedUploadCompleteRequest,
+    responses((status = 200, description = "Chunks merged into the target file", body = ApiResponse<ChunkedUploadCompleteResponse>)),
+)]
 pub async fn chunked_upload_complete(
     State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(req): Json<ChunkedUploadCompleteRequest>,
-) -> impl IntoResponse {
+) -> Result<Json<ApiResponse<ChunkedUploadCompleteResponse>>, ApiError> {
     let upload_id = req.upload_id;
 
+    // A lock taken since the upload started keeps the session for a retry with the token
+    let target = state
+        .upload_sessions
+        .read()
+        .await
+        .get(&upload_id)
+        .map(|s| state.mounts.logical_path(&s.upload_path.join(&s.filename)));
+    if let Some(target) = target {
+        state.locks.check(&target, false, &user, &headers)?;
+    }
+
     // Get and remove session
     let session = {
         let mut sessions = state.upload_sessions.write().await;
         match sessions.remove(&upload_id) {
             Some(s) => s,
-            None => return Json(ApiResponse::<()>::error("Upload session not found")).into_response(),
+            None => return Err(ApiError::NotFound(Msg::UploadSessionNotFound.into())),
         }
     };
 
@@ -896,81 +3121,1002 @@ pub async fn chunked_upload_complete(
             let mut sessions = state.upload_sessions.write().await;
             sessions.insert(upload_id, session);
         }
-        return Json(ApiResponse::<()>::error(format!("Missing chunks: {:?}", missing))).into_response();
+        return Err(ApiError::BadRequest(Msg::MissingChunks.with(&[&format!("{:?}", missing)])));
     }
 
+    // The session is gone, so its chunks are removed however this request ends
+    let _chunks = TempPath::dir(&session.temp_dir);
+
     // Ensure upload directory exists
-    if let Err(e) = fs::create_dir_all(&session.upload_path).await {
-        return Json(ApiResponse::<()>::error(format!("Failed to create upload directory: {}", e))).into_response();
-    }
+    fs::create_dir_all(&session.upload_path)
+        .await
+        .map_err(|e| ApiError::io(Msg::CreateDirFailed, e))?;
 
-    // Create final file
-    let final_path = session.upload_path.join(&session.filename);
-    let mut final_file = match fs::File::create(&final_path).await {
-        Ok(f) => f,
-        Err(e) => return Json(ApiResponse::<()>::error(format!("Failed to create final file: {}", e))).into_response(),
+    // Like a plain upload, replace the stored file even if its normalization differs
+    let filename = existing_name(&session.upload_path, &session.filename)
+        .await
+        .map_or_else(|| session.filename.clone(), |stored| stored.to_string_lossy().into_owned());
+    let final_path = session.upload_path.join(&filename);
+    let logical = state.mounts.logical_path(&final_path);
+    // With an upload hook the chunks are merged aside and only moved in once it passes them
+    let merge_path = match state.upload_hook {
+        Some(_) => session.upload_path.join(format!("{}upload_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple())),
+        None => final_path.clone(),
     };
-
-    // Merge chunks in order
-    let mut total_written: u64 = 0;
-    for i in 0..session.total_chunks {
-        let chunk_path = session.temp_dir.join(format!("chunk_{:06}", i));
-        let chunk_data = match fs::read(&chunk_path).await {
-            Ok(data) => data,
-            Err(e) => {
-                // Cleanup partial file
-                let _ = fs::remove_file(&final_path).await;
-                return Json(ApiResponse::<()>::error(format!("Failed to read chunk {}: {}", i, e))).into_response();
+    let _merged = state.upload_hook.is_some().then(|| TempPath::file(&merge_path));
+    let mut result = merge_chunks(&session, &merge_path).await;
+    let mut hook_warning = None;
+    if state.upload_hook.is_some()
+        && let Ok(size) = result
+    {
+        result = match hooks::check_upload(&state, &merge_path, &logical, &user.username, addr.ip()).await {
+            Ok(warning) => {
+                hook_warning = warning;
+                fs::rename(&merge_path, &final_path)
+                    .await
+                    .map(|()| size)
+                    .map_err(|e| ApiError::io(Msg::WriteFileFailed, e))
             }
+            Err(e) => Err(e),
         };
-
-        if let Err(e) = final_file.write_all(&chunk_data).await {
-            let _ = fs::remove_file(&final_path).await;
-            return Json(ApiResponse::<()>::error(format!("Failed to write chunk {} to final file: {}", i, e))).into_response();
-        }
-
-        total_written += chunk_data.len() as u64;
     }
-
-    // Sync to disk
-    if let Err(e) = final_file.sync_all().await {
-        let _ = fs::remove_file(&final_path).await;
-        return Json(ApiResponse::<()>::error(format!("Failed to sync file: {}", e))).into_response();
+    let mut entry = AuditEntry::new("upload", &user.username, addr.ip(), &logical).result(&result);
+    if let Ok(size) = result {
+        entry = entry.size(size);
     }
+    state.audit.record(entry);
+    sizes_changed(&state, &final_path).await;
+    let total_written = result?;
+    let (modified_at, mtime_warning) = apply_client_mtime(&final_path, session.last_modified).await;
+    let warning = hook_warning.or(mtime_warning);
 
     // Cleanup temp directory
     let _ = fs::remove_dir_all(&session.temp_dir).await;
 
     // Build response path
-    let response_path = relative_path(&state.root_dir, &final_path);
+    let response_path = state.mounts.logical_path(&final_path);
+    state.activity.check_free_space(state.disks.clone(), final_path, response_path.clone());
 
-    Json(ApiResponse::success(ChunkedUploadCompleteResponse {
-        name: session.filename,
+    Ok(Json(ApiResponse::success(ChunkedUploadCompleteResponse {
+        name: filename,
         size: total_written,
         path: response_path,
-    })).into_response()
+        modified_at,
+        warning,
+    })))
+}
+
+/// Refuse a chunked upload of `size` bytes that the disks can't hold above `--min-free-space`
+///
+/// The chunks wait in the system temp dir and are then merged next to the target, so a
+/// temp dir on the target's disk needs room for both. Space isn't reserved: sessions
+/// started together can still run out, which the preallocation at merge time catches.
+async fn check_upload_space(state: &AppState, target: &Path, size: u64) -> Result<(), ApiError> {
+    let temp = std::env::temp_dir();
+    // The target folder may not exist yet; its nearest existing ancestor is on the same disk
+    let existing = target.ancestors().find(|dir| dir.exists()).unwrap_or(target);
+    let target_disk = state.disks.disk_of(existing).await;
+    let temp_disk = state.disks.disk_of(&temp).await;
+    let shared = matches!((&target_disk, &temp_disk), (Some(a), Some(b)) if a.mount_point == b.mount_point);
+    if shared {
+        return disks::ensure_space(target, size.saturating_mul(2), state.min_free_space).await;
+    }
+    disks::ensure_space(target, size, state.min_free_space).await?;
+    disks::ensure_space(&temp, size, state.min_free_space).await
+}
+
+/// Refuse to copy `source` into `dest_dir`, or move it there from another disk, when the
+/// copy wouldn't fit above `--min-free-space`
+///
+/// A folder's size comes from the folder size cache or a walk bounded like `/api/info`'s,
+/// so a very large folder may be measured short. A move within one disk is a rename and
+/// needs no room; so is one whose disks can't be told apart.
+pub(crate) async fn check_transfer_space(
+    state: &AppState,
+    logical: &Path,
+    source: &Path,
+    dest_dir: &Path,
+    is_move: bool,
+) -> Result<(), ApiError> {
+    if is_move {
+        let from = state.disks.disk_of(source).await;
+        let to = state.disks.disk_of(dest_dir).await;
+        if !matches!((from, to), (Some(a), Some(b)) if a.mount_point != b.mount_point) {
+            return Ok(());
+        }
+    }
+    let size = source_size(state, logical, source).await;
+    disks::ensure_space(dest_dir, size, state.min_free_space).await
+}
+
+/// Bytes in `source`: a file's length, or a folder's size measured as `check_transfer_space`
+/// does
+pub(crate) async fn source_size(state: &AppState, logical: &Path, source: &Path) -> u64 {
+    if source.is_dir() {
+        state.dir_sizes.bounded(state, logical, source).await.size
+    } else {
+        fs::metadata(source).await.map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+/// Reserve `len` bytes for a file about to be written, so it's laid out in one piece and a
+/// full disk shows up before any data is copied
+///
+/// Filesystems that can't preallocate just grow the file as it's written.
+async fn preallocate(file: fs::File, len: u64) -> Result<fs::File, ApiError> {
+    use fs4::fs_std::FileExt;
+    let file = file.into_std().await;
+    let (file, result) = tokio::task::spawn_blocking(move || {
+        let result = file.allocate(len);
+        (file, result)
+    })
+    .await
+    .map_err(|e| ApiError::Io(e.to_string()))?;
+    match result.map_err(|e| ApiError::io(Msg::CreateFileFailed, e)) {
+        // Out of space or over a quota; anything else means no preallocation here
+        Err(e @ ApiError::QuotaExceeded(_)) => Err(e),
+        Err(e) => {
+            tracing::debug!("Preallocation unavailable, growing the file instead: {}", e);
+            Ok(fs::File::from_std(file))
+        }
+        Ok(()) => Ok(fs::File::from_std(file)),
+    }
+}
+
+/// Merge the received chunks into the final file, removing it again on failure
+async fn merge_chunks(session: &UploadSession, final_path: &Path) -> Result<u64, ApiError> {
+    // Create final file; the guard removes it unless every chunk is merged
+    let final_file = fs::File::create(final_path)
+        .await
+        .map_err(|e| ApiError::io(Msg::CreateFileFailed, e))?;
+    let partial = TempPath::file(final_path);
+    let mut final_file = preallocate(final_file, session.total_size).await?;
+
+    // Merge chunks in order
+    let mut total_written: u64 = 0;
+    for i in 0..session.total_chunks {
+        let chunk_path = session.temp_dir.join(format!("chunk_{:06}", i));
+        let chunk_data = fs::read(&chunk_path)
+            .await
+            .map_err(|e| ApiError::io(Msg::MergeChunkFailed.with(&[&i]), e))?;
+
+        final_file
+            .write_all(&chunk_data)
+            .await
+            .map_err(|e| ApiError::io(Msg::MergeChunkFailed.with(&[&i]), e))?;
+
+        total_written += chunk_data.len() as u64;
+    }
+    // Chunks shorter than declared leave preallocated space past the data
+    if total_written < session.total_size {
+        final_file
+            .set_len(total_written)
+            .await
+            .map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    }
+
+    // Sync to disk
+    final_file
+        .sync_all()
+        .await
+        .map_err(|e| ApiError::io(Msg::SyncFileFailed, e))?;
+
+    partial.keep();
+    Ok(total_written)
 }
 
 /// Abort chunked upload - cleanup temp files
+#[utoipa::path(
+    post, path = "/api/upload/abort", tag = "upload", request_body = ChunkedUploadAbortRequest,
+    responses((status = 200, description = "Session and its chunks removed", body = ApiResponse<OperationResponse>)),
+)]
 pub async fn chunked_upload_abort(
     State(state): State<AppState>,
     Json(req): Json<ChunkedUploadAbortRequest>,
 ) -> impl IntoResponse {
-    let upload_id = req.upload_id;
+    remove_upload_session(&state, &req.upload_id).await;
 
-    // Get and remove session
-    let session = {
-        let mut sessions = state.upload_sessions.write().await;
-        sessions.remove(&upload_id)
+    Json(ApiResponse::success(OperationResponse {
+        message: Msg::UploadAborted.into(),
+        new_path: None,
+    })).into_response()
+}
+/// Remove a chunked upload session and its chunk directory
+///
+/// A chunk being written at that moment fails, as its directory is gone.
+async fn remove_upload_session(state: &AppState, upload_id: &str) -> Option<UploadSession> {
+    let session = state.upload_sessions.write().await.remove(upload_id)?;
+    let _ = fs::remove_dir_all(&session.temp_dir).await;
+    Some(session)
+}
+/// Bytes of the received chunks of a session; only the last chunk may be shorter
+fn received_bytes(session: &UploadSession) -> u64 {
+    session
+        .received_chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, received)| **received)
+        .map(|(i, _)| session.chunk_size.min(session.total_size.saturating_sub(i as u64 * session.chunk_size)))
+        .sum()
+}
+/// Remove sessions that received nothing for `idle`, returning how many
+async fn drop_stale_sessions(upload_sessions: &UploadSessions, idle: std::time::Duration) -> usize {
+    let stale: Vec<UploadSession> = {
+        let mut sessions = upload_sessions.write().await;
+        let ids: Vec<String> = sessions
+            .values()
+            .filter(|s| s.idle() > idle)
+            .map(|s| s.upload_id.clone())
+            .collect();
+        ids.iter().filter_map(|id| sessions.remove(id)).collect()
     };
-
-    if let Some(session) = session {
-        // Cleanup temp directory
+    for session in &stale {
+        tracing::info!("Dropping idle chunked upload {} ({})", session.upload_id, session.filename);
         let _ = fs::remove_dir_all(&session.temp_dir).await;
     }
+    stale.len()
+}
 
+/// Unfinished chunked uploads and upload slots in use (administrator only)
+///
+/// Also served at `/api/upload/sessions`, its former path.
+#[utoipa::path(
+    get, path = "/api/admin/uploads", tag = "admin",
+    responses((status = 200, description = "Slot usage and unfinished sessions, oldest first", body = ApiResponse<UploadSessionsResponse>)),
+)]
+pub async fn list_upload_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    if let Some(response) = crate::auth::require_admin(&state, &user) {
+        return response;
+    }
+    let mut sessions: Vec<UploadSessionInfo> = state
+        .upload_sessions
+        .read()
+        .await
+        .values()
+        .map(|s| UploadSessionInfo {
+            upload_id: s.upload_id.clone(),
+            owner: s.owner.clone(),
+            client: s.client.to_string(),
+            filename: s.filename.clone(),
+            path: state.mounts.logical_path(&s.upload_path.join(&s.filename)),
+            total_size: s.total_size,
+            received_bytes: received_bytes(s),
+            received_chunks: s.received_chunks.iter().filter(|&&r| r).count(),
+            total_chunks: s.total_chunks,
+            age_secs: s.created_at.elapsed().as_secs(),
+        })
+        .collect();
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.age_secs));
+    Json(ApiResponse::success(UploadSessionsResponse {
+        usage: state.upload_slots.usage(),
+        sessions,
+    }))
+    .into_response()
+}
+
+/// Cancel someone's unfinished chunked upload, removing its chunks (administrator only)
+///
+/// Further chunks and the final `complete` call of that upload fail with `NOT_FOUND`.
+#[utoipa::path(
+    post, path = "/api/admin/uploads/cancel", tag = "admin", request_body = ChunkedUploadAbortRequest,
+    responses(
+        (status = 200, description = "Session and its chunks removed", body = ApiResponse<OperationResponse>),
+        (status = 404, description = "No such upload session", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn cancel_upload_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<ChunkedUploadAbortRequest>,
+) -> Response {
+    if let Some(response) = crate::auth::require_admin(&state, &user) {
+        return response;
+    }
+    let Some(session) = remove_upload_session(&state, &req.upload_id).await else {
+        return ApiError::NotFound(Msg::UploadSessionNotFound.into()).into_response();
+    };
+    let logical = state.mounts.logical_path(&session.upload_path.join(&session.filename));
+    tracing::info!("{} cancelled the upload of {} by {}", user.username, logical, session.owner);
+    state.audit.record(
+        AuditEntry::new("cancel_upload", &user.username, addr.ip(), &logical).result(&Ok::<(), String>(())),
+    );
     Json(ApiResponse::success(OperationResponse {
-        message: "Upload aborted".to_string(),
+        message: Msg::UploadCancelled.with(&[&session.owner]),
         new_path: None,
+    }))
+    .into_response()
+}
+
+/// Drop chunked upload sessions idle for `--upload-idle-secs`, every `SESSION_REAP_INTERVAL` until shutdown
+///
+/// A chunk still being received keeps its session alive, however slowly it arrives.
+pub fn start_session_reaper(state: AppState, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SESSION_REAP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {
+                    drop_stale_sessions(&state.upload_sessions, state.upload_limits.idle_timeout).await;
+                }
+            }
+        }
+    });
+}
+
+/// Remove the chunk directories of all unfinished upload sessions (run at shutdown)
+pub async fn cleanup_upload_sessions(upload_sessions: &UploadSessions) {
+    let sessions = std::mem::take(&mut *upload_sessions.write().await);
+    for session in sessions.values() {
+        let _ = fs::remove_dir_all(&session.temp_dir).await;
+    }
+    if !sessions.is_empty() {
+        tracing::info!("Removed {} unfinished chunked upload(s)", sessions.len());
+    }
+}
+// ========== Token Authentication API ==========
+
+/// Login - exchange username/password for an expiring bearer token
+#[utoipa::path(
+    post, path = "/api/login", tag = "auth", request_body = LoginRequest, security(()),
+    responses(
+        (status = 200, description = "Bearer token and its lifetime in seconds", body = ApiResponse<LoginResponse>),
+        (status = 401, description = "`INVALID_CREDENTIALS`", body = crate::openapi::ErrorBody),
+        (status = 429, description = "Locked out after repeated failures, see `Retry-After`", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<LoginRequest>,
+) -> impl IntoResponse {
+    let ip = addr.ip();
+    if let Some(retry_after) = crate::auth::lockout_remaining(&state, ip).await {
+        return crate::auth::too_many_attempts(retry_after);
+    }
+
+    let Some(user) = crate::auth::authenticate(&state, &req.username, &req.password) else {
+        crate::auth::record_failure(&state, ip, &req.username).await;
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::<()>::error_with_code("INVALID_CREDENTIALS", Msg::InvalidCredentials)),
+        ).into_response();
+    };
+
+    crate::auth::reset_failures(&state, ip).await;
+
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let now = std::time::Instant::now();
+
+    {
+        let mut tokens = state.auth_tokens.write().await;
+        // Prune expired tokens so the store can't grow unbounded
+        tokens.retain(|_, t| t.expires_at > now);
+        tokens.insert(token.clone(), AuthToken {
+            username: user.username,
+            expires_at: now + std::time::Duration::from_secs(state.token_ttl_secs),
+        });
+    }
+
+    Json(ApiResponse::success(LoginResponse {
+        token,
+        expires_in: state.token_ttl_secs,
     })).into_response()
-}
\ No newline at end of file
+}
+
+/// Logout - revoke the bearer token used for this request
+#[utoipa::path(
+    post, path = "/api/logout", tag = "auth",
+    responses((status = 200, description = "Bearer token revoked", body = ApiResponse<OperationResponse>)),
+)]
+pub async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string());
+
+    if let Some(token) = token {
+        state.auth_tokens.write().await.remove(&token);
+    }
+
+    Json(ApiResponse::success(OperationResponse {
+        message: Msg::LoggedOut.into(),
+        new_path: None,
+    }))
+}
+
+// ========== Cookie Session API ==========
+
+/// Create a browser session - validates credentials and sets an HttpOnly session cookie
+#[utoipa::path(
+    post, path = "/api/session", tag = "auth", request_body = LoginRequest, security(()),
+    responses(
+        (status = 200, description = "Session cookie set", body = ApiResponse<OperationResponse>),
+        (status = 401, description = "`INVALID_CREDENTIALS`", body = crate::openapi::ErrorBody),
+        (status = 429, description = "Locked out after repeated failures, see `Retry-After`", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn create_session(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<LoginRequest>,
+) -> impl IntoResponse {
+    let ip = addr.ip();
+    if let Some(retry_after) = crate::auth::lockout_remaining(&state, ip).await {
+        return crate::auth::too_many_attempts(retry_after);
+    }
+
+    let Some(user) = crate::auth::authenticate(&state, &req.username, &req.password) else {
+        crate::auth::record_failure(&state, ip, &req.username).await;
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::<()>::error_with_code("INVALID_CREDENTIALS", Msg::InvalidCredentials)),
+        ).into_response();
+    };
+
+    crate::auth::reset_failures(&state, ip).await;
+
+    let session_id = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let now = std::time::Instant::now();
+
+    {
+        let mut sessions = state.sessions.write().await;
+        // Prune expired sessions so the store can't grow unbounded
+        let idle = std::time::Duration::from_secs(state.session_idle_secs);
+        let max = std::time::Duration::from_secs(state.session_max_secs);
+        sessions.retain(|_, s| now.duration_since(s.last_seen) <= idle && now.duration_since(s.created_at) <= max);
+        sessions.insert(session_id.clone(), Session {
+            username: user.username,
+            created_at: now,
+            last_seen: now,
+        });
+    }
+
+    (
+        [(header::SET_COOKIE, crate::auth::session_set_cookie(&state, &session_id))],
+        Json(ApiResponse::success(OperationResponse {
+            message: Msg::LoggedIn.into(),
+            new_path: None,
+        })),
+    ).into_response()
+}
+
+/// Delete the browser session - logs out and clears the cookie
+#[utoipa::path(
+    delete, path = "/api/session", tag = "auth",
+    responses((status = 200, description = "Session ended and cookie cleared", body = ApiResponse<OperationResponse>)),
+)]
+pub async fn delete_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(session_id) = crate::auth::session_cookie(&headers) {
+        state.sessions.write().await.remove(&session_id);
+    }
+
+    (
+        [(header::SET_COOKIE, crate::auth::session_set_cookie(&state, ""))],
+        Json(ApiResponse::success(OperationResponse {
+            message: Msg::LoggedOut.into(),
+            new_path: None,
+        })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::{NameForm, DEFAULT_MAX_FILENAME_BYTES};
+
+    const RULES: NameRules = NameRules { max_bytes: DEFAULT_MAX_FILENAME_BYTES, deny_dot_files: false, normalize: None };
+
+    #[test]
+    fn times_are_shown_in_the_zone_and_sent_as_utc() {
+        // 2026-09-01T10:00:00.250Z
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1_788_256_800_250);
+        let shanghai = parse_tz(Some("Asia/Shanghai")).unwrap();
+        assert_eq!(
+            format_time(Ok(time), shanghai),
+            ("2026-09-01 18:00".to_string(), Some("2026-09-01T10:00:00.250Z".to_string()))
+        );
+        let new_york = parse_tz(Some("America/New_York")).unwrap();
+        let (display, at) = format_time(Ok(time), new_york);
+        assert_eq!(display, "2026-09-01 06:00");
+        assert_eq!(at.as_deref(), Some("2026-09-01T10:00:00.250Z"));
+        assert!(DateTime::parse_from_rfc3339(&at.unwrap()).is_ok());
+
+        let unknown = Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+        assert_eq!(format_time(unknown, shanghai), ("-".to_string(), None));
+    }
+
+    #[test]
+    fn time_zones_are_iana_names() {
+        assert_eq!(parse_tz(None).unwrap(), None);
+        assert_eq!(parse_tz(Some("UTC")).unwrap(), Some(Tz::UTC));
+        for name in ["Mars/Olympus", "+08:00", ""] {
+            let err = parse_tz(Some(name)).unwrap_err();
+            assert!(matches!(err, ApiError::BadRequest(_)), "{name}");
+            assert!(err.message().contains(name), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn listings_take_the_tz_parameter() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|_| {});
+        app.write("a.txt", "a");
+        let list = async |tz: &str| {
+            app.json(admin(Method::GET, &format!("/api/files?path=/&tz={tz}")).body(Body::empty()).unwrap()).await
+        };
+
+        let (status, body) = list("Asia/Shanghai").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let modified_at = body["files"][0]["modifiedAt"].as_str().unwrap();
+        assert!(modified_at.ends_with('Z') && DateTime::parse_from_rfc3339(modified_at).is_ok(), "{modified_at}");
+
+        let (status, body) = list("Not/AZone").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "BAD_REQUEST");
+    }
+
+    #[test]
+    fn folder_names_split_on_slashes() {
+        assert_eq!(folder_names(&RULES, "a").unwrap(), ["a"]);
+        assert_eq!(folder_names(&RULES, "a/b/c").unwrap(), ["a", "b", "c"]);
+        assert_eq!(folder_names(&RULES, "a/b/").unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn folder_names_refuse_any_bad_component() {
+        for name in ["", "/", "a//b", "/a", "a/./b", "a/../b", "a/b//", "a\\b/c"] {
+            assert!(folder_names(&RULES, name).is_err(), "{name}");
+        }
+        let dots = NameRules { deny_dot_files: true, ..RULES };
+        assert!(folder_names(&dots, "a/.b").is_err());
+    }
+
+    #[test]
+    fn folder_names_are_normalized() {
+        let nfc = NameRules { normalize: Some(NameForm::Nfc), ..RULES };
+        assert_eq!(folder_names(&nfc, "cafe\u{301}/x").unwrap(), ["caf\u{e9}", "x"]);
+    }
+
+    #[tokio::test]
+    async fn folders_contain_themselves_and_what_is_below() {
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().join("a");
+        std::fs::create_dir_all(folder.join("b/c")).unwrap();
+        std::fs::create_dir(root.path().join("ab")).unwrap();
+
+        assert!(is_within_folder(&folder, &folder).await);
+        assert!(is_within_folder(&folder.join("b/c"), &folder).await);
+        assert!(!is_within_folder(root.path(), &folder).await);
+        // A sibling whose name starts with the folder's is not inside it
+        assert!(!is_within_folder(&root.path().join("ab"), &folder).await);
+        // Spellings with `..` resolve first
+        assert!(is_within_folder(&folder.join("b/../b/c"), &folder).await);
+        assert!(!is_within_folder(&folder.join("../ab"), &folder).await);
+    }
+
+    #[tokio::test]
+    async fn a_folder_not_yet_created_is_compared_as_written() {
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().join("a");
+        std::fs::create_dir(&folder).unwrap();
+        assert!(is_within_folder(&folder.join("new/deeper"), &folder).await);
+        assert!(!is_within_folder(&root.path().join("new"), &folder).await);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn links_into_the_folder_count_as_inside() {
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().join("a");
+        std::fs::create_dir_all(folder.join("b")).unwrap();
+        std::os::unix::fs::symlink(folder.join("b"), root.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(&folder, root.path().join("alias")).unwrap();
+
+        assert!(is_within_folder(&root.path().join("link"), &folder).await);
+        assert!(is_within_folder(&root.path().join("alias/b"), &folder).await);
+        assert!(is_within_folder(&folder.join("b"), &root.path().join("alias")).await);
+        // A link out of the folder is outside it
+        std::os::unix::fs::symlink(root.path(), folder.join("up")).unwrap();
+        assert!(!is_within_folder(&folder.join("up"), &folder).await);
+    }
+
+    fn conditional(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value.parse().unwrap());
+        headers
+    }
+
+    async fn listing(headers: &HeaderMap, content_type: &str, body: &'static str) -> Response {
+        with_listing_etag(headers, ([(header::CONTENT_TYPE, content_type)], body).into_response()).await
+    }
+
+    fn tag_of(response: &Response) -> String {
+        response.headers()[header::ETAG].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn listings_are_tagged_by_content() {
+        let none = HeaderMap::new();
+        let first = listing(&none, "application/json", "[1]").await;
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(first.headers()[header::CACHE_CONTROL], "no-cache");
+        let tag = tag_of(&first);
+        assert_eq!(axum::body::to_bytes(first.into_body(), usize::MAX).await.unwrap(), "[1]");
+
+        assert_eq!(tag_of(&listing(&none, "application/json", "[1]").await), tag);
+        assert_ne!(tag_of(&listing(&none, "application/json", "[2]").await), tag);
+        // Another format of the same bytes is another representation
+        assert_ne!(tag_of(&listing(&none, "text/plain", "[1]").await), tag);
+    }
+
+    #[tokio::test]
+    async fn listings_the_client_has_are_not_sent_again() {
+        let tag = tag_of(&listing(&HeaderMap::new(), "application/json", "[1]").await);
+        for value in [tag.clone(), format!("W/{tag}"), format!("\"other\", {tag}"), "*".to_string()] {
+            let response = listing(&conditional(header::IF_NONE_MATCH, &value), "application/json", "[1]").await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{value}");
+            assert_eq!(tag_of(&response), tag);
+            assert!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().is_empty());
+        }
+        let stale = conditional(header::IF_NONE_MATCH, &tag);
+        assert_eq!(listing(&stale, "application/json", "[2]").await.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn if_none_match_wins_over_if_modified_since() {
+        let modified = DateTime::parse_from_rfc2822("Tue, 01 Sep 2026 10:00:00 GMT").unwrap().to_utc();
+        let tag = etag(10, Some(modified));
+        assert_eq!(tag, etag(10, Some(modified)));
+        assert_ne!(tag, etag(11, Some(modified)));
+
+        assert!(!not_modified(&HeaderMap::new(), &tag, Some(modified)));
+        assert!(not_modified(&conditional(header::IF_NONE_MATCH, &tag), &tag, Some(modified)));
+        assert!(!not_modified(&conditional(header::IF_NONE_MATCH, "\"other\""), &tag, Some(modified)));
+
+        let since = |date| conditional(header::IF_MODIFIED_SINCE, date);
+        assert!(not_modified(&since("Tue, 01 Sep 2026 10:00:00 GMT"), &tag, Some(modified)));
+        assert!(not_modified(&since("Wed, 02 Sep 2026 10:00:00 GMT"), &tag, Some(modified)));
+        assert!(!not_modified(&since("Tue, 01 Sep 2026 09:59:59 GMT"), &tag, Some(modified)));
+        assert!(!not_modified(&since("yesterday"), &tag, Some(modified)));
+        assert!(!not_modified(&since("Tue, 01 Sep 2026 10:00:00 GMT"), &tag, None));
+
+        let mut both = since("Wed, 02 Sep 2026 10:00:00 GMT");
+        both.insert(header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        assert!(!not_modified(&both, &tag, Some(modified)));
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse_range(Some("bytes=0-"), 10), Ok(Some(vec![(0, 9)])));
+        assert_eq!(parse_range(Some("bytes=2-4"), 10), Ok(Some(vec![(2, 4)])));
+        assert_eq!(parse_range(Some("bytes=-3"), 10), Ok(Some(vec![(7, 9)])));
+        // Past the end is cut short
+        assert_eq!(parse_range(Some("bytes=-30"), 10), Ok(Some(vec![(0, 9)])));
+        assert_eq!(parse_range(Some("bytes=8-100"), 10), Ok(Some(vec![(8, 9)])));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        for range in ["bytes=10-", "bytes=10-20", "bytes=5-4", "bytes=-0", "bytes=x-1", "bytes=10-,20-30"] {
+            assert_eq!(parse_range(Some(range), 10), Err(()), "{range}");
+        }
+        assert_eq!(parse_range(Some("bytes=-1"), 0), Err(()));
+        assert_eq!(parse_range(Some("bytes=0-"), 0), Err(()));
+    }
+
+    #[test]
+    fn ignored_range_headers() {
+        for range in [None, Some("items=0-1"), Some("bytes=5"), Some("bytes=0-1,5")] {
+            assert_eq!(parse_range(range, 10), Ok(None), "{range:?}");
+        }
+        // Too many parts: the whole file
+        let many = (0..=MAX_BYTE_RANGES).map(|i| format!("{}-{}", i * 2, i * 2)).collect::<Vec<_>>();
+        assert_eq!(parse_range(Some(&format!("bytes={}", many.join(","))), 1000), Ok(None));
+    }
+
+    #[test]
+    fn several_ranges_are_sorted_and_merged() {
+        assert_eq!(parse_range(Some("bytes=0-1, 5-6"), 10), Ok(Some(vec![(0, 1), (5, 6)])));
+        assert_eq!(parse_range(Some("bytes=-2,0-1"), 10), Ok(Some(vec![(0, 1), (8, 9)])));
+        // Overlapping and adjacent ranges become one
+        assert_eq!(parse_range(Some("bytes=0-4,2-6,7-7"), 10), Ok(Some(vec![(0, 7)])));
+        assert_eq!(parse_range(Some("bytes=0-0,0-0,0-0"), 10), Ok(Some(vec![(0, 0)])));
+        // Unsatisfiable ranges among others are dropped
+        assert_eq!(parse_range(Some("bytes=0-1,,20-30"), 10), Ok(Some(vec![(0, 1)])));
+    }
+
+    async fn download(file: &Path, range: &str) -> (StatusCode, HeaderMap, String) {
+        let response = file_response(file, &conditional(header::RANGE, range), "attachment").await;
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn files_are_served_in_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("digits.txt");
+        std::fs::write(&file, "0123456789").unwrap();
+
+        let (status, headers, body) = download(&file, "bytes=-3").await;
+        assert_eq!((status, body.as_str()), (StatusCode::PARTIAL_CONTENT, "789"));
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 7-9/10");
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(headers[header::CONTENT_LENGTH], "3");
+
+        let (status, _, body) = download(&file, "bytes=0-").await;
+        assert_eq!((status, body.as_str()), (StatusCode::PARTIAL_CONTENT, "0123456789"));
+
+        let (status, headers, body) = download(&file, "bytes=10-").await;
+        assert_eq!((status, body.as_str()), (StatusCode::RANGE_NOT_SATISFIABLE, ""));
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes */10");
+
+        let (status, _, body) = download(&file, "bytes=5").await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "0123456789"));
+    }
+
+    #[tokio::test]
+    async fn several_ranges_are_served_as_multipart() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("digits.txt");
+        std::fs::write(&file, "0123456789").unwrap();
+
+        let (status, headers, body) = download(&file, "bytes=-2,1-2").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert!(headers.get(header::CONTENT_RANGE).is_none());
+        let content_type = headers[header::CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        assert_eq!(headers[header::CONTENT_LENGTH], body.len().to_string());
+        let part = |range: &str, data: &str| {
+            format!("\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes {range}/10\r\n\r\n{data}")
+        };
+        let expected = format!("{}{}\r\n--{boundary}--\r\n", part("1-2", "12"), part("8-9", "89"));
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn trashed_files_cannot_be_read_by_path() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|_| {});
+        app.write("notes.md", "secret");
+        let delete = admin(Method::DELETE, "/api/delete")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"path":"/notes.md"}"#))
+            .unwrap();
+        let (status, body) = app.json(delete).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        // Trashed items are kept under their id
+        let id = std::fs::read_dir(app.path(".filest_trash")).unwrap().next().unwrap().unwrap().file_name();
+        let trashed = format!("/.filest_trash/{}", id.to_string_lossy());
+        assert_eq!(app.read(&trashed), b"secret");
+        for uri in [
+            format!("/api/download?path={trashed}"),
+            format!("/api/download?path={trashed}&format=zip"),
+            format!("/api/info?path={trashed}"),
+            format!("/api/content?path={trashed}"),
+            format!("/api/render/markdown?path={trashed}"),
+            format!("/api/media-info?path={trashed}"),
+            "/api/files?path=/.filest_trash".to_string(),
+            format!("/raw{trashed}"),
+        ] {
+            let response = app.send(admin(Method::GET, &uri).body(Body::empty()).unwrap()).await;
+            let status = response.status();
+            assert!(matches!(status, StatusCode::FORBIDDEN | StatusCode::NOT_FOUND), "{uri}: {status}");
+        }
+    }
+
+    #[tokio::test]
+    async fn hidden_dotfiles_are_not_listed_or_served() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|c| c.hide = vec![".*".to_string()]);
+        app.write("a.txt", "visible");
+        app.write(".env", "TOKEN=1");
+        app.write("sub/.env", "TOKEN=2");
+
+        for dir in ["/", "/sub"] {
+            let request = admin(Method::GET, &format!("/api/files?path={dir}")).body(Body::empty()).unwrap();
+            let (status, body) = app.json(request).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let names: Vec<&str> = body["files"].as_array().unwrap().iter().map(|f| f["name"].as_str().unwrap()).collect();
+            assert!(!names.contains(&".env"), "{dir}: {names:?}");
+        }
+        for uri in ["/api/download?path=/.env", "/api/download?path=/sub/.env", "/api/content?path=/.env", "/raw/sub/.env"] {
+            let status = app.send(admin(Method::GET, uri).body(Body::empty()).unwrap()).await.status();
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn protected_subtrees_refuse_writes_and_moves_into_them() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|c| c.protect = vec!["secrets".to_string()]);
+        app.write("secrets/keys/id.txt", "key");
+        app.write("a.txt", "outside");
+        let send = async |method: Method, uri: &str, body: &str| {
+            let request = admin(method, uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            app.json(request).await
+        };
+
+        let (status, body) = send(Method::POST, "/api/file", r#"{"path":"/secrets/keys","name":"new.txt"}"#).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert!(!app.path("secrets/keys/new.txt").exists());
+        let (status, body) = send(Method::POST, "/api/folder", r#"{"path":"/secrets","name":"more"}"#).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = send(Method::PUT, "/api/rename", r#"{"path":"/secrets/keys/id.txt","newName":"b.txt"}"#).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = send(Method::DELETE, "/api/delete", r#"{"path":"/secrets/keys"}"#).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert_eq!(app.read("secrets/keys/id.txt"), b"key");
+
+        let (status, body) = send(Method::PUT, "/api/move", r#"{"source":"/a.txt","destination":"/secrets/keys"}"#).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert_eq!(body["code"], "ACCESS_DENIED");
+        assert_eq!(app.read("a.txt"), b"outside");
+        assert!(!app.path("secrets/keys/a.txt").exists());
+    }
+
+    /// "café" composed (NFC) and decomposed (NFD), as Linux and macOS clients send it
+    const CAFE_NFC: &str = "caf\u{e9}.txt";
+    const CAFE_NFD: &str = "cafe\u{301}.txt";
+
+    #[tokio::test]
+    async fn names_differing_only_in_normalization_collide() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|_| {});
+        app.write(CAFE_NFC, "composed");
+        app.write("other.txt", "other");
+        let send = async |method: Method, uri: &str, body: serde_json::Value| {
+            let request = admin(method, uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            app.json(request).await
+        };
+
+        let (status, body) = send(Method::POST, "/api/file", serde_json::json!({ "path": "/", "name": CAFE_NFD })).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{body}");
+        let (status, body) = send(Method::PUT, "/api/rename", serde_json::json!({ "path": "/other.txt", "newName": CAFE_NFD })).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{body}");
+        assert_eq!(body["code"], "ALREADY_EXISTS");
+
+        let mut names: Vec<String> = std::fs::read_dir(app.root())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| !n.starts_with('.'))
+            .collect();
+        names.sort();
+        assert_eq!(names, [CAFE_NFC, "other.txt"]);
+        assert_eq!(app.read(CAFE_NFC), b"composed");
+    }
+
+    #[tokio::test]
+    async fn new_names_are_stored_normalized_when_asked() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|c| c.normalize_names = Some(crate::paths::NameForm::Nfc));
+        let request = admin(Method::POST, "/api/file")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({ "path": "/", "name": CAFE_NFD }).to_string()))
+            .unwrap();
+        let (status, body) = app.json(request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(app.path(CAFE_NFC).exists());
+        assert!(std::fs::read_dir(app.root()).unwrap().all(|e| e.unwrap().file_name() != CAFE_NFD));
+    }
+
+    #[tokio::test]
+    async fn search_ignores_normalization() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|_| {});
+        app.write(&format!("nfc/{CAFE_NFC}"), "");
+        app.write(&format!("nfd/{CAFE_NFD}"), "");
+        // "café" percent-encoded both ways
+        for query in ["caf%C3%A9", "cafe%CC%81"] {
+            let uri = format!("/api/search?query={query}");
+            let (status, body) = app.json(admin(Method::GET, &uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let mut paths: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["path"].as_str().unwrap()).collect();
+            paths.sort();
+            let expected = [format!("/nfc/{CAFE_NFC}"), format!("/nfd/{CAFE_NFD}")];
+            assert_eq!(paths, expected, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn stopped_searches_return_nothing_more() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|_| {});
+        for i in 0..20 {
+            app.write(&format!("d{i}/match{i}.txt"), "");
+        }
+        let search = async |uri: &str| {
+            let (status, body) = app.json(admin(Method::GET, uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            body
+        };
+
+        let body = search("/api/search?query=match").await;
+        assert_eq!(body["results"].as_array().unwrap().len(), 20, "{body}");
+
+        // Out of time before the first entry
+        let body = search("/api/search?query=match&timeout_secs=0").await;
+        assert_eq!(body["timedOut"], true, "{body}");
+        assert_eq!(body["results"], serde_json::json!([]));
+        assert_eq!(body["dirsScanned"], 0);
+
+        // Cancelled by the server stopping
+        app.state.shutdown.cancel();
+        let body = search("/api/search?query=match").await;
+        assert_eq!(body["results"], serde_json::json!([]), "{body}");
+        assert_eq!(body["dirsScanned"], 0);
+    }
+
+    #[tokio::test]
+    async fn search_budgets_stop_when_cancelled() {
+        let app = crate::testing::TestApp::with(|_| {});
+        let mut budget = SearchBudget::new(&app.state, "/".to_string(), None);
+        assert!(!budget.exhausted());
+        app.state.shutdown.cancel();
+        assert!(budget.exhausted());
+        assert!(!budget.timed_out);
+        budget.finished = true;
+    }
+
+    #[tokio::test]
+    async fn deleting_folders_needs_force_over_the_threshold() {
+        use crate::testing::{admin, TestApp};
+        use axum::http::Method;
+        let app = TestApp::with(|c| c.confirm_delete_entries = Some(3));
+        app.write("small/a.txt", "a");
+        for i in 0..5 {
+            app.write(&format!("large/{i}.txt"), "x");
+        }
+        let delete = async |body: &str| {
+            let request = admin(Method::DELETE, "/api/delete")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            app.json(request).await
+        };
+
+        let (status, body) = delete(r#"{"path":"/"}"#).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert!(app.path("small/a.txt").exists());
+
+        let (status, body) = delete(r#"{"path":"/small"}"#).await;
+        assert_eq!((status, &body["success"]), (StatusCode::OK, &serde_json::Value::Bool(true)), "{body}");
+        assert!(!app.path("small").exists());
+
+        let (_, body) = delete(r#"{"path":"/large"}"#).await;
+        assert_eq!(body["code"], "CONFIRM_REQUIRED", "{body}");
+        assert!(body["entries"].as_u64().unwrap() > 3, "{body}");
+        assert!(app.path("large/4.txt").exists());
+
+        let (status, body) = delete(r#"{"path":"/large","force":true}"#).await;
+        assert_eq!((status, &body["success"]), (StatusCode::OK, &serde_json::Value::Bool(true)), "{body}");
+        assert!(!app.path("large").exists());
+    }
+}
@@ -0,0 +1,171 @@
+use axum::{
+    extract::{Extension, State},
+    http::HeaderMap,
+    Json,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{RwLock, Semaphore};
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, safe_path};
+use crate::i18n::Msg;
+use crate::models::{ApiResponse, UploadCheckItem, UploadCheckResponse, UploadCheckResult};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Most files in one `/api/upload/check` request
+const MAX_CHECK_FILES: usize = 1000;
+/// Hashes kept; the oldest is dropped to make room
+const MAX_CACHED: usize = 10_000;
+/// Files hashed at a time on behalf of upload checks and skips
+const HASH_CONCURRENCY: usize = 2;
+
+/// SHA-256 of a file's content, streamed
+pub(crate) async fn file_sha256(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Lowercase SHA-256 values listed in `If-None-Match`, quoted as entity tags
+///
+/// Other tags, such as the size-and-time ones of downloads or `*`, aren't content hashes
+/// and are left out.
+pub(crate) fn content_tags(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(axum::http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/").trim_matches('"'))
+        .filter(|tag| tag.len() == 64 && tag.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+struct CachedHash {
+    size: u64,
+    modified: Option<SystemTime>,
+    sha256: String,
+    at: Instant,
+}
+
+/// Content hashes of files, reused while their size and modification time stay the same
+///
+/// Keyed by the file's path on disk, so a file reached through several logical paths is
+/// hashed once. A file changed without its mtime moving (within the filesystem's
+/// resolution) keeps its old hash until the size changes too.
+pub struct HashCache {
+    entries: RwLock<HashMap<PathBuf, CachedHash>>,
+    hashing: Semaphore,
+}
+
+impl Default for HashCache {
+    fn default() -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+            hashing: Semaphore::new(HASH_CONCURRENCY),
+        }
+    }
+}
+
+impl HashCache {
+    /// SHA-256 of the file at `path`, whose current metadata is `metadata`
+    pub async fn sha256(&self, path: &Path, metadata: &Metadata) -> std::io::Result<String> {
+        let modified = metadata.modified().ok();
+        if let Some(cached) = self.entries.read().await.get(path)
+            && cached.size == metadata.len()
+            && cached.modified == modified
+        {
+            return Ok(cached.sha256.clone());
+        }
+
+        let _permit = self.hashing.acquire().await.map_err(std::io::Error::other)?;
+        let sha256 = file_sha256(path).await?;
+        // A file written to while it was read has no trustworthy hash to keep
+        let after = fs::metadata(path).await?;
+        if after.len() == metadata.len() && after.modified().ok() == modified {
+            let mut entries = self.entries.write().await;
+            if entries.len() >= MAX_CACHED
+                && !entries.contains_key(path)
+                && let Some(oldest) = entries.iter().min_by_key(|(_, c)| c.at).map(|(p, _)| p.clone())
+            {
+                entries.remove(&oldest);
+            }
+            entries.insert(
+                path.to_path_buf(),
+                CachedHash { size: metadata.len(), modified, sha256: sha256.clone(), at: Instant::now() },
+            );
+        }
+        Ok(sha256)
+    }
+}
+
+/// Whether a file with this size and hash is already stored at `path`
+async fn check_one(state: &AppState, user: &AuthUser, item: &UploadCheckItem) -> Result<(bool, bool), ApiError> {
+    let paths = safe_path(&state.mounts, &item.path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    // Hashes of protected files would reveal what can't be downloaded
+    check_patterns(state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    let metadata = match fs::metadata(&paths.actual).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Ok((false, false)),
+    };
+    if metadata.len() != item.size {
+        return Ok((true, false));
+    }
+    let sha256 = state
+        .hashes
+        .sha256(&paths.actual, &metadata)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+    Ok((true, sha256.eq_ignore_ascii_case(item.sha256.trim())))
+}
+
+/// Which of the listed files the server already has, so a sync client can skip them
+///
+/// A file matches when one is stored at its path with the same size and SHA-256. Hashes
+/// are computed on the server and cached by path, size and modification time, so checking
+/// unchanged files again is cheap. `exists: true, matches: false` means a different file
+/// is in the way, which the upload then replaces. Entries that can't be checked carry an
+/// `error` instead of failing the whole request.
+#[utoipa::path(
+    post, path = "/api/upload/check", tag = "upload", request_body = Vec<UploadCheckItem>,
+    responses(
+        (status = 200, description = "One result per listed file, in request order", body = ApiResponse<UploadCheckResponse>),
+        (status = 400, description = "More than 1000 files", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn check_uploads(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(items): Json<Vec<UploadCheckItem>>,
+) -> Result<Json<ApiResponse<UploadCheckResponse>>, ApiError> {
+    if items.len() > MAX_CHECK_FILES {
+        return Err(ApiError::BadRequest(Msg::TooManyCheckFiles.with(&[&MAX_CHECK_FILES])));
+    }
+    let mut files = Vec::with_capacity(items.len());
+    for item in items {
+        let (exists, matches, error) = match check_one(&state, &user, &item).await {
+            Ok((exists, matches)) => (exists, matches, None),
+            Err(e) => (false, false, Some(e.message().to_string())),
+        };
+        files.push(UploadCheckResult { path: item.path, exists, matches, error });
+    }
+    Ok(Json(ApiResponse::success(UploadCheckResponse { files })))
+}
@@ -0,0 +1,121 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+use crate::models::{HealthDetails, HealthQuery, HealthResponse, RootHealth};
+use crate::AppState;
+
+/// Upper bound for each filesystem check, so a hung mount fails the probe instead of blocking it
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether the root can be statted and is a directory
+async fn root_accessible(root: &Path) -> bool {
+    matches!(
+        tokio::time::timeout(CHECK_TIMEOUT, fs::metadata(root)).await,
+        Ok(Ok(metadata)) if metadata.is_dir()
+    )
+}
+
+/// Create, write and remove a uniquely named probe file in the root
+///
+/// `create_new` guarantees an existing file is never opened, so user files are never touched.
+async fn root_writable(root: &Path) -> bool {
+    let probe = root.join(format!("{}health_{}.tmp", crate::paths::INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let check = async {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+            .await?;
+        let written = file.write_all(b"ok").await;
+        drop(file);
+        let removed = fs::remove_file(&probe).await;
+        written.and(removed)
+    };
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(result) => result.is_ok(),
+        Err(_) => {
+            // The probe may have been created before timing out
+            let _ = fs::remove_file(&probe).await;
+            false
+        }
+    }
+}
+
+/// Health and readiness check (no authentication)
+///
+/// Returns 200 when every root is accessible (and writable, unless the write probe is
+/// disabled) and 503 naming the failed checks otherwise. `?verbose=false` only stats the
+/// roots and returns just the status.
+#[utoipa::path(
+    get, path = "/api/health", tag = "system", params(HealthQuery), security(()),
+    responses(
+        (status = 200, description = "All roots accessible", body = HealthResponse),
+        (status = 503, description = "A root is inaccessible", body = HealthResponse),
+    ),
+)]
+pub async fn health(
+    State(state): State<AppState>,
+    Query(query): Query<HealthQuery>,
+) -> impl IntoResponse {
+    let verbose = query.verbose.unwrap_or(true);
+    let multi = state.mounts.is_multi();
+
+    let mut failed = Vec::new();
+    let mut roots = Vec::new();
+    for mount in state.mounts.list() {
+        let path = if multi { format!("/{}", mount.name) } else { "/".to_string() };
+        // Check names carry the mount when there are several
+        let check_name = |check: &str| if multi { format!("{}:{}", check, path) } else { check.to_string() };
+
+        let accessible = root_accessible(&mount.root).await;
+        if !accessible {
+            failed.push(check_name("root_accessible"));
+        }
+        if !verbose {
+            continue;
+        }
+
+        // An inaccessible root has already failed; don't probe it
+        let writable = if state.health_write_probe && accessible {
+            Some(root_writable(&mount.root).await)
+        } else {
+            None
+        };
+        if writable == Some(false) {
+            failed.push(check_name("root_writable"));
+        }
+        roots.push(RootHealth {
+            path,
+            accessible,
+            writable,
+            free_bytes: state.disks.disk_of(&mount.root).await.map(|d| d.free),
+        });
+    }
+
+    let details = if verbose {
+        Some(HealthDetails {
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_secs: state.started_at.elapsed().as_secs(),
+            active_uploads: state.upload_sessions.read().await.len(),
+            uploads: state.upload_slots.usage(),
+            roots,
+        })
+    } else {
+        None
+    };
+
+    let status = if failed.is_empty() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(HealthResponse {
+        status: if failed.is_empty() { "ok" } else { "error" },
+        failed,
+        details,
+    }))
+}
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use crate::audit::AuditEntry;
+use crate::error::ApiError;
+use crate::i18n::Msg;
+use crate::AppState;
+
+/// Characters of the command's output kept in errors and the audit log
+const MAX_OUTPUT_CHARS: usize = 500;
+
+/// What a failing `--upload-hook` does to the upload
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HookMode {
+    /// Delete the file and fail the upload with `REJECTED_BY_HOOK`
+    #[default]
+    Reject,
+    /// Keep the file, with a warning in the response and the audit log
+    Warn,
+}
+
+/// The command template split into arguments, with `{file}`, `{name}` and `{user}` filled in
+///
+/// The template is split on whitespace before substituting, so a value containing spaces
+/// stays one argument. No shell is involved.
+pub fn command_args(template: &str, file: &str, name: &str, user: &str) -> Vec<String> {
+    let values = [("{file}", file), ("{name}", name), ("{user}", user)];
+    template.split_whitespace().map(|arg| expand(arg, &values)).collect()
+}
+
+/// `arg` with each placeholder replaced by its value
+///
+/// Done in one pass, so a file named `{user}.txt` stays that name instead of being
+/// expanded again. Other braces are kept as they are.
+fn expand(arg: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match values.iter().find(|(placeholder, _)| rest.starts_with(placeholder)) {
+            Some((placeholder, value)) => {
+                out.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The last `MAX_OUTPUT_CHARS` characters of what the command printed, trimmed
+fn tail(output: &[u8]) -> String {
+    let text = String::from_utf8_lossy(output);
+    let text = text.trim();
+    let skip = text.chars().count().saturating_sub(MAX_OUTPUT_CHARS);
+    text.chars().skip(skip).collect()
+}
+
+/// A command run on each uploaded file before it is stored under its name (`--upload-hook`)
+pub struct UploadHook {
+    command: String,
+    mode: HookMode,
+    timeout: Duration,
+    /// Hook commands running at once (`--upload-hook-jobs`)
+    running: Semaphore,
+}
+
+impl UploadHook {
+    pub fn new(command: String, mode: HookMode, timeout: Duration, jobs: usize) -> Self {
+        Self { command, mode, timeout, running: Semaphore::new(jobs) }
+    }
+
+    /// Run the command on `file`, returning why it failed if it did
+    ///
+    /// A command still running after the timeout is killed and counts as failed.
+    async fn run(&self, file: &Path, name: &str, user: &str) -> Result<(), String> {
+        let args = command_args(&self.command, &file.to_string_lossy(), name, user);
+        let Some((program, args)) = args.split_first() else {
+            return Ok(());
+        };
+        let _permit = self.running.acquire().await.map_err(|e| e.to_string())?;
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| Msg::HookStartFailed.with(&[&program, &e]))?;
+        let output = match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+            Ok(output) => output.map_err(|e| Msg::HookStartFailed.with(&[&program, &e]))?,
+            Err(_) => return Err(Msg::HookTimedOut.with(&[&self.timeout.as_secs()])),
+        };
+        if output.status.success() {
+            return Ok(());
+        }
+        // Scanners such as clamdscan report findings on stdout
+        let mut detail = tail(&output.stderr);
+        if detail.is_empty() {
+            detail = tail(&output.stdout);
+        }
+        Err(Msg::HookFailed.with(&[&output.status, &detail]))
+    }
+}
+
+/// Pass a written upload through `--upload-hook`, if one is set, before it is moved into place
+///
+/// `file` is where the data was written, `logical` the path it is being uploaded to.
+/// Failures are audited as `upload_hook`. In reject mode they come back as
+/// `REJECTED_BY_HOOK` for the caller to discard the file; in warn mode as the warning to
+/// report with the upload.
+pub async fn check_upload(
+    state: &AppState,
+    file: &Path,
+    logical: &str,
+    user: &str,
+    ip: IpAddr,
+) -> Result<Option<String>, ApiError> {
+    let Some(hook) = &state.upload_hook else {
+        return Ok(None);
+    };
+    let name = logical.rsplit('/').next().unwrap_or_default();
+    let Err(reason) = hook.run(file, name, user).await else {
+        return Ok(None);
+    };
+    state.audit.record(AuditEntry::new("upload_hook", user, ip, logical).result(&Err::<(), _>(&reason)));
+    match hook.mode {
+        HookMode::Reject => Err(ApiError::RejectedByHook(Msg::RejectedByHook.with(&[&reason]))),
+        HookMode::Warn => Ok(Some(Msg::HookWarning.with(&[&reason]))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let args = command_args("scan --file={file} {name} -u {user}", "/tmp/up.part", "report.pdf", "alice");
+        assert_eq!(args, ["scan", "--file=/tmp/up.part", "report.pdf", "-u", "alice"]);
+        assert_eq!(command_args("echo {name}{name}", "f", "x", "u"), ["echo", "xx"]);
+    }
+
+    #[test]
+    fn values_with_spaces_stay_one_argument() {
+        let args = command_args("  check   {file}  ", "/data/my report.pdf", "my report.pdf", "bob");
+        assert_eq!(args, ["check", "/data/my report.pdf"]);
+    }
+
+    #[test]
+    fn values_are_not_expanded_again() {
+        let args = command_args("hook {name} {user}", "/tmp/x", "{user}{file}.txt", "alice");
+        assert_eq!(args, ["hook", "{user}{file}.txt", "alice"]);
+    }
+
+    #[test]
+    fn other_braces_are_kept() {
+        assert_eq!(command_args("a {} {nam {name}} {", "f", "n", "u"), ["a", "{}", "{nam", "n}", "{"]);
+        assert!(command_args("   ", "f", "n", "u").is_empty());
+    }
+
+    #[test]
+    fn output_keeps_its_trimmed_end() {
+        assert_eq!(tail(b"  found: EICAR  \n"), "found: EICAR");
+        let long = format!("{}end", "x".repeat(MAX_OUTPUT_CHARS));
+        let kept = tail(long.as_bytes());
+        assert_eq!(kept.chars().count(), MAX_OUTPUT_CHARS);
+        assert!(kept.ends_with("end"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn commands_pass_fail_and_time_out() {
+        let hook = |command: &str, timeout| UploadHook::new(command.to_string(), HookMode::Reject, timeout, 1);
+        let file = std::env::temp_dir();
+        let enough = Duration::from_secs(5);
+        assert_eq!(hook("test -d {file}", enough).run(&file, "x", "u").await, Ok(()));
+        assert!(hook("test -f {file}", enough).run(&file, "x", "u").await.is_err());
+        assert!(hook("filest-no-such-command", enough).run(&file, "x", "u").await.is_err());
+        let slow = hook("sleep 5", Duration::from_millis(100)).run(&file, "x", "u").await;
+        assert_eq!(slow, Err(Msg::HookTimedOut.with(&[&0])));
+    }
+}
@@ -0,0 +1,353 @@
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Language of the messages in API responses
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Lang {
+    /// Simplified Chinese
+    #[default]
+    #[serde(rename = "zh-CN")]
+    #[value(name = "zh-CN")]
+    ZhCn,
+    /// English
+    #[serde(rename = "en")]
+    #[value(name = "en")]
+    En,
+}
+
+impl Lang {
+    /// Match a language tag such as `en-US` or `zh-Hans-CN`
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        if primary.eq_ignore_ascii_case("zh") {
+            Some(Self::ZhCn)
+        } else if primary.eq_ignore_ascii_case("en") {
+            Some(Self::En)
+        } else {
+            None
+        }
+    }
+
+    /// Pick the supported language the client prefers most, honouring `q` weights
+    pub fn negotiate(accept_language: Option<&str>, default: Lang) -> Lang {
+        let Some(header) = accept_language else {
+            return default;
+        };
+        let mut best: Option<(f32, Lang)> = None;
+        for item in header.split(',') {
+            let mut parts = item.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let lang = if tag == "*" { Some(default) } else { Self::from_tag(tag) };
+            // The first of equally weighted tags wins
+            if let Some(lang) = lang
+                && q > 0.0
+                && best.is_none_or(|(best_q, _)| q > best_q)
+            {
+                best = Some((q, lang));
+            }
+        }
+        best.map_or(default, |(_, lang)| lang)
+    }
+}
+
+tokio::task_local! {
+    static LANG: Lang;
+}
+
+/// Language of the request being handled
+pub fn current() -> Lang {
+    LANG.try_with(|lang| *lang).unwrap_or_default()
+}
+
+/// Run the request with the language from `Accept-Language` (or `--default-lang`) in scope
+///
+/// Messages are looked up when errors and responses are built, which may be deep inside
+/// helpers, so the choice is kept in a task-local instead of being passed around.
+pub async fn select_lang(State(default): State<Lang>, request: Request, next: Next) -> Response {
+    let header = request
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok());
+    let lang = Lang::negotiate(header, default);
+    LANG.scope(lang, next.run(request)).await
+}
+
+macro_rules! messages {
+    ($($name:ident => $zh:literal, $en:literal;)*) => {
+        /// Keys of the message catalog
+        ///
+        /// Only the text is localized; the `code` sent with an error never changes.
+        #[derive(Clone, Copy, Debug)]
+        pub enum Msg {
+            $($name,)*
+        }
+
+        impl Msg {
+            /// The message in `lang`; `{}` marks where arguments go
+            pub fn text_in(self, lang: Lang) -> &'static str {
+                match lang {
+                    Lang::ZhCn => match self {
+                        $(Self::$name => $zh,)*
+                    },
+                    Lang::En => match self {
+                        $(Self::$name => $en,)*
+                    },
+                }
+            }
+        }
+    };
+}
+
+messages! {
+    // Paths and access
+    InvalidPath => "访问被拒绝：无效路径", "Access denied: invalid path";
+    PathOutsideMount => "路径必须位于挂载目录内", "Path must be inside a mount";
+    MountNotFound => "挂载不存在: {}", "Mount not found: {}";
+    AccessDenied => "无权访问: {}", "Access denied: {}";
+    ProtectedPath => "受保护的路径: {}", "Protected path: {}";
+    FolderContainsProtected => "文件夹包含受保护的文件: {}", "Folder contains protected files: {}";
+    SymlinkNotFollowed => "不跟随符号链接: {}", "Symlink not followed: {}";
+    SymlinkOutsideRoot => "符号链接指向根目录之外，未复制", "Symlink leads outside the root, not copied";
+    CopiedWithSkipped => "复制完成，{} 项未能复制", "Copied; {} entries were left out";
+    InternalFile => "filest 的内部临时文件: {}", "Internal filest file: {}";
+    AdminOnly => "仅管理员可用", "Administrator only";
+    ApiKeyReadOnly => "API 密钥为只读", "API key is read-only";
+    ApiNotFound => "接口不存在", "No such API endpoint";
+    // File operations
+    FileNotFound => "文件不存在", "File not found";
+    DirNotFound => "目录不存在", "Directory not found";
+    NotADirectory => "不是有效的目录", "Not a directory";
+    SourceNotFound => "源文件不存在", "Source not found";
+    FolderExists => "文件夹已存在", "Folder already exists";
+    NameExists => "目标名称已存在", "Target name already exists";
+    DestinationExists => "目标位置已存在同名文件", "An item with the same name already exists at the destination";
+    CannotDownloadFolder => "不能直接下载文件夹，可加上 format=zip 或 format=tar.gz 打包下载", "Cannot download a folder as is; add format=zip or format=tar.gz to download it as an archive";
+    InvalidBatchPaths => "请指定 1 到 {} 个路径", "Give between 1 and {} paths";
+    FileChangedWhileArchiving => "{} 在打包过程中被修改", "{} changed while being archived";
+    NotAFile => "不是文件", "Not a file";
+    NotMarkdown => "只能渲染 Markdown 文件（.md、.markdown）", "Only Markdown files (.md, .markdown) can be rendered";
+    MarkdownTooLarge => "文件过大，无法预览（最大 {}）", "File too large to preview (at most {})";
+    TextTooLarge => "文件过大，无法查看（最大 {}）", "File too large to view (at most {})";
+    NotATextFile => "不是文本文件", "Not a text file";
+    ContentChanged => "文件已被修改，请重新打开后再保存", "The file has changed since it was opened; reload it before saving";
+    ContentExists => "文件已存在，请先打开再保存", "The file already exists; open it before saving";
+    NoAppendData => "请求中没有要追加的数据", "No data to append";
+    UnknownEncoding => "未知的编码: {}", "Unknown encoding: {}";
+    NotEncodable => "内容包含 {} 无法表示的字符", "The content has characters {} cannot represent";
+    InvalidDownloadRate => "下载速率无效：{}，应为每秒字节数，如 500KB", "Invalid download rate {}, expected bytes per second such as 500KB";
+    TooManyTailLines => "最多返回 {} 行", "At most {} lines can be returned";
+    NotAnImage => "不支持的文件类型（仅支持 JPEG、PNG、TIFF、HEIC 图片）", "Unsupported file type (JPEG, PNG, TIFF or HEIC images only)";
+    ImageParseFailed => "无法解析图片", "Failed to parse image";
+    NotAMediaFile => "仅支持视频和音频文件", "Only video and audio files can be streamed";
+    NotAResizableImage => "仅支持缩放 JPEG、PNG、TIFF 图片", "Only JPEG, PNG and TIFF images can be resized";
+    InvalidImageSize => "宽度和高度应在 1 到 {} 之间，质量应在 1 到 100 之间", "Width and height must be between 1 and {}, quality between 1 and 100";
+    RawIndexDisabled => "未开启目录索引（--raw-autoindex）", "Folder index is disabled (--raw-autoindex)";
+    CannotRenameRoot => "不能重命名根目录", "Cannot rename the root folder";
+    CannotMoveRoot => "不能移动根目录", "Cannot move the root folder";
+    CannotDeleteRoot => "不能删除根目录", "Cannot delete the root folder";
+    DeletePartial => "已删除 {} 项，{} 项未能删除", "Deleted {} entries; {} could not be removed";
+    DeleteInterrupted => "服务器正在停止，删除未完成", "Server shutting down; delete not finished";
+    DeleteCancelled => "删除已取消，已删除 {} 项", "Delete cancelled after removing {} entries";
+    MoveIntoSelf => "不能移动到自身子目录", "Cannot move a folder into itself";
+    CopyIntoSelf => "不能复制到自身子目录", "Cannot copy a folder into itself";
+    InvalidDestination => "无效的 Destination 请求头", "Invalid Destination header";
+    FolderTooLarge => "文件夹过大（至少 {} 项，{}），请确认后再删除", "Folder is large (at least {} entries, {}), confirm to delete it";
+    RootFolder => "根目录", "Root";
+    InvalidTimezone => "无效的时区: {}", "Invalid time zone: {}";
+    EmptySearchText => "搜索内容不能为空", "The text to search for can't be empty";
+    InvalidSearchSize => "无效的大小: {}", "Invalid size: {}";
+    InvalidSearchTime => "无效的时间（应为 RFC 3339 格式或日期，如 2024-06-01）: {}", "Invalid time (expected RFC 3339 or a date such as 2024-06-01): {}";
+    InvalidSearchToken => "无效的搜索续传标记，请重新搜索", "Invalid search token; start the search again";
+    InvalidSearchGlob => "无效的通配符模式: {}", "Invalid glob pattern: {}";
+    InvalidSearchRegex => "无效的正则表达式: {}", "Invalid regular expression: {}";
+    InvalidTime => "无效的时间（应为 RFC 3339 格式）: {}", "Invalid time (expected RFC 3339): {}";
+    InvalidFileName => "无效的文件名: {}", "Invalid file name: {}";
+    ReservedFileName => "文件名为系统保留名称: {}", "Reserved file name: {}";
+    FileNameTooLong => "文件名过长（最多 {} 字节）: {}", "File name too long (max {} bytes): {}";
+    DotFileNotAllowed => "不允许以点开头的文件名: {}", "Names starting with a dot are not allowed: {}";
+    FolderCreated => "文件夹创建成功", "Folder created";
+    FileCreated => "文件创建成功", "File created";
+    JobStarted => "已在后台开始", "Started in the background";
+    JobCancelled => "已取消", "Cancelled";
+    JobNotCancellable => "只能取消后台执行的复制、移动、删除和打包", "Only background copies, moves, deletes and archives can be cancelled";
+    Renamed => "重命名成功", "Renamed";
+    Moved => "移动成功", "Moved";
+    Copied => "复制成功", "Copied";
+    Deleted => "删除成功", "Deleted";
+    MovedToTrash => "已移到回收站", "Moved to the trash";
+    TrashItemNotFound => "回收站中没有该项", "Item not found in the trash";
+    RestoreTargetExists => "原位置已有同名文件或文件夹: {}", "Something already exists at the original location: {}";
+    Restored => "已还原", "Restored";
+    TrashPurged => "已永久删除 {} 项", "Permanently deleted {} item(s)";
+    TrashPurgePartial => "已永久删除 {} 项，{} 项未能删除", "Permanently deleted {} item(s); {} could not be removed";
+    NothingToUndo => "没有可撤销的操作", "Nothing to undo";
+    UndoTargetExists => "无法撤销，原位置已有同名文件或文件夹: {}", "Cannot undo: something already exists at {}";
+    UndoneRename => "已撤销重命名", "Rename undone";
+    UndoneMove => "已撤销移动", "Move undone";
+    UndoneCopy => "已撤销复制，副本已移到回收站", "Copy undone; the copy was moved to the trash";
+    UndoneCopyDeleted => "已撤销复制，副本已删除", "Copy undone; the copy was deleted";
+    UndoneDelete => "已撤销删除", "Delete undone";
+    ArchiveCreated => "压缩包已创建", "Archive created";
+    Extracted => "已解压 {} 个文件", "Extracted {} file(s)";
+    ExtractedWithSkipped => "已解压 {} 个文件，跳过 {} 项", "Extracted {} file(s); {} entries skipped";
+    ExtractTargetExists => "目标位置已存在: {}", "Already exists in the destination: {}";
+    UnsupportedArchive => "不是支持的压缩包（ZIP、tar 或 tar.gz）", "Not a supported archive (ZIP, tar or tar.gz)";
+    InvalidArchive => "压缩包已损坏", "Damaged archive";
+    TooManyArchiveEntries => "压缩包中的条目过多（最多 {} 个）", "Too many entries in the archive (at most {})";
+    UnsafeArchiveEntry => "路径指向目标文件夹之外", "Path leads outside the destination";
+    EncryptedZipEntry => "不支持加密的条目", "Encrypted entries are not supported";
+    UnsupportedZipMethod => "不支持的压缩方式: {}", "Unsupported compression method: {}";
+    ArchiveSymlinkEntry => "不支持符号链接条目", "Symlink entries are not supported";
+    ArchiveSpecialEntry => "不支持硬链接、设备等特殊条目", "Hard links, devices and other special entries are not supported";
+    ArchiveEntryNotFound => "压缩包中没有该条目", "No such entry in the archive";
+    ArchiveEntryExists => "目标位置已存在", "Already exists in the destination";
+    DamagedArchiveEntry => "数据已损坏", "Data is damaged";
+    // Filesystem failures, followed by the system error
+    MetadataFailed => "获取文件信息失败", "Failed to get file info";
+    ReadDirFailed => "读取目录失败", "Failed to read directory";
+    WatchFailed => "监视目录失败", "Failed to watch directory";
+    SizeJobNotFound => "大小计算任务不存在或已过期", "Size job not found or expired";
+    JobNotFound => "任务不存在或已过期", "Job not found or expired";
+    CreateFailed => "创建失败", "Failed to create";
+    CreateDirFailed => "创建目录失败", "Failed to create directory";
+    CreateFileFailed => "创建文件失败", "Failed to create file";
+    OpenFileFailed => "打开文件失败", "Failed to open file";
+    ReadFileFailed => "读取文件失败", "Failed to read file";
+    WriteFileFailed => "写入文件失败", "Failed to write file";
+    SyncFileFailed => "同步文件失败", "Failed to sync file";
+    RenameFailed => "重命名失败", "Failed to rename";
+    MoveFailed => "移动失败", "Failed to move";
+    CopyFailed => "复制失败", "Failed to copy";
+    DeleteFailed => "删除失败", "Failed to delete";
+    RestoreFailed => "还原失败", "Failed to restore";
+    ExtractFailed => "解压失败", "Failed to extract";
+    RemoveSourceFailed => "复制后删除源文件失败", "Failed to remove source after copy";
+    // Upload
+    ReadUploadFailed => "读取上传数据失败", "Failed to read upload data";
+    TooManyCheckFiles => "一次最多检查 {} 个文件", "At most {} files can be checked at once";
+    NoUploadDir => "未指定上传目录，请使用 ?path= 或在文件之前发送 path 字段", "No upload folder: pass ?path= or send a path field before the files";
+    InsufficientSpace => "磁盘空间不足：需要 {}（{} 字节），可用 {}（{} 字节），另须保留 {}", "Not enough disk space: {} ({} bytes) needed, {} ({} bytes) free, and {} must stay free";
+    FreeSpaceExhausted => "磁盘仅剩 {}，低于须保留的 {}，写入已中止", "Only {} of disk space left, below the {} that must stay free; the write was stopped";
+    LastModifiedIgnored => "已忽略无效的修改时间: {}", "Ignored invalid modification time: {}";
+    HookStartFailed => "无法运行上传检查命令 {}: {}", "Failed to run upload hook {}: {}";
+    HookTimedOut => "上传检查命令超过 {} 秒未结束", "Upload hook still running after {} seconds";
+    HookFailed => "上传检查命令失败（{}）: {}", "Upload hook failed ({}): {}";
+    RejectedByHook => "上传被检查命令拒绝: {}", "Upload rejected by hook: {}";
+    HookWarning => "上传检查未通过，文件仍已保存: {}", "Upload hook failed, file kept anyway: {}";
+    UploadPathAfterFiles => "上传目录必须在文件之前指定", "The upload folder must be given before the files";
+    FileTooLarge => "文件超过大小限制", "File exceeds the size limit";
+    ContentRangeRequired => "缺少 Content-Range 请求头", "Content-Range header required";
+    InvalidContentRange => "Content-Range 格式应为 bytes start-end/total", "Content-Range must be bytes start-end/total";
+    RangeOutsideFile => "范围超出文件大小: {}", "Range outside the file: {}";
+    ContentLengthMismatch => "请求体长度与范围不符，应为 {} 字节", "Body length doesn't match the range; expected {} bytes";
+    ChecksumMismatch => "X-Content-Sha256 校验失败", "X-Content-Sha256 doesn't match the body";
+    BodyTooLarge => "请求体超过大小限制", "Request body exceeds the size limit";
+    InvalidChunkSize => "分片大小无效（最大 {}）", "Invalid chunk size (max {})";
+    UploadSessionNotFound => "上传会话不存在", "Upload session not found";
+    InvalidChunkIndex => "分片序号无效", "Invalid chunk index";
+    NoChunkData => "未提供分片数据", "No chunk data provided";
+    ReadChunkFailed => "读取分片数据失败", "Failed to read chunk data";
+    WriteChunkFailed => "写入分片失败", "Failed to write chunk";
+    MergeChunkFailed => "合并分片 {} 失败", "Failed to merge chunk {}";
+    MissingChunks => "缺少分片: {}", "Missing chunks: {}";
+    UploadCancelled => "已取消 {} 的上传", "Cancelled the upload of {}";
+    UploadIdle => "上传超过 {} 秒没有收到数据，已中止", "No upload data arrived for {} seconds; the upload was aborted";
+    UploadAborted => "上传已取消", "Upload aborted";
+    TooManySessions => "未完成的分片上传已达上限（{} 个），请稍后重试", "Too many unfinished chunked uploads (limit {}), retry later";
+    TooManyUploads => "同时进行的上传过多，请稍后重试", "Too many uploads in progress, retry later";
+    // Authentication
+    InvalidCredentials => "用户名或密码错误", "Invalid username or password";
+    TooManyConnections => "来自该地址的并发请求过多，请稍后重试", "Too many requests in progress from this address, retry shortly";
+    TooManyAttempts => "登录失败次数过多，请 {} 秒后重试", "Too many failed logins, retry in {} seconds";
+    ApiKeyInvalid => "API 密钥无效", "Invalid API key";
+    TokenInvalid => "登录凭证无效，请重新登录", "Invalid token, please log in again";
+    TokenExpired => "登录已过期，请重新登录", "Login expired, please log in again";
+    SessionExpired => "会话已过期，请重新登录", "Session expired, please log in again";
+    LoggedIn => "登录成功", "Logged in";
+    LoggedOut => "已退出登录", "Logged out";
+    // API keys and audit log
+    NameRequired => "名称不能为空", "Name must not be empty";
+    ApiKeyNotFound => "API 密钥不存在", "API key not found";
+    ApiKeyRevoked => "API 密钥已吊销", "API key revoked";
+    // File metadata
+    InvalidTag => "无效的标签: {}（最多 {} 个字符，不能包含逗号）", "Invalid tag: {} (at most {} characters, no commas)";
+    TooManyTags => "标签过多（最多 {} 个）", "Too many tags (at most {})";
+    NoteTooLong => "备注过长（最多 {} 个字符）", "Note too long (at most {} characters)";
+    // Favorites
+    FavoriteNotFound => "收藏不存在", "Favorite not found";
+    PathLocked => "{} 已被 {} 锁定，直到 {}", "{} is locked by {} until {}";
+    PathLockedHidden => "{} 或其中的内容已被他人锁定", "{} or something in it is locked by someone else";
+    LockNotFound => "锁不存在或已过期", "Lock not found or expired";
+    InvalidLockTtl => "锁的有效期必须在 1 到 {} 秒之间", "Lock lifetime must be between 1 and {} seconds";
+    LockReleased => "锁已释放", "Lock released";
+    ClipboardEmpty => "剪贴板为空", "The clipboard is empty";
+    TooManyClipboardPaths => "剪贴板最多容纳 {} 项", "The clipboard holds at most {} items";
+    FavoriteRemoved => "已取消收藏", "Favorite removed";
+    LabelTooLong => "名称过长（最多 {} 个字符）", "Label too long (at most {} characters)";
+    InvalidSince => "无效的 since 参数: {}", "Invalid since: {}";
+    // Share links
+    ShareNotFound => "分享不存在", "Share not found";
+    UploadShareNeedsFolder => "上传链接只能指向文件夹", "Upload links must point to a folder";
+    ShareRevoked => "分享已取消", "Share revoked";
+    SharePasswordRequired => "需要分享密码", "Share password required";
+    RateLimited => "请求过于频繁，请 {} 秒后重试", "Too many requests, retry in {} seconds";
+    FileNotAllowed => "不允许上传该文件: {}", "File not allowed: {}";
+    ShareQuotaReached => "上传总量已达上限", "Upload quota reached";
+    // Share pages
+    ShareUnavailable => "分享不存在或已过期", "Share not found or expired";
+    SharePageTitle => "分享", "Share";
+    SharePasswordPrompt => "此分享需要密码", "This share needs a password";
+    SharePasswordWrong => "密码错误", "Wrong password";
+    SharePasswordField => "密码", "Password";
+    ShareOpen => "访问", "Open";
+    DropPageTitle => "上传文件", "Upload files";
+    DropSubmit => "上传", "Upload";
+    DropMaxFileSize => "单个文件不超过 {}", "Files up to {} each";
+    DropRemaining => "剩余容量 {}", "{} left";
+    DropLimitSeparator => "，", ", ";
+    DropUploaded => "已上传 {} 个文件", "Uploaded {} files";
+    DropMore => "继续上传", "Upload more";
+}
+
+impl Msg {
+    /// The message in the current request's language
+    pub fn text(self) -> &'static str {
+        self.text_in(current())
+    }
+
+    /// The message with each `{}` replaced by the next argument
+    pub fn with(self, args: &[&dyn fmt::Display]) -> String {
+        let mut args = args.iter();
+        let mut parts = self.text().split("{}");
+        let mut out = parts.next().unwrap_or_default().to_string();
+        for part in parts {
+            if let Some(arg) = args.next() {
+                out.push_str(&arg.to_string());
+            }
+            out.push_str(part);
+        }
+        out
+    }
+}
+
+impl fmt::Display for Msg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.text())
+    }
+}
+
+impl From<Msg> for String {
+    fn from(msg: Msg) -> Self {
+        msg.text().to_string()
+    }
+}
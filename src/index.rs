@@ -0,0 +1,429 @@
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tantivy::collector::TopDocs;
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, Occur, Query, RangeQuery, TermQuery, TermSetQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, FAST, STRING};
+use tantivy::{Index, IndexReader, IndexWriter, Order, ReloadPolicy, Searcher, TantivyDocument, Term};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use crate::handlers::{get_file_info, safe_path, SearchFilter};
+use crate::models::FileInfo;
+use crate::mounts::Mounts;
+use crate::paths::{is_internal_artifact, search_key, NameMatcher};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// How often the whole tree is walked again, picking up changes made outside filest
+const REBUILD_INTERVAL: Duration = Duration::from_secs(3600);
+/// How long changes made through filest are gathered before the index is updated
+const UPDATE_DELAY: Duration = Duration::from_secs(2);
+/// Memory the writer fills before writing out a segment
+const WRITER_MEMORY: usize = 50 * 1024 * 1024;
+/// Hits read from the index at a time while picking the ones a user may see
+const PAGE: usize = 200;
+
+struct Fields {
+    /// Logical path of the entry
+    path: Field,
+    /// The path with `/` as NUL, which sorts in the order searches go through entries:
+    /// a folder's contents right after it, before names that only start with its name
+    order: Field,
+    /// `search_key` of its name
+    key: Field,
+    /// Every folder above it, `/` included, so a search can be limited to one of them
+    /// and a changed folder can be dropped with everything in it
+    dirs: Field,
+}
+
+/// Names of everything below the mounts, for `/api/search` without a walk (`--index`)
+///
+/// The index lives in `<data_dir>/index` and is kept across restarts: one built for the
+/// same mounts answers searches at once while it is walked again. Entries are indexed
+/// like the walk would report them, hidden paths and internal files left out; access
+/// rules are applied per search, and every hit is looked up on disk before it is
+/// returned, so an entry removed since the last walk never shows up.
+pub struct SearchIndex {
+    fields: Fields,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    /// The mounts the index is built for, stored with every commit
+    mounts: String,
+    /// Whether the index holds a complete walk of these mounts; until then searches walk
+    ready: AtomicBool,
+    /// Logical paths changed through filest, waiting for the next update
+    changed: Mutex<HashSet<String>>,
+    wake: Notify,
+}
+
+impl SearchIndex {
+    /// Open the index in `<data_dir>/index`, starting over when it can't be opened
+    pub fn open(data_dir: &Path, mounts: &Mounts) -> Result<Self, String> {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            path: builder.add_text_field("path", STRING),
+            order: builder.add_text_field("order", STRING | FAST),
+            key: builder.add_text_field("key", STRING),
+            dirs: builder.add_text_field("dirs", STRING),
+        };
+        let schema = builder.build();
+        let folder = data_dir.join("index");
+        let index = match open_index(&folder, schema.clone()) {
+            Ok(index) => index,
+            Err(e) => {
+                // Left by another version or damaged; it is only a cache of the tree
+                warn!("Starting a new search index in {:?}: {}", folder, e);
+                let _ = std::fs::remove_dir_all(&folder);
+                open_index(&folder, schema).map_err(|e| format!("Failed to open search index {:?}: {}", folder, e))?
+            }
+        };
+        let mounts = mounts
+            .list()
+            .iter()
+            .map(|mount| format!("{}={}", mount.name, mount.root.display()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let ready = index.load_metas().is_ok_and(|metas| metas.payload.as_deref() == Some(mounts.as_str()));
+        let failed = |e: tantivy::TantivyError| format!("Failed to open search index {:?}: {}", folder, e);
+        let reader = index.reader_builder().reload_policy(ReloadPolicy::Manual).try_into().map_err(failed)?;
+        let writer = index.writer_with_num_threads(1, WRITER_MEMORY).map_err(failed)?;
+        Ok(Self {
+            fields,
+            reader,
+            writer: Mutex::new(writer),
+            mounts,
+            ready: AtomicBool::new(ready),
+            changed: Mutex::default(),
+            wake: Notify::new(),
+        })
+    }
+
+    /// Queue a path changed through filest (`actual`, as `sizes_changed` gets it) to be
+    /// indexed again with everything below it
+    pub fn changed(&self, mounts: &Mounts, actual: &Path) {
+        // Reached through a link out of every mount; the next rebuild picks it up
+        if mounts.find(actual).is_none() {
+            return;
+        }
+        self.changed.lock().unwrap_or_else(|e| e.into_inner()).insert(mounts.logical_path(actual));
+        self.wake.notify_one();
+    }
+
+    /// Commit what the writer holds, marked with the mounts, and show it to searches
+    fn commit(&self, writer: &mut IndexWriter) -> tantivy::Result<()> {
+        let mut commit = writer.prepare_commit()?;
+        commit.set_payload(&self.mounts);
+        commit.commit()?;
+        self.reader.reload()
+    }
+
+    /// Replace the whole index with a new walk of the mounts; `None` when stopped by shutdown
+    fn rebuild(&self, state: &AppState, shutdown: &CancellationToken) -> tantivy::Result<Option<u64>> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writer.delete_all_documents()?;
+        let mut walk = Walk::new(self, state, &writer, shutdown);
+        for mount in state.mounts.list() {
+            if state.hidden.matches(&state.mounts.logical_path(&mount.root)) {
+                continue;
+            }
+            if !walk.folder(&mount.root, &mount.root)? {
+                writer.rollback()?;
+                return Ok(None);
+            }
+        }
+        let entries = walk.entries;
+        self.commit(&mut writer)?;
+        self.ready.store(true, Ordering::Relaxed);
+        Ok(Some(entries))
+    }
+
+    /// Index the queued paths again; false when stopped by shutdown
+    fn update(&self, state: &AppState, shutdown: &CancellationToken) -> tantivy::Result<bool> {
+        let mut paths: Vec<String> = self.changed.lock().unwrap_or_else(|e| e.into_inner()).drain().collect();
+        paths.sort();
+        // A path below another queued folder is walked with it
+        paths.dedup_by(|path, folder| *folder == "/" || path.strip_prefix(folder.as_str()).is_some_and(|rest| rest.starts_with('/')));
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let mut walk = Walk::new(self, state, &writer, shutdown);
+        for path in &paths {
+            walk.writer.delete_term(Term::from_field_text(self.fields.path, path));
+            walk.writer.delete_term(Term::from_field_text(self.fields.dirs, path));
+            let Ok(found) = safe_path(&state.mounts, path) else { continue };
+            if state.hidden.matches(path) || is_internal_artifact(path, &found.actual) || !found.actual.exists() {
+                continue;
+            }
+            // A mount's root isn't an entry of its own, only what is in it
+            if !found.is_root() {
+                let name = found.logical.file_name().unwrap_or_default().to_string_lossy();
+                walk.add(path, &name)?;
+            }
+            if found.actual.is_dir() && !state.mounts.refuses_link(&found.logical) && !walk.folder(&found.logical, &found.actual)? {
+                writer.rollback()?;
+                return Ok(false);
+            }
+        }
+        self.commit(&mut writer)?;
+        Ok(true)
+    }
+
+    /// The query for entries below `base` whose names `name` matches
+    ///
+    /// Names are matched against each segment's term dictionary, which holds every
+    /// distinct name once, and the query looks up the ones that matched.
+    fn query(&self, searcher: &Searcher, base: &str, name: &NameMatcher) -> tantivy::Result<BooleanQuery> {
+        let below = TermQuery::new(Term::from_field_text(self.fields.dirs, base), IndexRecordOption::Basic);
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, Box::new(below))];
+        if !name.matches_all() {
+            let mut keys = BTreeSet::new();
+            for segment in searcher.segment_readers() {
+                let inverted = segment.inverted_index(self.fields.key)?;
+                let mut terms = inverted.terms().stream()?;
+                while terms.advance() {
+                    if let Ok(key) = std::str::from_utf8(terms.key())
+                        && name.matches(key)
+                    {
+                        keys.insert(Term::from_field_text(self.fields.key, key));
+                    }
+                }
+            }
+            clauses.push((Occur::Must, Box::new(TermSetQuery::new(keys))));
+        }
+        Ok(BooleanQuery::new(clauses))
+    }
+
+    /// One page of the paths `query` finds after the path `after`, in order
+    fn hits(&self, searcher: &Searcher, query: &BooleanQuery, after: Option<&str>) -> tantivy::Result<Vec<String>> {
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, Box::new(query.clone()))];
+        if let Some(after) = after {
+            let lower = Term::from_field_text(self.fields.order, &order_key(after));
+            clauses.push((Occur::Must, Box::new(RangeQuery::new(Bound::Excluded(lower), Bound::Unbounded))));
+        }
+        let top = TopDocs::with_limit(PAGE).order_by_string_fast_field("order", Order::Asc);
+        let hits = searcher.search(&BooleanQuery::new(clauses), &top)?;
+        Ok(hits.into_iter().map(|(key, _)| key.replace('\0', "/")).collect())
+    }
+
+    /// Up to `limit` entries below `base` after the path `after` that pass `filter` and
+    /// the user may see, and the last one gone through; `None` while the index isn't
+    /// ready, so the caller walks
+    pub(crate) async fn search(
+        self: &Arc<Self>,
+        state: &AppState,
+        user: &AuthUser,
+        base: &str,
+        filter: &SearchFilter,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Option<(Vec<FileInfo>, Option<String>)> {
+        if !self.ready.load(Ordering::Relaxed) {
+            return None;
+        }
+        let searcher = self.reader.searcher();
+        let (index, task_searcher, base, name) = (self.clone(), searcher.clone(), base.to_string(), filter.name.clone());
+        let query = match tokio::task::spawn_blocking(move || index.query(&task_searcher, &base, &name)).await {
+            Ok(Ok(query)) => Arc::new(query),
+            Ok(Err(e)) => {
+                warn!("Search index query failed: {}", e);
+                return None;
+            }
+            Err(e) => {
+                warn!("Search index query failed: {}", e);
+                return None;
+            }
+        };
+        let mut results = Vec::new();
+        let mut last = after.map(str::to_string);
+        loop {
+            let (index, searcher, query, after) = (self.clone(), searcher.clone(), query.clone(), last.clone());
+            let page = match tokio::task::spawn_blocking(move || index.hits(&searcher, &query, after.as_deref())).await {
+                Ok(Ok(page)) => page,
+                Ok(Err(e)) => {
+                    warn!("Search index query failed: {}", e);
+                    return None;
+                }
+                Err(e) => {
+                    warn!("Search index query failed: {}", e);
+                    return None;
+                }
+            };
+            let full = page.len() == PAGE;
+            for path in page {
+                if results.len() >= limit {
+                    return Some((results, last));
+                }
+                if user.can_see(&path)
+                    && !state.hidden.matches(&path)
+                    && let Ok(found) = safe_path(&state.mounts, &path)
+                    && let Ok(info) = get_file_info(&state.mounts, &found.logical, &found.actual, None).await
+                    && filter.accepts(&info)
+                {
+                    results.push(info);
+                }
+                last = Some(path);
+            }
+            if !full {
+                return Some((results, last));
+            }
+        }
+    }
+}
+
+/// The `order` of a path
+fn order_key(path: &str) -> String {
+    path.replace('/', "\0")
+}
+
+fn open_index(folder: &Path, schema: Schema) -> tantivy::Result<Index> {
+    std::fs::create_dir_all(folder)?;
+    Index::open_or_create(MmapDirectory::open(folder)?, schema)
+}
+
+/// A walk adding entries to the index, the way `/api/search` walks
+struct Walk<'a> {
+    index: &'a SearchIndex,
+    state: &'a AppState,
+    writer: &'a IndexWriter,
+    shutdown: &'a CancellationToken,
+    /// The resolved folders being walked, so a symlink back up the tree isn't followed forever
+    ancestors: Vec<PathBuf>,
+    entries: u64,
+}
+
+impl<'a> Walk<'a> {
+    fn new(index: &'a SearchIndex, state: &'a AppState, writer: &'a IndexWriter, shutdown: &'a CancellationToken) -> Self {
+        Self { index, state, writer, shutdown, ancestors: Vec::new(), entries: 0 }
+    }
+
+    /// Add the entry at logical path `path`
+    fn add(&mut self, path: &str, name: &str) -> tantivy::Result<()> {
+        let fields = &self.index.fields;
+        let mut doc = TantivyDocument::new();
+        doc.add_text(fields.path, path);
+        doc.add_text(fields.order, order_key(path));
+        doc.add_text(fields.key, search_key(name));
+        doc.add_text(fields.dirs, "/");
+        for (end, _) in path.match_indices('/').skip(1) {
+            doc.add_text(fields.dirs, &path[..end]);
+        }
+        self.writer.add_document(doc)?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Add everything in `actual`, reporting it under `logical`; false when stopped by shutdown
+    fn folder(&mut self, logical: &Path, actual: &Path) -> tantivy::Result<bool> {
+        if self.shutdown.is_cancelled() {
+            return Ok(false);
+        }
+        let resolved = actual.canonicalize().unwrap_or_else(|_| actual.to_path_buf());
+        if self.ancestors.contains(&resolved) {
+            return Ok(true);
+        }
+        let Ok(entries) = std::fs::read_dir(actual) else { return Ok(true) };
+        self.ancestors.push(resolved);
+        let mounts = &self.state.mounts;
+        for entry in entries.flatten() {
+            let logical = logical.join(entry.file_name());
+            let actual = entry.path();
+            let path = mounts.logical_path(&logical);
+            if self.state.hidden.matches(&path) || is_internal_artifact(&path, &actual) {
+                continue;
+            }
+            self.add(&path, &entry.file_name().to_string_lossy())?;
+            if actual.is_dir() && !mounts.refuses_link(&actual) && !self.folder(&logical, &actual)? {
+                return Ok(false);
+            }
+        }
+        self.ancestors.pop();
+        Ok(true)
+    }
+}
+
+/// Build the index, then keep it current until shutdown: paths changed through filest
+/// are indexed again within seconds, and the whole tree is walked every `REBUILD_INTERVAL`
+pub fn start_indexing(state: AppState, shutdown: CancellationToken) {
+    let Some(index) = state.index.clone() else { return };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REBUILD_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {
+                    // The walk sees what was queued so far
+                    index.changed.lock().unwrap_or_else(|e| e.into_inner()).clear();
+                    let started = Instant::now();
+                    let (task_index, task_state, task_shutdown) = (index.clone(), state.clone(), shutdown.clone());
+                    let result = tokio::task::spawn_blocking(move || task_index.rebuild(&task_state, &task_shutdown)).await;
+                    match result {
+                        Ok(Ok(Some(entries))) => {
+                            info!("Search index rebuilt: {} entries in {:.1?}", entries, started.elapsed())
+                        }
+                        Ok(Ok(None)) => {}
+                        Ok(Err(e)) => warn!("Failed to rebuild the search index: {}", e),
+                        Err(e) => warn!("Failed to rebuild the search index: {}", e),
+                    }
+                }
+                _ = index.wake.notified() => {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = tokio::time::sleep(UPDATE_DELAY) => {}
+                    }
+                    let (task_index, task_state, task_shutdown) = (index.clone(), state.clone(), shutdown.clone());
+                    let result = tokio::task::spawn_blocking(move || task_index.update(&task_state, &task_shutdown)).await;
+                    match result {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => warn!("Failed to update the search index: {}", e),
+                        Err(e) => warn!("Failed to update the search index: {}", e),
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{admin, TestApp};
+    use axum::{body::Body, http::{Method, StatusCode}};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn folders_behind_symlinks_are_indexed_and_found() {
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(outside.path().join("deep")).unwrap();
+        std::fs::write(outside.path().join("deep/needle.txt"), "found").unwrap();
+        let app = TestApp::with(|c| c.index = true);
+        app.write("plain/hay.txt", "");
+        std::os::unix::fs::symlink(outside.path(), app.path("linked")).unwrap();
+
+        let search = async || {
+            let (status, body) = app.json(admin(Method::GET, "/api/search?query=needle").body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let results = body["results"].as_array().unwrap();
+            assert_eq!(results.len(), 1, "{body}");
+            (body["indexed"].as_bool().unwrap(), results[0]["path"].as_str().unwrap().to_string())
+        };
+        // Walked until the index is built
+        assert_eq!(search().await, (false, "/linked/deep/needle.txt".to_string()));
+
+        let (index, state) = (app.state.index.clone().unwrap(), app.state.clone());
+        let built = tokio::task::spawn_blocking(move || index.rebuild(&state, &CancellationToken::new())).await.unwrap();
+        assert!(built.unwrap().is_some());
+        let (indexed, path) = search().await;
+        assert!(indexed);
+        assert_eq!(path, "/linked/deep/needle.txt");
+
+        // Reported under the link, and readable by that path
+        let response = app.send(admin(Method::GET, &format!("/api/download?path={path}")).body(Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let data = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(data, "found");
+    }
+}
@@ -0,0 +1,481 @@
+use axum::{
+    extract::{Extension, Path as AxumPath, Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, safe_path};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::{ApiResponse, JobQuery, JobResponse, JobStatus};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// How long a report is reused while its folder is unchanged
+const CACHE_TTL: Duration = Duration::from_secs(600);
+/// Finished jobs can be polled for this long
+const JOB_TTL: Duration = Duration::from_secs(600);
+/// A report finishing within this long is returned directly instead of as a job
+const INLINE_WAIT: Duration = Duration::from_secs(2);
+/// How often `/api/jobs/{token}/events` looks for progress to send
+const EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Counters a running walk or operation updates and pollers read
+#[derive(Default)]
+pub struct WalkProgress {
+    pub entries: AtomicU64,
+    pub bytes: AtomicU64,
+    /// Entries an operation couldn't handle, such as files a copy left out
+    pub errors: AtomicU64,
+    /// Fired by `/api/jobs/{token}/cancel` or at shutdown; operations check it between
+    /// entries
+    pub cancel: CancellationToken,
+    /// Bytes an operation expects to handle, 0 when unknown; it comes from the folder size
+    /// cache, so it can be off for a folder too large to measure in full
+    pub total_bytes: AtomicU64,
+    /// The entry being copied, by its path below the operation's source
+    current: std::sync::Mutex<String>,
+}
+
+impl WalkProgress {
+    pub fn add(&self, entries: u64, bytes: u64) {
+        self.entries.fetch_add(entries, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.entries.load(Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn set_total(&self, bytes: u64) {
+        self.total_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_current(&self, rel: &Path) {
+        if let Ok(mut current) = self.current.lock() {
+            *current = rel.to_string_lossy().replace('\\', "/");
+        }
+    }
+
+    fn current(&self) -> Option<String> {
+        self.current.lock().ok().map(|current| current.clone()).filter(|current| !current.is_empty())
+    }
+
+    /// Share of `total_bytes` handled so far, held at 99 until the job is done since the
+    /// total may be short
+    fn percent(&self) -> Option<u8> {
+        let total = self.total_bytes.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        (total > 0).then(|| (bytes.saturating_mul(100) / total).min(99) as u8)
+    }
+}
+
+/// What an endpoint would have answered, as a job's `result`: its response body on
+/// success, or the error body with `success: false`
+pub fn outcome<T: Serialize>(result: Result<T, ApiError>) -> Value {
+    let body = match result {
+        Ok(data) => serde_json::to_value(ApiResponse::success(data)),
+        Err(e) => serde_json::to_value(ApiResponse::<()>::error_with_code(e.code(), e.message())),
+    };
+    body.unwrap_or_default()
+}
+
+/// A finished report
+struct Cached {
+    at: Instant,
+    /// Folders walked, to drop the report when something below them changes
+    roots: Vec<PathBuf>,
+    report: Arc<Value>,
+}
+
+/// Finished reports by cache key
+type ReportCache = Arc<RwLock<HashMap<String, Cached>>>;
+
+/// A report walk started by `/api/usage` or a similar endpoint, or an operation run in
+/// the background
+struct Job {
+    owner: String,
+    kind: &'static str,
+    /// Logical path, as reported back
+    path: String,
+    /// Cache key of the report, so repeated requests join the running job
+    key: String,
+    progress: Arc<WalkProgress>,
+    result: watch::Receiver<Option<Arc<Value>>>,
+    created: Instant,
+    /// Operations can be cancelled; reports are shared and run to the end
+    cancellable: bool,
+}
+
+/// A report to produce, and the folders on disk it covers
+pub struct ReportRequest {
+    kind: &'static str,
+    /// Logical path, as reported back
+    path: String,
+    key: String,
+    roots: Vec<PathBuf>,
+}
+
+impl ReportRequest {
+    /// The cache key covers the kind and options, the user (reports leave out what they
+    /// can't see), and each folder walked with its modification time
+    pub async fn new(kind: &'static str, options: &str, user: &str, path: String, roots: &[(PathBuf, PathBuf)]) -> Self {
+        let mut key = format!("{}\0{}\0{}", kind, options, user);
+        for (_, actual) in roots {
+            let modified = tokio::fs::metadata(actual).await.and_then(|m| m.modified()).ok();
+            key.push_str(&format!("\0{}\0{:?}", actual.display(), modified));
+        }
+        let roots = roots.iter().map(|(_, actual)| actual.clone()).collect();
+        Self { kind, path, key, roots }
+    }
+}
+
+/// The folders a report on `user_path` walks, as (logical, actual), with the logical path
+///
+/// From the virtual root of several mounts that is every mount the user can see.
+pub fn report_roots(
+    state: &AppState,
+    user: &AuthUser,
+    user_path: &str,
+) -> Result<(String, Vec<(PathBuf, PathBuf)>), ApiError> {
+    if state.mounts.is_virtual_root(user_path) {
+        let roots = state
+            .mounts
+            .list()
+            .iter()
+            .filter(|m| {
+                let logical = format!("/{}", m.name);
+                user.can_see(&logical) && !state.hidden.matches(&logical)
+            })
+            .map(|m| (m.root.clone(), m.root.clone()))
+            .collect();
+        return Ok(("/".to_string(), roots));
+    }
+    let paths = safe_path(&state.mounts, user_path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &logical, false)?;
+    check_not_internal(&logical, &paths.actual)?;
+    if !paths.actual.is_dir() {
+        return Err(ApiError::NotFound(Msg::DirNotFound.into()));
+    }
+    Ok((logical, vec![(paths.logical, paths.actual)]))
+}
+
+/// What a report request gets back
+pub enum Outcome {
+    Done(Arc<Value>),
+    /// Still walking; poll `/api/jobs?token=`
+    Running { token: String, progress: Arc<WalkProgress> },
+}
+
+/// Background report walks and their cached results
+///
+/// Reports are cached by a key the caller builds (path, folder mtime, options, user), so
+/// reopening a report is instant until something below it changes or `CACHE_TTL` passes.
+pub struct ReportJobs {
+    jobs: RwLock<HashMap<String, Job>>,
+    cache: ReportCache,
+    /// Shared with the folder-size jobs (`--dir-size-jobs`)
+    walks: Arc<Semaphore>,
+    /// Stops running operations at shutdown
+    shutdown: CancellationToken,
+}
+
+impl ReportJobs {
+    pub fn new(walks: Arc<Semaphore>, shutdown: CancellationToken) -> Self {
+        Self { jobs: RwLock::default(), cache: Arc::default(), walks, shutdown }
+    }
+
+    /// The cached report for `key`, or the result of `walk` if it finishes within
+    /// `INLINE_WAIT`; otherwise the job to poll
+    ///
+    /// `walk` runs in the background with a `--dir-size-jobs` permit and reports through
+    /// the progress counters it is given.
+    pub async fn run<F, Fut>(&self, owner: &str, request: ReportRequest, walk: F) -> Outcome
+    where
+        F: FnOnce(Arc<WalkProgress>) -> Fut + Send + 'static,
+        Fut: Future<Output = Value> + Send + 'static,
+    {
+        let ReportRequest { kind, path, key, roots } = request;
+        if let Some(cached) = self.cache.read().await.get(&key)
+            && cached.at.elapsed() < CACHE_TTL
+        {
+            return Outcome::Done(cached.report.clone());
+        }
+
+        let (token, progress, mut result) = {
+            let mut jobs = self.jobs.write().await;
+            jobs.retain(|_, job| job.result.borrow().is_none() || job.created.elapsed() < JOB_TTL);
+            match jobs.iter().find(|(_, job)| job.owner == owner && job.key == key && job.result.borrow().is_none()) {
+                Some((token, job)) => (token.clone(), job.progress.clone(), job.result.clone()),
+                None => {
+                    let token = Uuid::new_v4().simple().to_string();
+                    let progress = Arc::new(WalkProgress::default());
+                    let (done, result) = watch::channel(None);
+                    jobs.insert(token.clone(), Job {
+                        owner: owner.to_string(),
+                        kind,
+                        path,
+                        key: key.clone(),
+                        progress: progress.clone(),
+                        result: result.clone(),
+                        created: Instant::now(),
+                        cancellable: false,
+                    });
+                    self.spawn(key, roots, walk(progress.clone()), done);
+                    (token, progress, result)
+                }
+            }
+        };
+
+        // Small trees are answered directly
+        match tokio::time::timeout(INLINE_WAIT, result.wait_for(Option::is_some)).await {
+            Ok(Ok(report)) => Outcome::Done((*report).clone().unwrap_or_default()),
+            _ => Outcome::Running { token, progress },
+        }
+    }
+
+    /// Run an operation, such as copying a large folder, in the background and return the
+    /// job's token at once
+    ///
+    /// Unlike reports, operations are never cached or joined: every call is a job of its
+    /// own, and can be cancelled through `progress.cancel`, which shutdown fires too. They
+    /// don't take a `--dir-size-jobs` permit either, so a long copy doesn't hold up
+    /// reports.
+    pub async fn start<F, Fut>(&self, owner: &str, kind: &'static str, path: String, work: F) -> String
+    where
+        F: FnOnce(Arc<WalkProgress>) -> Fut,
+        Fut: Future<Output = Value> + Send + 'static,
+    {
+        let token = Uuid::new_v4().simple().to_string();
+        let progress = Arc::new(WalkProgress { cancel: self.shutdown.child_token(), ..WalkProgress::default() });
+        let (done, result) = watch::channel(None);
+        let work = work(progress.clone());
+        {
+            let mut jobs = self.jobs.write().await;
+            jobs.retain(|_, job| job.result.borrow().is_none() || job.created.elapsed() < JOB_TTL);
+            jobs.insert(token.clone(), Job {
+                owner: owner.to_string(),
+                kind,
+                path,
+                key: token.clone(),
+                progress,
+                result,
+                created: Instant::now(),
+                cancellable: true,
+            });
+        }
+        tokio::spawn(async move {
+            let _ = done.send(Some(Arc::new(work.await)));
+        });
+        token
+    }
+
+    /// Drop the reports of every folder at or above `actual`
+    pub async fn invalidate(&self, actual: &Path) {
+        self.cache.write().await.retain(|_, cached| !cached.roots.iter().any(|root| actual.starts_with(root)));
+    }
+
+    fn spawn(
+        &self,
+        key: String,
+        roots: Vec<PathBuf>,
+        walk: impl Future<Output = Value> + Send + 'static,
+        done: watch::Sender<Option<Arc<Value>>>,
+    ) {
+        let (walks, cache) = (self.walks.clone(), self.cache.clone());
+        tokio::spawn(async move {
+            let Ok(_permit) = walks.acquire_owned().await else { return };
+            let report = Arc::new(walk.await);
+            {
+                let mut cache = cache.write().await;
+                cache.retain(|_, cached| cached.at.elapsed() < CACHE_TTL);
+                cache.insert(key, Cached { at: Instant::now(), roots, report: report.clone() });
+            }
+            let _ = done.send(Some(report));
+        });
+    }
+}
+
+/// The state of the caller's job `token`
+async fn job_response(state: &AppState, user: &AuthUser, token: String) -> Result<JobResponse, ApiError> {
+    let jobs = state.reports.jobs.read().await;
+    let job = jobs
+        .get(&token)
+        .filter(|job| job.owner == user.username)
+        .ok_or_else(|| ApiError::NotFound(Msg::JobNotFound.into()))?;
+    let result = job.result.borrow().clone();
+    let status = match &result {
+        None => JobStatus::Computing,
+        Some(_) if job.cancellable && job.progress.is_cancelled() => JobStatus::Cancelled,
+        Some(_) => JobStatus::Done,
+    };
+    let total_bytes = job.progress.total_bytes.load(Ordering::Relaxed);
+    let percent = match status {
+        JobStatus::Done if total_bytes > 0 => Some(100),
+        _ => job.progress.percent(),
+    };
+    Ok(JobResponse {
+        kind: job.kind,
+        path: job.path.clone(),
+        status,
+        entries: job.progress.entries(),
+        bytes: job.progress.bytes.load(Ordering::Relaxed),
+        errors: job.progress.errors.load(Ordering::Relaxed),
+        total_bytes: (total_bytes > 0).then_some(total_bytes),
+        percent,
+        current: job.progress.current(),
+        result: result.map(|r| (*r).clone()),
+        token,
+    })
+}
+
+/// Poll a report job or background operation
+#[utoipa::path(
+    get, path = "/api/jobs", tag = "files", params(JobQuery),
+    responses((status = 200, description = "Progress, and the report once `done`", body = ApiResponse<JobResponse>)),
+)]
+pub async fn job_status(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<JobQuery>,
+) -> Result<Json<ApiResponse<JobResponse>>, ApiError> {
+    Ok(Json(ApiResponse::success(job_response(&state, &user, query.token).await?)))
+}
+
+/// Poll a report or background operation by its token, the same as `/api/jobs?token=`
+///
+/// Operations started with `background: true` (`/api/copy`, `/api/move`, `/api/delete`)
+/// and by `/api/archive` count entries and bytes handled so far and, in `errors`, entries that failed; once
+/// `done`, `result` is what the endpoint would have answered, failures included.
+#[utoipa::path(
+    get, path = "/api/jobs/{token}", tag = "files",
+    params(("token" = String, Path, description = "`job` from a report endpoint or a background operation")),
+    responses(
+        (status = 200, description = "Progress, and the result once `done`", body = ApiResponse<JobResponse>),
+        (status = 404, description = "No such job, or it finished over 10 minutes ago", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn job_by_token(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    AxumPath(token): AxumPath<String>,
+) -> Result<Json<ApiResponse<JobResponse>>, ApiError> {
+    Ok(Json(ApiResponse::success(job_response(&state, &user, token).await?)))
+}
+
+/// Stop a background operation started with `background: true`
+///
+/// Copies and moves stop before their next entry and remove what they had copied, so
+/// the destination is left as it was; a single large file is finished first. Deletes
+/// stop before their next entry, and what was already deleted stays deleted. The job
+/// is then `cancelled`, with the operation's answer as `result`: `CANCELLED` for a copy
+/// or move, the partial counts for a delete. Cancelling a finished job changes nothing.
+#[utoipa::path(
+    post, path = "/api/jobs/{token}/cancel", tag = "files",
+    params(("token" = String, Path, description = "`job` from a background operation")),
+    responses(
+        (status = 200, description = "The job, stopping or stopped", body = ApiResponse<JobResponse>),
+        (status = 400, description = "A report job, which can't be cancelled", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No such job", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    AxumPath(token): AxumPath<String>,
+) -> Result<Json<ApiResponse<JobResponse>>, ApiError> {
+    {
+        let jobs = state.reports.jobs.read().await;
+        let job = jobs
+            .get(&token)
+            .filter(|job| job.owner == user.username)
+            .ok_or_else(|| ApiError::NotFound(Msg::JobNotFound.into()))?;
+        if !job.cancellable {
+            return Err(ApiError::BadRequest(Msg::JobNotCancellable.into()));
+        }
+        if job.result.borrow().is_none() {
+            job.progress.cancel.cancel();
+        }
+    }
+    Ok(Json(ApiResponse::success(job_response(&state, &user, token).await?)))
+}
+
+/// Follow a report or background operation as a `text/event-stream` instead of polling
+///
+/// A `progress` event, the job as `/api/jobs/{token}` answers it, is sent at once and
+/// then whenever the counters or `current` change, at most every half second. Copies,
+/// moves and deletes also carry `totalBytes` and `percent`, and copies the entry they
+/// are on. When the job ends a `done` event with its `result` is sent and the stream
+/// closes; a job that can no longer be found ends it too.
+#[utoipa::path(
+    get, path = "/api/jobs/{token}/events", tag = "files",
+    params(("token" = String, Path, description = "`job` from a report endpoint or a background operation")),
+    responses(
+        (status = 200, description = "`text/event-stream` of `progress` events, then one `done` event", content_type = "text/event-stream", body = JobResponse),
+        (status = 404, description = "No such job", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn job_events(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    AxumPath(token): AxumPath<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let first = job_response(&state, &user, token.clone()).await?;
+    let mut interval = tokio::time::interval(EVENT_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // Ends the stream at shutdown instead of letting it hold up the grace period
+    let shutdown = state.shutdown.clone().cancelled_owned();
+    let follow = (Some(first), None::<String>, interval);
+    let events = stream::unfold(follow, move |(mut next, last, mut interval)| {
+        let (state, user, token) = (state.clone(), user.clone(), token.clone());
+        async move {
+            loop {
+                let job = match next.take() {
+                    Some(job) => job,
+                    // Done, or the job is gone
+                    None if last.is_none() => return None,
+                    None => {
+                        interval.tick().await;
+                        job_response(&state, &user, token.clone()).await.ok()?
+                    }
+                };
+                if job.status != JobStatus::Computing {
+                    let event = Event::default().event("done").json_data(job).ok()?;
+                    return Some((Ok(event), (None, None, interval)));
+                }
+                let data = serde_json::to_string(&job).ok()?;
+                if last.as_ref() == Some(&data) {
+                    continue;
+                }
+                let event = Event::default().event("progress").data(&data);
+                return Some((Ok(event), (None, Some(data), interval)));
+            }
+        }
+    })
+    .take_until(shutdown);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,92 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use crate::error::ApiError;
+
+/// A JSON document in the data directory, read once at startup and rewritten whole
+///
+/// Shared by the stores kept in `--data-dir` (API keys, shares, favorites, file metadata,
+/// the trash index). Each holds its data in memory behind a lock and saves it here after
+/// every change, while still holding the lock.
+pub struct JsonFile {
+    path: PathBuf,
+    /// What the file holds, for error messages ("share store")
+    what: &'static str,
+}
+
+impl JsonFile {
+    pub fn new(data_dir: &Path, name: &str, what: &'static str) -> Self {
+        Self { path: data_dir.join(name), what }
+    }
+
+    /// The stored value, or the default when the file doesn't exist yet
+    pub fn load<T: DeserializeOwned + Default>(&self) -> Result<T, String> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(content) => {
+                serde_json::from_str(&content).map_err(|e| format!("Invalid {} {:?}: {}", self.what, self.path, e))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+            Err(e) => Err(format!("Failed to read {} {:?}: {}", self.what, self.path, e)),
+        }
+    }
+
+    /// Replace the file atomically (temp file + rename), so a crash leaves the old or the
+    /// new version, never half of one
+    pub async fn save<T: Serialize + ?Sized>(&self, value: &T) -> Result<(), ApiError> {
+        let json = serde_json::to_string_pretty(value)
+            .map_err(|e| ApiError::Io(format!("Failed to serialize {}: {}", self.what, e)))?;
+        let tmp = self.path.with_extension("json.tmp");
+        let context = || format!("Failed to write {}", self.what);
+        fs::write(&tmp, json).await.map_err(|e| ApiError::io(context(), e))?;
+        fs::rename(&tmp, &self.path).await.map_err(|e| ApiError::io(context(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn values_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = JsonFile::new(dir.path(), "things.json", "thing store");
+        let things = HashMap::from([("a".to_string(), vec![1, 2]), ("b".to_string(), vec![])]);
+        file.save(&things).await.unwrap();
+        assert_eq!(file.load::<HashMap<String, Vec<u32>>>().unwrap(), things);
+
+        // Saving again replaces the whole document and leaves no temp file behind
+        file.save(&HashMap::<String, Vec<u32>>::new()).await.unwrap();
+        assert!(file.load::<HashMap<String, Vec<u32>>>().unwrap().is_empty());
+        let names: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, ["things.json"]);
+    }
+
+    #[test]
+    fn a_missing_file_is_the_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = JsonFile::new(dir.path(), "things.json", "thing store");
+        assert_eq!(file.load::<Vec<String>>().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn a_damaged_file_is_an_error_naming_it() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("things.json"), "{\"a\": [1,").unwrap();
+        let file = JsonFile::new(dir.path(), "things.json", "thing store");
+        let error = file.load::<HashMap<String, Vec<u32>>>().unwrap_err();
+        assert!(error.starts_with("Invalid thing store") && error.contains("things.json"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn a_failed_save_keeps_the_old_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = JsonFile::new(dir.path(), "things.json", "thing store");
+        file.save(&vec![1]).await.unwrap();
+        // The temp file's name taken by a folder makes the write fail
+        std::fs::create_dir(dir.path().join("things.json.tmp")).unwrap();
+        let error = file.save(&vec![2]).await.unwrap_err();
+        assert!(error.to_string().starts_with("Failed to write thing store"), "{error}");
+        assert_eq!(file.load::<Vec<u32>>().unwrap(), [1]);
+    }
+}
@@ -0,0 +1,250 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::error::ApiError;
+use crate::handlers::format_size;
+use crate::i18n::Msg;
+use crate::models::{UploadCapabilities, UploadUsage};
+
+/// Largest chunk accepted by `/api/upload/chunk`
+const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+/// Chunk size suggested to clients (5MB - safe for most proxies)
+const DEFAULT_CHUNK_SIZE: u64 = 5 * 1024 * 1024;
+/// Room for multipart boundaries and part headers around the file data
+const MULTIPART_OVERHEAD: u64 = 16 * 1024;
+/// Smallest allowed `--max-request-body`
+pub const MIN_REQUEST_BODY: u64 = 64 * 1024;
+/// How long an upload waits for a free stream slot before it's refused
+const STREAM_WAIT: Duration = Duration::from_secs(5);
+
+/// Upload size limits derived from `--max-request-body`, and the `--upload-idle-secs` timeout
+///
+/// The same limit applies to every upload path: a plain multipart request can't exceed
+/// it, and a chunked upload can't assemble a file larger than it.
+#[derive(Clone, Copy)]
+pub struct UploadLimits {
+    /// Maximum request body for API and share uploads
+    pub max_request_body: u64,
+    /// Maximum size of a single uploaded file
+    pub max_file_size: u64,
+    /// Maximum data in one chunk of a chunked upload
+    pub max_chunk_size: u64,
+    /// How long an upload may receive nothing before it is aborted
+    pub idle_timeout: Duration,
+}
+
+impl UploadLimits {
+    pub fn new(max_request_body: u64, idle_timeout: Duration) -> Self {
+        Self {
+            max_request_body,
+            max_file_size: max_request_body,
+            max_chunk_size: MAX_CHUNK_SIZE.min(max_request_body - MULTIPART_OVERHEAD),
+            idle_timeout,
+        }
+    }
+
+    /// Body limit for a chunk request, including the multipart framing
+    pub fn chunk_body(&self) -> u64 {
+        self.max_chunk_size + MULTIPART_OVERHEAD
+    }
+
+    /// The limits as reported by `/api/capabilities`
+    pub fn capabilities(&self) -> UploadCapabilities {
+        let chunk_size = DEFAULT_CHUNK_SIZE.min(self.max_chunk_size);
+        UploadCapabilities {
+            max_request_body: self.max_request_body,
+            max_file_size: self.max_file_size,
+            max_chunk_size: self.max_chunk_size,
+            chunk_size,
+            // Files above this go through chunked upload; a plain upload must fit in one body
+            chunked_threshold: (2 * chunk_size).min(self.max_request_body - MULTIPART_OVERHEAD),
+            upload_idle_secs: self.idle_timeout.as_secs(),
+        }
+    }
+}
+
+/// Error for a body over `limit` bytes
+pub(crate) fn too_large(limit: u64) -> ApiError {
+    ApiError::PayloadTooLarge(format!("{} ({})", Msg::BodyTooLarge, format_size(limit)))
+}
+
+/// Error for an upload that received nothing for `idle`
+pub(crate) fn idle_timeout(idle: Duration) -> ApiError {
+    ApiError::IdleTimeout(Msg::UploadIdle.with(&[&idle.as_secs()]))
+}
+
+/// The request's `Content-Length`, if it sent a valid one
+pub(crate) fn declared_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+}
+
+/// Companion to `DefaultBodyLimit::max(limit)` that answers oversized requests with JSON
+///
+/// A declared `Content-Length` over the limit is rejected before the body is read; axum's
+/// plain-text 413 for bodies that turn out too large while streaming is replaced as well.
+pub async fn reject_oversized(State(limit): State<u64>, request: Request, next: Next) -> Response {
+    let declared = declared_length(request.headers());
+    if declared.is_some_and(|len| len > limit) {
+        return too_large(limit).into_response();
+    }
+
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        return too_large(limit).into_response();
+    }
+    response
+}
+
+/// Caps on uploads in progress, from `--max-upload-streams` and `--max-upload-sessions`
+///
+/// A slot is a semaphore permit owned by the request or chunked upload session using it,
+/// so it's returned however that ends, including a dropped connection or a panic.
+#[derive(Clone)]
+pub struct UploadSlots {
+    streams: Arc<Semaphore>,
+    max_streams: usize,
+    sessions: Arc<Semaphore>,
+    max_sessions: usize,
+}
+
+impl UploadSlots {
+    pub fn new(max_streams: usize, max_sessions: usize) -> Self {
+        Self {
+            streams: Arc::new(Semaphore::new(max_streams)),
+            max_streams,
+            sessions: Arc::new(Semaphore::new(max_sessions)),
+            max_sessions,
+        }
+    }
+
+    /// A slot for a request writing upload data to disk, waiting briefly for one to free up
+    pub async fn stream(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        match tokio::time::timeout(STREAM_WAIT, self.streams.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(ApiError::TooManyUploads(Msg::TooManyUploads.into())),
+        }
+    }
+
+    /// A slot for a new chunked upload session, held until it completes or is aborted
+    pub fn session(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        self.sessions
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| ApiError::TooManySessions(Msg::TooManySessions.with(&[&self.max_sessions])))
+    }
+
+    /// Slots in use and their limits
+    pub fn usage(&self) -> UploadUsage {
+        UploadUsage {
+            streams: self.max_streams - self.streams.available_permits(),
+            max_streams: self.max_streams,
+            sessions: self.max_sessions - self.sessions.available_permits(),
+            max_sessions: self.max_sessions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{admin, TestApp};
+    use axum::body::Body;
+    use axum::http::Method;
+    use futures::StreamExt;
+
+    /// A multipart upload of `a.bin` exactly `total` bytes long
+    fn upload(total: u64, declare_length: bool) -> axum::http::Request<Body> {
+        let head = "--B\r\nContent-Disposition: form-data; name=\"files\"; filename=\"a.bin\"\r\n\r\n";
+        let tail = "\r\n--B--\r\n";
+        let data = vec![b'x'; total as usize - head.len() - tail.len()];
+        let body = [head.as_bytes(), &data, tail.as_bytes()].concat();
+        let mut request = admin(Method::POST, "/api/upload?path=/")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=B");
+        let body = if declare_length {
+            request = request.header(header::CONTENT_LENGTH, body.len());
+            Body::from(body)
+        } else {
+            let chunks = body.chunks(4096).map(|c| Ok::<_, std::io::Error>(c.to_vec())).collect::<Vec<_>>();
+            Body::from_stream(futures::stream::iter(chunks))
+        };
+        request.body(body).unwrap()
+    }
+
+    const HEAD: &str = "--B\r\nContent-Disposition: form-data; name=\"files\"; filename=\"slow.bin\"\r\n\r\n";
+
+    /// An upload of `slow.bin` sending `parts` pieces of data `gap` apart, then waiting forever
+    /// unless `finish`
+    fn trickle(parts: usize, gap: Duration, finish: bool) -> axum::http::Request<Body> {
+        let data = futures::stream::iter(0..parts).then(move |_| async move {
+            tokio::time::sleep(gap).await;
+            Ok::<_, std::io::Error>(b"data".to_vec())
+        });
+        let end = futures::stream::iter(finish.then(|| Ok(b"\r\n--B--\r\n".to_vec())));
+        let body = futures::stream::iter([Ok(HEAD.as_bytes().to_vec())])
+            .chain(data)
+            .chain(end)
+            .chain(futures::stream::pending());
+        admin(Method::POST, "/api/upload?path=/")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=B")
+            .body(Body::from_stream(body))
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_uploads_time_out_and_active_ones_do_not() {
+        let app = TestApp::with(|c| c.upload_idle_secs = 10);
+
+        // Data every 6s for a minute: never idle for 10s
+        let started = tokio::time::Instant::now();
+        let (status, body) = app.json(trickle(10, Duration::from_secs(6), true)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(started.elapsed() >= Duration::from_secs(60));
+        assert_eq!(app.read("slow.bin"), b"data".repeat(10));
+
+        // Stops sending after two pieces: cut off 10s later, leaving nothing behind
+        std::fs::remove_file(app.path("slow.bin")).unwrap();
+        let started = tokio::time::Instant::now();
+        let (status, body) = app.json(trickle(2, Duration::from_secs(6), false)).await;
+        assert_eq!(status, StatusCode::REQUEST_TIMEOUT, "{body}");
+        assert_eq!(body["code"], "IDLE_TIMEOUT");
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(22) && elapsed < Duration::from_secs(30), "{elapsed:?}");
+        assert!(!app.path("slow.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn bodies_up_to_the_limit_are_accepted() {
+        let app = TestApp::with(|c| c.max_request_body = MIN_REQUEST_BODY);
+        for declare_length in [true, false] {
+            let (status, body) = app.json(upload(MIN_REQUEST_BODY, declare_length)).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert!(app.path("a.bin").exists());
+            std::fs::remove_file(app.path("a.bin")).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_refused() {
+        let app = TestApp::with(|c| c.max_request_body = MIN_REQUEST_BODY);
+        for declare_length in [true, false] {
+            let (status, body) = app.json(upload(MIN_REQUEST_BODY + 1, declare_length)).await;
+            assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{body}");
+            assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+            assert!(!app.path("a.bin").exists(), "declared: {declare_length}");
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use axum::{
+    extract::{Extension, Query, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use crate::error::ApiError;
+use crate::handlers::{check_patterns, safe_path};
+use crate::i18n::Msg;
+use crate::models::{ApiResponse, LockInfo, LockQuery, LockRequest, LocksResponse, OperationResponse};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Lifetime of a lock when the request names none
+const DEFAULT_TTL_SECS: u64 = 300;
+/// Longest lifetime; holders renew to keep a lock longer
+const MAX_TTL_SECS: u64 = 24 * 3600;
+/// How often expired locks are dropped
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+/// Request header a lock holder sends its token in
+pub const LOCK_TOKEN_HEADER: &str = "x-lock-token";
+
+struct FileLock {
+    token: String,
+    owner: String,
+    subtree: bool,
+    /// Unix seconds
+    expires_at: i64,
+}
+
+impl FileLock {
+    fn info(&self, path: &str, with_token: bool) -> LockInfo {
+        LockInfo {
+            path: path.to_string(),
+            owner: self.owner.clone(),
+            token: with_token.then(|| self.token.clone()),
+            subtree: self.subtree,
+            expires_at: self.expires_at,
+        }
+    }
+
+    /// Whether `user`, sending `token`, is this lock's holder
+    fn held_by(&self, user: &AuthUser, token: Option<&str>) -> bool {
+        self.owner == user.username && token == Some(self.token.as_str())
+    }
+
+    /// Whether this lock, on `path`, stands in the way of a change to `target`
+    ///
+    /// `tree` means the change reaches everything below `target` too, as a delete or move does.
+    fn blocks(&self, path: &str, target: &str, tree: bool) -> bool {
+        path == target || (self.subtree && is_within(target, path)) || (tree && is_within(path, target))
+    }
+}
+
+/// Whether the logical path `inner` is `outer` or below it
+fn is_within(inner: &str, outer: &str) -> bool {
+    outer == "/" || inner == outer || inner.strip_prefix(outer).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The lock token sent with a request, if any
+fn sent_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(LOCK_TOKEN_HEADER).and_then(|v| v.to_str().ok()).map(str::trim)
+}
+
+/// The refusal of a change to `target` because of `lock` on `locked`, naming the lock's
+/// path and owner only to a user who may see that path
+fn locked_error(user: &AuthUser, target: &str, locked: &str, lock: &FileLock) -> ApiError {
+    if !user.can_see(locked) {
+        return ApiError::Locked(Msg::PathLockedHidden.with(&[&target]));
+    }
+    let until = DateTime::<Utc>::from_timestamp(lock.expires_at, 0).map_or_else(String::new, |t| t.to_rfc3339());
+    ApiError::Locked(Msg::PathLocked.with(&[&locked, &lock.owner, &until]))
+}
+
+/// Advisory locks on logical paths, held in memory until released or expired
+///
+/// Every operation runs under one mutex and treats expired locks as gone, so taking,
+/// renewing, releasing and checking can't interleave, and a lock that just expired is
+/// free to take even before the reaper has dropped it. Locks follow paths, not files: a
+/// locked file that is renamed leaves the lock on its old path.
+#[derive(Default)]
+pub struct LockStore {
+    locks: Mutex<HashMap<String, FileLock>>,
+}
+
+impl LockStore {
+    /// The live locks, with expired ones dropped
+    fn live(&self) -> std::sync::MutexGuard<'_, HashMap<String, FileLock>> {
+        let now = Utc::now().timestamp();
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.retain(|_, lock| lock.expires_at > now);
+        locks
+    }
+
+    /// Refuse a write to `path` (and, with `tree`, below it) while someone holds a lock in
+    /// the way, unless the request carries that lock's token and comes from its owner
+    pub fn check(&self, path: &str, tree: bool, user: &AuthUser, headers: &HeaderMap) -> Result<(), ApiError> {
+        let token = sent_token(headers);
+        let locks = self.live();
+        let blocking = locks.iter().find(|(locked, lock)| {
+            lock.blocks(locked, path, tree) && !lock.held_by(user, token)
+        });
+        match blocking {
+            Some((locked, lock)) => Err(locked_error(user, path, locked, lock)),
+            None => Ok(()),
+        }
+    }
+
+    fn acquire(&self, path: &str, user: &AuthUser, req: &LockRequest) -> Result<LockInfo, ApiError> {
+        let ttl = req.ttl_secs.unwrap_or(DEFAULT_TTL_SECS);
+        if ttl == 0 || ttl > MAX_TTL_SECS {
+            return Err(ApiError::BadRequest(Msg::InvalidLockTtl.with(&[&MAX_TTL_SECS])));
+        }
+        let mut locks = self.live();
+        // Renewing replaces the caller's own lock on this path
+        let renewing = locks.get(path).is_some_and(|lock| lock.held_by(user, req.token.as_deref()));
+        if req.token.is_some() && !renewing {
+            return Err(ApiError::NotFound(Msg::LockNotFound.into()));
+        }
+        let conflict = locks.iter().find(|(locked, lock)| {
+            (!renewing || locked.as_str() != path) && lock.blocks(locked, path, req.subtree)
+        });
+        if let Some((locked, lock)) = conflict {
+            return Err(locked_error(user, path, locked, lock));
+        }
+        let lock = FileLock {
+            token: req.token.clone().filter(|_| renewing).unwrap_or_else(|| Uuid::new_v4().simple().to_string()),
+            owner: user.username.clone(),
+            subtree: req.subtree,
+            expires_at: Utc::now().timestamp() + ttl as i64,
+        };
+        let info = lock.info(path, true);
+        locks.insert(path.to_string(), lock);
+        Ok(info)
+    }
+
+    /// Drop the lock on exactly `path`; its owner needs the token, the administrator doesn't
+    fn release(&self, path: &str, user: &AuthUser, token: Option<&str>, is_admin: bool) -> Result<(), ApiError> {
+        let mut locks = self.live();
+        let lock = locks.get(path).ok_or_else(|| ApiError::NotFound(Msg::LockNotFound.into()))?;
+        if !(is_admin || lock.held_by(user, token)) {
+            return Err(locked_error(user, path, path, lock));
+        }
+        locks.remove(path);
+        Ok(())
+    }
+
+    /// Locks on `path`, on folders above it covering their subtree, and anywhere below it
+    fn around(&self, path: &str) -> Vec<LockInfo> {
+        let locks = self.live();
+        let mut found: Vec<LockInfo> = locks
+            .iter()
+            .filter(|(locked, lock)| lock.blocks(locked, path, true))
+            .map(|(locked, lock)| lock.info(locked, false))
+            .collect();
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        found
+    }
+}
+
+/// Drop expired locks every `REAP_INTERVAL` until shutdown
+pub fn start_reaper(locks: std::sync::Arc<LockStore>, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => drop(locks.live()),
+            }
+        }
+    });
+}
+
+/// Take a lock on a file or folder, or renew one by passing its `token`
+///
+/// While the lock lasts, content writes, uploads, renames, moves and deletes touching the
+/// path answer 423 `LOCKED` unless they carry the token in `X-Lock-Token` and come from
+/// the holder. With `subtree` the lock covers everything below a folder too. The path
+/// needn't exist yet, so a name can be reserved before uploading to it.
+#[utoipa::path(
+    post, path = "/api/lock", tag = "files", request_body = LockRequest,
+    responses(
+        (status = 200, description = "The lock, with the token to write and release with", body = ApiResponse<LockInfo>),
+        (status = 404, description = "`token` doesn't match a lock the caller holds on the path", body = crate::openapi::ErrorBody),
+        (status = 423, description = "`LOCKED`: another lock is in the way", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn acquire_lock(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<LockRequest>,
+) -> Result<Json<ApiResponse<LockInfo>>, ApiError> {
+    let paths = safe_path(&state.mounts, &req.path)?;
+    let path = state.mounts.logical_path(&paths.logical);
+    user.check(&path).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &path, true)?;
+    let info = state.locks.acquire(&path, &user, &req)?;
+    Ok(Json(ApiResponse::success(info)))
+}
+
+/// Release a lock, sending its token in `X-Lock-Token`
+///
+/// The administrator can release anyone's lock without the token.
+#[utoipa::path(
+    delete, path = "/api/lock", tag = "files", params(LockQuery),
+    responses(
+        (status = 200, description = "Lock released", body = ApiResponse<OperationResponse>),
+        (status = 404, description = "No lock on the path", body = crate::openapi::ErrorBody),
+        (status = 423, description = "`LOCKED`: the lock belongs to someone else or the token is wrong", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn release_lock(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<LockQuery>,
+    headers: HeaderMap,
+) -> Result<Json<ApiResponse<OperationResponse>>, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let path = state.mounts.logical_path(&paths.logical);
+    user.check(&path).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &path, false)?;
+    let is_admin = user.username == state.username;
+    state.locks.release(&path, &user, sent_token(&headers), is_admin)?;
+    Ok(Json(ApiResponse::success(OperationResponse {
+        message: Msg::LockReleased.into(),
+        new_path: None,
+    })))
+}
+
+/// Locks that affect a path: on it, on folders above it covering their subtree, and below it
+///
+/// Tokens are never listed.
+#[utoipa::path(
+    get, path = "/api/lock", tag = "files", params(LockQuery),
+    responses((status = 200, description = "Live locks, by path", body = ApiResponse<LocksResponse>)),
+)]
+pub async fn get_locks(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<LockQuery>,
+) -> Result<Json<ApiResponse<LocksResponse>>, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let path = state.mounts.logical_path(&paths.logical);
+    user.check_visible(&path).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &path, false)?;
+    let locks = state.locks.around(&path).into_iter().filter(|lock| user.can_see(&lock.path)).collect();
+    Ok(Json(ApiResponse::success(LocksResponse { locks })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::users::PathRules;
+    use std::sync::Arc;
+
+    fn user(name: &str) -> AuthUser {
+        AuthUser { username: name.to_string(), rules: None, readonly: false }
+    }
+
+    fn request(path: &str, subtree: bool, token: Option<&str>) -> LockRequest {
+        LockRequest { path: path.to_string(), ttl_secs: None, subtree, token: token.map(str::to_string) }
+    }
+
+    fn with_token(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCK_TOKEN_HEADER, token.parse().unwrap());
+        headers
+    }
+
+    fn message(error: ApiError) -> String {
+        match error {
+            ApiError::Locked(message) => message,
+            _ => panic!("not a lock refusal"),
+        }
+    }
+
+    #[test]
+    fn only_the_holder_with_the_token_gets_past_a_lock() {
+        let (store, alice, bob) = (LockStore::default(), user("alice"), user("bob"));
+        let token = store.acquire("/a/f.txt", &alice, &request("/a/f.txt", false, None)).unwrap().token.unwrap();
+        let none = HeaderMap::new();
+
+        assert!(store.check("/a/f.txt", false, &alice, &with_token(&token)).is_ok());
+        assert!(store.check("/a/f.txt", false, &alice, &none).is_err());
+        assert!(store.check("/a/f.txt", false, &bob, &with_token(&token)).is_err());
+        assert!(store.check("/a/g.txt", false, &bob, &none).is_ok());
+        // Deleting or moving the folder would take the locked file along
+        assert!(store.check("/a", true, &bob, &none).is_err());
+        assert!(store.check("/a", false, &bob, &none).is_ok());
+    }
+
+    #[test]
+    fn subtree_locks_cover_what_is_below() {
+        let (store, alice, bob) = (LockStore::default(), user("alice"), user("bob"));
+        store.acquire("/a", &alice, &request("/a", true, None)).unwrap();
+        assert!(store.check("/a/b/c.txt", false, &bob, &HeaderMap::new()).is_err());
+        assert!(store.check("/ab", false, &bob, &HeaderMap::new()).is_ok());
+        assert!(store.acquire("/a/b", &bob, &request("/a/b", false, None)).is_err());
+    }
+
+    #[test]
+    fn renewing_keeps_the_token() {
+        let (store, alice, bob) = (LockStore::default(), user("alice"), user("bob"));
+        let token = store.acquire("/f", &alice, &request("/f", false, None)).unwrap().token.unwrap();
+        let renewed = store.acquire("/f", &alice, &request("/f", false, Some(&token))).unwrap();
+        assert_eq!(renewed.token.as_deref(), Some(token.as_str()));
+        assert!(matches!(store.acquire("/f", &bob, &request("/f", false, Some(&token))), Err(ApiError::NotFound(_))));
+        assert!(matches!(store.acquire("/f", &alice, &request("/f", false, Some("other"))), Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn releasing_needs_the_holder_and_token_or_the_administrator() {
+        let (store, alice, bob) = (LockStore::default(), user("alice"), user("bob"));
+        let token = store.acquire("/f", &alice, &request("/f", false, None)).unwrap().token.unwrap();
+        assert!(store.release("/f", &bob, Some(&token), false).is_err());
+        assert!(store.release("/f", &alice, None, false).is_err());
+        assert!(store.release("/f", &alice, Some(&token), false).is_ok());
+        assert!(matches!(store.release("/f", &alice, Some(&token), false), Err(ApiError::NotFound(_))));
+
+        store.acquire("/f", &alice, &request("/f", false, None)).unwrap();
+        assert!(store.release("/f", &bob, None, true).is_ok());
+    }
+
+    #[test]
+    fn refusals_name_the_owner_only_to_users_who_see_the_lock() {
+        let store = LockStore::default();
+        store.acquire("/team/private/f", &user("alice"), &request("/team/private/f", false, None)).unwrap();
+        let rules = PathRules::new(&[], &["/team/private".to_string()]);
+        let bob = AuthUser { rules: Some(Arc::new(rules)), ..user("bob") };
+
+        let hidden = message(store.check("/team", true, &bob, &HeaderMap::new()).unwrap_err());
+        assert!(!hidden.contains("alice") && !hidden.contains("private"), "{hidden}");
+        assert!(hidden.contains("/team"), "{hidden}");
+
+        let shown = message(store.check("/team", true, &user("carol"), &HeaderMap::new()).unwrap_err());
+        assert!(shown.contains("alice") && shown.contains("/team/private/f"), "{shown}");
+    }
+}
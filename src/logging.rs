@@ -0,0 +1,89 @@
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::{field::Empty, info, info_span, Instrument, Span};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use uuid::Uuid;
+use crate::config::LogFormat;
+
+/// Header carrying the request ID, accepted from a proxy and echoed in the response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Install the global subscriber in the configured format
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "filest=info,tower_http=info".into());
+    let registry = tracing_subscriber::registry().with(filter);
+    match format {
+        LogFormat::Text => registry.with(fmt::layer()).init(),
+        // One object per line; the enclosing request span supplies the correlation fields
+        LogFormat::Json => registry
+            .with(fmt::layer().json().with_current_span(true).with_span_list(false))
+            .init(),
+    }
+}
+
+/// Record the logical path an operation works on in the current request span
+pub fn record_path(path: &str) {
+    Span::current().record("path", path);
+}
+
+/// Use the client's request ID if it is sane, otherwise generate one
+fn request_id_of(request: &Request) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string())
+}
+
+/// Tag every request with an ID and echo it in the `X-Request-Id` response header
+///
+/// With JSON logs the request also runs inside a span carrying the ID, method, URI path,
+/// client address, authenticated user and logical path, so every line it logs can be correlated. Text
+/// logs are left exactly as they were.
+pub async fn request_id(
+    State(format): State<LogFormat>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let id = request_id_of(&request);
+
+    let mut response = match format {
+        LogFormat::Text => next.run(request).await,
+        LogFormat::Json => {
+            let span = info_span!(
+                "request",
+                request_id = %id,
+                method = %request.method(),
+                uri = %request.uri().path(),
+                client = %crate::auth::client_ip(&request),
+                user = Empty,
+                path = Empty,
+            );
+            let started = Instant::now();
+            async move {
+                let response = next.run(request).await;
+                info!(
+                    status = response.status().as_u16(),
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "request completed"
+                );
+                response
+            }
+            .instrument(span)
+            .await
+        }
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
@@ -14,151 +14,1400 @@
 //! # 自定义配置
 //! ./filest --root /path/to/files --port 8080 --user admin --password secret
 //! ```
+mod activity;
+mod apikeys;
+mod archive;
+mod assets;
+mod audit;
 mod auth;
+mod capabilities;
+mod category;
+mod clipboard;
+mod config;
+mod content;
+mod dav;
+mod error;
+mod dirsize;
+mod disks;
+mod events;
+mod extract;
+mod favorites;
+mod grep;
 mod handlers;
+mod hashes;
+mod health;
+mod index;
+mod hooks;
+mod i18n;
+mod jobs;
+mod jsonfile;
+mod limits;
+mod locks;
+mod logging;
+mod manifest;
+mod markdown;
+mod media;
+mod meta;
 mod models;
+mod mounts;
+mod openapi;
+mod paths;
+mod patterns;
+mod proxy;
+mod ratelimit;
+mod raw;
+mod render;
+mod share;
+mod stats;
+mod tail;
+#[cfg(test)]
+mod testing;
+mod throttle;
+mod tls;
+mod trash;
+mod undo;
+mod usage;
+mod users;
 use axum::{
     body::Body,
-    extract::DefaultBodyLimit,
-    http::{header, Method, Response, StatusCode},
+    extract::{connect_info::IntoMakeServiceWithConnectInfo, DefaultBodyLimit, State},
+    http::{header, HeaderValue, Method, Response, StatusCode},
     middleware,
-    routing::{delete, get, post, put},
-    Router,
+    routing::{any, delete, get, post, put},
+    Json, Router,
 };
 use clap::Parser;
-use std::{net::SocketAddr, path::PathBuf};
-use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use models::{new_upload_sessions, UploadSessions};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::{info, warn};
+use models::{
+    new_auth_failures, new_auth_tokens, new_sessions, new_upload_sessions, AuthFailures,
+    AuthTokens, Sessions, UploadSessions,
+};
 
 /// 应用状态
 #[derive(Clone)]
 pub struct AppState {
-    pub root_dir: PathBuf,
+    /// URL 前缀（如 "/filest"，根路径时为空），用于生成链接和 Cookie 路径
+    pub base_path: String,
+    /// 文件根目录（单个 --root 或多个命名挂载）
+    pub mounts: Arc<mounts::Mounts>,
     pub username: String,
     pub password: String,
+    /// 密码哈希（bcrypt 或 argon2 PHC），设置后优先于明文密码
+    pub password_hash: Option<String>,
     pub upload_sessions: UploadSessions,
+    pub auth_tokens: AuthTokens,
+    /// 登录令牌有效期（秒）
+    pub token_ttl_secs: u64,
+    pub auth_failures: AuthFailures,
+    /// 锁定前允许的连续认证失败次数
+    pub auth_max_failures: u32,
+    /// 失败计数窗口与锁定时长（秒）
+    pub auth_lockout_secs: u64,
+    pub sessions: Sessions,
+    /// 会话空闲超时（秒）
+    pub session_idle_secs: u64,
+    /// 会话绝对有效期（秒）
+    pub session_max_secs: u64,
+    /// 是否启用 HTTPS（决定会话 Cookie 是否带 Secure）
+    pub tls_enabled: bool,
+    /// 用户文件中的附加用户（含路径访问规则）
+    pub users: users::Users,
+    /// 数据目录（分享等持久化状态）
+    pub data_dir: PathBuf,
+    pub shares: Arc<share::ShareStore>,
+    pub api_keys: Arc<apikeys::ApiKeyStore>,
+    /// 每个用户的收藏（data_dir/favorites.json）
+    pub favorites: Arc<favorites::FavoriteStore>,
+    /// 文件标签与备注（data_dir/file_meta.json）
+    pub meta: Arc<meta::MetaStore>,
+    /// 按逻辑路径的协作锁（/api/lock），仅保存在内存中
+    pub locks: Arc<locks::LockStore>,
+    /// 每个用户的剪贴板（/api/clipboard、/api/paste），仅保存在内存中
+    pub clipboards: Arc<clipboard::ClipboardStore>,
+    /// 回收站（各挂载根目录下的 .filest_trash，索引为 data_dir/trash.json）
+    pub trash: Arc<trash::TrashStore>,
+    /// 每个用户最近的重命名、移动、复制和删除，供 /api/undo 撤销，仅保存在内存中
+    pub undo: Arc<undo::UndoStore>,
+    /// 文件内容哈希缓存（按路径、大小和修改时间；/api/upload/check 与跳过相同内容的上传）
+    pub hashes: Arc<hashes::HashCache>,
+    /// 按文件串行化的局部写入、保存与追加（PATCH、PUT /api/content，/api/append）
+    pub content_locks: Arc<content::PathLocks>,
+    /// 写操作审计日志
+    pub audit: Arc<audit::AuditLog>,
+    /// 隐藏路径（不出现在列表/搜索中，也不可访问）
+    pub hidden: Arc<patterns::PathPatterns>,
+    /// 受保护路径（拒绝下载、覆盖、重命名、移动、复制目标和删除）
+    pub protected: Arc<patterns::PathPatterns>,
+    /// 服务启动时间（健康检查的 uptime）
+    pub started_at: std::time::Instant,
+    /// 健康检查是否探测根目录可写
+    pub health_write_probe: bool,
+    /// 上传大小限制
+    pub upload_limits: limits::UploadLimits,
+    /// 服务端能力说明（/api/capabilities），启动时按配置生成
+    pub capabilities: Arc<capabilities::Capabilities>,
+    /// 同时进行的上传数量限制（写入中的请求、未完成的分片会话）
+    pub upload_slots: limits::UploadSlots,
+    /// 上传文件存入前运行的检查命令（--upload-hook）
+    pub upload_hook: Option<Arc<hooks::UploadHook>>,
+    /// 新文件名规则
+    pub name_rules: paths::NameRules,
+    /// 目录变更通知（/api/events）共享的文件系统监视器
+    pub watchers: events::Watchers,
+    /// 磁盘列表缓存（/api/disk、健康检查、空间不足提醒）
+    pub disks: Arc<disks::DiskCache>,
+    /// 文件夹大小缓存与后台计算（/api/info）
+    pub dir_sizes: Arc<dirsize::DirSizes>,
+    /// 后台报告任务及其结果缓存（/api/usage、/api/stats、/api/jobs）
+    pub reports: Arc<jobs::ReportJobs>,
+    /// 报告遍历的最多条目数
+    pub report_max_entries: u64,
+    /// 查看文件末尾时最多读取的字节数（/api/tail）
+    pub tail_max_bytes: u64,
+    /// 每个下载的最大速率（字节/秒）
+    pub max_download_rate: Option<u64>,
+    /// 同时进行的搜索遍历名额（/api/search）
+    pub search_permits: Arc<tokio::sync::Semaphore>,
+    /// 同时缩放图片的名额（/api/image）
+    pub image_permits: Arc<tokio::sync::Semaphore>,
+    /// 搜索的最长时间上限
+    pub search_timeout: Option<Duration>,
+    /// 每次搜索响应最多返回的结果数（/api/search）
+    pub search_max_results: usize,
+    /// 内容搜索读取的单个文件大小上限（/api/search/content）
+    pub content_search_max_bytes: u64,
+    /// 按名称搜索使用的索引（--index，data_dir/index），由后台任务维护
+    pub index: Option<Arc<index::SearchIndex>>,
+    /// 同步清单中计算哈希的单个文件大小上限（/api/manifest）
+    pub manifest_hash_max_bytes: u64,
+    /// 写入后磁盘须保留的剩余空间（字节，0 为不检查）
+    pub min_free_space: u64,
+    /// /raw 下的文件夹显示目录索引
+    pub raw_autoindex: bool,
+    /// 服务端活动广播（/api/ws/events）
+    pub activity: activity::ActivityHub,
+    /// 停止信号；长连接（SSE、WebSocket）据此提前结束
+    pub shutdown: CancellationToken,
+    /// 删除超过该大小的文件夹需要确认（force）
+    pub confirm_delete_bytes: Option<u64>,
+    /// 删除超过该条目数的文件夹需要确认（force）
+    pub confirm_delete_entries: Option<u64>,
+    /// 按客户端 IP 限制 API 的并发请求数和请求频率
+    pub client_limits: Arc<ratelimit::ClientLimits>,
 }
 /// 命令行参数
+///
+/// 未在命令行给出的选项取自 `--config` 配置文件，再回退到默认值。
 #[derive(Parser, Debug)]
 #[command(name = "filest")]
 #[command(author = "File Manager")]
 #[command(version = "1.0")]
 #[command(about = "远程文件管理器 - Web UI + HTTP API", long_about = None)]
 struct Args {
-    /// 文件根目录
-    #[arg(short, long, default_value = "./files")]
-    root: PathBuf,
-    /// 服务端口
-    #[arg(short, long, default_value_t = 3000)]
-    port: u16,
-    /// 用户名
-    #[arg(short, long, default_value = "admin")]
-    user: String,
-    /// 密码
-    #[arg(short = 'P', long, default_value = "admin123")]
-    password: String,
-    /// 绑定地址
-    #[arg(short, long, default_value = "0.0.0.0")]
-    bind: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// 配置文件（TOML），命令行参数优先于配置文件
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// 文件根目录 [默认: ./files]
+    #[arg(short, long)]
+    root: Option<PathBuf>,
+    /// 命名挂载 name=path（可重复），根目录 `/` 将列出各挂载；与 --root 互斥
+    #[arg(long = "mount", conflicts_with = "root")]
+    mounts: Vec<String>,
+    /// 服务端口 [默认: 3000]
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// 用户名 [默认: admin]
+    #[arg(short, long)]
+    user: Option<String>,
+    /// 密码 [默认: admin123]
+    #[arg(short = 'P', long)]
+    password: Option<String>,
+    /// 密码哈希（bcrypt 或 argon2 PHC 字符串），可用 `filest hash-password` 生成
+    #[arg(long, conflicts_with = "password")]
+    password_hash: Option<String>,
+    /// 绑定地址 [默认: 0.0.0.0]
+    #[arg(short, long)]
+    bind: Option<String>,
+    /// URL 前缀，用于反向代理到子路径（如 /filest）[默认: /]
+    #[arg(long)]
+    base_path: Option<String>,
+    /// 登录令牌有效期（秒）[默认: 86400]
+    #[arg(long)]
+    token_ttl_secs: Option<u64>,
+    /// 锁定前允许的认证失败次数 [默认: 10]
+    #[arg(long)]
+    auth_max_failures: Option<u32>,
+    /// 认证失败计数窗口及锁定时长（秒）[默认: 300]
+    #[arg(long)]
+    auth_lockout_secs: Option<u64>,
+    /// TLS 证书文件（PEM），与 --tls-key 同时提供时启用 HTTPS
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+    /// TLS 私钥文件（PEM）
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+    /// 启用 TLS 时额外监听的 HTTP 端口，将请求重定向到 HTTPS
+    #[arg(long)]
+    tls_redirect_port: Option<u16>,
+    /// 允许跨域访问的来源（可重复，如 https://app.example.com）；`none` 禁用跨域，默认仅同源
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+    /// 允许任意来源跨域访问（恢复旧的宽松行为）
+    #[arg(long, conflicts_with = "cors_origins")]
+    cors_allow_any: bool,
+    /// 浏览器会话空闲超时（秒）[默认: 1800]
+    #[arg(long)]
+    session_idle_secs: Option<u64>,
+    /// 浏览器会话绝对有效期（秒）[默认: 86400]
+    #[arg(long)]
+    session_max_secs: Option<u64>,
+    /// 附加用户文件（JSON），可为每个用户配置 allow/deny 路径规则
+    #[arg(long)]
+    users_file: Option<PathBuf>,
+    /// 数据目录，用于保存分享链接等持久化状态（建议位于文件根目录之外）[默认: ./filest_data]
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// 隐藏的路径模式（glob，可重复），如 `.env`、`secrets/**`
+    #[arg(long)]
+    hide: Vec<String>,
+    /// 受保护的路径模式（glob，可重复），禁止下载、覆盖、重命名、移动和删除
+    #[arg(long)]
+    protect: Vec<String>,
+    /// 删除超过该大小的文件夹时要求确认（如 10GB）
+    #[arg(long, value_parser = handlers::parse_size)]
+    confirm_delete_over: Option<u64>,
+    /// 删除超过该条目数的文件夹时要求确认
+    #[arg(long)]
+    confirm_delete_entries: Option<u64>,
+    /// 上传请求体大小上限，同时限制单个文件（如 500MB）[默认: 10GB]
+    #[arg(long, value_parser = handlers::parse_size)]
+    max_request_body: Option<u64>,
+    /// 收到停止信号后等待进行中请求完成的时间（秒）[默认: 30]
+    #[arg(long)]
+    shutdown_grace_secs: Option<u64>,
+    /// 自定义前端目录，替代内置页面（未知路径回退到其中的 index.html）
+    #[arg(long)]
+    static_dir: Option<PathBuf>,
+    /// 日志格式：text（默认）或 json（带请求 ID，便于日志采集）
+    #[arg(long, value_enum)]
+    log_format: Option<config::LogFormat>,
+    /// 客户端未发送可识别的 Accept-Language 时的消息语言 [默认: zh-CN]
+    #[arg(long, value_enum)]
+    default_lang: Option<i18n::Lang>,
+    /// 健康检查不探测根目录是否可写（只读部署）
+    #[arg(long)]
+    no_health_write_probe: bool,
+    /// 无需认证即可访问 /api/openapi.json 和 /api/docs
+    #[arg(long)]
+    public_api_docs: bool,
+    /// 关闭 /dav WebDAV 接口
+    #[arg(long)]
+    no_webdav: bool,
+    /// /raw 下访问文件夹时显示目录索引（默认拒绝）
+    #[arg(long)]
+    raw_autoindex: bool,
+    /// 新建、重命名、上传时文件名的最大长度（字节）[默认: 255]
+    #[arg(long)]
+    max_filename_bytes: Option<usize>,
+    /// 禁止新建以点开头的文件/文件夹（如 .htaccess）
+    #[arg(long)]
+    deny_dot_files: bool,
+    /// 新文件名的 Unicode 规范化形式（nfc：将 macOS 上传的 NFD 名称转为 NFC）
+    #[arg(long, value_enum)]
+    normalize_names: Option<paths::NameForm>,
+    /// 不跟随符号链接：outside（默认，拒绝指向根目录之外或不存在的链接）或 all（拒绝所有链接）
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "outside")]
+    no_follow_symlinks: Option<mounts::NoFollow>,
+    /// 查看文件夹信息时遍历的最长时间（秒），超时返回部分大小 [默认: 3]
+    #[arg(long)]
+    dir_size_timeout_secs: Option<u64>,
+    /// 查看文件夹信息时遍历的最多条目数，超出返回部分大小 [默认: 200000]
+    #[arg(long)]
+    dir_size_max_entries: Option<u64>,
+    /// 同时进行的后台遍历（完整文件夹大小、空间占用报告）数量 [默认: 2]
+    #[arg(long)]
+    dir_size_jobs: Option<usize>,
+    /// 查看文件末尾（/api/tail）时最多读取的字节数（如 4MB）[默认: 1MB]
+    #[arg(long, value_parser = handlers::parse_size)]
+    tail_max_bytes: Option<u64>,
+    /// 同时进行的搜索遍历数量，超出的搜索排队等待 [默认: 4]
+    #[arg(long)]
+    search_jobs: Option<usize>,
+    /// 同时缩放的图片数量（/api/image），超出的请求排队等待 [默认: 2]
+    #[arg(long)]
+    image_jobs: Option<usize>,
+    /// 搜索的最长时间（秒），到时返回已找到的结果；请求的 timeout_secs 不能超过它 [默认: 不限制]
+    #[arg(long)]
+    search_timeout_secs: Option<u64>,
+    /// 每次搜索响应最多返回的结果数，其余用 nextToken 继续获取 [默认: 1000]
+    #[arg(long)]
+    search_max_results: Option<usize>,
+    /// 内容搜索（/api/search/content）读取的单个文件大小上限，更大的文件跳过（如 50MB）[默认: 10MB]
+    #[arg(long, value_parser = handlers::parse_size)]
+    content_search_max_bytes: Option<u64>,
+    /// 在数据目录中维护文件名索引，按名称搜索时查询索引而不遍历目录
+    #[arg(long)]
+    index: bool,
+    /// 空间占用报告遍历的最多条目数，超出返回部分结果 [默认: 1000000]
+    #[arg(long)]
+    report_max_entries: Option<u64>,
+    /// 同步清单中计算哈希的单个文件大小上限，更大的文件不带哈希（如 500MB）[默认: 1GB]
+    #[arg(long, value_parser = handlers::parse_size)]
+    manifest_hash_max_bytes: Option<u64>,
+    /// 写入后磁盘须保留的剩余空间，空间不够的上传、复制提前拒绝（如 500MB，0 为不检查）[默认: 1GB]
+    #[arg(long, value_parser = handlers::parse_size)]
+    min_free_space: Option<u64>,
+    /// 同时写入磁盘的上传请求数，超出的请求等待 5 秒后返回 429 [默认: 32]
+    #[arg(long)]
+    max_upload_streams: Option<usize>,
+    /// 未完成的分片上传会话数上限，超出时返回 429 [默认: 100]
+    #[arg(long)]
+    max_upload_sessions: Option<usize>,
+    /// 上传（普通上传的文件数据、分片上传会话）超过该秒数未收到数据即中止并清理 [默认: 120]
+    #[arg(long)]
+    upload_idle_secs: Option<u64>,
+    /// 剪贴板超过该秒数未被设置、读取或粘贴即清空 [默认: 3600]
+    #[arg(long)]
+    clipboard_idle_secs: Option<u64>,
+    /// 删除时直接永久删除，不移到回收站
+    #[arg(long)]
+    no_trash: bool,
+    /// 回收站中的项目保留的天数，到期后永久删除（0 为一直保留）[默认: 30]
+    #[arg(long)]
+    trash_retention_days: Option<u64>,
+    /// 回收站的容量上限，超出时从最早删除的项目开始永久删除（如 50GB）[默认: 不限制]
+    #[arg(long, value_parser = handlers::parse_size)]
+    trash_max_size: Option<u64>,
+    /// 上传的文件存入前运行的检查命令（如 "clamdscan --no-summary {file}"），可用 {file}、{name}、{user} 占位符
+    #[arg(long)]
+    upload_hook: Option<String>,
+    /// 检查命令失败时的处理：reject（删除文件并拒绝上传）或 warn（保留文件并给出警告）[默认: reject]
+    #[arg(long, value_enum)]
+    upload_hook_mode: Option<hooks::HookMode>,
+    /// 检查命令的最长运行时间（秒），超时视为失败 [默认: 60]
+    #[arg(long)]
+    upload_hook_timeout_secs: Option<u64>,
+    /// 同时运行的检查命令数量 [默认: 2]
+    #[arg(long)]
+    upload_hook_jobs: Option<usize>,
+    /// 每个客户端 IP 同时进行的 API 请求数上限，超出返回 429
+    #[arg(long)]
+    max_conns_per_ip: Option<usize>,
+    /// 每个客户端 IP 的 API 请求频率上限（如 100/10s，单位 s/m/h），超出返回 429
+    #[arg(long, value_parser = ratelimit::parse_rate)]
+    rate_limit: Option<ratelimit::RateLimit>,
+    /// 每个下载的最大速率，每秒字节数（如 10MB），请求的 rate 参数不能超过它 [默认: 不限制]
+    #[arg(long, value_parser = handlers::parse_size)]
+    max_download_rate: Option<u64>,
+    /// 可信的反向代理地址或网段（逗号分隔或重复，如 10.0.0.0/8,127.0.0.1），仅信任它们发来的 X-Forwarded-For / X-Real-IP
+    #[arg(long, value_delimiter = ',', value_parser = proxy::parse_range)]
+    trusted_proxies: Vec<proxy::IpRange>,
+}
+impl Args {
+    /// 用命令行中显式给出的参数覆盖配置
+    fn apply_to(self, config: &mut config::Config) {
+        // A password given on the command line replaces a hash from the config file
+        if self.password.is_some() {
+            config.password_hash = None;
+        }
+        if let Some(root) = self.root {
+            config.root = root;
+            config.mounts.clear();
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(base_path) = self.base_path {
+            config.base_path = base_path;
+        }
+        if let Some(user) = self.user {
+            config.user = user;
+        }
+        if let Some(password) = self.password {
+            config.password = password;
+        }
+        if let Some(secs) = self.token_ttl_secs {
+            config.token_ttl_secs = secs;
+        }
+        if let Some(max) = self.auth_max_failures {
+            config.auth_max_failures = max;
+        }
+        if let Some(secs) = self.auth_lockout_secs {
+            config.auth_lockout_secs = secs;
+        }
+        if let Some(secs) = self.session_idle_secs {
+            config.session_idle_secs = secs;
+        }
+        if let Some(secs) = self.session_max_secs {
+            config.session_max_secs = secs;
+        }
+        if let Some(secs) = self.shutdown_grace_secs {
+            config.shutdown_grace_secs = secs;
+        }
+        if let Some(format) = self.log_format {
+            config.log_format = format;
+        }
+        if let Some(lang) = self.default_lang {
+            config.default_lang = lang;
+        }
+        if let Some(dir) = self.data_dir {
+            config.data_dir = dir;
+        }
+        if let Some(max) = self.max_request_body {
+            config.max_request_body = max;
+        }
+        if let Some(max) = self.max_filename_bytes {
+            config.max_filename_bytes = max;
+        }
+        if let Some(secs) = self.dir_size_timeout_secs {
+            config.dir_size_timeout_secs = secs;
+        }
+        if let Some(max) = self.dir_size_max_entries {
+            config.dir_size_max_entries = max;
+        }
+        if let Some(jobs) = self.dir_size_jobs {
+            config.dir_size_jobs = jobs;
+        }
+        if let Some(bytes) = self.tail_max_bytes {
+            config.tail_max_bytes = bytes;
+        }
+        if let Some(jobs) = self.search_jobs {
+            config.search_jobs = jobs;
+        }
+        if let Some(jobs) = self.image_jobs {
+            config.image_jobs = jobs;
+        }
+        if let Some(max) = self.report_max_entries {
+            config.report_max_entries = max;
+        }
+        if let Some(max) = self.manifest_hash_max_bytes {
+            config.manifest_hash_max_bytes = max;
+        }
+        if let Some(bytes) = self.min_free_space {
+            config.min_free_space = bytes;
+        }
+        if let Some(max) = self.max_upload_streams {
+            config.max_upload_streams = max;
+        }
+        if let Some(max) = self.max_upload_sessions {
+            config.max_upload_sessions = max;
+        }
+        if let Some(secs) = self.upload_idle_secs {
+            config.upload_idle_secs = secs;
+        }
+        if let Some(secs) = self.clipboard_idle_secs {
+            config.clipboard_idle_secs = secs;
+        }
+        if let Some(days) = self.trash_retention_days {
+            config.trash_retention_days = days;
+        }
+        if let Some(mode) = self.upload_hook_mode {
+            config.upload_hook_mode = mode;
+        }
+        if let Some(secs) = self.upload_hook_timeout_secs {
+            config.upload_hook_timeout_secs = secs;
+        }
+        if let Some(jobs) = self.upload_hook_jobs {
+            config.upload_hook_jobs = jobs;
+        }
+        config.password_hash = self.password_hash.or(config.password_hash.take());
+        config.users_file = self.users_file.or(config.users_file.take());
+        config.upload_hook = self.upload_hook.or(config.upload_hook.take());
+        config.static_dir = self.static_dir.or(config.static_dir.take());
+        config.tls_cert = self.tls_cert.or(config.tls_cert.take());
+        config.tls_key = self.tls_key.or(config.tls_key.take());
+        config.tls_redirect_port = self.tls_redirect_port.or(config.tls_redirect_port);
+        config.confirm_delete_over = self.confirm_delete_over.or(config.confirm_delete_over);
+        config.confirm_delete_entries = self.confirm_delete_entries.or(config.confirm_delete_entries);
+        config.search_timeout_secs = self.search_timeout_secs.or(config.search_timeout_secs);
+        if let Some(max) = self.search_max_results {
+            config.search_max_results = max;
+        }
+        if let Some(bytes) = self.content_search_max_bytes {
+            config.content_search_max_bytes = bytes;
+        }
+        if self.index {
+            config.index = true;
+        }
+        config.max_conns_per_ip = self.max_conns_per_ip.or(config.max_conns_per_ip);
+        config.rate_limit = self.rate_limit.or(config.rate_limit);
+        config.max_download_rate = self.max_download_rate.or(config.max_download_rate);
+        config.trash_max_size = self.trash_max_size.or(config.trash_max_size);
+        config.normalize_names = self.normalize_names.or(config.normalize_names);
+        config.no_follow_symlinks = self.no_follow_symlinks.or(config.no_follow_symlinks);
+        if !self.mounts.is_empty() {
+            config.mounts = self.mounts;
+        }
+        if !self.hide.is_empty() {
+            config.hide = self.hide;
+        }
+        if !self.protect.is_empty() {
+            config.protect = self.protect;
+        }
+        if !self.trusted_proxies.is_empty() {
+            config.trusted_proxies = self.trusted_proxies;
+        }
+        if self.no_health_write_probe {
+            config.health_write_probe = false;
+        }
+        if self.public_api_docs {
+            config.public_api_docs = true;
+        }
+        if self.no_webdav {
+            config.webdav = false;
+        }
+        if self.no_trash {
+            config.trash = false;
+        }
+        if self.raw_autoindex {
+            config.raw_autoindex = true;
+        }
+        if self.deny_dot_files {
+            config.deny_dot_files = true;
+        }
+        if !self.cors_origins.is_empty() {
+            config.cors_origins = self.cors_origins;
+            config.cors_allow_any = false;
+        }
+        if self.cors_allow_any {
+            config.cors_allow_any = true;
+            config.cors_origins.clear();
+        }
+    }
+}
+/// 子命令
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// 从标准输入读取密码并输出 argon2 哈希
+    HashPassword,
+    /// 输出合并命令行与配置文件后的生效配置（TOML）
+    PrintConfig,
+}
+/// 从标准输入读取密码并打印其哈希
+fn print_password_hash() {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .expect("Failed to read password from stdin");
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        eprintln!("Password must not be empty");
+        std::process::exit(1);
+    }
+    match auth::hash_password(password) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+/// 等待 Ctrl+C 或 SIGTERM，然后通知所有任务开始停止
+async fn shutdown_signal(shutdown: CancellationToken, grace: Duration) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down, waiting up to {}s for active requests", grace.as_secs());
+    shutdown.cancel();
+}
+/// 提供 HTTP 服务直到 `shutdown`，之后不再接受新连接，进行中的请求最多再等待 `grace`
+async fn serve_http(
+    listener: tokio::net::TcpListener,
+    app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    shutdown: CancellationToken,
+    grace: Duration,
+) {
+    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown.clone().cancelled_owned());
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = async {
+            shutdown.cancelled().await;
+            tokio::time::sleep(grace).await;
+        } => warn!("Grace period expired, closing remaining connections"),
+    }
 }
 /// 嵌入的前端 HTML
 const INDEX_HTML: &str = include_str!("../static/index.html");
 /// 提供前端页面
-async fn serve_index() -> Response<Body> {
+///
+/// 设置了 URL 前缀时注入 `window.FILEST_BASE_PATH`，页面据此拼接 API 地址。
+async fn serve_index(State(state): State<AppState>) -> Response<Body> {
+    let body = if state.base_path.is_empty() {
+        Body::from(INDEX_HTML)
+    } else {
+        let script = format!(
+            "<script>window.FILEST_BASE_PATH = {};</script>\n</head>",
+            serde_json::to_string(&state.base_path).unwrap_or_default()
+        );
+        Body::from(INDEX_HTML.replacen("</head>", &script, 1))
+    };
     Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-        .body(Body::from(INDEX_HTML))
+        .body(body)
         .unwrap()
 }
-#[tokio::main]
-async fn main() {
-    // 初始化日志
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "filest=info,tower_http=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-    // 解析命令行参数
-    let args = Args::parse();
-    // 确保根目录存在
-    let root_dir = args.root.canonicalize().unwrap_or_else(|_| {
-        std::fs::create_dir_all(&args.root).expect("Failed to create root directory");
-        args.root.canonicalize().expect("Failed to resolve root directory")
-    });
-    info!("文件根目录: {:?}", root_dir);
+/// 未知的 API 路径
+async fn api_not_found() -> (StatusCode, Json<models::ApiResponse<()>>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(models::ApiResponse::<()>::error_with_code("NOT_FOUND", i18n::Msg::ApiNotFound)),
+    )
+}
+/// 根据配置构建 CORS 层
+///
+/// 默认不返回任何 Access-Control-Allow-* 头，即仅允许同源访问；
+/// `--cors-origin` 列出的来源会得到精确匹配的放行（携带 `Vary: Origin`）。
+fn build_cors(config: &config::Config) -> CorsLayer {
+    let methods = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+        Method::OPTIONS,
+    ];
+    // Read by clients: request IDs for support, listing pages, and validators to revalidate with
+    let exposed = [
+        header::HeaderName::from_static(logging::REQUEST_ID_HEADER),
+        header::HeaderName::from_static(handlers::NEXT_TOKEN_HEADER),
+        header::ETAG,
+    ];
+    if config.cors_allow_any {
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(methods)
+            .allow_headers(Any)
+            .expose_headers(exposed);
+    }
+
+    let origins: Vec<HeaderValue> = config
+        .cors_origins
+        .iter()
+        .filter(|o| !o.eq_ignore_ascii_case("none"))
+        .map(|o| {
+            HeaderValue::from_str(o.trim_end_matches('/')).unwrap_or_else(|_| {
+                eprintln!("cors_origins: invalid origin {:?}", o);
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    if origins.is_empty() {
+        // Same-origin only: browsers block cross-origin requests without CORS headers
+        return CorsLayer::new();
+    }
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(methods)
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::HeaderName::from_static(apikeys::API_KEY_HEADER),
+            header::HeaderName::from_static(logging::REQUEST_ID_HEADER),
+            header::HeaderName::from_static(locks::LOCK_TOKEN_HEADER),
+            header::IF_NONE_MATCH,
+            header::IF_MATCH,
+            header::IF_MODIFIED_SINCE,
+        ])
+        .expose_headers(exposed)
+        .allow_credentials(true)
+}
+
+/// The application state for `config`: mounts, users and the stores kept in the data directory
+///
+/// Fails with a message naming the setting that is wrong. Background tasks are left for
+/// the caller to start.
+fn load_state(config: &config::Config, shutdown: CancellationToken) -> Result<AppState, String> {
+    // 确保根目录存在；命名挂载必须是已存在的目录
+    let mounts = if config.mounts.is_empty() {
+        let root_dir = match config.root.canonicalize() {
+            Ok(root_dir) => root_dir,
+            Err(_) => {
+                std::fs::create_dir_all(&config.root)
+                    .and_then(|()| config.root.canonicalize())
+                    .map_err(|e| format!("Failed to create root directory {:?}: {}", config.root, e))?
+            }
+        };
+        info!("文件根目录: {:?}", root_dir);
+        mounts::Mounts::single(root_dir).no_follow_symlinks(config.no_follow_symlinks)
+    } else {
+        let mut list = Vec::new();
+        for spec in &config.mounts {
+            // Syntax was checked by Config::validate
+            let (name, path) = mounts::Mounts::parse_spec(spec).map_err(|e| format!("mounts: {}", e))?;
+            let root = path
+                .canonicalize()
+                .map_err(|e| format!("mounts: failed to resolve {:?}: {}", path, e))?;
+            info!("挂载 /{}: {:?}", name, root);
+            list.push(mounts::Mount { name, root });
+        }
+        mounts::Mounts::new(list)
+            .map_err(|e| format!("mounts: {}", e))?
+            .no_follow_symlinks(config.no_follow_symlinks)
+    };
+    // 加载附加用户
+    let users = match &config.users_file {
+        Some(path) => users::load_users_file(path).map_err(|e| format!("users_file: {}", e))?,
+        None => Default::default(),
+    };
+    if users.contains_key(&config.user) {
+        return Err(format!("users_file: user {:?} duplicates user", config.user));
+    }
+    // 确保数据目录存在
+    let data_dir = std::fs::create_dir_all(&config.data_dir)
+        .and_then(|()| config.data_dir.canonicalize())
+        .map_err(|e| format!("Failed to create data directory {:?}: {}", config.data_dir, e))?;
+    let shares = share::ShareStore::load(&data_dir)?;
+    let api_keys = apikeys::ApiKeyStore::load(&data_dir)?;
+    let favorites = favorites::FavoriteStore::load(&data_dir)?;
+    let file_meta = meta::MetaStore::load(&data_dir)?;
+    let trash = trash::TrashStore::load(&data_dir, config.trash, config.trash_retention_days, config.trash_max_size)?;
+    let search_index = config.index.then(|| index::SearchIndex::open(&data_dir, &mounts)).transpose()?;
+    let activity = activity::ActivityHub::new();
+    let audit = audit::AuditLog::start(&data_dir, activity.clone());
+    // 编译隐藏/保护路径模式；位于根目录内的内部目录自动隐藏
+    let mut hide = config.hide.clone();
+    if let Some(mount) = mounts.find(&data_dir)
+        && mount.root != data_dir
+    {
+        hide.push(mounts.logical_path(&data_dir));
+    }
+    let hidden = patterns::PathPatterns::new(&hide).map_err(|e| format!("hide: {}", e))?;
+    let protected = patterns::PathPatterns::new(&config.protect).map_err(|e| format!("protect: {}", e))?;
     // 创建应用状态
-    let state = AppState {
-        root_dir,
-        username: args.user.clone(),
-        password: args.password.clone(),
+    let upload_limits = limits::UploadLimits::new(config.max_request_body, Duration::from_secs(config.upload_idle_secs));
+    let client_limits = Arc::new(ratelimit::ClientLimits::new(config.max_conns_per_ip, config.rate_limit));
+    // Everything /api/capabilities reports; an optional feature registers itself here
+    let capabilities = capabilities::Capabilities::new(&upload_limits, &client_limits)
+        .feature("webdav", config.webdav)
+        .feature("raw", serde_json::json!({ "autoindex": config.raw_autoindex }))
+        .feature("shares", true)
+        .feature("apiKeys", true)
+        .feature("locks", true)
+        .feature("clipboard", serde_json::json!({ "idleSecs": config.clipboard_idle_secs }))
+        .feature(
+            "trash",
+            if config.trash {
+                serde_json::json!({ "retentionDays": config.trash_retention_days, "maxSize": config.trash_max_size })
+            } else {
+                false.into()
+            },
+        )
+        .feature("undo", true)
+        .feature("archive", true)
+        .feature("archiveBrowse", true)
+        .feature("extract", serde_json::json!({ "formats": extract::FORMATS }))
+        .feature("meta", true)
+        .feature("events", true)
+        .feature("activity", true)
+        .feature("markdown", true)
+        .feature("mediaInfo", true)
+        .feature("stream", true)
+        .feature("image", serde_json::json!({ "maxSide": media::MAX_IMAGE_SIDE }))
+        .feature("contentPatch", true)
+        .feature("contentSave", true)
+        .feature("append", true)
+        .feature("content", serde_json::json!({ "maxBytes": content::MAX_TEXT_BYTES }))
+        .feature("manifest", serde_json::json!({ "hashMaxBytes": config.manifest_hash_max_bytes }))
+        .feature(
+            "search",
+            serde_json::json!({
+                "maxResults": config.search_max_results,
+                "timeoutSecs": config.search_timeout_secs,
+            }),
+        )
+        .feature(
+            "contentSearch",
+            serde_json::json!({
+                "maxBytes": config.content_search_max_bytes,
+                "maxResults": grep::MAX_RESULTS,
+            }),
+        )
+        .feature("searchIndex", config.index)
+        .feature("tail", serde_json::json!({ "maxBytes": config.tail_max_bytes }))
+        .feature("downloadRate", serde_json::json!({ "max": config.max_download_rate }))
+        .feature(
+            "uploadHook",
+            match &config.upload_hook {
+                Some(_) => serde_json::json!({ "mode": config.upload_hook_mode }),
+                None => false.into(),
+            },
+        )
+        .feature(
+            "deleteConfirmation",
+            match (config.confirm_delete_over, config.confirm_delete_entries) {
+                (None, None) => false.into(),
+                (bytes, entries) => serde_json::json!({ "overBytes": bytes, "overEntries": entries }),
+            },
+        )
+        .feature("apiDocs", serde_json::json!({ "public": config.public_api_docs, "swaggerUi": cfg!(feature = "swagger-ui") }));
+    // Background walks of folder sizes and reports share one limit
+    let walk_permits = Arc::new(tokio::sync::Semaphore::new(config.dir_size_jobs));
+    Ok(AppState {
+        base_path: config.base_prefix(),
+        mounts: Arc::new(mounts),
+        username: config.user.clone(),
+        password: config.password.clone(),
+        password_hash: config.password_hash.clone(),
         upload_sessions: new_upload_sessions(),
-    };
+        auth_tokens: new_auth_tokens(),
+        token_ttl_secs: config.token_ttl_secs,
+        auth_failures: new_auth_failures(),
+        auth_max_failures: config.auth_max_failures.max(1),
+        auth_lockout_secs: config.auth_lockout_secs,
+        sessions: new_sessions(),
+        session_idle_secs: config.session_idle_secs,
+        session_max_secs: config.session_max_secs,
+        tls_enabled: config.tls_cert.is_some() && config.tls_key.is_some(),
+        users,
+        data_dir,
+        shares: Arc::new(shares),
+        api_keys: Arc::new(api_keys),
+        favorites: Arc::new(favorites),
+        hashes: Arc::new(hashes::HashCache::default()),
+        content_locks: Arc::new(content::PathLocks::default()),
+        meta: Arc::new(file_meta),
+        locks: Arc::default(),
+        clipboards: Arc::new(clipboard::ClipboardStore::new(Duration::from_secs(config.clipboard_idle_secs))),
+        trash: Arc::new(trash),
+        undo: Arc::new(undo::UndoStore::default()),
+        audit: Arc::new(audit),
+        hidden: Arc::new(hidden),
+        protected: Arc::new(protected),
+        started_at: std::time::Instant::now(),
+        health_write_probe: config.health_write_probe,
+        upload_limits,
+        capabilities: Arc::new(capabilities),
+        upload_slots: limits::UploadSlots::new(config.max_upload_streams, config.max_upload_sessions),
+        upload_hook: config.upload_hook.clone().map(|command| {
+            Arc::new(hooks::UploadHook::new(
+                command,
+                config.upload_hook_mode,
+                Duration::from_secs(config.upload_hook_timeout_secs),
+                config.upload_hook_jobs,
+            ))
+        }),
+        name_rules: paths::NameRules {
+            max_bytes: config.max_filename_bytes,
+            deny_dot_files: config.deny_dot_files,
+            normalize: config.normalize_names,
+        },
+        watchers: events::Watchers::default(),
+        disks: Arc::default(),
+        dir_sizes: Arc::new(dirsize::DirSizes::new(
+            std::time::Duration::from_secs(config.dir_size_timeout_secs),
+            config.dir_size_max_entries,
+            walk_permits.clone(),
+        )),
+        reports: Arc::new(jobs::ReportJobs::new(walk_permits, shutdown.clone())),
+        report_max_entries: config.report_max_entries,
+        tail_max_bytes: config.tail_max_bytes,
+        max_download_rate: config.max_download_rate,
+        search_permits: Arc::new(tokio::sync::Semaphore::new(config.search_jobs)),
+        image_permits: Arc::new(tokio::sync::Semaphore::new(config.image_jobs)),
+        search_timeout: config.search_timeout_secs.map(Duration::from_secs),
+        search_max_results: config.search_max_results,
+        content_search_max_bytes: config.content_search_max_bytes,
+        index: search_index.map(Arc::new),
+        manifest_hash_max_bytes: config.manifest_hash_max_bytes,
+        min_free_space: config.min_free_space,
+        raw_autoindex: config.raw_autoindex,
+        activity,
+        shutdown: shutdown.clone(),
+        confirm_delete_bytes: config.confirm_delete_over,
+        confirm_delete_entries: config.confirm_delete_entries,
+        client_limits,
+    })
+}
+
+/// Every route of the server with its layers, as `main` serves it
+fn build_app(config: &config::Config, state: AppState) -> Router {
     // CORS 配置
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
-        .allow_headers(Any);
+    let cors = build_cors(config);
     // API routes (require authentication)
-    // Set upload limit to 10GB for large file uploads
+    // Request bodies are limited by --max-request-body; oversized requests get a JSON 413
     // With streaming upload, memory usage stays constant regardless of file size
-    // Chunked upload uses smaller chunks (5MB default) to bypass proxy limits
+    // Chunk requests get a smaller limit, as chunked upload exists to bypass proxy limits
+    let limits = state.upload_limits;
+    // OpenAPI document, plus Swagger UI when built with `--features swagger-ui`
+    let docs_routes = Router::new().route("/openapi.json", get(openapi::openapi_json));
+    #[cfg(feature = "swagger-ui")]
+    let docs_routes = docs_routes.merge(openapi::swagger_ui(&state.base_path));
+    let (public_docs, docs_routes) = if config.public_api_docs {
+        (docs_routes, Router::new())
+    } else {
+        (Router::new(), docs_routes)
+    };
     let api_routes = Router::new()
         .route("/files", get(handlers::get_files))
         .route("/folder", post(handlers::create_folder))
+        .route("/file", post(handlers::create_file))
         .route("/upload", post(handlers::upload_files))
         .route("/download", get(handlers::download_file))
+        .route("/download/batch", post(archive::download_batch))
+        .route("/archive", post(archive::create_archive))
+        .route("/archive/list", get(extract::list_archive))
+        .route("/archive/get", get(extract::get_archive_entry))
+        .route("/extract", post(extract::extract))
         .route("/rename", put(handlers::rename))
         .route("/move", put(handlers::move_file))
         .route("/copy", post(handlers::copy_file))
         .route("/delete", delete(handlers::delete_file))
+        .route("/delete/batch", delete(handlers::delete_batch))
+        .route("/trash", get(trash::list_trash))
+        .route("/trash/restore", post(trash::restore_trash))
+        .route("/trash/purge", delete(trash::purge_trash))
+        .route("/undo", get(undo::list_undo).post(undo::undo))
         .route("/info", get(handlers::get_info))
+        .route("/info/size", get(dirsize::size_job))
+        .route("/usage", get(usage::usage_report))
+        .route("/stats", get(stats::file_stats))
+        .route("/jobs", get(jobs::job_status))
+        .route("/jobs/{token}", get(jobs::job_by_token))
+        .route("/jobs/{token}/cancel", post(jobs::cancel_job))
+        .route("/jobs/{token}/events", get(jobs::job_events))
+        .route("/manifest", get(manifest::manifest))
         .route("/folders", get(handlers::get_folders))
         .route("/disk", get(handlers::get_disk_info))
         .route("/search", get(handlers::search_files))
+        .route("/search/content", get(grep::search_content))
+        .route("/meta", get(meta::get_meta).put(meta::set_meta))
+        .route("/render/markdown", get(markdown::render_markdown))
+        .route("/media-info", get(media::media_info))
+        .route("/image", get(media::image))
+        .route("/stream", get(media::stream))
+        .route("/tail", get(tail::tail))
+        .route("/append", post(content::append_content))
+        .route("/content", get(content::get_content).put(content::put_content).patch(content::patch_content))
+        .route("/lock", get(locks::get_locks).post(locks::acquire_lock).delete(locks::release_lock))
+        .route("/clipboard", get(clipboard::get_clipboard).post(clipboard::set_clipboard))
+        .route("/paste", post(clipboard::paste))
+        .route(
+            "/favorites",
+            get(favorites::list_favorites)
+                .post(favorites::add_favorite)
+                .delete(favorites::remove_favorite),
+        )
+        .route("/events", get(events::watch_events))
+        .route("/ws/events", get(activity::ws_events))
+        .route("/capabilities", get(capabilities::capabilities))
         // Chunked upload routes
         .route("/upload/init", post(handlers::chunked_upload_init))
-        .route("/upload/chunk", post(handlers::chunked_upload_chunk))
+        .route(
+            "/upload/chunk",
+            post(handlers::chunked_upload_chunk)
+                .layer(DefaultBodyLimit::max(limits.chunk_body() as usize))
+                .layer(middleware::from_fn_with_state(limits.chunk_body(), limits::reject_oversized)),
+        )
+        .route("/upload/check", post(hashes::check_uploads))
         .route("/upload/complete", post(handlers::chunked_upload_complete))
         .route("/upload/abort", post(handlers::chunked_upload_abort))
-        .layer(DefaultBodyLimit::max(10 * 1024 * 1024 * 1024)) // 10GB limit
+        .route("/upload/sessions", get(handlers::list_upload_sessions))
+        .route("/admin/uploads", get(handlers::list_upload_sessions))
+        .route("/admin/uploads/cancel", post(handlers::cancel_upload_session))
+        .route("/logout", post(handlers::logout))
+        .route("/session", delete(handlers::delete_session))
+        // Share links
+        .route("/share", get(share::list_shares).post(share::create_share))
+        .route("/share/{token}", delete(share::revoke_share))
+        // API keys (administrator only)
+        .route("/keys", get(apikeys::list_keys).post(apikeys::create_key))
+        .route("/keys/{id}", delete(apikeys::revoke_key))
+        // Audit log (administrator only)
+        .route("/audit", get(audit::query_audit))
+        .merge(docs_routes)
+        .layer(DefaultBodyLimit::max(limits.max_request_body as usize))
+        .layer(middleware::from_fn_with_state(limits.max_request_body, limits::reject_oversized))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth::auth_middleware,
         ));
+    // Login is public: it exchanges credentials for a bearer token or session cookie
+    // The OpenAPI document and Swagger UI join it with --public-api-docs
+    let public_api_routes = Router::new()
+        .route("/login", post(handlers::login))
+        .route("/session", post(handlers::create_session))
+        .merge(public_docs);
+    // Per-IP limits come before authentication, so floods are turned away cheaply;
+    // health checks are left out for load balancers and uptime monitors
+    let api_routes = public_api_routes
+        .merge(api_routes)
+        .layer(middleware::from_fn_with_state(state.client_limits.clone(), ratelimit::limit_clients))
+        .route("/health", get(health::health));
+    // WebDAV shares the API's authentication and request body limit
+    let dav_routes = if config.webdav {
+        Router::new()
+            .route("/dav", any(dav::dav))
+            .route("/dav/", any(dav::dav))
+            .route("/dav/{*path}", any(dav::dav))
+            .layer(middleware::from_fn_with_state(limits.max_request_body, limits::reject_oversized))
+            .layer(middleware::from_fn_with_state(state.clone(), auth::auth_middleware))
+    } else {
+        Router::new()
+    };
+    // Files by URL path, authenticated like the API
+    let raw_routes = Router::new()
+        .route("/raw", get(raw::raw))
+        .route("/raw/", get(raw::raw))
+        .route("/raw/{*path}", get(raw::raw))
+        .layer(middleware::from_fn_with_state(state.clone(), auth::auth_middleware));
     // Main routes - static resources don't require authentication
     let app = Router::new()
-        .route("/", get(serve_index))
-        .nest("/api", api_routes)
-        .layer(cors)
-        .with_state(state);
+        .merge(raw_routes)
+        // Public share links (no authentication)
+        .route("/s/{token}", get(share::access_share).post(share::unlock_share))
+        .route(
+            "/s/{token}/upload",
+            post(share::drop_upload)
+                .layer(DefaultBodyLimit::max(limits.max_request_body as usize))
+                .layer(middleware::from_fn_with_state(limits.max_request_body, limits::reject_oversized)),
+        )
+        // Unknown API paths get a JSON 404 rather than falling through to the frontend
+        .nest("/api", api_routes.fallback(api_not_found));
+    // Frontend: a custom static directory, or the embedded page
+    let app = match &config.static_dir {
+        Some(dir) => app.merge(assets::static_router(dir)),
+        None => app.route("/", get(serve_index)),
+    };
+    // CorsLayer answers every OPTIONS request itself, so WebDAV is added outside it
+    let app = app.layer(cors).merge(dav_routes);
+    // 设置了 URL 前缀时整个应用挂在前缀下，未带前缀的路径返回 404；
+    // nest 只匹配 "/filest" 本身，反向代理常用的 "/filest/" 同样返回前端页面
+    let base_path = config.base_prefix();
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        let index = match &config.static_dir {
+            Some(dir) => assets::index_route(dir),
+            None => get(serve_index),
+        };
+        Router::new()
+            .nest(&base_path, app)
+            .route(&format!("{}/", base_path), index)
+    };
+    app
+        .layer(middleware::from_fn_with_state(config.default_lang, i18n::select_lang))
+        .layer(middleware::from_fn_with_state(config.log_format, logging::request_id))
+        // Outermost, so every layer and handler sees the client behind a trusted proxy
+        .layer(middleware::from_fn_with_state(
+            proxy::TrustedProxies::new(&config.trusted_proxies),
+            proxy::resolve_client,
+        ))
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() {
+    // 解析命令行参数
+    let args = Args::parse();
+    if let Some(Command::HashPassword) = args.command {
+        print_password_hash();
+        return;
+    }
+    // 合并配置：命令行 > 配置文件 > 默认值
+    let mut config = match &args.config {
+        Some(path) => config::Config::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => config::Config::default(),
+    };
+    let print_config = matches!(args.command, Some(Command::PrintConfig));
+    args.apply_to(&mut config);
+    if let Err(e) = config.validate() {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    }
+    if print_config {
+        match config.to_toml() {
+            Ok(toml) => print!("{}", toml),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // 初始化日志（格式来自配置，因此在解析配置之后）
+    logging::init(config.log_format);
+    for key in config.unknown.keys() {
+        warn!("Unknown config key ignored: {}", key);
+    }
+    // 停止信号，由 shutdown_signal 触发
+    let shutdown = CancellationToken::new();
+    let state = load_state(&config, shutdown.clone()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let root_display = if state.mounts.is_multi() {
+        state.mounts.list().iter().map(|m| format!("/{}", m.name)).collect::<Vec<_>>().join(" ")
+    } else {
+        config.root.display().to_string()
+    };
+    // 停止时需要清理的状态
+    let upload_sessions = state.upload_sessions.clone();
+    // 定期清理已在 filest 之外删除的文件的标签与备注
+    meta::start_pruning(state.clone(), shutdown.clone());
+    handlers::start_session_reaper(state.clone(), shutdown.clone());
+    ratelimit::start_pruning(state.client_limits.clone(), shutdown.clone());
+    locks::start_reaper(state.locks.clone(), shutdown.clone());
+    clipboard::start_reaper(state.clipboards.clone(), shutdown.clone());
+    trash::start_sweeping(state.clone(), shutdown.clone());
+    index::start_indexing(state.clone(), shutdown.clone());
+    let audit_log = state.audit.clone();
+    let app = build_app(&config, state);
     // 启动服务器
-    let addr: SocketAddr = format!("{}:{}", args.bind, args.port)
+    let addr: SocketAddr = format!("{}:{}", config.bind, config.port)
         .parse()
         .expect("Invalid address");
+    // 加载 TLS 证书（解析失败或证书与私钥不匹配时立即退出）
+    let tls_config = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => match tls::load_rustls_config(cert, key).await {
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
     println!(
         r#"
 ╔════════════════════════════════════════════════════════════════╗
 ║           Filest - 远程文件管理器 v1.0                          ║
 ╠════════════════════════════════════════════════════════════════╣
-║  访问地址:  http://{}:{:<36}║
+║  访问地址:  {}://{}:{:<35}║
 ║  文件目录:  {:<50}║
 ║  用户名:    {:<50}║
-║  密码:      {:<50}║
+║  认证方式:  {:<46}║
 ╠════════════════════════════════════════════════════════════════╣
 ║  使用 Ctrl+C 停止服务器                                         ║
 ╚════════════════════════════════════════════════════════════════╝
 "#,
-        if args.bind == "0.0.0.0" { "localhost" } else { &args.bind },
-        args.port,
-        args.root.display(),
-        args.user,
-        args.password
+        scheme,
+        if config.bind == "0.0.0.0" { "localhost" } else { &config.bind },
+        config.port,
+        root_display,
+        config.user,
+        if config.password_hash.is_some() { "密码哈希" } else { "明文密码" }
     );
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
\ No newline at end of file
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    // 收到停止信号后不再接受新连接，进行中的请求最多等待 grace 秒
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    tokio::spawn(shutdown_signal(shutdown.clone(), grace));
+    match tls_config {
+        Some(rustls_config) => {
+            if let Some(http_port) = config.tls_redirect_port {
+                tokio::spawn(tls::redirect_http_to_https(
+                    config.bind.clone(),
+                    http_port,
+                    config.port,
+                    shutdown.clone(),
+                ));
+            }
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    shutdown.cancelled().await;
+                    handle.graceful_shutdown(Some(grace));
+                }
+            });
+            axum_server::bind_rustls(addr, rustls_config)
+                .handle(handle)
+                .serve(app)
+                .await
+                .unwrap();
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            serve_http(listener, app, shutdown.clone(), grace).await;
+        }
+    }
+    // 清理未完成的分块上传并写完审计日志；被中断的上传文件由 TempPath 在任务释放时删除
+    handlers::cleanup_upload_sessions(&upload_sessions).await;
+    audit_log.flush().await;
+    info!("Server stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    const ORIGIN: &str = "https://app.example.com";
+
+    /// The headers of a preflight for `method` with `headers` against `build_cors(config)`
+    async fn preflight(config: &config::Config, method: &str, headers: &str) -> axum::http::HeaderMap {
+        let app = Router::new().route("/api/x", get(|| async { "" })).layer(build_cors(config));
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/x")
+            .header(header::ORIGIN, ORIGIN)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    fn listed(headers: &axum::http::HeaderMap, name: header::HeaderName) -> Vec<String> {
+        let value = headers.get(name).map_or("", |v| v.to_str().unwrap());
+        value.split(',').map(|v| v.trim().to_ascii_lowercase()).filter(|v| !v.is_empty()).collect()
+    }
+
+    #[tokio::test]
+    async fn cors_allows_every_method_the_api_uses() {
+        let origins = config::Config { cors_origins: vec![ORIGIN.to_string()], ..Default::default() };
+        let any = config::Config { cors_allow_any: true, ..Default::default() };
+        for config in [&origins, &any] {
+            let methods = listed(&preflight(config, "PATCH", "content-type").await, header::ACCESS_CONTROL_ALLOW_METHODS);
+            for method in ["get", "post", "put", "patch", "delete", "options"] {
+                assert!(methods.iter().any(|m| m == method), "{method} in {methods:?}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cors_allows_the_request_headers_the_api_reads() {
+        let config = config::Config { cors_origins: vec![ORIGIN.to_string()], ..Default::default() };
+        let wanted = [
+            "authorization",
+            "content-type",
+            apikeys::API_KEY_HEADER,
+            locks::LOCK_TOKEN_HEADER,
+            "if-none-match",
+            "if-match",
+            "if-modified-since",
+        ];
+        let allowed = listed(&preflight(&config, "PUT", &wanted.join(",")).await, header::ACCESS_CONTROL_ALLOW_HEADERS);
+        for name in wanted {
+            assert!(allowed.iter().any(|a| a == name), "{name} in {allowed:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cors_exposes_validators() {
+        let origins = config::Config { cors_origins: vec![ORIGIN.to_string()], ..Default::default() };
+        let any = config::Config { cors_allow_any: true, ..Default::default() };
+        for config in [&origins, &any] {
+            let app = Router::new().route("/api/x", get(|| async { "" })).layer(build_cors(config));
+            let request = Request::builder().uri("/api/x").header(header::ORIGIN, ORIGIN).body(Body::empty()).unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let exposed = listed(response.headers(), header::ACCESS_CONTROL_EXPOSE_HEADERS);
+            for name in ["etag", logging::REQUEST_ID_HEADER, handlers::NEXT_TOKEN_HEADER] {
+                assert!(exposed.iter().any(|e| e == name), "{name} in {exposed:?}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cors_echoes_only_listed_origins_on_the_app() {
+        let app = crate::testing::TestApp::with(|c| c.cors_origins = vec![ORIGIN.to_string()]);
+        let allow_origin = async |origin: &str| {
+            let request = crate::testing::admin(Method::GET, "/api/health")
+                .header(header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.send(request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).map(|v| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(allow_origin(ORIGIN).await.as_deref(), Some(ORIGIN));
+        assert_eq!(allow_origin("https://evil.example.com").await, None);
+    }
+
+    /// Serve a route that answers once `release` is notified, returning the address and server task
+    async fn slow_server(
+        started: Arc<tokio::sync::Notify>,
+        release: Arc<tokio::sync::Notify>,
+        shutdown: CancellationToken,
+        grace: Duration,
+    ) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let app = Router::new().route(
+            "/slow",
+            get(async move || {
+                started.notify_one();
+                release.notified().await;
+                "done"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        (addr, tokio::spawn(serve_http(listener, app, shutdown, grace)))
+    }
+
+    async fn send_slow(addr: SocketAddr) -> tokio::net::TcpStream {
+        use tokio::io::AsyncWriteExt;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await.unwrap();
+        stream
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_active_requests_finish() {
+        use tokio::io::AsyncReadExt;
+        let (started, release) = (Arc::new(tokio::sync::Notify::new()), Arc::new(tokio::sync::Notify::new()));
+        let shutdown = CancellationToken::new();
+        let (addr, server) = slow_server(started.clone(), release.clone(), shutdown.clone(), Duration::from_secs(30)).await;
+
+        let mut stream = send_slow(addr).await;
+        started.notified().await;
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!server.is_finished(), "stopped with a request in flight");
+
+        release.notify_one();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("done"), "{response}");
+        tokio::time::timeout(Duration::from_secs(5), server).await.expect("server still running").unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_waiting_after_the_grace_period() {
+        let (started, release) = (Arc::new(tokio::sync::Notify::new()), Arc::new(tokio::sync::Notify::new()));
+        let shutdown = CancellationToken::new();
+        let (addr, server) = slow_server(started.clone(), release, shutdown.clone(), Duration::from_millis(100)).await;
+
+        let _stream = send_slow(addr).await;
+        started.notified().await;
+        shutdown.cancel();
+        // The request never finishes, so only the grace period ends the server
+        tokio::time::timeout(Duration::from_secs(5), server).await.expect("server still running").unwrap();
+    }
+
+    #[tokio::test]
+    async fn cors_is_off_without_origins() {
+        let headers = preflight(&config::Config::default(), "GET", "content-type").await;
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+}
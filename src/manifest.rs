@@ -0,0 +1,145 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{stream, StreamExt};
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::sync::mpsc;
+use crate::error::ApiError;
+use crate::hashes::file_sha256;
+use crate::i18n::Msg;
+use crate::jobs::report_roots;
+use crate::models::{ManifestEntry, ManifestHash, ManifestQuery};
+use crate::paths::is_internal_artifact;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Files one manifest hashes at a time
+const HASH_CONCURRENCY: usize = 4;
+/// Files found ahead of the hashing; the walk waits while the queue is full
+const QUEUE_LEN: usize = 256;
+
+/// A file found by the walk, not yet hashed
+struct Found {
+    path: String,
+    actual: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+    hash: bool,
+}
+
+/// The walk behind one manifest, sending the files it finds to the response stream
+struct Walk {
+    state: AppState,
+    user: AuthUser,
+    hash: ManifestHash,
+    since: Option<SystemTime>,
+    found: mpsc::Sender<Found>,
+}
+
+impl Walk {
+    /// Send the files below a folder, skipping what the user can't see; `false` once the
+    /// response is gone
+    async fn folder(&self, logical: &Path, actual: &Path) -> bool {
+        let Ok(mut entries) = fs::read_dir(actual).await else { return true };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let entry_logical = logical.join(entry.file_name());
+            let path = self.state.mounts.logical_path(&entry_logical);
+            if self.state.hidden.matches(&path)
+                || is_internal_artifact(&path, &entry.path())
+                || self.state.mounts.refuses_link(&entry.path())
+            {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(entry.path()).await else { continue };
+            if metadata.is_dir() {
+                // A folder's mtime only follows its own entries, so `since` can't prune it
+                if self.user.can_see(&path) && !Box::pin(self.folder(&entry_logical, &entry.path())).await {
+                    return false;
+                }
+                continue;
+            }
+            let modified = metadata.modified().ok();
+            if self.user.check(&path).is_err() || self.since.is_some_and(|since| modified.is_some_and(|m| m < since)) {
+                continue;
+            }
+            // Hashes of protected files would reveal what can't be downloaded
+            let hash = self.hash == ManifestHash::Sha256
+                && metadata.len() <= self.state.manifest_hash_max_bytes
+                && !self.state.protected.matches(&path);
+            let found = Found { path, actual: entry.path(), size: metadata.len(), modified, hash };
+            if self.found.send(found).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The NDJSON line of a file
+async fn line(found: Found) -> Result<String, Infallible> {
+    let hash = if found.hash { file_sha256(&found.actual).await.ok() } else { None };
+    let entry = ManifestEntry {
+        path: found.path,
+        size: found.size,
+        modified: found.modified.map(|m| DateTime::<Utc>::from(m).to_rfc3339_opts(SecondsFormat::Millis, true)),
+        hash,
+    };
+    let mut line = serde_json::to_string(&entry).unwrap_or_default();
+    line.push('\n');
+    Ok(line)
+}
+
+/// Every file below a path with its size, modification time and content hash, for sync tools
+///
+/// Streamed as NDJSON, one `ManifestEntry` per line, while the tree is walked; hidden
+/// paths and files the user can't access are left out. The walk stops when the client
+/// disconnects.
+#[utoipa::path(
+    get, path = "/api/manifest", tag = "files", params(ManifestQuery),
+    responses((
+        status = 200,
+        description = "`application/x-ndjson`, one JSON object per file",
+        content_type = "application/x-ndjson",
+        body = Vec<ManifestEntry>,
+    )),
+)]
+pub async fn manifest(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ManifestQuery>,
+) -> Result<Response, ApiError> {
+    let user_path = query.path.unwrap_or_else(|| "/".to_string());
+    let since = query
+        .since
+        .as_deref()
+        .map(|since| {
+            DateTime::parse_from_rfc3339(since)
+                .map(SystemTime::from)
+                .map_err(|_| ApiError::BadRequest(Msg::InvalidTime.with(&[&since])))
+        })
+        .transpose()?;
+    let (_, roots) = report_roots(&state, &user, &user_path)?;
+
+    let (found, queue) = mpsc::channel(QUEUE_LEN);
+    let walk = Walk { state, user, hash: query.hash.unwrap_or_default(), since, found };
+    // Dropping the response body drops the queue, which ends the walk at its next send
+    tokio::spawn(async move {
+        for (logical, actual) in &roots {
+            if !walk.folder(logical, actual).await {
+                break;
+            }
+        }
+    });
+
+    let lines = stream::unfold(queue, |mut queue| async move { queue.recv().await.map(|found| (found, queue)) })
+        .map(line)
+        .buffered(HASH_CONCURRENCY);
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(lines)).into_response())
+}
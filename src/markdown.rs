@@ -0,0 +1,154 @@
+use axum::{
+    extract::{Extension, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use std::borrow::Cow;
+use tokio::fs;
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, format_size, safe_path};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::MarkdownQuery;
+use crate::paths::url_path;
+use crate::raw::RAW_PREFIX;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Largest file rendered
+const MAX_MARKDOWN_BYTES: u64 = 1024 * 1024;
+/// Extensions rendered as Markdown
+const MARKDOWN_EXTENSIONS: [&str; 4] = ["md", "markdown", "mdown", "mkd"];
+/// Only images from this server (and inline data) may load; nothing else runs or connects
+const MARKDOWN_CSP: &str =
+    "default-src 'none'; img-src 'self' data:; style-src 'unsafe-inline'; base-uri 'none'; form-action 'none'";
+
+/// Whether a link target starts with a URL scheme such as `https:` or `javascript:`
+fn has_scheme(dest: &str) -> bool {
+    let scheme = dest.split(':').next().unwrap_or_default();
+    dest.contains(':')
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// A relative link target as a `/raw` URL, resolved against the folder of the rendered file
+///
+/// Absolute URLs, root-relative paths and fragments are left alone; `..` stops at the root.
+fn rewrite(prefix: &str, dir: &str, dest: CowStr<'_>) -> CowStr<'static> {
+    if dest.is_empty() || dest.starts_with(['/', '#', '?']) || has_scheme(&dest) {
+        return dest.into_static();
+    }
+    let (target, suffix) = match dest.find(['?', '#']) {
+        Some(i) => dest.split_at(i),
+        None => (&*dest, ""),
+    };
+    let mut segments: Vec<Cow<str>> = dir.split('/').filter(|s| !s.is_empty()).map(Cow::Borrowed).collect();
+    for segment in target.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            name => segments.push(percent_decode_str(name).decode_utf8_lossy()),
+        }
+    }
+    let logical = segments.join("/");
+    format!("{}{}", url_path(prefix, &logical, target.ends_with('/')), suffix).into()
+}
+
+/// HTML of a Markdown document with relative links and images pointing at `/raw`
+fn render(state: &AppState, dir: &str, source: &str) -> String {
+    let prefix = format!("{}{}", state.base_path, RAW_PREFIX);
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(source, options).map(|event| match event {
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: rewrite(&prefix, dir, dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: rewrite(&prefix, dir, dest_url),
+            title,
+            id,
+        }),
+        // As text, so the sanitizer needn't let any `<input>` through
+        Event::TaskListMarker(checked) => Event::Text(if checked { "☑ " } else { "☐ " }.into()),
+        event => event,
+    });
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events);
+    sanitize(&unsafe_html)
+}
+
+/// Strip scripts, event handlers, `javascript:` URLs and the like from rendered HTML
+///
+/// Raw HTML in the document passes through the renderer, so this is what keeps it from
+/// running in the file manager's origin.
+fn sanitize(html: &str) -> String {
+    ammonia::clean(html)
+}
+
+/// A Markdown file rendered to sanitized HTML, for README previews
+///
+/// Relative links and images are rewritten to `/raw` URLs, so pictures next to the file
+/// display with the viewer's own credentials. The response carries a CSP that blocks
+/// scripts, should any get past the sanitizer.
+#[utoipa::path(
+    get, path = "/api/render/markdown", tag = "files", params(MarkdownQuery),
+    responses(
+        (status = 200, description = "Sanitized HTML fragment", content_type = "text/html", body = String),
+        (status = 400, description = "Not a Markdown file", body = crate::openapi::ErrorBody),
+        (status = 413, description = "File over the 1MB rendering limit", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn render_markdown(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<MarkdownQuery>,
+) -> Result<Response, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+
+    let is_markdown = paths
+        .actual
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| MARKDOWN_EXTENSIONS.iter().any(|m| e.eq_ignore_ascii_case(m)));
+    if !is_markdown {
+        return Err(ApiError::BadRequest(Msg::NotMarkdown.into()));
+    }
+    let metadata = fs::metadata(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::FileNotFound, e))?;
+    if !metadata.is_file() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    if metadata.len() > MAX_MARKDOWN_BYTES {
+        return Err(ApiError::PayloadTooLarge(
+            Msg::MarkdownTooLarge.with(&[&format_size(MAX_MARKDOWN_BYTES)]),
+        ));
+    }
+    let bytes = fs::read(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+
+    let dir = logical.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let html = render(&state, dir, &String::from_utf8_lossy(&bytes));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CONTENT_SECURITY_POLICY, MARKDOWN_CSP),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        html,
+    )
+        .into_response())
+}
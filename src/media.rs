@@ -0,0 +1,362 @@
+use axum::{
+    extract::{Extension, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use exif::{DateTime, Exif, In, Reader, Tag, Value};
+use image::codecs::jpeg::JpegEncoder;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::io::Cursor;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use crate::error::ApiError;
+use crate::handlers::{
+    check_not_internal, check_patterns, content_disposition, etag, file_response, not_modified, safe_path, HTTP_DATE,
+};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::{ApiResponse, ImageQuery, MediaFormat, MediaInfo, MediaInfoQuery, PathQuery};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Bytes read from the start of a file; EXIF and the size fields sit near the front
+const MEDIA_HEADER_BYTES: u64 = 512 * 1024;
+
+/// Longest width or height `/api/image` accepts
+pub(crate) const MAX_IMAGE_SIDE: u32 = 8192;
+/// JPEG quality when the request names none
+const DEFAULT_QUALITY: u8 = 80;
+
+/// HEIF brands of still images
+const HEIF_BRANDS: [&[u8]; 6] = [b"heic", b"heix", b"heim", b"heis", b"mif1", b"msf1"];
+
+/// The image format of a file, from its first bytes rather than its name
+fn sniff(head: &[u8]) -> Option<MediaFormat> {
+    if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(MediaFormat::Jpeg)
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(MediaFormat::Png)
+    } else if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+        Some(MediaFormat::Tiff)
+    } else if head.get(4..8) == Some(b"ftyp") && head.get(8..12).is_some_and(|b| HEIF_BRANDS.contains(&b)) {
+        Some(MediaFormat::Heic)
+    } else {
+        None
+    }
+}
+
+/// An ASCII field of the primary image, without padding
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|v| String::from_utf8_lossy(v).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string())
+            .filter(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+/// When the picture was taken, as `2024-06-01T14:30:00`, with the offset when recorded
+fn taken_at(exif: &Exif) -> Option<String> {
+    let (time, offset) = [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTime, Tag::OffsetTime),
+    ]
+    .into_iter()
+    .find(|(time, _)| exif.get_field(*time, In::PRIMARY).is_some())?;
+    let mut dt = DateTime::from_ascii(ascii(exif, time)?.as_bytes()).ok()?;
+    if let Some(offset) = ascii(exif, offset) {
+        let _ = dt.parse_offset(offset.as_bytes());
+    }
+    let mut text = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+    );
+    if let Some(minutes) = dt.offset {
+        let sign = if minutes < 0 { '-' } else { '+' };
+        text.push_str(&format!("{}{:02}:{:02}", sign, minutes.abs() / 60, minutes.abs() % 60));
+    }
+    Some(text)
+}
+
+/// A GPS coordinate in signed decimal degrees; `negative` is the reference of the south or west half
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: &str) -> Option<f64> {
+    let Value::Rational(dms) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    if dms.len() < 3 || dms.iter().any(|r| r.denom == 0) {
+        return None;
+    }
+    let degrees = dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0;
+    Some(if ascii(exif, reference).as_deref() == Some(negative) { -degrees } else { degrees })
+}
+
+/// Dimensions and EXIF fields from the start of an image file
+///
+/// Fails only when neither the size nor the EXIF data can be read, so pictures without
+/// EXIF still report their size.
+fn parse(head: Vec<u8>, format: MediaFormat, include_gps: bool) -> Result<MediaInfo, String> {
+    let image_format = match format {
+        MediaFormat::Jpeg => Some(ImageFormat::Jpeg),
+        MediaFormat::Png => Some(ImageFormat::Png),
+        MediaFormat::Tiff => Some(ImageFormat::Tiff),
+        // Not decoded by `image`; the size comes from EXIF
+        MediaFormat::Heic => None,
+    };
+    let dimensions = image_format
+        .and_then(|f| ImageReader::with_format(Cursor::new(&head), f).into_dimensions().ok());
+    let exif = match Reader::new().read_from_container(&mut Cursor::new(&head)) {
+        Ok(exif) => Some(exif),
+        Err(exif::Error::NotFound(_)) => None,
+        Err(e) if dimensions.is_none() => return Err(e.to_string()),
+        Err(_) => None,
+    };
+    if dimensions.is_none() && exif.is_none() && format != MediaFormat::Heic {
+        return Err("no image header found".to_string());
+    }
+
+    let exif = exif.as_ref();
+    let field = |read: fn(&Exif) -> Option<String>| exif.and_then(read);
+    let number = |tag| exif.and_then(|exif| uint(exif, tag));
+    let gps = |tag, reference, negative| exif.filter(|_| include_gps).and_then(|exif| coordinate(exif, tag, reference, negative));
+    Ok(MediaInfo {
+        format,
+        width: dimensions.map(|(w, _)| w).or_else(|| number(Tag::PixelXDimension)).or_else(|| number(Tag::ImageWidth)),
+        height: dimensions.map(|(_, h)| h).or_else(|| number(Tag::PixelYDimension)).or_else(|| number(Tag::ImageLength)),
+        taken_at: field(taken_at),
+        make: field(|exif| ascii(exif, Tag::Make)),
+        model: field(|exif| ascii(exif, Tag::Model)),
+        orientation: number(Tag::Orientation),
+        has_gps: exif.is_some_and(|exif| exif.get_field(Tag::GPSLatitude, In::PRIMARY).is_some()),
+        latitude: gps(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+        longitude: gps(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+    })
+}
+
+/// Capture date, camera, orientation, size and GPS presence of a JPEG, PNG, TIFF or HEIC image
+///
+/// Only the first 512KB of the file are read. Coordinates are left out unless
+/// `include_gps=true`, since they reveal where a picture was taken.
+#[utoipa::path(
+    get, path = "/api/media-info", tag = "files", params(MediaInfoQuery),
+    responses(
+        (status = 200, description = "Image details; fields the file doesn't record are null", body = ApiResponse<MediaInfo>),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: not a JPEG, PNG, TIFF or HEIC image", body = crate::openapi::ErrorBody),
+        (status = 422, description = "`PARSE_ERROR`: the image headers are corrupt", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn media_info(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<MediaInfoQuery>,
+) -> Result<Json<ApiResponse<MediaInfo>>, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    if paths.actual.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+
+    let file = fs::File::open(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+    let mut head = Vec::new();
+    file.take(MEDIA_HEADER_BYTES)
+        .read_to_end(&mut head)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+    let format = sniff(&head).ok_or_else(|| ApiError::UnsupportedType(Msg::NotAnImage.into()))?;
+
+    // Decoders run off the async threads; a panic in one ends up as a parse error
+    let include_gps = query.include_gps;
+    let info = tokio::task::spawn_blocking(move || parse(head, format, include_gps))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|parsed| parsed)
+        .map_err(|e| ApiError::ParseError(format!("{}: {}", Msg::ImageParseFailed, e)))?;
+    Ok(Json(ApiResponse::success(info)))
+}
+
+/// The image at `path` decoded, turned upright, shrunk to fit `width` x `height` and encoded
+/// again: as PNG when it has transparency, else as JPEG at `quality`
+fn resize(path: &Path, width: Option<u32>, height: Option<u32>, quality: u8) -> Result<(Vec<u8>, ImageFormat), String> {
+    let mut decoder = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| e.to_string())?
+        .into_decoder()
+        .map_err(|e| e.to_string())?;
+    // The EXIF data doesn't survive encoding, so its rotation is applied to the pixels
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    image.apply_orientation(orientation);
+    let (width, height) = (width.unwrap_or(image.width()), height.unwrap_or(image.height()));
+    if image.width() > width || image.height() > height {
+        image = image.thumbnail(width.min(image.width()), height.min(image.height()));
+    }
+
+    let mut out = Vec::new();
+    let format = if image.color().has_alpha() {
+        image.write_to(&mut Cursor::new(&mut out), ImageFormat::Png).map_err(|e| e.to_string())?;
+        ImageFormat::Png
+    } else {
+        image
+            .into_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut out, quality))
+            .map_err(|e| e.to_string())?;
+        ImageFormat::Jpeg
+    };
+    Ok((out, format))
+}
+
+/// A JPEG, PNG or TIFF image scaled down and compressed again, for clients on slow links
+///
+/// `w` and `h` bound the size and the aspect ratio is kept; images are never enlarged, and
+/// without either the image is only compressed again. EXIF orientation is applied to the
+/// pixels since the metadata is dropped. Pictures with transparency come back as PNG,
+/// others as JPEG at quality `q`. At most `--image-jobs` images are decoded at once;
+/// other requests wait. The `ETag` follows the file and the parameters, so a client can
+/// revalidate and get 304.
+#[utoipa::path(
+    get, path = "/api/image", tag = "files", params(ImageQuery),
+    responses(
+        (status = 200, description = "The image as JPEG, or PNG when it has transparency", content_type = "image/jpeg"),
+        (status = 304, description = "Not modified"),
+        (status = 400, description = "`w` or `h` outside 1 to 8192, or `q` outside 1 to 100", body = crate::openapi::ErrorBody),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: not a JPEG, PNG or TIFF image", body = crate::openapi::ErrorBody),
+        (status = 422, description = "`PARSE_ERROR`: the image can't be decoded", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn image(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let quality = query.q.unwrap_or(DEFAULT_QUALITY);
+    let sides_valid = [query.w, query.h].into_iter().flatten().all(|side| (1..=MAX_IMAGE_SIDE).contains(&side));
+    if !sides_valid || !(1..=100).contains(&quality) {
+        return Err(ApiError::BadRequest(Msg::InvalidImageSize.with(&[&MAX_IMAGE_SIDE])));
+    }
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    let metadata = fs::metadata(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::FileNotFound, e))?;
+    if metadata.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+
+    let mut head = Vec::new();
+    fs::File::open(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?
+        .take(16)
+        .read_to_end(&mut head)
+        .await
+        .map_err(|e| ApiError::io(Msg::ReadFileFailed, e))?;
+    if !matches!(sniff(&head), Some(MediaFormat::Jpeg | MediaFormat::Png | MediaFormat::Tiff)) {
+        return Err(ApiError::UnsupportedType(Msg::NotAResizableImage.into()));
+    }
+
+    let modified = metadata.modified().ok().map(chrono::DateTime::<Utc>::from);
+    let etag = format!(
+        "{}-{}x{}q{}\"",
+        etag(metadata.len(), modified).trim_end_matches('"'),
+        query.w.unwrap_or(0),
+        query.h.unwrap_or(0),
+        quality
+    );
+    let last_modified = modified.map(|m| m.format(HTTP_DATE).to_string());
+    if not_modified(&headers, &etag, modified) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    // Decoding a large photo takes a lot of memory and CPU, so few run at once, off the
+    // async threads
+    let _permit = state.image_permits.acquire().await;
+    let (path, width, height) = (paths.actual.clone(), query.w, query.h);
+    let (bytes, format) = tokio::task::spawn_blocking(move || resize(&path, width, height, quality))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|resized| resized)
+        .map_err(|e| ApiError::ParseError(format!("{}: {}", Msg::ImageParseFailed, e)))?;
+
+    let stem = paths.actual.file_stem().map_or_else(|| "image".into(), |s| s.to_string_lossy());
+    let filename = format!("{}.{}", stem, format.extensions_str()[0]);
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, format.to_mime_type().to_string()),
+            (header::ETAG, etag),
+            (header::CONTENT_DISPOSITION, content_disposition("inline", &filename)),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        bytes,
+    )
+        .into_response();
+    if let Some(last_modified) = last_modified
+        && let Ok(value) = last_modified.parse()
+    {
+        response.headers_mut().insert(header::LAST_MODIFIED, value);
+    }
+    Ok(crate::throttle::limit(response, state.max_download_rate))
+}
+
+/// A video or audio file for a `<video>` or `<audio>` element, which seeks with range requests
+///
+/// Served inline with the type its extension implies and the same `Range` and conditional
+/// request support as `/api/download`. A request for several ranges at once gets the whole
+/// file, as HTTP allows; players ask for one. Other types are refused, so the endpoint
+/// can't be used to open pages in the browser.
+#[utoipa::path(
+    get, path = "/api/stream", tag = "files", params(PathQuery),
+    responses(
+        (status = 200, description = "The whole file", content_type = "video/mp4"),
+        (status = 206, description = "Requested byte range", content_type = "video/mp4"),
+        (status = 304, description = "Not modified"),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: not a video or audio file", body = crate::openapi::ErrorBody),
+        (status = 416, description = "Range not satisfiable"),
+    ),
+)]
+pub async fn stream(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<PathQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path.unwrap_or_default())?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    if paths.actual.is_dir() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let mime = mime_guess::from_path(&paths.actual).first_or_octet_stream();
+    if mime.type_() != mime_guess::mime::VIDEO && mime.type_() != mime_guess::mime::AUDIO {
+        return Err(ApiError::UnsupportedType(Msg::NotAMediaFile.into()));
+    }
+    let mut response = file_response(&paths.actual, &headers, "inline").await;
+    response
+        .headers_mut()
+        .insert(header::X_CONTENT_TYPE_OPTIONS, header::HeaderValue::from_static("nosniff"));
+    Ok(crate::throttle::limit(response, state.max_download_rate))
+}
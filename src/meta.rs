@@ -0,0 +1,289 @@
+use axum::{
+    extract::{Extension, Query, State},
+    Json,
+};
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, get_file_info, safe_path, SearchFilter};
+use crate::i18n::Msg;
+use crate::jsonfile::JsonFile;
+use crate::logging::record_path;
+use crate::models::*;
+use crate::paths::{search_key, NameMatcher};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Most tags on one file
+const MAX_TAGS: usize = 32;
+/// Longest tag, in characters
+const MAX_TAG_CHARS: usize = 64;
+/// Longest note, in characters
+const MAX_NOTE_CHARS: usize = 2000;
+/// How often metadata of files removed outside filest is dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Tags and notes of files, keyed by logical path and persisted as JSON in the data directory
+///
+/// Files themselves are never touched. The store lives in `--data-dir`, which listings and
+/// search already leave out, and isn't counted in usage reports. The lock is held across
+/// every load-modify-save cycle.
+pub struct MetaStore {
+    file: JsonFile,
+    entries: Mutex<HashMap<String, FileMeta>>,
+}
+
+/// Whether `path` is `base` or below it
+fn is_within(path: &str, base: &str) -> bool {
+    base == "/" || path == base || path.strip_prefix(base).is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl MetaStore {
+    /// Load the store from `<data_dir>/file_meta.json`, starting empty if it doesn't exist
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let file = JsonFile::new(data_dir, "file_meta.json", "file metadata store");
+        let entries = file.load()?;
+        Ok(Self {
+            file,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub async fn get(&self, path: &str) -> Option<FileMeta> {
+        self.entries.lock().await.get(path).cloned()
+    }
+
+    /// Store a file's metadata, or drop it when there are no tags and no note
+    async fn set(&self, path: &str, meta: Option<FileMeta>) -> Result<(), ApiError> {
+        let mut entries = self.entries.lock().await;
+        let previous = match meta {
+            Some(meta) => entries.insert(path.to_string(), meta),
+            None => entries.remove(path),
+        };
+        if let Err(e) = self.file.save(&*entries).await {
+            match previous {
+                Some(previous) => entries.insert(path.to_string(), previous),
+                None => entries.remove(path),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Follow a rename or move of `from` (and everything below it) to `to`
+    ///
+    /// Metadata of whatever `to` replaced is dropped first. Called after the file
+    /// operation succeeded, so a failed save is only logged.
+    pub async fn moved(&self, from: &str, to: &str) {
+        let mut entries = self.entries.lock().await;
+        let replaced = entries.keys().any(|path| is_within(path, to));
+        entries.retain(|path, _| !is_within(path, to));
+        let keys: Vec<String> = entries.keys().filter(|path| is_within(path, from)).cloned().collect();
+        if keys.is_empty() && !replaced {
+            return;
+        }
+        for key in keys {
+            if let Some(meta) = entries.remove(&key) {
+                entries.insert(format!("{}{}", to, &key[from.len()..]), meta);
+            }
+        }
+        if let Err(e) = self.file.save(&*entries).await {
+            warn!("{}", e);
+        }
+    }
+
+    /// Drop the metadata of a deleted file or folder and everything below it
+    pub async fn removed(&self, path: &str) {
+        let mut entries = self.entries.lock().await;
+        let before = entries.len();
+        entries.retain(|key, _| !is_within(key, path));
+        if entries.len() != before
+            && let Err(e) = self.file.save(&*entries).await
+        {
+            warn!("{}", e);
+        }
+    }
+
+    /// Add the stored tags to listed files
+    pub async fn fill_tags(&self, files: &mut [FileInfo]) {
+        let entries = self.entries.lock().await;
+        for file in files {
+            file.tags = Some(entries.get(&file.path).map(|m| m.tags.clone()).unwrap_or_default());
+        }
+    }
+
+    /// Paths at or below `base` carrying every tag in `tags`, whose names `name` matches,
+    /// in the order searches go through entries
+    pub async fn find(&self, base: &str, tags: &[String], name: &NameMatcher) -> Vec<String> {
+        let entries = self.entries.lock().await;
+        let mut paths: Vec<String> = entries
+            .iter()
+            .filter(|(path, meta)| {
+                is_within(path, base)
+                    && tags.iter().all(|tag| meta.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+                    && name.matches(&search_key(path.rsplit('/').next().unwrap_or_default()))
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        paths.sort_by(|a, b| Path::new(a).cmp(Path::new(b)));
+        paths
+    }
+
+    /// Drop metadata of files that no longer exist, such as ones deleted outside filest
+    async fn prune(&self, state: &AppState) {
+        let paths: Vec<String> = self.entries.lock().await.keys().cloned().collect();
+        let gone: HashSet<String> = paths
+            .into_iter()
+            .filter(|path| safe_path(&state.mounts, path).is_ok_and(|p| !p.actual.exists()))
+            .collect();
+        if gone.is_empty() {
+            return;
+        }
+        let mut entries = self.entries.lock().await;
+        // A file may have been recreated since it was checked
+        entries.retain(|path, _| {
+            !gone.contains(path) || safe_path(&state.mounts, path).is_ok_and(|p| p.actual.exists())
+        });
+        match self.file.save(&*entries).await {
+            Ok(()) => info!("Pruned metadata of {} missing file(s)", gone.len()),
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+/// Prune orphaned metadata every `PRUNE_INTERVAL` until shutdown
+pub fn start_pruning(state: AppState, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => state.meta.prune(&state).await,
+            }
+        }
+    });
+}
+
+/// The logical path of an existing file the user may annotate
+fn checked_path(state: &AppState, user: &AuthUser, user_path: &str) -> Result<String, ApiError> {
+    let paths = safe_path(&state.mounts, user_path)?;
+    if paths.is_root() {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    }
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(state, &logical, false)?;
+    check_not_internal(&logical, &paths.actual)?;
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    Ok(logical)
+}
+
+fn meta_response(path: String, meta: Option<FileMeta>) -> MetaResponse {
+    let meta = meta.unwrap_or_default();
+    MetaResponse {
+        path,
+        tags: meta.tags,
+        note: meta.note,
+        updated_at: meta.updated_at,
+    }
+}
+
+/// Tags and note of a file or folder; both empty if none were set
+#[utoipa::path(
+    get, path = "/api/meta", tag = "files", params(MetaQuery),
+    responses((status = 200, description = "The stored metadata", body = ApiResponse<MetaResponse>)),
+)]
+pub async fn get_meta(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<MetaQuery>,
+) -> Result<Json<ApiResponse<MetaResponse>>, ApiError> {
+    let path = checked_path(&state, &user, &query.path)?;
+    let meta = state.meta.get(&path).await;
+    Ok(Json(ApiResponse::success(meta_response(path, meta))))
+}
+
+/// Replace the tags and note of a file or folder
+///
+/// Tags are trimmed and kept once each, ignoring ASCII case. No tags and no note
+/// removes the metadata.
+#[utoipa::path(
+    put, path = "/api/meta", tag = "files", request_body = SetMetaRequest,
+    responses((status = 200, description = "The stored metadata", body = ApiResponse<MetaResponse>)),
+)]
+pub async fn set_meta(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<SetMetaRequest>,
+) -> Result<Json<ApiResponse<MetaResponse>>, ApiError> {
+    let path = checked_path(&state, &user, &req.path)?;
+    user.check(&path).map_err(ApiError::AccessDenied)?;
+
+    let mut tags: Vec<String> = Vec::new();
+    for tag in req.tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        if tag.chars().count() > MAX_TAG_CHARS || tag.contains(',') || tag.contains(char::is_control) {
+            return Err(ApiError::BadRequest(Msg::InvalidTag.with(&[&tag, &MAX_TAG_CHARS])));
+        }
+        if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+    if tags.len() > MAX_TAGS {
+        return Err(ApiError::BadRequest(Msg::TooManyTags.with(&[&MAX_TAGS])));
+    }
+    let note = req.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    if note.as_ref().is_some_and(|n| n.chars().count() > MAX_NOTE_CHARS) {
+        return Err(ApiError::BadRequest(Msg::NoteTooLong.with(&[&MAX_NOTE_CHARS])));
+    }
+
+    let meta = (!tags.is_empty() || note.is_some()).then(|| FileMeta {
+        tags,
+        note,
+        updated_at: Some(Utc::now().timestamp()),
+    });
+    state.meta.set(&path, meta.clone()).await?;
+    Ok(Json(ApiResponse::success(meta_response(path, meta))))
+}
+
+/// Files below a search path carrying all `tags`, in place of the name walk of `/api/search`
+///
+/// Gives up to `limit` of those after the path `after`, and the last one gone through.
+pub(crate) async fn search_tagged(
+    state: &AppState,
+    user: &AuthUser,
+    base: &str,
+    tags: &[String],
+    filter: &SearchFilter,
+    after: Option<&str>,
+    limit: usize,
+) -> (Vec<FileInfo>, Option<String>) {
+    let mut results = Vec::new();
+    let mut last = None;
+    for path in state.meta.find(base, tags, &filter.name).await {
+        if results.len() >= limit {
+            break;
+        }
+        if after.is_some_and(|after| Path::new(&path) <= Path::new(after)) {
+            continue;
+        }
+        if !user.can_see(&path) || state.hidden.matches(&path) {
+            continue;
+        }
+        let Ok(paths) = safe_path(&state.mounts, &path) else { continue };
+        if let Ok(info) = get_file_info(&state.mounts, &paths.logical, &paths.actual, None).await
+            && filter.accepts(&info)
+        {
+            results.push(info);
+        }
+        last = Some(path);
+    }
+    (results, last)
+}
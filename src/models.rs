@@ -1,14 +1,20 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use utoipa::{IntoParams, ToSchema};
+use crate::category::FileCategory;
 
 /// API 响应包装
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ApiResponse<T: Serialize> {
     pub success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Machine-readable error code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
     #[serde(flatten)]
     pub data: Option<T>,
 }
@@ -17,19 +23,30 @@ impl<T: Serialize> ApiResponse<T> {
         Self {
             success: true,
             error: None,
+            code: None,
             data: Some(data),
         }
     }
-    pub fn error(message: impl Into<String>) -> ApiResponse<()> {
+    pub fn error_with_code(code: &'static str, message: impl Into<String>) -> ApiResponse<()> {
         ApiResponse {
             success: false,
             error: Some(message.into()),
+            code: Some(code),
             data: None,
         }
     }
+    /// Error that also carries details for the client (e.g. what needs confirming)
+    pub fn error_with_data(code: &'static str, message: impl Into<String>, data: T) -> Self {
+        Self {
+            success: false,
+            error: Some(message.into()),
+            code: Some(code),
+            data: Some(data),
+        }
+    }
 }
 /// 文件信息
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, ToSchema)]
 pub struct FileInfo {
     pub name: String,
     pub path: String,
@@ -40,38 +57,209 @@ pub struct FileInfo {
     pub size_formatted: String,
     pub modified: String,
     pub created: String,
+    /// RFC 3339 UTC; null where the platform doesn't record the time
+    #[serde(rename = "modifiedAt")]
+    pub modified_at: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    /// Kind of file from its extension; absent for folders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<FileCategory>,
+    /// Total size of a folder's contents, when a recent `/api/info` computed it
+    #[serde(rename = "dirSize", skip_serializing_if = "Option::is_none")]
+    pub dir_size: Option<u64>,
+    /// `dirSize` is a lower bound from a walk that stopped early
+    #[serde(rename = "dirSizeIsEstimate", skip_serializing_if = "Option::is_none")]
+    pub dir_size_is_estimate: Option<bool>,
+    /// Tags from `/api/meta`, with `include_meta=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// `true` for a symlink; one refused by `--no-follow-symlinks` is described as the
+    /// link itself and can't be opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink: Option<bool>,
 }
 /// 文件列表响应
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct FilesResponse {
     pub path: String,
     pub files: Vec<FileInfo>,
 }
 /// 文件夹列表响应
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct FoldersResponse {
     pub folders: Vec<FolderItem>,
 }
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct FolderItem {
     pub path: String,
     pub display: String,
 }
 /// 磁盘信息响应
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct DiskResponse {
     pub total: u64,
     pub used: u64,
+    /// Free right now, as the filesystem reports it
     pub free: u64,
+    /// What writes may use: `free` less `minFreeSpace`
+    pub writable: u64,
+    /// Free space every write must leave (`--min-free-space`); 0 when not enforced
+    #[serde(rename = "minFreeSpace")]
+    pub min_free_space: u64,
     #[serde(rename = "usedFormatted")]
     pub used_formatted: String,
+    /// No disk holding the root was found; the figures are zero
+    pub unknown: bool,
+    /// Mount point of the disk holding the root
+    #[serde(rename = "mountPoint", skip_serializing_if = "Option::is_none")]
+    pub mount_point: Option<String>,
+    /// Filesystem type, such as `ext4` or `NTFS`
+    #[serde(rename = "fileSystem", skip_serializing_if = "Option::is_none")]
+    pub file_system: Option<String>,
+    /// 各挂载的磁盘信息（仅多挂载模式）
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<MountDiskInfo>,
+}
+/// 单个挂载的磁盘信息
+#[derive(Serialize, ToSchema)]
+pub struct MountDiskInfo {
+    pub name: String,
+    pub total: u64,
+    pub used: u64,
+    /// Free right now, as the filesystem reports it
+    pub free: u64,
+    /// What writes may use: `free` less `minFreeSpace`
+    pub writable: u64,
+    #[serde(rename = "usedFormatted")]
+    pub used_formatted: String,
+    /// No disk holding the root was found; the figures are zero
+    pub unknown: bool,
+    /// Mount point of the disk holding the root
+    #[serde(rename = "mountPoint", skip_serializing_if = "Option::is_none")]
+    pub mount_point: Option<String>,
+    /// Filesystem type, such as `ext4` or `NTFS`
+    #[serde(rename = "fileSystem", skip_serializing_if = "Option::is_none")]
+    pub file_system: Option<String>,
+}
+/// 健康检查查询参数
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HealthQuery {
+    /// `false` returns only the status
+    pub verbose: Option<bool>,
+}
+/// 健康检查响应
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
+    /// "ok" or "error"
+    pub status: &'static str,
+    /// Names of the failed checks
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<String>,
+    #[serde(flatten)]
+    pub details: Option<HealthDetails>,
+}
+#[derive(Serialize, ToSchema)]
+pub struct HealthDetails {
+    pub version: &'static str,
+    #[serde(rename = "uptimeSecs")]
+    pub uptime_secs: u64,
+    #[serde(rename = "activeUploads")]
+    pub active_uploads: usize,
+    pub uploads: UploadUsage,
+    pub roots: Vec<RootHealth>,
+}
+/// Upload slots in use against `--max-upload-streams` and `--max-upload-sessions`
+#[derive(Serialize, ToSchema)]
+pub struct UploadUsage {
+    /// Requests writing upload data to disk
+    pub streams: usize,
+    #[serde(rename = "maxStreams")]
+    pub max_streams: usize,
+    /// Unfinished chunked upload sessions
+    pub sessions: usize,
+    #[serde(rename = "maxSessions")]
+    pub max_sessions: usize,
+}
+/// 上传限制，供前端选择普通上传或分片上传
+#[derive(Serialize, ToSchema, Clone)]
+pub struct UploadCapabilities {
+    #[serde(rename = "maxRequestBody")]
+    pub max_request_body: u64,
+    #[serde(rename = "maxFileSize")]
+    pub max_file_size: u64,
+    #[serde(rename = "maxChunkSize")]
+    pub max_chunk_size: u64,
+    #[serde(rename = "chunkSize")]
+    pub chunk_size: u64,
+    #[serde(rename = "chunkedThreshold")]
+    pub chunked_threshold: u64,
+    /// An upload receiving nothing for this long is aborted, as is a chunked session between chunks
+    #[serde(rename = "uploadIdleSecs")]
+    pub upload_idle_secs: u64,
+}
+/// 调用者的角色
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    /// The configured `--user`, who manages API keys, sessions and the audit log
+    Admin,
+    /// A user from `--users-file`
+    #[default]
+    User,
+}
+/// 服务端能力：版本、限制、可选功能及调用者身份，供客户端按服务端配置调整行为
+#[derive(Serialize, ToSchema, Clone)]
+pub struct CapabilitiesResponse {
+    /// Server version
+    pub version: String,
+    pub username: String,
+    pub role: UserRole,
+    /// Whether the caller can only read, as with a read-only API key
+    #[serde(rename = "readOnly")]
+    pub read_only: bool,
+    #[serde(flatten)]
+    pub upload: UploadCapabilities,
+    /// API requests one client address may have in progress (`--max-conns-per-ip`)
+    #[serde(rename = "maxConnsPerIp")]
+    pub max_conns_per_ip: Option<usize>,
+    /// API request rate per client address (`--rate-limit`)
+    #[serde(rename = "rateLimit")]
+    pub rate_limit: Option<RateLimitInfo>,
+    /// How `/api/search` can match: `name` (part of the name) and `tags`
+    #[serde(rename = "searchModes")]
+    pub search_modes: Vec<String>,
+    /// Formats a folder can be downloaded as with `/api/download?format=`
+    #[serde(rename = "archiveFormats")]
+    pub archive_formats: Vec<String>,
+    /// Optional features by name: `true` or `false`, or the feature's settings when it has any
+    #[schema(value_type = Object)]
+    pub features: BTreeMap<String, serde_json::Value>,
+}
+#[derive(Serialize, ToSchema, Clone)]
+pub struct RateLimitInfo {
+    pub requests: usize,
+    #[serde(rename = "windowSecs")]
+    pub window_secs: u64,
+}
+/// 单个根目录（挂载）的检查结果
+#[derive(Serialize, ToSchema)]
+pub struct RootHealth {
+    pub path: String,
+    pub accessible: bool,
+    /// Absent when the write probe is disabled or the root is inaccessible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writable: Option<bool>,
+    #[serde(rename = "freeBytes", skip_serializing_if = "Option::is_none")]
+    pub free_bytes: Option<u64>,
 }
 /// 文件详情响应
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct InfoResponse {
     pub info: FileInfoDetail,
 }
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct FileInfoDetail {
     pub name: String,
     pub path: String,
@@ -82,67 +270,820 @@ pub struct FileInfoDetail {
     pub size_formatted: String,
     pub modified: String,
     pub created: String,
+    #[serde(rename = "modifiedAt")]
+    pub modified_at: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub children: Option<usize>,
+    /// The folder walk stopped at `--dir-size-timeout-secs` / `--dir-size-max-entries`,
+    /// so `size` is a lower bound
+    #[serde(rename = "sizeIsEstimate")]
+    pub size_is_estimate: bool,
+    /// `computing` while an `exact=true` walk runs in the background
+    #[serde(rename = "sizeStatus", skip_serializing_if = "Option::is_none")]
+    pub size_status: Option<JobStatus>,
+    /// Token to poll `/api/info/size` with
+    #[serde(rename = "sizeJob", skip_serializing_if = "Option::is_none")]
+    pub size_job: Option<String>,
+}
+/// State of a background walk (folder size, reports) or operation
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Computing,
+    Done,
+    /// An operation that stopped because it was cancelled
+    Cancelled,
+}
+/// 文件夹大小计算结果
+#[derive(Serialize, ToSchema)]
+pub struct SizeJobResponse {
+    pub token: String,
+    pub status: JobStatus,
+    pub path: String,
+    /// Set once `status` is `done`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(rename = "sizeFormatted", skip_serializing_if = "Option::is_none")]
+    pub size_formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<u64>,
+}
+/// Answer of `/api/copy`, `/api/move` and `/api/delete` with `background: true`, and of
+/// `/api/archive`
+#[derive(Serialize, ToSchema)]
+pub struct JobStarted {
+    pub message: String,
+    /// Token to poll `/api/jobs/{token}` with
+    pub job: String,
+}
+/// Query params for `/api/jobs`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobQuery {
+    /// `job` from a report endpoint such as `/api/usage`
+    pub token: String,
+}
+/// 后台报告任务状态
+#[derive(Serialize, ToSchema)]
+pub struct JobResponse {
+    pub token: String,
+    /// Which report or operation, e.g. `usage` or `copy`
+    pub kind: &'static str,
+    pub path: String,
+    pub status: JobStatus,
+    /// Entries walked or handled so far
+    pub entries: u64,
+    /// Bytes counted or copied so far
+    pub bytes: u64,
+    /// Entries an operation failed on so far
+    pub errors: u64,
+    /// Bytes a copy, move, delete or archive expects to handle, when known
+    #[serde(rename = "totalBytes", skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<u64>,
+    /// `bytes` as a share of `totalBytes`, 0 to 100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<u8>,
+    /// The entry a copy or move is on, by its path below the source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,
+    /// The result, once `status` is `done`; same shape as the endpoint's own response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+/// Query params for `/api/usage`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageQuery {
+    pub path: Option<String>,
+    /// How many files and folders to list, default 20 (at most 1000)
+    pub top: Option<usize>,
+}
+/// A file or folder in the usage report
+#[derive(Serialize, ToSchema)]
+pub struct UsageEntry {
+    pub path: String,
+    pub size: u64,
+    #[serde(rename = "sizeFormatted")]
+    pub size_formatted: String,
+    /// Files below a folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<u64>,
+}
+/// 空间占用报告
+#[derive(Serialize, ToSchema)]
+pub struct UsageReport {
+    pub path: String,
+    /// Largest files anywhere below `path`
+    #[serde(rename = "largestFiles")]
+    pub largest_files: Vec<UsageEntry>,
+    /// Immediate subfolders by the total size of their contents
+    #[serde(rename = "largestFolders")]
+    pub largest_folders: Vec<UsageEntry>,
+    #[serde(rename = "totalFiles")]
+    pub total_files: u64,
+    #[serde(rename = "totalBytes")]
+    pub total_bytes: u64,
+    #[serde(rename = "totalFormatted")]
+    pub total_formatted: String,
+    /// The walk stopped at `maxEntries`; totals and lists only cover what was reached
+    pub truncated: bool,
+    #[serde(rename = "maxEntries")]
+    pub max_entries: u64,
+    /// When the walk finished (RFC 3339 UTC)
+    #[serde(rename = "generatedAt")]
+    pub generated_at: String,
+}
+/// 空间占用报告响应
+#[derive(Serialize, ToSchema)]
+pub struct UsageResponse {
+    pub status: JobStatus,
+    /// Token to poll `/api/jobs` with while `computing`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<UsageReport>)]
+    pub report: Option<serde_json::Value>,
+}
+/// How `/api/stats` groups files
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsGroupBy {
+    /// Lowercase extension without the dot; `""` for none
+    #[default]
+    Extension,
+    /// The `category` field of listings
+    Category,
+}
+/// Query params for `/api/stats`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    pub path: Option<String>,
+    /// Default `extension`
+    pub group_by: Option<StatsGroupBy>,
+    /// Levels to walk, 1 for the folder's own files only; default unlimited
+    pub depth: Option<u32>,
+}
+/// Files of one extension or category
+#[derive(Serialize, ToSchema)]
+pub struct StatsGroup {
+    pub key: String,
+    pub files: u64,
+    pub bytes: u64,
+    #[serde(rename = "bytesFormatted")]
+    pub bytes_formatted: String,
+    /// Largest file of the group the user can see
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub largest: Option<UsageEntry>,
+}
+/// 文件类型统计
+#[derive(Serialize, ToSchema)]
+pub struct StatsReport {
+    pub path: String,
+    #[serde(rename = "groupBy")]
+    pub group_by: StatsGroupBy,
+    /// Largest groups first
+    pub groups: Vec<StatsGroup>,
+    #[serde(rename = "totalFiles")]
+    pub total_files: u64,
+    #[serde(rename = "totalBytes")]
+    pub total_bytes: u64,
+    #[serde(rename = "totalFormatted")]
+    pub total_formatted: String,
+    /// The walk stopped at `maxEntries`; the figures only cover what was reached
+    pub truncated: bool,
+    #[serde(rename = "maxEntries")]
+    pub max_entries: u64,
+    /// Depth limit that applied, if any
+    #[serde(rename = "maxDepth", skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<u32>,
+    #[serde(rename = "generatedAt")]
+    pub generated_at: String,
+}
+/// 文件类型统计响应
+#[derive(Serialize, ToSchema)]
+pub struct StatsResponse {
+    pub status: JobStatus,
+    /// Token to poll `/api/jobs` with while `computing`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<StatsReport>)]
+    pub report: Option<serde_json::Value>,
+}
+/// Content hash listed by `/api/manifest`
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestHash {
+    #[default]
+    Sha256,
+    /// Metadata only, without reading any file
+    None,
+}
+/// Query params for `/api/manifest`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ManifestQuery {
+    pub path: Option<String>,
+    /// Default `sha256`
+    pub hash: Option<ManifestHash>,
+    /// Only list files modified at or after this time (RFC 3339)
+    pub since: Option<String>,
+}
+/// One line of the `/api/manifest` stream
+#[derive(Serialize, ToSchema)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    /// RFC 3339 UTC; absent when the platform doesn't record it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    /// Lowercase hex; `null` with `hash=none`, for files over `--manifest-hash-max-bytes`,
+    /// protected files, and files that could not be read
+    pub hash: Option<String>,
 }
 /// 搜索结果响应
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct SearchResponse {
     pub results: Vec<FileInfo>,
+    /// The search ran out of `timeout_secs` (or the server's cap); `results` is what it found by then
+    #[serde(rename = "timedOut")]
+    pub timed_out: bool,
+    /// Folders read by the walk; 0 for a tag search or one answered from the index
+    #[serde(rename = "dirsScanned")]
+    pub dirs_scanned: u64,
+    /// Answered from the search index (`--index`) instead of walking
+    pub indexed: bool,
+    /// Set when the search stopped at `limit` or ran out of time: pass it as `token`, with
+    /// the same query, for the results after these
+    #[serde(rename = "nextToken", skip_serializing_if = "Option::is_none")]
+    pub next_token: Option<String>,
+}
+/// Query for `POST /api/upload`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadQuery {
+    /// Target folder; takes the place of a `path` form field
+    pub path: Option<String>,
 }
 /// 上传结果响应
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct UploadResponse {
     pub files: Vec<UploadedFile>,
 }
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct UploadedFile {
     pub name: String,
     pub size: u64,
     pub path: String,
+    /// The stored file already had this content (`If-None-Match`), so it wasn't rewritten
+    pub skipped: bool,
+    /// The stored file's modification time, RFC 3339 UTC, after any `lastModified` was applied
+    #[serde(rename = "modifiedAt")]
+    pub modified_at: Option<String>,
+    /// Why a `lastModified` value was ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+/// A file a client is about to upload, for `/api/upload/check`
+#[derive(Deserialize, ToSchema)]
+pub struct UploadCheckItem {
+    /// Where the file would be stored
+    pub path: String,
+    pub size: u64,
+    /// SHA-256 of the content, hex
+    pub sha256: String,
+}
+#[derive(Serialize, ToSchema)]
+pub struct UploadCheckResult {
+    pub path: String,
+    /// A file is stored at the path
+    pub exists: bool,
+    /// ...with the same size and SHA-256, so the upload can be skipped
+    pub matches: bool,
+    /// Why the entry couldn't be checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+/// Result of `/api/upload/check`
+#[derive(Serialize, ToSchema)]
+pub struct UploadCheckResponse {
+    pub files: Vec<UploadCheckResult>,
 }
 /// 操作结果响应
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct OperationResponse {
     pub message: String,
     #[serde(rename = "newPath", skip_serializing_if = "Option::is_none")]
     pub new_path: Option<String>,
 }
+/// Result of `/api/folder`
+#[derive(Serialize, ToSchema)]
+pub struct CreateFolderResponse {
+    pub message: String,
+    /// Logical path of the innermost folder
+    #[serde(rename = "newPath")]
+    pub new_path: String,
+    /// Logical paths of the folders that didn't exist before, outermost first
+    pub created: Vec<String>,
+}
+/// Result of `/api/file`
+#[derive(Serialize, ToSchema)]
+pub struct CreateFileResponse {
+    pub message: String,
+    #[serde(rename = "newPath")]
+    pub new_path: String,
+    /// The file's modification time, RFC 3339 UTC, after any `lastModified` was applied
+    #[serde(rename = "modifiedAt")]
+    pub modified_at: Option<String>,
+    /// Why a `lastModified` value was ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+/// Result of `/api/copy`
+#[derive(Serialize, ToSchema)]
+pub struct CopyResponse {
+    pub message: String,
+    #[serde(rename = "newPath")]
+    pub new_path: String,
+    /// Entries of a copied folder that were left out; the rest was copied
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedEntry>,
+}
+/// An entry a folder copy left out, such as an unreadable file or a symlink leading out of the root
+#[derive(Serialize, ToSchema)]
+pub struct SkippedEntry {
+    /// Path below the copied folder, or of the entry in the archive
+    pub path: String,
+    pub reason: String,
+}
+/// Result of `/api/delete`; with code `PARTIAL_FAILURE` some entries remain
+#[derive(Serialize, ToSchema)]
+pub struct DeleteResult {
+    pub message: String,
+    /// Files and folders removed, the deleted item itself included; 1 when it went to the trash
+    pub deleted: u64,
+    /// Entries that could not be removed
+    #[serde(skip_serializing_if = "is_zero")]
+    pub failed: u64,
+    /// The first of them, up to 50
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<DeleteFailure>,
+    /// Id to restore the item with at `/api/trash/restore`, when it went to the trash
+    #[serde(rename = "trashId", skip_serializing_if = "Option::is_none")]
+    pub trash_id: Option<String>,
+}
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+/// An entry a delete couldn't remove
+#[derive(Serialize, ToSchema)]
+pub struct DeleteFailure {
+    pub path: String,
+    pub error: String,
+}
+/// How deleting one path of `/api/delete/batch` went
+#[derive(Serialize, ToSchema)]
+pub struct BatchDeleteItem {
+    pub path: String,
+    pub success: bool,
+    /// Files and folders removed, the item itself included
+    pub deleted: u64,
+    /// Entries of a folder that could not be removed
+    #[serde(skip_serializing_if = "is_zero")]
+    pub failed: u64,
+    /// Error code of a failed item, such as `NOT_FOUND`, `CONFIRM_REQUIRED` or `PARTIAL_FAILURE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+/// Results of `/api/delete/batch`, one per path in request order
+#[derive(Serialize, ToSchema)]
+pub struct BatchDeleteResult {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub items: Vec<BatchDeleteItem>,
+}
 // ========== 请求体 ==========
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateFolderRequest {
     pub path: String,
+    /// Folder name, or several separated by `/` (`2024/q3/reports`) to create nested folders
     pub name: String,
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
+pub struct CreateFileRequest {
+    /// Folder to create the file in
+    pub path: String,
+    pub name: String,
+    /// Modification time to give the file, in epoch milliseconds
+    #[serde(rename = "lastModified", default)]
+    pub last_modified: Option<i64>,
+}
+#[derive(Deserialize, ToSchema)]
 pub struct RenameRequest {
     pub path: String,
     #[serde(rename = "newName")]
     pub new_name: String,
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct MoveRequest {
     pub source: String,
     pub destination: String,
+    /// Run as a job and answer at once with a `JobStarted`, for large folders
+    #[serde(default)]
+    pub background: bool,
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CopyRequest {
     pub source: String,
     pub destination: String,
+    /// Run as a job and answer at once with a `JobStarted`, for large folders
+    #[serde(default)]
+    pub background: bool,
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct DeleteRequest {
     pub path: String,
+    /// Confirm deleting a folder above the `--confirm-delete-*` thresholds
+    #[serde(default)]
+    pub force: bool,
+    /// Admin only: delete one of filest's own temporary files, such as one left by a crash
+    #[serde(default, rename = "forceInternal")]
+    pub force_internal: bool,
+    /// Run as a job and answer at once with a `JobStarted`, for large folders
+    #[serde(default)]
+    pub background: bool,
+    /// Delete at once instead of moving to the trash
+    #[serde(default)]
+    pub permanent: bool,
+}
+/// Details returned when a delete needs confirmation (lower bounds, the scan stops at the threshold)
+#[derive(Serialize, ToSchema)]
+pub struct DeleteConfirmation {
+    pub size: u64,
+    #[serde(rename = "sizeFormatted")]
+    pub size_formatted: String,
+    pub entries: u64,
 }
 // ========== 查询参数 ==========
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct PathQuery {
     pub path: Option<String>,
 }
+/// Archive a folder is downloaded as
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    Zip,
+    /// A gzip-compressed tar archive, for `curl ... | tar xz`
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+}
+/// Request body for `/api/delete/batch`
+#[derive(Deserialize, ToSchema)]
+pub struct BatchDeleteRequest {
+    /// Files and folders to delete, at most 1000
+    pub paths: Vec<String>,
+    /// Confirm deleting folders above the `--confirm-delete-*` thresholds
+    #[serde(default)]
+    pub force: bool,
+    /// Delete at once instead of moving to the trash
+    #[serde(default)]
+    pub permanent: bool,
+}
+/// Request body for `/api/download/batch`
+#[derive(Deserialize, ToSchema)]
+pub struct BatchDownloadRequest {
+    /// Files and folders to pack, at most 1000
+    pub paths: Vec<String>,
+}
+/// `/api/download/batch` posted as a form: `paths` holds the JSON array
 #[derive(Deserialize)]
+pub struct BatchDownloadForm {
+    pub paths: String,
+}
+/// Request body for `/api/archive`
+#[derive(Deserialize, ToSchema)]
+pub struct CreateArchiveRequest {
+    /// Files and folders to pack, at most 1000
+    pub paths: Vec<String>,
+    /// Folder to write the archive into
+    pub destination: String,
+    /// File name of the archive; by default the name a download of the same paths gets
+    pub name: Option<String>,
+    /// `zip` (the default) or `tar.gz`
+    pub format: Option<ArchiveFormat>,
+}
+/// Result of an `/api/archive` job
+#[derive(Serialize, ToSchema)]
+pub struct CreateArchiveResult {
+    pub message: String,
+    /// Logical path of the archive
+    #[serde(rename = "newPath")]
+    pub new_path: String,
+    /// Bytes written
+    pub size: u64,
+}
+/// What `/api/extract` does with a file whose name is taken in the destination
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractConflict {
+    /// Extract nothing and answer 409
+    #[default]
+    Fail,
+    /// Keep the existing file and leave the entry out
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Extract under a free name such as `report (1).pdf`
+    Rename,
+}
+/// Query params for `/api/archive/list`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ArchiveQuery {
+    /// The ZIP, tar or tar.gz archive
+    pub path: String,
+}
+/// Query params for `/api/archive/get`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ArchiveEntryQuery {
+    /// The ZIP, tar or tar.gz archive
+    pub path: String,
+    /// Name of the entry as `/api/archive/list` gives it
+    pub entry: String,
+    /// `inline` to show the entry in the browser instead of saving it
+    pub disposition: Option<Disposition>,
+}
+/// A file or folder in an archive
+#[derive(Serialize, ToSchema)]
+pub struct ArchiveEntry {
+    /// Path in the archive as stored
+    pub name: String,
+    /// `file`, `folder`, `symlink`, or `other` for hard links and devices
+    #[serde(rename = "type")]
+    pub file_type: String,
+    /// Uncompressed size
+    pub size: u64,
+    /// Bytes it takes in a ZIP archive; absent for tar, compressed as a whole
+    #[serde(rename = "compressedSize", skip_serializing_if = "Option::is_none")]
+    pub compressed_size: Option<u64>,
+    /// RFC 3339 UTC; absent when the archive doesn't record it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    /// Whether `/api/archive/get` can send it
+    pub readable: bool,
+}
+/// Result of `/api/archive/list`
+#[derive(Serialize, ToSchema)]
+pub struct ArchiveListResponse {
+    pub entries: Vec<ArchiveEntry>,
+    pub files: u64,
+    pub folders: u64,
+    /// Uncompressed size of all files
+    pub size: u64,
+}
+/// Request body for `/api/extract`
+#[derive(Deserialize, ToSchema)]
+pub struct ExtractRequest {
+    /// The ZIP, tar or tar.gz archive
+    pub path: String,
+    /// Folder to extract into, created if missing
+    pub destination: String,
+    /// For files already in the destination; folders are always merged
+    #[serde(default)]
+    pub conflict: ExtractConflict,
+}
+/// Result of `/api/extract`
+#[derive(Serialize, ToSchema)]
+pub struct ExtractResult {
+    pub message: String,
+    /// Logical path of the folder extracted into
+    pub destination: String,
+    /// Files written
+    pub files: u64,
+    /// Folders created
+    pub folders: u64,
+    /// Bytes written
+    pub size: u64,
+    /// Entries left out, such as unsafe paths, symlinks, or files kept by `skip`
+    pub skipped: Vec<SkippedEntry>,
+}
+/// How `/api/download` asks the browser to handle a file
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Disposition {
+    /// Saved as a file
+    #[default]
+    Attachment,
+    /// Shown in the browser where it can be, such as images, PDFs, text and media
+    Inline,
+}
+impl Disposition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Disposition::Attachment => "attachment",
+            Disposition::Inline => "inline",
+        }
+    }
+}
+/// Query params for `/api/download`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    pub path: Option<String>,
+    /// Download a folder, or a file, as an archive built while it is sent
+    pub format: Option<ArchiveFormat>,
+    /// `inline` to show the file in the browser instead of saving it; archives are always saved
+    pub disposition: Option<Disposition>,
+    /// Bytes per second to send at, such as `500KB`; capped by `--max-download-rate`
+    pub rate: Option<String>,
+}
+/// Output of `/api/files` and `/api/search`
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ListingFormat {
+    Json,
+    /// Aligned columns for a terminal
+    Text,
+    Csv,
+}
+/// What `/api/files` sorts entries by
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ListingSort {
+    /// Case ignored, numbers in names by value (`img2` before `img10`)
+    Name,
+    /// Files by size, folders by `dirSize` where it is known
+    Size,
+    Modified,
+    /// File extension
+    Type,
+}
+/// Direction of a sort
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+/// Query params for endpoints returning file metadata
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MetadataQuery {
+    pub path: Option<String>,
+    /// IANA time zone (e.g. `Europe/Berlin`) for `modified` / `created`, default the server's
+    pub tz: Option<String>,
+    /// Overrides the `Accept` header (`text/plain`, `text/csv`); default JSON
+    pub format: Option<ListingFormat>,
+    /// Add each entry's `tags`
+    #[serde(default)]
+    pub include_meta: bool,
+    /// Sort entries, folders first, ties by name; default the order they are read from disk
+    pub sort: Option<ListingSort>,
+    /// `asc` (default) or `desc`; on its own it sorts by name
+    pub order: Option<SortOrder>,
+}
+/// Query params for `/api/info`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct InfoQuery {
+    pub path: Option<String>,
+    /// IANA time zone for `modified` / `created`, default the server's
+    pub tz: Option<String>,
+    /// Compute a folder's full size in the background instead of a bounded walk
+    #[serde(default)]
+    pub exact: bool,
+}
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SizeJobQuery {
+    /// `sizeJob` from `/api/info?exact=true`
+    pub token: String,
+}
+/// How `query` matches names in `/api/search`
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// The name contains `query`
+    #[default]
+    Substring,
+    /// The whole name matches a glob: `*`, `?`, `[a-z]`, `{log,txt}`
+    Glob,
+    /// A regular expression (Rust `regex` syntax) matches somewhere in the name
+    Regex,
+}
+/// Entries `/api/search` finds with `type`
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(untagged)]
+pub enum SearchType {
+    /// `file` or `folder`
+    Entry(EntryType),
+    /// Files of a `category`, as listings show it
+    Category(FileCategory),
+}
+#[derive(Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    File,
+    Folder,
+}
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchQuery {
+    /// Part of the name, or a glob or regular expression per `mode`; empty matches every name
+    #[serde(default)]
     pub query: String,
+    /// Default `substring`; case is ignored in every mode
+    pub mode: Option<SearchMode>,
     pub path: Option<String>,
+    /// Overrides the `Accept` header (`text/plain`, `text/csv`); default JSON
+    pub format: Option<ListingFormat>,
+    /// Comma-separated tags a result must all carry
+    pub tags: Option<String>,
+    /// Add each result's `tags` (always set with `tags`)
+    #[serde(default)]
+    pub include_meta: bool,
+    /// Seconds the search may take before returning what it found; no more than `--search-timeout-secs`
+    pub timeout_secs: Option<u64>,
+    /// Only files at least this large, in bytes or such as `1GB`; leaves out folders
+    pub min_size: Option<String>,
+    /// Only files at most this large, in bytes or such as `500MB`; leaves out folders
+    pub max_size: Option<String>,
+    /// Only entries modified at or after this time: RFC 3339, or a date such as `2024-06-01` for
+    /// the start of that day (UTC)
+    pub modified_after: Option<String>,
+    /// Only entries modified before this time, in the same forms as `modified_after`
+    pub modified_before: Option<String>,
+    /// `file`, `folder`, or a file category such as `image`
+    #[serde(rename = "type")]
+    pub kind: Option<SearchType>,
+    /// Most results to return; default and at most `--search-max-results`
+    pub limit: Option<usize>,
+    /// `nextToken` of the previous response, to continue where it stopped
+    pub token: Option<String>,
+}
+/// Query params for `/api/search/content`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ContentSearchQuery {
+    /// Text to find in a line, or with `regex=true` a regular expression
+    #[serde(default)]
+    pub query: String,
+    pub path: Option<String>,
+    /// Take `query` as a regular expression (Rust `regex` syntax)
+    #[serde(default)]
+    pub regex: bool,
+    /// Match case exactly; by default case is ignored
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Seconds the search may take before returning what it found; no more than `--search-timeout-secs`
+    pub timeout_secs: Option<u64>,
+}
+/// A line that matched `/api/search/content`
+#[derive(Serialize, ToSchema)]
+pub struct ContentMatch {
+    /// 1-based line number
+    pub line: u64,
+    /// 1-based character position of the match in the line
+    pub column: u64,
+    /// The line, cut around the match with `…` when long
+    pub text: String,
+}
+/// A file with lines matching `/api/search/content`
+#[derive(Serialize, ToSchema)]
+pub struct ContentSearchFile {
+    pub path: String,
+    pub size: u64,
+    pub matches: Vec<ContentMatch>,
+    /// The file has more matching lines than `matches` holds
+    #[serde(rename = "moreMatches")]
+    pub more_matches: bool,
+}
+/// Result of `/api/search/content`
+#[derive(Serialize, ToSchema)]
+pub struct ContentSearchResponse {
+    /// Files with matches, in the order the walk found them
+    pub results: Vec<ContentSearchFile>,
+    /// The search ran out of `timeout_secs` (or the server's cap); `results` is what it found by then
+    #[serde(rename = "timedOut")]
+    pub timed_out: bool,
+    /// Text files read
+    #[serde(rename = "filesScanned")]
+    pub files_scanned: u64,
+    /// Files passed over as binary or over `--content-search-max-bytes`
+    #[serde(rename = "filesSkipped")]
+    pub files_skipped: u64,
 }
 
 // ========== Chunked Upload ==========
@@ -159,6 +1100,29 @@ pub struct UploadSession {
     pub temp_dir: std::path::PathBuf,
     pub received_chunks: Vec<bool>,
     pub created_at: std::time::Instant,
+    /// User who started the upload
+    pub owner: String,
+    /// Address the upload was started from
+    pub client: std::net::IpAddr,
+    /// Modification time to give the assembled file, in epoch milliseconds
+    pub last_modified: Option<i64>,
+    /// Milliseconds after `created_at` when data last arrived, shared by all copies
+    pub last_activity: Arc<AtomicU64>,
+    /// The session's `--max-upload-sessions` slot, returned when the last copy is dropped
+    pub slot: Arc<tokio::sync::OwnedSemaphorePermit>,
+}
+
+impl UploadSession {
+    /// Note that data arrived, so the session isn't reaped as idle
+    pub fn touch(&self) {
+        self.last_activity.store(self.created_at.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Time since data last arrived
+    pub fn idle(&self) -> std::time::Duration {
+        let last = std::time::Duration::from_millis(self.last_activity.load(Ordering::Relaxed));
+        self.created_at.elapsed().saturating_sub(last)
+    }
 }
 
 /// Global upload sessions manager
@@ -170,7 +1134,7 @@ pub fn new_upload_sessions() -> UploadSessions {
 }
 
 /// Request to initialize chunked upload
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct ChunkedUploadInitRequest {
     pub path: String,
     pub filename: String,
@@ -180,10 +1144,13 @@ pub struct ChunkedUploadInitRequest {
     pub chunk_size: u64,
     #[serde(rename = "totalChunks")]
     pub total_chunks: u32,
+    /// Modification time to give the assembled file, in epoch milliseconds
+    #[serde(rename = "lastModified", default)]
+    pub last_modified: Option<i64>,
 }
 
 /// Response for chunked upload init
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ChunkedUploadInitResponse {
     #[serde(rename = "uploadId")]
     pub upload_id: String,
@@ -191,8 +1158,40 @@ pub struct ChunkedUploadInitResponse {
     pub chunk_size: u64,
 }
 
+/// An unfinished chunked upload, as listed by `/api/admin/uploads`
+#[derive(Serialize, ToSchema)]
+pub struct UploadSessionInfo {
+    #[serde(rename = "uploadId")]
+    pub upload_id: String,
+    pub owner: String,
+    /// Address the upload was started from
+    pub client: String,
+    pub filename: String,
+    /// Logical path of the file being uploaded
+    pub path: String,
+    #[serde(rename = "totalSize")]
+    pub total_size: u64,
+    /// Bytes in the chunks received so far
+    #[serde(rename = "receivedBytes")]
+    pub received_bytes: u64,
+    #[serde(rename = "receivedChunks")]
+    pub received_chunks: usize,
+    #[serde(rename = "totalChunks")]
+    pub total_chunks: u32,
+    #[serde(rename = "ageSecs")]
+    pub age_secs: u64,
+}
+/// Response for the upload sessions listing
+#[derive(Serialize, ToSchema)]
+pub struct UploadSessionsResponse {
+    pub usage: UploadUsage,
+    /// Oldest first
+    pub sessions: Vec<UploadSessionInfo>,
+}
+
 /// Query params for chunk upload
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ChunkUploadQuery {
     #[serde(rename = "uploadId")]
     pub upload_id: String,
@@ -201,7 +1200,7 @@ pub struct ChunkUploadQuery {
 }
 
 /// Response for chunk upload
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ChunkUploadResponse {
     #[serde(rename = "chunkIndex")]
     pub chunk_index: u32,
@@ -209,23 +1208,752 @@ pub struct ChunkUploadResponse {
 }
 
 /// Request to complete chunked upload
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct ChunkedUploadCompleteRequest {
     #[serde(rename = "uploadId")]
     pub upload_id: String,
 }
 
 /// Response for chunked upload complete
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ChunkedUploadCompleteResponse {
     pub name: String,
     pub size: u64,
     pub path: String,
+    /// The file's modification time, RFC 3339 UTC, after any `lastModified` was applied
+    #[serde(rename = "modifiedAt")]
+    pub modified_at: Option<String>,
+    /// Why `lastModified` was ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
 }
 
 /// Request to abort chunked upload
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct ChunkedUploadAbortRequest {
     #[serde(rename = "uploadId")]
     pub upload_id: String,
-}
\ No newline at end of file
+}
+// ========== Token Authentication ==========
+
+/// Issued login token info
+#[derive(Clone)]
+pub struct AuthToken {
+    pub username: String,
+    pub expires_at: std::time::Instant,
+}
+
+/// Global login tokens store (opaque token -> info)
+pub type AuthTokens = Arc<RwLock<HashMap<String, AuthToken>>>;
+
+/// Create a new login tokens store
+pub fn new_auth_tokens() -> AuthTokens {
+    Arc::new(RwLock::new(HashMap::new()))
+}
+
+/// Login request
+#[derive(Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// Login response
+#[derive(Serialize, ToSchema)]
+pub struct LoginResponse {
+    pub token: String,
+    #[serde(rename = "expiresIn")]
+    pub expires_in: u64,
+}
+
+// ========== Brute-force Protection ==========
+
+/// Failed authentication attempts from one client IP
+#[derive(Clone)]
+pub struct AuthFailure {
+    pub count: u32,
+    pub window_start: std::time::Instant,
+    pub locked_until: Option<std::time::Instant>,
+}
+
+/// Per-IP authentication failure tracker
+pub type AuthFailures = Arc<RwLock<HashMap<std::net::IpAddr, AuthFailure>>>;
+
+/// Create a new authentication failure tracker
+pub fn new_auth_failures() -> AuthFailures {
+    Arc::new(RwLock::new(HashMap::new()))
+}
+
+// ========== Cookie Sessions ==========
+
+/// Browser session backed by an HttpOnly cookie
+#[derive(Clone)]
+pub struct Session {
+    pub username: String,
+    pub created_at: std::time::Instant,
+    pub last_seen: std::time::Instant,
+}
+
+/// Global cookie sessions store (session id -> session)
+pub type Sessions = Arc<RwLock<HashMap<String, Session>>>;
+
+/// Create a new cookie sessions store
+pub fn new_sessions() -> Sessions {
+    Arc::new(RwLock::new(HashMap::new()))
+}
+
+// ========== Share Links ==========
+
+/// What a share link grants
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareKind {
+    /// Download the shared file or browse the shared folder
+    #[default]
+    Download,
+    /// Upload into the shared folder only (no listing, no downloads)
+    UploadOnly,
+}
+
+/// Persisted public share link
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShareRecord {
+    pub token: String,
+    /// Logical path of the shared file or folder
+    pub path: String,
+    /// User who created the share
+    pub owner: String,
+    /// Creation time (unix seconds)
+    pub created_at: i64,
+    /// Expiry time (unix seconds), `None` = never
+    pub expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// Whether a shared folder may be browsed
+    #[serde(default)]
+    pub allow_listing: bool,
+    #[serde(default)]
+    pub kind: ShareKind,
+    /// Per-file size limit for upload-only shares
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    /// Total size limit for upload-only shares
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_bytes: Option<u64>,
+    /// Bytes received so far through an upload-only share
+    #[serde(default)]
+    pub received_bytes: u64,
+}
+
+/// Request to create a share link
+#[derive(Deserialize, ToSchema)]
+pub struct CreateShareRequest {
+    pub path: String,
+    /// Lifetime in seconds, omitted = never expires
+    #[serde(rename = "expiresIn")]
+    pub expires_in: Option<u64>,
+    pub password: Option<String>,
+    #[serde(rename = "allowListing", default)]
+    pub allow_listing: bool,
+    #[serde(default)]
+    pub kind: ShareKind,
+    #[serde(rename = "maxFileSize")]
+    pub max_file_size: Option<u64>,
+    #[serde(rename = "maxTotalBytes")]
+    pub max_total_bytes: Option<u64>,
+}
+
+/// Share link as returned by the API (never includes the password hash)
+#[derive(Serialize, ToSchema)]
+pub struct ShareInfo {
+    pub token: String,
+    pub path: String,
+    pub url: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<i64>,
+    #[serde(rename = "hasPassword")]
+    pub has_password: bool,
+    #[serde(rename = "allowListing")]
+    pub allow_listing: bool,
+    pub kind: ShareKind,
+    #[serde(rename = "maxFileSize", skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(rename = "maxTotalBytes", skip_serializing_if = "Option::is_none")]
+    pub max_total_bytes: Option<u64>,
+    #[serde(rename = "receivedBytes")]
+    pub received_bytes: u64,
+}
+
+/// Share link creation response
+#[derive(Serialize, ToSchema)]
+pub struct ShareResponse {
+    pub share: ShareInfo,
+}
+
+/// Share link list response
+#[derive(Serialize, ToSchema)]
+pub struct SharesResponse {
+    pub shares: Vec<ShareInfo>,
+}
+
+/// Query params for accessing a share
+#[derive(Deserialize)]
+pub struct ShareAccessQuery {
+    /// Path relative to a shared folder
+    pub path: Option<String>,
+}
+
+/// Password form for protected shares
+#[derive(Deserialize)]
+pub struct ShareUnlockForm {
+    pub password: String,
+}
+
+/// File received through an upload-only share
+#[derive(Serialize)]
+pub struct DroppedFile {
+    /// Stored name (may differ from the uploaded name after auto-rename)
+    pub name: String,
+    pub size: u64,
+}
+
+/// Upload-only share response
+#[derive(Serialize)]
+pub struct DropResponse {
+    pub files: Vec<DroppedFile>,
+}
+
+// ========== API Keys ==========
+
+/// Persisted API key (only the hash of the secret is stored)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub name: String,
+    /// User the key acts as
+    pub owner: String,
+    /// SHA-256 of the key, hex encoded
+    pub key_hash: String,
+    pub created_at: i64,
+    #[serde(default)]
+    pub last_used: Option<i64>,
+    /// Only allow read (GET/HEAD) requests
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// Request to create an API key
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// API key as returned by the API (never includes the key or its hash)
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+    #[serde(rename = "lastUsed")]
+    pub last_used: Option<i64>,
+    pub readonly: bool,
+}
+
+/// API key creation response; `key` is only ever shown here
+#[derive(Serialize, ToSchema)]
+pub struct CreateApiKeyResponse {
+    pub key: String,
+    pub info: ApiKeyInfo,
+}
+
+/// API key list response
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeysResponse {
+    pub keys: Vec<ApiKeyInfo>,
+}
+
+/// Query for `/api/render/markdown`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MarkdownQuery {
+    pub path: String,
+}
+
+/// Query for `GET` and `PATCH /api/content`, and for `/api/append`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ContentQuery {
+    pub path: String,
+}
+/// A text file's content, from `GET /api/content`
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TextContent {
+    pub content: String,
+    /// What the file was decoded from, such as `UTF-8`, `GBK` or `UTF-16LE`
+    pub encoding: String,
+    /// The file starts with a byte order mark, left out of `content`
+    pub bom: bool,
+    pub size: u64,
+    /// Modification time, RFC 3339 in UTC
+    pub modified_at: Option<String>,
+    /// Sent back with `PUT /api/content` so a save can't overwrite someone else's change
+    pub etag: String,
+}
+
+/// Request body for `PUT /api/content`
+#[derive(Deserialize, ToSchema)]
+pub struct SaveContentRequest {
+    pub path: String,
+    pub content: String,
+    /// The `etag` the file was opened with; without it the file is created and must not exist yet
+    pub etag: Option<String>,
+    /// Encoding to save in, as `GET /api/content` reported it [default: UTF-8]
+    pub encoding: Option<String>,
+    /// Start the file with a byte order mark (UTF-8 and UTF-16 only)
+    #[serde(default)]
+    pub bom: bool,
+}
+
+/// The saved file, from `PUT /api/content`
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedContent {
+    pub size: u64,
+    /// Modification time, RFC 3339 in UTC
+    pub modified_at: Option<String>,
+    /// For the next save
+    pub etag: String,
+    /// From the `--upload-hook` command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// Query for `/api/media-info`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MediaInfoQuery {
+    pub path: String,
+    /// Add `latitude` / `longitude` when the image records them
+    #[serde(default)]
+    pub include_gps: bool,
+}
+/// Query for `/api/image`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImageQuery {
+    pub path: String,
+    /// Largest width in pixels; the aspect ratio is kept and images are never enlarged
+    pub w: Option<u32>,
+    /// Largest height in pixels
+    pub h: Option<u32>,
+    /// JPEG quality from 1 to 100 [default: 80]
+    pub q: Option<u8>,
+}
+/// Image container, detected from the file's first bytes
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaFormat {
+    Jpeg,
+    Png,
+    Tiff,
+    Heic,
+}
+/// Image details from its headers and EXIF data
+#[derive(Serialize, ToSchema)]
+pub struct MediaInfo {
+    pub format: MediaFormat,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Capture time as recorded by the camera, with its UTC offset when known
+    #[serde(rename = "takenAt")]
+    pub taken_at: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// EXIF orientation, 1 to 8
+    pub orientation: Option<u32>,
+    #[serde(rename = "hasGps")]
+    pub has_gps: bool,
+    /// Decimal degrees, only with `include_gps=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+/// Query for `/api/tail`
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TailQuery {
+    pub path: String,
+    /// Lines to return from the end, at most 10000 (default 100)
+    pub lines: Option<usize>,
+    /// Keep the response open as server-sent events carrying lines as they are appended
+    #[serde(default)]
+    pub follow: bool,
+}
+/// The end of a text file
+#[derive(Serialize, ToSchema)]
+pub struct TailResponse {
+    /// Oldest first, without line endings
+    pub lines: Vec<String>,
+    /// File size when read; where a follow would continue
+    pub size: u64,
+    /// `--tail-max-bytes` was read before enough lines were found
+    pub truncated: bool,
+}
+/// Why a followed file starts over, sent as a `reset` event
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TailReset {
+    /// The file got shorter
+    Truncated,
+    /// Another file now has the name, as after log rotation
+    Rotated,
+}
+
+// ========== File Metadata ==========
+
+/// Persisted tags and note of one file
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct FileMeta {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+}
+
+/// Query for a file's metadata
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MetaQuery {
+    pub path: String,
+}
+
+/// Request replacing a file's tags and note
+#[derive(Deserialize, ToSchema)]
+pub struct SetMetaRequest {
+    pub path: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+/// A file's tags and note
+#[derive(Serialize, ToSchema)]
+pub struct MetaResponse {
+    pub path: String,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    /// Unix time of the last change; null if none was ever set
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<i64>,
+}
+
+// ========== Favorites ==========
+
+/// Persisted favorite of one user
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FavoriteRecord {
+    /// Logical path, as returned by the file listing
+    pub path: String,
+    pub label: String,
+    pub created_at: i64,
+}
+
+/// Request to add or update a favorite
+#[derive(Deserialize, ToSchema)]
+pub struct AddFavoriteRequest {
+    pub path: String,
+    /// Defaults to the last path component
+    pub label: Option<String>,
+    /// Zero-based place in the list; new favorites go last by default
+    pub position: Option<usize>,
+}
+
+/// Query for removing a favorite
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FavoriteQuery {
+    pub path: String,
+}
+
+/// A favorite as listed
+#[derive(Serialize, ToSchema)]
+pub struct FavoriteInfo {
+    pub path: String,
+    pub label: String,
+    pub position: usize,
+    /// `false` once the target is removed or no longer accessible
+    pub exists: bool,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+}
+
+/// Favorites list response
+#[derive(Serialize, ToSchema)]
+pub struct FavoritesResponse {
+    pub favorites: Vec<FavoriteInfo>,
+}
+
+// ========== Trash ==========
+
+/// A trashed file or folder, persisted in `<data_dir>/trash.json`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TrashRecord {
+    /// Logical path it was deleted from
+    pub path: String,
+    /// Root of the mount whose trash folder holds it
+    pub root: std::path::PathBuf,
+    pub deleted_by: String,
+    pub deleted_at: i64,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// A trashed item as listed
+#[derive(Serialize, ToSchema)]
+pub struct TrashItem {
+    pub id: String,
+    pub name: String,
+    /// Where it is restored to
+    #[serde(rename = "originalPath")]
+    pub original_path: String,
+    #[serde(rename = "type")]
+    pub file_type: String,
+    pub size: u64,
+    #[serde(rename = "sizeFormatted")]
+    pub size_formatted: String,
+    #[serde(rename = "deletedBy")]
+    pub deleted_by: String,
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: i64,
+}
+
+/// Trash list response, most recently deleted first
+#[derive(Serialize, ToSchema)]
+pub struct TrashResponse {
+    pub items: Vec<TrashItem>,
+    /// Bytes held by everything in the trash, including items the caller can't see
+    pub size: u64,
+    /// Days items are kept before they are purged; absent when they are kept until purged
+    #[serde(rename = "retentionDays", skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u64>,
+    /// Bytes the trash may hold before the oldest items are purged
+    #[serde(rename = "maxSize", skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+}
+
+/// Request to put a trashed item back where it was deleted from
+#[derive(Deserialize, ToSchema)]
+pub struct RestoreTrashRequest {
+    pub id: String,
+}
+
+/// Request to delete trashed items for good
+#[derive(Deserialize, ToSchema)]
+pub struct PurgeTrashRequest {
+    /// Items to purge; all the caller may delete when absent
+    pub ids: Option<Vec<String>>,
+}
+
+/// Result of `/api/trash/purge`
+#[derive(Serialize, ToSchema)]
+pub struct PurgeTrashResult {
+    pub message: String,
+    pub purged: u64,
+    /// Items that couldn't be removed completely; they stay in the trash
+    #[serde(skip_serializing_if = "is_zero")]
+    pub failed: u64,
+}
+
+// ========== Audit Log ==========
+
+/// Query params for the audit endpoint
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// Only entries whose source or destination is at or below this path
+    pub path: Option<String>,
+    pub op: Option<String>,
+    /// RFC 3339 timestamp or unix seconds
+    pub since: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Audit query response
+#[derive(Serialize, ToSchema)]
+pub struct AuditResponse {
+    pub entries: Vec<crate::audit::AuditEntry>,
+}
+
+// ========== Locks ==========
+
+/// Request to take or renew a lock
+#[derive(Deserialize, ToSchema)]
+pub struct LockRequest {
+    pub path: String,
+    /// Lifetime in seconds [default: 300, max: 86400]
+    #[serde(rename = "ttlSecs")]
+    pub ttl_secs: Option<u64>,
+    /// Also cover everything below a folder
+    #[serde(default)]
+    pub subtree: bool,
+    /// Token of a lock the caller holds on the same path, to extend it
+    pub token: Option<String>,
+}
+
+/// Query for inspecting or releasing locks
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LockQuery {
+    pub path: String,
+}
+
+/// A lock as reported; the token only goes to whoever took it
+#[derive(Serialize, ToSchema)]
+pub struct LockInfo {
+    pub path: String,
+    pub owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub subtree: bool,
+    /// Expiry time (unix seconds)
+    #[serde(rename = "expiresAt")]
+    pub expires_at: i64,
+}
+
+/// Locks on a path, on folders above it that cover their subtree, and below it
+#[derive(Serialize, ToSchema)]
+pub struct LocksResponse {
+    pub locks: Vec<LockInfo>,
+}
+
+// ========== Clipboard ==========
+
+/// Whether pasting copies the clipboard items or moves them
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardOp {
+    Copy,
+    Cut,
+}
+
+/// Request to fill the clipboard
+#[derive(Deserialize, ToSchema)]
+pub struct ClipboardRequest {
+    pub op: ClipboardOp,
+    /// Files and folders to paste later; empty clears the clipboard
+    pub paths: Vec<String>,
+}
+
+/// The caller's clipboard; empty when `op` is missing
+#[derive(Serialize, ToSchema)]
+pub struct ClipboardResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub op: Option<ClipboardOp>,
+    pub paths: Vec<String>,
+    /// When the clipboard is dropped unless used again (unix seconds)
+    #[serde(rename = "expiresAt", skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+/// Request to paste the clipboard
+#[derive(Deserialize, ToSchema)]
+pub struct PasteRequest {
+    /// Folder to copy or move the items into
+    pub destination: String,
+}
+
+/// How pasting one clipboard item went
+#[derive(Serialize, ToSchema)]
+pub struct PasteItem {
+    pub source: String,
+    pub success: bool,
+    #[serde(rename = "newPath", skip_serializing_if = "Option::is_none")]
+    pub new_path: Option<String>,
+    /// Entries of a copied folder that were left out
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedEntry>,
+    /// Error code of a failed item, such as `NOT_FOUND` for a source that is gone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Results of a paste, one per clipboard item in clipboard order
+#[derive(Serialize, ToSchema)]
+pub struct PasteResult {
+    pub op: ClipboardOp,
+    pub destination: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub items: Vec<PasteItem>,
+}
+
+/// Response of `/api/paste`
+#[derive(Serialize, ToSchema)]
+pub struct PasteResponse {
+    pub status: JobStatus,
+    /// Token to poll `/api/jobs` with while `computing`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<String>,
+    /// Items pasted so far
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<PasteResult>)]
+    pub result: Option<serde_json::Value>,
+}
+
+// ========== Undo ==========
+
+/// An operation `/api/undo` can reverse
+#[derive(Serialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum UndoOp {
+    Rename,
+    Move,
+    Copy,
+    /// A delete that went to the trash
+    Delete,
+}
+
+/// A recorded operation, as listed by `/api/undo`
+#[derive(Serialize, ToSchema, Clone)]
+pub struct UndoEntry {
+    pub op: UndoOp,
+    /// Where the item is now: the new name or place, the copy, or where a deleted item was
+    pub path: String,
+    /// Where a renamed or moved item came from, which undoing puts it back to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Unix time it was done
+    pub at: i64,
+}
+
+/// The caller's undoable operations, newest first: the one `POST /api/undo` reverses
+#[derive(Serialize, ToSchema)]
+pub struct UndoListResponse {
+    pub entries: Vec<UndoEntry>,
+}
+
+/// Result of `POST /api/undo`
+#[derive(Serialize, ToSchema)]
+pub struct UndoResult {
+    pub message: String,
+    /// The operation that was reversed
+    pub op: UndoOp,
+    /// Where the item is back at; absent when a copy was removed
+    #[serde(rename = "newPath", skip_serializing_if = "Option::is_none")]
+    pub new_path: Option<String>,
+}
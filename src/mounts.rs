@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use crate::error::ApiError;
+use crate::i18n::Msg;
+use crate::handlers::relative_path;
+use crate::paths::PATH_SEPARATORS;
+
+/// A directory served under a name
+pub struct Mount {
+    /// Empty for the implicit mount of `--root`
+    pub name: String,
+    /// Canonical root directory
+    pub root: PathBuf,
+}
+
+/// Symlinks refused by `--no-follow-symlinks`
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NoFollow {
+    /// Links resolving outside their mount, or nowhere
+    Outside,
+    /// Every link
+    All,
+}
+
+/// Directories served by filest, in the order given on the command line
+///
+/// With `--root` there is a single unnamed mount and logical paths are relative to it.
+/// With `--mount name=path` the virtual root `/` lists the mounts and the first path
+/// segment selects one, e.g. `/media/movies` is `movies` inside the `media` mount.
+pub struct Mounts {
+    mounts: Vec<Mount>,
+    no_follow: Option<NoFollow>,
+}
+
+impl Mounts {
+    /// The implicit mount for a single root directory
+    pub fn single(root: PathBuf) -> Self {
+        Self {
+            mounts: vec![Mount {
+                name: String::new(),
+                root,
+            }],
+            no_follow: None,
+        }
+    }
+
+    /// Named mounts; roots must already be canonical and must not contain one another
+    pub fn new(mounts: Vec<Mount>) -> Result<Self, String> {
+        for (i, a) in mounts.iter().enumerate() {
+            for b in &mounts[i + 1..] {
+                if a.name == b.name {
+                    return Err(format!("duplicate mount name {:?}", a.name));
+                }
+                if a.root.starts_with(&b.root) || b.root.starts_with(&a.root) {
+                    return Err(format!("mounts {:?} and {:?} overlap", a.name, b.name));
+                }
+            }
+        }
+        Ok(Self { mounts, no_follow: None })
+    }
+
+    /// Refuse symlinks per `--no-follow-symlinks`; by default every link is followed
+    pub fn no_follow_symlinks(mut self, policy: Option<NoFollow>) -> Self {
+        self.no_follow = policy;
+        self
+    }
+
+    /// Whether `path` on disk is a symlink refused by `--no-follow-symlinks`
+    ///
+    /// Only the last component is looked at, so callers check a path one name at a time
+    /// or walk down from an already checked folder. A dangling link is refused too: writing
+    /// through it would create its target, wherever that is.
+    pub fn refuses_link(&self, path: &Path) -> bool {
+        let Some(policy) = self.no_follow else {
+            return false;
+        };
+        if !std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return false;
+        }
+        match policy {
+            NoFollow::All => true,
+            NoFollow::Outside => !path
+                .canonicalize()
+                .is_ok_and(|target| self.find(path).is_some_and(|mount| target.starts_with(&mount.root))),
+        }
+    }
+
+    /// Parse a `name=path` mount specification
+    pub fn parse_spec(spec: &str) -> Result<(String, PathBuf), String> {
+        let (name, path) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=path, got {:?}", spec))?;
+        let name = name.trim();
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(format!("invalid mount name {:?}", name));
+        }
+        if path.is_empty() {
+            return Err(format!("missing path for mount {:?}", name));
+        }
+        Ok((name.to_string(), PathBuf::from(path)))
+    }
+
+    /// Whether `/` is a virtual directory listing named mounts
+    pub fn is_multi(&self) -> bool {
+        !self.mounts[0].name.is_empty()
+    }
+
+    pub fn list(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Whether the user path refers to the virtual root listing the mounts
+    pub fn is_virtual_root(&self, user_path: &str) -> bool {
+        self.is_multi() && user_path.split(PATH_SEPARATORS).all(|c| c.is_empty() || c == ".")
+    }
+
+    /// Split a user path into the selected mount and the remaining path inside it
+    pub fn select<'a>(&self, user_path: &'a str) -> Result<(&Mount, &'a str), ApiError> {
+        if !self.is_multi() {
+            return Ok((&self.mounts[0], user_path));
+        }
+        let mut rest = user_path;
+        loop {
+            let (first, tail) = rest.split_once(PATH_SEPARATORS).unwrap_or((rest, ""));
+            match first {
+                "" | "." if !tail.is_empty() => rest = tail,
+                "" | "." => return Err(ApiError::InvalidPath(Msg::PathOutsideMount.into())),
+                ".." => return Err(ApiError::InvalidPath(Msg::InvalidPath.into())),
+                name => {
+                    let mount = self
+                        .mounts
+                        .iter()
+                        .find(|m| m.name == name)
+                        .ok_or_else(|| ApiError::NotFound(Msg::MountNotFound.with(&[&name])))?;
+                    return Ok((mount, tail));
+                }
+            }
+        }
+    }
+
+    /// The mount containing a path on disk
+    pub fn find(&self, path: &Path) -> Option<&Mount> {
+        self.mounts.iter().find(|m| path.starts_with(&m.root))
+    }
+
+    /// Logical path ("/media/a/b") of a path on disk
+    pub fn logical_path(&self, path: &Path) -> String {
+        match self.find(path) {
+            Some(mount) if self.is_multi() => {
+                let rel = relative_path(&mount.root, path);
+                if rel == "/" {
+                    format!("/{}", mount.name)
+                } else {
+                    format!("/{}{}", mount.name, rel)
+                }
+            }
+            Some(mount) => relative_path(&mount.root, path),
+            None => "/".to_string(),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::handlers::safe_path;
+    use std::os::unix::fs::symlink;
+
+    /// A root holding `dir/file` and links `inside` -> dir, `outside` -> a folder next
+    /// to the root and `dangling` -> a missing name
+    fn tree() -> (tempfile::TempDir, PathBuf) {
+        let temp = tempfile::tempdir().unwrap();
+        let base = temp.path().canonicalize().unwrap();
+        let root = base.join("root");
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::write(root.join("dir/file"), "x").unwrap();
+        std::fs::create_dir(base.join("elsewhere")).unwrap();
+        symlink(root.join("dir"), root.join("inside")).unwrap();
+        symlink(base.join("elsewhere"), root.join("outside")).unwrap();
+        symlink(root.join("missing"), root.join("dangling")).unwrap();
+        (temp, root)
+    }
+
+    fn mounts(root: &Path, policy: Option<NoFollow>) -> Mounts {
+        Mounts::single(root.to_path_buf()).no_follow_symlinks(policy)
+    }
+
+    #[test]
+    fn links_are_followed_by_default() {
+        let (_temp, root) = tree();
+        let mounts = mounts(&root, None);
+        for name in ["dir", "inside", "outside", "dangling"] {
+            assert!(!mounts.refuses_link(&root.join(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn outside_refuses_links_leaving_the_mount() {
+        let (_temp, root) = tree();
+        let mounts = mounts(&root, Some(NoFollow::Outside));
+        assert!(!mounts.refuses_link(&root.join("dir")));
+        assert!(!mounts.refuses_link(&root.join("inside")));
+        assert!(mounts.refuses_link(&root.join("outside")));
+        assert!(mounts.refuses_link(&root.join("dangling")));
+        assert!(!mounts.refuses_link(&root.join("not-there")));
+    }
+
+    #[test]
+    fn all_refuses_every_link() {
+        let (_temp, root) = tree();
+        let mounts = mounts(&root, Some(NoFollow::All));
+        assert!(!mounts.refuses_link(&root.join("dir")));
+        assert!(!mounts.refuses_link(&root.join("dir/file")));
+        for name in ["inside", "outside", "dangling"] {
+            assert!(mounts.refuses_link(&root.join(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn paths_through_refused_links_are_refused() {
+        let (_temp, root) = tree();
+        let outside = mounts(&root, Some(NoFollow::Outside));
+        assert_eq!(safe_path(&outside, "/inside/file").unwrap().actual, root.join("dir/file"));
+        assert!(matches!(safe_path(&outside, "/outside/new"), Err(ApiError::AccessDenied(_))));
+        assert!(matches!(safe_path(&outside, "/dangling"), Err(ApiError::AccessDenied(_))));
+
+        let all = mounts(&root, Some(NoFollow::All));
+        assert!(matches!(safe_path(&all, "/inside/file"), Err(ApiError::AccessDenied(_))));
+        assert!(safe_path(&all, "/dir/file").is_ok());
+    }
+}
@@ -0,0 +1,394 @@
+use axum::{extract::State, Json};
+use utoipa::{
+    openapi::{
+        path::Operation,
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+        server::Server,
+        RefOr, Response, ResponseBuilder,
+    },
+    Modify, OpenApi, ToSchema,
+};
+use crate::{
+    activity, apikeys, archive, audit, capabilities, clipboard, content, dirsize, events, extract, favorites, grep, handlers, hashes, health, jobs, locks,
+    manifest, markdown, media, meta, models, share, stats, tail, trash, undo, usage, AppState,
+};
+
+/// OpenAPI document for all `/api` routes
+///
+/// Paths come from the `#[utoipa::path]` annotations on the handlers; the annotations only
+/// list the success responses, the shared error responses are added by [`ErrorResponses`].
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Filest API",
+        description = r#"Every JSON response is wrapped as `{"success": true, ...}`, with the payload fields inlined
+next to `success`. Failures carry `success: false`, a human-readable `error` and a stable
+`code` (see the `ErrorBody` schema) and use a matching HTTP status. Messages are in Chinese or
+English according to `Accept-Language`; codes never change.
+
+Paths are logical paths starting at `/`; with several mounts the first segment is the mount name.
+
+**Chunked upload** - for files above `chunkedThreshold` from `/api/capabilities`:
+
+1. `POST /api/upload/init` with the target folder, file name, total size, chunk size and number
+   of chunks; returns an `uploadId`.
+2. `POST /api/upload/chunk?uploadId=...&chunkIndex=N` once per chunk (0-based, any order, retries
+   allowed), with the chunk data as the first multipart field. Chunks except the last must be
+   exactly `chunkSize` bytes.
+3. `POST /api/upload/complete` with the `uploadId` merges the chunks into the target file, or
+   `POST /api/upload/abort` discards them. Unfinished sessions are removed at shutdown.
+
+Share links (`/s/{token}`) are meant for browsers and are not part of this document."#,
+    ),
+    paths(
+        handlers::get_files,
+        handlers::create_folder,
+        handlers::create_file,
+        handlers::upload_files,
+        handlers::download_file,
+        archive::download_batch,
+        archive::create_archive,
+        extract::extract,
+        extract::list_archive,
+        extract::get_archive_entry,
+        handlers::rename,
+        handlers::move_file,
+        handlers::copy_file,
+        handlers::delete_file,
+        handlers::delete_batch,
+        trash::list_trash,
+        trash::restore_trash,
+        trash::purge_trash,
+        undo::list_undo,
+        undo::undo,
+        handlers::get_info,
+        dirsize::size_job,
+        usage::usage_report,
+        stats::file_stats,
+        jobs::job_status,
+        jobs::job_by_token,
+        jobs::cancel_job,
+        jobs::job_events,
+        manifest::manifest,
+        handlers::get_folders,
+        handlers::get_disk_info,
+        handlers::search_files,
+        grep::search_content,
+        events::watch_events,
+        activity::ws_events,
+        capabilities::capabilities,
+        hashes::check_uploads,
+        handlers::chunked_upload_init,
+        handlers::chunked_upload_chunk,
+        handlers::chunked_upload_complete,
+        handlers::chunked_upload_abort,
+        handlers::list_upload_sessions,
+        handlers::cancel_upload_session,
+        handlers::login,
+        handlers::logout,
+        handlers::create_session,
+        handlers::delete_session,
+        health::health,
+        markdown::render_markdown,
+        media::media_info,
+        media::image,
+        media::stream,
+        tail::tail,
+        content::get_content,
+        content::put_content,
+        content::patch_content,
+        content::append_content,
+        meta::get_meta,
+        meta::set_meta,
+        favorites::list_favorites,
+        favorites::add_favorite,
+        favorites::remove_favorite,
+        locks::acquire_lock,
+        locks::release_lock,
+        locks::get_locks,
+        clipboard::set_clipboard,
+        clipboard::get_clipboard,
+        clipboard::paste,
+        share::create_share,
+        share::list_shares,
+        share::revoke_share,
+        apikeys::create_key,
+        apikeys::list_keys,
+        apikeys::revoke_key,
+        audit::query_audit,
+        openapi_json,
+    ),
+    components(schemas(
+        ErrorBody, UploadForm, ChunkForm, models::ShareKind, models::DeleteConfirmation, models::SearchMode,
+        models::SearchType, models::EntryType, models::ListingSort, models::SortOrder,
+    )),
+    modifiers(&SecuritySchemes, &ErrorResponses),
+    security(("basic" = []), ("bearer" = []), ("apiKey" = []), ("session" = [])),
+    tags(
+        (name = "files", description = "Browse and manage files"),
+        (name = "upload", description = "Plain and chunked upload"),
+        (name = "auth", description = "Bearer tokens and browser sessions"),
+        (name = "share", description = "Share links"),
+        (name = "admin", description = "API keys, the audit log and upload sessions (administrator only)"),
+        (name = "system", description = "Health, limits and this document"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Body of every failed request
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ErrorBody {
+    /// Always `false`
+    success: bool,
+    error: String,
+    /// `INVALID_PATH`, `INVALID_FILE_NAME`, `BAD_REQUEST`, `ACCESS_DENIED`, `READ_ONLY`, `NOT_FOUND`, `ALREADY_EXISTS`,
+    /// `PAYLOAD_TOO_LARGE`, `QUOTA_EXCEEDED`, `IO_ERROR`, or an endpoint-specific code
+    code: Option<String>,
+}
+
+/// Multipart body of `POST /api/upload`
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadForm {
+    /// Target folder, unless given as `?path=`; must come before the files
+    path: Option<String>,
+    /// Modification time, in epoch milliseconds, for the file part right after it
+    #[schema(rename = "lastModified")]
+    last_modified: Option<i64>,
+    /// One part per file, stored under its file name
+    #[schema(value_type = Vec<String>, format = Binary)]
+    files: Vec<Vec<u8>>,
+}
+
+/// Multipart body of `POST /api/upload/chunk`
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ChunkForm {
+    /// Chunk data; only the first part is read
+    #[schema(value_type = String, format = Binary)]
+    chunk: Vec<u8>,
+}
+
+/// Authentication methods accepted by the auth middleware
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Basic).build()),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("Token from /api/login, or an API key"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "apiKey",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(crate::apikeys::API_KEY_HEADER))),
+        );
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(crate::auth::SESSION_COOKIE))),
+        );
+    }
+}
+
+/// Add the error responses shared by all operations
+struct ErrorResponses;
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let error = |description: &str| -> RefOr<Response> {
+            ResponseBuilder::new()
+                .description(description)
+                .content(
+                    "application/json",
+                    utoipa::openapi::ContentBuilder::new()
+                        .schema(Some(utoipa::openapi::Ref::from_schema_name("ErrorBody")))
+                        .build(),
+                )
+                .build()
+                .into()
+        };
+        let global_security = openapi.security.is_some();
+        for item in openapi.paths.paths.values_mut() {
+            let operations = [
+                &mut item.get,
+                &mut item.post,
+                &mut item.put,
+                &mut item.delete,
+            ];
+            for operation in operations.into_iter().flatten() {
+                add_error_responses(operation, global_security, &error);
+            }
+        }
+    }
+}
+
+fn add_error_responses(
+    operation: &mut Operation,
+    global_security: bool,
+    error: &impl Fn(&str) -> RefOr<Response>,
+) {
+    // `security(())` on an operation marks it public
+    let public = operation
+        .security
+        .as_ref()
+        .is_some_and(|s| s.iter().all(|r| *r == Default::default()));
+    let responses = &mut operation.responses.responses;
+    if global_security && !public {
+        responses
+            .entry("401".to_string())
+            .or_insert_with(|| error("Missing or invalid credentials"));
+    }
+    responses
+        .entry("default".to_string())
+        .or_insert_with(|| error("Error, see `code`"));
+}
+
+/// The OpenAPI document, with the server URL set to `--base-path`
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "system",
+    responses((status = 200, description = "OpenAPI 3.1 document", content_type = "application/json")),
+)]
+pub async fn openapi_json(State(state): State<AppState>) -> Json<utoipa::openapi::OpenApi> {
+    let mut doc = ApiDoc::openapi();
+    if !state.base_path.is_empty() {
+        doc.servers = Some(vec![Server::new(state.base_path.as_str())]);
+    }
+    Json(doc)
+}
+
+/// Swagger UI at `/api/docs/`, loading the document from `/api/openapi.json`
+///
+/// Routed by hand rather than through `SwaggerUi`'s router, whose `/api/docs` redirect
+/// ignores `--base-path`.
+#[cfg(feature = "swagger-ui")]
+pub fn swagger_ui(base_path: &str) -> axum::Router<AppState> {
+    use axum::{response::Redirect, routing::get, Extension};
+    use std::sync::Arc;
+    use utoipa_swagger_ui::Config;
+
+    let config = Arc::new(Config::new([format!("{}/api/openapi.json", base_path)]));
+    let index = format!("{}/api/docs/", base_path);
+    axum::Router::new()
+        .route("/docs", get(move || async move { Redirect::to(&index) }))
+        .route("/docs/", get(swagger_file))
+        .route("/docs/{*file}", get(swagger_file))
+        .layer(Extension(config))
+}
+
+#[cfg(feature = "swagger-ui")]
+async fn swagger_file(
+    file: Option<axum::extract::Path<String>>,
+    axum::Extension(config): axum::Extension<std::sync::Arc<utoipa_swagger_ui::Config<'static>>>,
+) -> axum::response::Response {
+    use axum::{http::{header, StatusCode}, response::IntoResponse};
+
+    let file = file.map(|f| f.0).unwrap_or_default();
+    match utoipa_swagger_ui::serve(&file, config) {
+        Ok(Some(file)) => ([(header::CONTENT_TYPE, file.content_type)], file.bytes.into_owned()).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::{Lang, Msg};
+    use crate::testing::{admin, TestApp};
+    use axum::{body::Body, http::{header, Method, StatusCode}};
+    use std::collections::BTreeSet;
+
+    /// Routes served but left out of the document on purpose
+    const UNDOCUMENTED: &[(&str, &str)] = &[
+        // Former path of /api/admin/uploads
+        ("get", "/api/upload/sessions"),
+    ];
+
+    /// `(method, path)` of every operation in the document
+    fn documented() -> BTreeSet<(String, String)> {
+        let doc = ApiDoc::openapi();
+        let mut operations = BTreeSet::new();
+        for (path, item) in &doc.paths.paths {
+            let methods = [
+                ("get", &item.get),
+                ("post", &item.post),
+                ("put", &item.put),
+                ("patch", &item.patch),
+                ("delete", &item.delete),
+            ];
+            for (method, operation) in methods {
+                if operation.is_some() {
+                    operations.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+        operations
+    }
+
+    /// `(method, path)` of every `/api` route `build_app` registers, read from its source
+    fn routed() -> BTreeSet<(String, String)> {
+        let source = include_str!("main.rs");
+        let start = source.find("let docs_routes = Router::new()").unwrap();
+        let end = start + source[start..].find("// WebDAV").unwrap();
+        let mut routes = BTreeSet::new();
+        for call in source[start..end].split(".route(").skip(1) {
+            let path = call.split('"').nth(1).unwrap();
+            for method in ["get", "post", "put", "patch", "delete"] {
+                let called = call.match_indices(&format!("{method}(")).any(|(i, _)| {
+                    !call[..i].ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+                });
+                if called {
+                    routes.insert((method.to_string(), format!("/api{path}")));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn every_route_is_documented_and_every_operation_routed() {
+        let documented = documented();
+        let mut routed = routed();
+        assert!(routed.len() > 50, "routes not found in main.rs: {routed:?}");
+        for (method, path) in UNDOCUMENTED {
+            assert!(routed.remove(&(method.to_string(), path.to_string())), "{method} {path} no longer routed");
+        }
+        let missing: Vec<_> = routed.difference(&documented).collect();
+        assert!(missing.is_empty(), "routed but not documented: {missing:?}");
+        let stale: Vec<_> = documented.difference(&routed).collect();
+        assert!(stale.is_empty(), "documented but not routed: {stale:?}");
+    }
+
+    #[tokio::test]
+    async fn documented_operations_reach_a_handler() {
+        let app = TestApp::with(|_| {});
+        let fallback = Msg::ApiNotFound.text_in(Lang::En);
+        for (method, path) in documented() {
+            let uri = path.replace(['{', '}'], "");
+            let request = admin(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap(), &uri)
+                .header(header::ACCEPT_LANGUAGE, "en")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.send(request).await;
+            let status = response.status();
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+            if status == StatusCode::NOT_FOUND {
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                assert_ne!(body["error"], fallback, "{method} {path}");
+            }
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tokio::fs;
+use unicode_normalization::UnicodeNormalization;
+use crate::error::ApiError;
+use crate::grep::REGEX_SIZE_LIMIT;
+use crate::i18n::Msg;
+use crate::models::SearchMode;
+
+/// Separators accepted in user paths; `\` is a separator on every platform
+pub(crate) const PATH_SEPARATORS: [char; 2] = ['/', '\\'];
+/// Windows device names, reserved with any extension (`NUL.txt`)
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Prefix of the temporary files filest itself creates inside the roots
+pub const INTERNAL_FILE_PREFIX: &str = ".filest_";
+/// Chunks of chunked uploads, below the system temp dir
+static UPLOAD_TEMP_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    let temp = std::env::temp_dir();
+    temp.canonicalize().unwrap_or(temp).join("filest_uploads")
+});
+/// Longest file name most filesystems accept, in bytes
+pub const DEFAULT_MAX_FILENAME_BYTES: usize = 255;
+
+/// Characters left as-is in a name used as a URL path segment; `#`, `?`, `%` and the
+/// like are percent-encoded
+const URL_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// A logical path as a URL path below `prefix`, each name percent-encoded
+///
+/// Folders end with a slash, as WebDAV clients and relative links expect.
+pub(crate) fn url_path(prefix: &str, logical: &str, is_dir: bool) -> String {
+    let mut url = prefix.to_string();
+    for segment in logical.split('/').filter(|s| !s.is_empty()) {
+        url.push('/');
+        url.extend(utf8_percent_encode(segment, URL_SEGMENT));
+    }
+    if is_dir {
+        url.push('/');
+    }
+    url
+}
+
+/// Where a chunked upload keeps its chunks until they are merged
+pub(crate) fn upload_temp_dir(upload_id: &str) -> PathBuf {
+    UPLOAD_TEMP_DIR.join(upload_id)
+}
+
+/// Whether a path is one of filest's own files rather than user content
+///
+/// Covers the `.filest_` temporary files written inside the roots (and anything below
+/// such a name) and the chunk folders of chunked uploads, which show up in a root that
+/// contains the system temp dir. Listings, search and tree walks leave them out, and
+/// the file operations refuse them.
+pub(crate) fn is_internal_artifact(logical: &str, actual: &Path) -> bool {
+    logical.split('/').any(|name| name.starts_with(INTERNAL_FILE_PREFIX)) || actual.starts_with(&*UPLOAD_TEMP_DIR)
+}
+
+/// Whether a path component is a drive such as `C:`
+fn is_drive(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Refuse user paths naming a drive (`C:\x`, `C:x`) or a UNC share (`\\server\share`)
+///
+/// They are rejected rather than interpreted: pushed onto a `PathBuf` on Windows they
+/// would replace the root instead of descending into it.
+pub(crate) fn check_no_prefix(user_path: &str) -> Result<(), ApiError> {
+    let unc = user_path.starts_with("\\\\") || user_path.starts_with("\\/") || user_path.starts_with("/\\");
+    let drive = user_path.split(PATH_SEPARATORS).find(|c| !c.is_empty()).is_some_and(is_drive);
+    // On Windows any `:` starts a drive-relative path (`C:x`) or an alternate data stream
+    let colon = cfg!(windows) && user_path.contains(':');
+    if unc || drive || colon {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    }
+    Ok(())
+}
+
+/// Unicode normalization form applied to incoming names (`--normalize-names`)
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NameForm {
+    /// Composed (`é` as one code point), as typed on Linux and Windows; macOS sends NFD
+    Nfc,
+}
+
+/// Which names are accepted for new files and folders (`--max-filename-bytes`, `--deny-dot-files`)
+#[derive(Clone, Copy)]
+pub struct NameRules {
+    /// Longest name in bytes of UTF-8, not characters
+    pub max_bytes: usize,
+    /// Refuse names starting with `.`
+    pub deny_dot_files: bool,
+    /// Normalize names before they are written
+    pub normalize: Option<NameForm>,
+}
+
+impl NameRules {
+    /// [`validate_filename`], with the refusal turned into an API error naming the file
+    pub fn check(&self, name: &str) -> Result<(), ApiError> {
+        validate_filename(name, self).map_err(|e| e.into_api_error(name, self))
+    }
+
+    /// Normalize a client-supplied name per `--normalize-names`, then validate it
+    pub fn prepare(&self, name: &str) -> Result<String, ApiError> {
+        let name = match self.normalize {
+            Some(NameForm::Nfc) => name.nfc().collect(),
+            None => name.to_string(),
+        };
+        self.check(&name)?;
+        Ok(name)
+    }
+}
+
+/// Whether two names are the same up to Unicode normalization (`café` in NFC and NFD)
+pub fn same_name(a: &str, b: &str) -> bool {
+    a == b || a.nfc().eq(b.nfc())
+}
+
+/// Order of names as people expect it: case ignored, and runs of digits compared by
+/// value, so `img2.jpg` comes before `img10.jpg`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let order = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => break,
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (digits(&mut a_chars), digits(&mut b_chars));
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            (Some(x), Some(y)) => {
+                let order = x.to_lowercase().cmp(y.to_lowercase());
+                a_chars.next();
+                b_chars.next();
+                order
+            }
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    // The same but for case or leading zeros, still in a fixed order
+    a.cmp(b)
+}
+
+/// The run of ASCII digits `chars` starts with
+fn digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// Form of a name compared by search: lowercase, then NFC
+pub fn search_key(name: &str) -> String {
+    name.to_lowercase().nfc().collect()
+}
+
+/// What a name search looks for, per `mode` of `/api/search`; case is ignored in every mode
+#[derive(Clone)]
+pub enum NameMatcher {
+    /// The name contains this `search_key`
+    Substring(String),
+    /// The whole name matches a glob such as `*.log`
+    Glob(globset::GlobMatcher),
+    /// A regular expression matches somewhere in the name, such as `^report_\d{4}`
+    Regex(regex::Regex),
+}
+
+impl NameMatcher {
+    /// Compile `query` for `mode`; an empty query matches every name whatever the mode
+    pub fn new(mode: SearchMode, query: &str) -> Result<Self, ApiError> {
+        if query.is_empty() {
+            return Ok(Self::Substring(String::new()));
+        }
+        Ok(match mode {
+            SearchMode::Substring => Self::Substring(search_key(query)),
+            SearchMode::Glob => globset::GlobBuilder::new(&query.nfc().collect::<String>())
+                .case_insensitive(true)
+                .build()
+                .map(|glob| Self::Glob(glob.compile_matcher()))
+                .map_err(|e| ApiError::BadRequest(Msg::InvalidSearchGlob.with(&[&e])))?,
+            SearchMode::Regex => regex::RegexBuilder::new(query)
+                .case_insensitive(true)
+                .size_limit(REGEX_SIZE_LIMIT)
+                .build()
+                .map(Self::Regex)
+                .map_err(|e| ApiError::BadRequest(Msg::InvalidSearchRegex.with(&[&e])))?,
+        })
+    }
+
+    /// Whether every name matches, so there is nothing to filter
+    pub fn matches_all(&self) -> bool {
+        matches!(self, Self::Substring(part) if part.is_empty())
+    }
+
+    /// Whether the name with this `search_key` matches
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            Self::Substring(part) => key.contains(part.as_str()),
+            Self::Glob(glob) => glob.is_match(key),
+            Self::Regex(regex) => regex.is_match(key),
+        }
+    }
+}
+
+/// The entry of `dir` named `name` up to normalization, as it is stored on disk
+///
+/// Conflict checks use this rather than `exists()`, so a name sent in NFD finds the file
+/// stored in NFC instead of creating a second entry that looks identical.
+pub(crate) async fn existing_name(dir: &Path, name: impl AsRef<OsStr>) -> Option<OsString> {
+    let name = name.as_ref();
+    if fs::symlink_metadata(dir.join(name)).await.is_ok() {
+        return Some(name.to_os_string());
+    }
+    let name = name.to_str()?;
+    let mut entries = fs::read_dir(dir).await.ok()?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let stored = entry.file_name();
+        if stored.to_str().is_some_and(|s| same_name(s, name)) {
+            return Some(stored);
+        }
+    }
+    None
+}
+
+/// Why a file name was refused
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileNameError {
+    Empty,
+    /// `.` or `..`
+    DotEntry,
+    /// Contains `/` or `\`
+    Separator,
+    /// Contains NUL, a newline or another control character
+    ControlChar,
+    TooLong,
+    /// Starts with `.` while `--deny-dot-files` is set
+    DotFile,
+    /// Invalid on Windows: trailing dot or space, or one of `<>:"|?*`
+    WindowsChar,
+    /// A Windows device name such as `CON` or `nul.txt`
+    Reserved,
+}
+
+impl FileNameError {
+    /// All refusals share the `INVALID_FILE_NAME` code; the message says which rule failed
+    pub fn into_api_error(self, name: &str, rules: &NameRules) -> ApiError {
+        let message = match self {
+            Self::TooLong => Msg::FileNameTooLong.with(&[&rules.max_bytes, &name]),
+            Self::DotFile => Msg::DotFileNotAllowed.with(&[&name]),
+            Self::Reserved => Msg::ReservedFileName.with(&[&name]),
+            _ => Msg::InvalidFileName.with(&[&name.escape_debug()]),
+        };
+        ApiError::InvalidFileName(message)
+    }
+}
+
+/// Validate a single name for a new file or folder (upload, rename, create folder, copy)
+///
+/// The rules of Windows filesystems apply when built for Windows.
+pub fn validate_filename(name: &str, rules: &NameRules) -> Result<(), FileNameError> {
+    if name.is_empty() {
+        return Err(FileNameError::Empty);
+    }
+    if name == "." || name == ".." {
+        return Err(FileNameError::DotEntry);
+    }
+    if name.contains(PATH_SEPARATORS) {
+        return Err(FileNameError::Separator);
+    }
+    if name.chars().any(char::is_control) {
+        return Err(FileNameError::ControlChar);
+    }
+    if name.len() > rules.max_bytes {
+        return Err(FileNameError::TooLong);
+    }
+    if rules.deny_dot_files && name.starts_with('.') {
+        return Err(FileNameError::DotFile);
+    }
+    if cfg!(windows) {
+        if name.ends_with(['.', ' ']) || name.contains(['<', '>', ':', '"', '|', '?', '*']) {
+            return Err(FileNameError::WindowsChar);
+        }
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if WINDOWS_RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+            return Err(FileNameError::Reserved);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: NameRules = NameRules { max_bytes: DEFAULT_MAX_FILENAME_BYTES, deny_dot_files: false, normalize: None };
+
+    #[test]
+    fn ordinary_names_are_accepted() {
+        for name in ["report.pdf", "a", "..hidden", "name with spaces", "日本語.txt", ".env"] {
+            assert_eq!(validate_filename(name, &RULES), Ok(()), "{name}");
+        }
+    }
+
+    #[test]
+    fn empty_and_dot_entries_are_refused() {
+        assert_eq!(validate_filename("", &RULES), Err(FileNameError::Empty));
+        assert_eq!(validate_filename(".", &RULES), Err(FileNameError::DotEntry));
+        assert_eq!(validate_filename("..", &RULES), Err(FileNameError::DotEntry));
+    }
+
+    #[test]
+    fn separators_and_control_characters_are_refused() {
+        assert_eq!(validate_filename("a/b", &RULES), Err(FileNameError::Separator));
+        assert_eq!(validate_filename("a\\b", &RULES), Err(FileNameError::Separator));
+        assert_eq!(validate_filename("../x", &RULES), Err(FileNameError::Separator));
+        assert_eq!(validate_filename("a\0b", &RULES), Err(FileNameError::ControlChar));
+        assert_eq!(validate_filename("line\nbreak", &RULES), Err(FileNameError::ControlChar));
+    }
+
+    #[test]
+    fn length_is_counted_in_bytes() {
+        assert_eq!(validate_filename(&"a".repeat(255), &RULES), Ok(()));
+        assert_eq!(validate_filename(&"a".repeat(256), &RULES), Err(FileNameError::TooLong));
+        // 86 three-byte characters are 258 bytes
+        assert_eq!(validate_filename(&"日".repeat(86), &RULES), Err(FileNameError::TooLong));
+        let short = NameRules { max_bytes: 4, ..RULES };
+        assert_eq!(validate_filename("abcde", &short), Err(FileNameError::TooLong));
+    }
+
+    #[test]
+    fn dot_files_are_refused_only_when_denied() {
+        let rules = NameRules { deny_dot_files: true, ..RULES };
+        assert_eq!(validate_filename(".env", &rules), Err(FileNameError::DotFile));
+        let trailing_dot = if cfg!(windows) { Err(FileNameError::WindowsChar) } else { Ok(()) };
+        assert_eq!(validate_filename("env.", &rules), trailing_dot);
+    }
+
+    #[test]
+    fn windows_names_follow_the_platform() {
+        let expected = |error| if cfg!(windows) { Err(error) } else { Ok(()) };
+        assert_eq!(validate_filename("a:b", &RULES), expected(FileNameError::WindowsChar));
+        assert_eq!(validate_filename("trailing ", &RULES), expected(FileNameError::WindowsChar));
+        assert_eq!(validate_filename("CON", &RULES), expected(FileNameError::Reserved));
+        assert_eq!(validate_filename("nul.txt", &RULES), expected(FileNameError::Reserved));
+        assert_eq!(validate_filename("console", &RULES), Ok(()));
+    }
+
+    #[test]
+    fn internal_artifacts_are_recognized_anywhere_in_a_path() {
+        let actual = Path::new("/srv/files/x");
+        for logical in ["/.filest_upload_1.tmp", "/a/.filest_trash", "/.filest_trash/a/b.txt", "/a/.filest_x/b"] {
+            assert!(is_internal_artifact(logical, actual), "{logical}");
+        }
+        for logical in ["/", "/a/b.txt", "/a/filest_x", "/a/x.filest_", "/a/.filest"] {
+            assert!(!is_internal_artifact(logical, actual), "{logical}");
+        }
+        assert!(is_internal_artifact("/a", &upload_temp_dir("abc").join("chunk_0")));
+    }
+}
@@ -0,0 +1,68 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Compiled `--hide` / `--protect` glob patterns
+///
+/// Patterns without a `/` match a single name at any depth (`.env`, `*.key`);
+/// patterns with a `/` match the logical path from the root (`secrets/**`, `/config/*.toml`).
+/// A path matches when it or any of its ancestors matches, so a matched directory
+/// covers its whole subtree.
+pub struct PathPatterns {
+    names: GlobSet,
+    paths: GlobSet,
+    empty: bool,
+}
+
+impl PathPatterns {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            let trimmed = pattern.trim().trim_end_matches('/');
+            let (builder, glob) = match trimmed.strip_prefix('/') {
+                Some(rest) => (&mut paths, rest),
+                None if trimmed.contains('/') => (&mut paths, trimmed),
+                None => (&mut names, trimmed),
+            };
+            if glob.is_empty() {
+                return Err(format!("Invalid pattern {:?}", pattern));
+            }
+            let glob = GlobBuilder::new(glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("Invalid pattern {:?}: {}", pattern, e))?;
+            builder.add(glob);
+        }
+
+        Ok(Self {
+            names: names.build().map_err(|e| e.to_string())?,
+            paths: paths.build().map_err(|e| e.to_string())?,
+            empty: patterns.is_empty(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    /// Whether the logical path ("/a/b") or one of its ancestors matches
+    pub fn matches(&self, path: &str) -> bool {
+        if self.empty {
+            return false;
+        }
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return false;
+        }
+        let mut start = 0;
+        loop {
+            let end = path[start..].find('/').map_or(path.len(), |i| start + i);
+            if self.names.is_match(&path[start..end]) || self.paths.is_match(&path[..end]) {
+                return true;
+            }
+            if end == path.len() {
+                return false;
+            }
+            start = end + 1;
+        }
+    }
+}
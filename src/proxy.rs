@@ -0,0 +1,253 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// An address or CIDR range in `--trusted-proxies`, such as `10.0.0.0/8` or `::1`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        parse_range(&text)
+    }
+}
+
+/// Parse `10.0.0.0/8`, `fd00::/8` or a single address such as `127.0.0.1`
+pub fn parse_range(text: &str) -> Result<IpRange, String> {
+    let invalid = || format!("Invalid proxy address {:?}, expected an IP address or CIDR range such as 10.0.0.0/8", text);
+    let (addr, prefix) = match text.trim().split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (text.trim(), None),
+    };
+    let network = addr.parse::<IpAddr>().map_err(|_| invalid())?.to_canonical();
+    let bits = if network.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().ok().filter(|&p| p <= bits).ok_or_else(invalid)?,
+        None => bits,
+    };
+    Ok(IpRange { network, prefix })
+}
+
+impl IpRange {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The proxies whose forwarding headers are believed (`--trusted-proxies`)
+///
+/// With none, forwarding headers are ignored and the socket peer is the client.
+#[derive(Clone, Default)]
+pub struct TrustedProxies(Arc<[IpRange]>);
+
+impl TrustedProxies {
+    pub fn new(ranges: &[IpRange]) -> Self {
+        Self(ranges.into())
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// The address of the client behind `peer`
+    ///
+    /// `X-Forwarded-For` is read from the right, each hop appended by the proxy before it:
+    /// trusted hops are skipped and the first untrusted one is the client, since anything
+    /// further left could have been made up by it. Without that header a trusted peer's
+    /// `X-Real-IP` is used. A hop that isn't an address ends the walk at the last trusted
+    /// one.
+    pub fn client(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.trusts(peer) {
+            return peer;
+        }
+        let hops: Vec<&str> = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|hop| !hop.is_empty())
+            .collect();
+        if hops.is_empty() {
+            return headers
+                .get(X_REAL_IP)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_hop)
+                .unwrap_or(peer);
+        }
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            let Some(ip) = parse_hop(hop) else {
+                break;
+            };
+            client = ip;
+            if !self.trusts(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// A forwarded address, which some proxies send with a port (`203.0.113.7:51234`, `[2001:db8::1]:443`)
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    let ip = hop.parse::<IpAddr>().or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip())).ok()?;
+    Some(ip.to_canonical())
+}
+
+/// Middleware putting the real client address in `ConnectInfo` for everything after it
+///
+/// Handlers, audit entries, lockouts, rate limits and logs all take the client address
+/// from `ConnectInfo`, so this runs first and replaces the proxy's address there. The
+/// peer's port is kept.
+pub async fn resolve_client(State(proxies): State<TrustedProxies>, mut request: Request, next: Next) -> Response {
+    if let Some(ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>().copied() {
+        let client = proxies.client(peer.ip(), request.headers());
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(client, peer.port())));
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn proxies(ranges: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(&ranges.iter().map(|r| parse_range(r).unwrap()).collect::<Vec<_>>())
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn headers_with(name: &'static str, value: &str) -> HeaderMap {
+        headers(&[(name, value)])
+    }
+
+    #[test]
+    fn ranges_parse_with_and_without_a_prefix() {
+        assert_eq!(parse_range("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(parse_range(" 127.0.0.1 ").unwrap().to_string(), "127.0.0.1/32");
+        assert_eq!(parse_range("fd00::/8").unwrap().to_string(), "fd00::/8");
+        assert_eq!(parse_range("::1").unwrap().to_string(), "::1/128");
+        // An IPv4-mapped address is the IPv4 address
+        assert_eq!(parse_range("::ffff:10.1.2.3").unwrap().to_string(), "10.1.2.3/32");
+    }
+
+    #[test]
+    fn invalid_ranges_are_refused() {
+        for text in ["", "10.0.0.0/33", "::/129", "10.0.0.0/x", "proxy.local", "10.0.0/8"] {
+            assert!(parse_range(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn ranges_contain_their_addresses_only() {
+        let range = parse_range("10.0.0.0/8").unwrap();
+        assert!(range.contains(ip("10.255.0.1")));
+        assert!(range.contains(ip("::ffff:10.0.0.1")));
+        assert!(!range.contains(ip("11.0.0.1")));
+        assert!(!range.contains(ip("fd00::1")));
+        assert!(parse_range("0.0.0.0/0").unwrap().contains(ip("203.0.113.7")));
+        assert!(parse_range("fd00::/8").unwrap().contains(ip("fd12::1")));
+        assert!(!parse_range("fd00::/8").unwrap().contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn headers_from_an_untrusted_peer_are_ignored() {
+        let headers = headers(&[(X_FORWARDED_FOR, "1.2.3.4"), (X_REAL_IP, "5.6.7.8")]);
+        assert_eq!(proxies(&[]).client(ip("203.0.113.7"), &headers), ip("203.0.113.7"));
+        assert_eq!(proxies(&["10.0.0.0/8"]).client(ip("203.0.113.7"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for_is_read_from_the_right_past_trusted_hops() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+        // The leftmost entry was sent by the client and isn't believed
+        let one = headers_with(X_FORWARDED_FOR, "6.6.6.6, 203.0.113.7, 10.0.0.2");
+        assert_eq!(proxies.client(ip("10.0.0.1"), &one), ip("203.0.113.7"));
+        // Hops split over several headers count in order
+        let split = headers(&[(X_FORWARDED_FOR, "6.6.6.6, 203.0.113.7"), (X_FORWARDED_FOR, "10.0.0.2")]);
+        assert_eq!(proxies.client(ip("10.0.0.1"), &split), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn hops_may_carry_a_port() {
+        let proxies = proxies(&["10.0.0.1"]);
+        let headers = headers_with(X_FORWARDED_FOR, "[2001:db8::1]:443");
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("2001:db8::1"));
+        let headers = headers_with(X_FORWARDED_FOR, "203.0.113.7:51234");
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn an_invalid_hop_stops_at_the_last_trusted_one() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+        let headers = headers_with(X_FORWARDED_FOR, "203.0.113.7, garbage, 10.0.0.2");
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn all_trusted_hops_give_the_leftmost() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+        let headers = headers_with(X_FORWARDED_FOR, "10.0.0.3, 10.0.0.2");
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn real_ip_is_used_without_forwarded_for() {
+        let proxies = proxies(&["127.0.0.1"]);
+        assert_eq!(proxies.client(ip("127.0.0.1"), &headers_with(X_REAL_IP, "203.0.113.7")), ip("203.0.113.7"));
+        assert_eq!(proxies.client(ip("127.0.0.1"), &headers_with(X_REAL_IP, "nonsense")), ip("127.0.0.1"));
+        assert_eq!(proxies.client(ip("::ffff:127.0.0.1"), &HeaderMap::new()), ip("127.0.0.1"));
+    }
+}
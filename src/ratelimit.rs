@@ -0,0 +1,306 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body::{Frame, SizeHint};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use crate::auth::client_ip;
+use crate::i18n::Msg;
+use crate::models::{ApiResponse, RateLimitInfo};
+
+/// How often clients with nothing in flight and no recent requests are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// At most `requests` requests per `window`, as in `--rate-limit 100/10s`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RateLimit {
+    pub requests: usize,
+    pub window: Duration,
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}s", self.requests, self.window.as_secs())
+    }
+}
+
+impl From<RateLimit> for String {
+    fn from(rate: RateLimit) -> Self {
+        rate.to_string()
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        parse_rate(&text)
+    }
+}
+
+/// Parse `100/10s`, `600/m` or `5000/1h`; the window is whole seconds
+pub fn parse_rate(text: &str) -> Result<RateLimit, String> {
+    let invalid = || format!("Invalid rate limit {:?}, expected requests/window such as 100/10s", text);
+    let (requests, window) = text.trim().split_once('/').ok_or_else(invalid)?;
+    let requests: usize = requests.trim().parse().map_err(|_| invalid())?;
+    let window = window.trim();
+    let split = window.find(|c: char| !c.is_ascii_digit()).unwrap_or(window.len());
+    let (count, unit) = window.split_at(split);
+    let count: u64 = if count.is_empty() { 1 } else { count.parse().map_err(|_| invalid())? };
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => return Err(invalid()),
+    };
+    if requests == 0 || count == 0 {
+        return Err(invalid());
+    }
+    Ok(RateLimit { requests, window: Duration::from_secs(count * unit_secs) })
+}
+
+#[derive(Default)]
+struct Client {
+    in_flight: usize,
+    /// Start times of the requests in the current window, oldest first
+    recent: VecDeque<Instant>,
+}
+
+/// Per-IP limits on requests in flight (`--max-conns-per-ip`) and request rate (`--rate-limit`)
+///
+/// Clients are told apart by `auth::client_ip`, the address everything else uses. A
+/// request counts as in flight until its response body has been sent, so long downloads
+/// and event streams hold their place. Rates use a sliding window of request start times.
+pub struct ClientLimits {
+    max_in_flight: Option<usize>,
+    rate: Option<RateLimit>,
+    clients: Mutex<HashMap<IpAddr, Client>>,
+}
+
+/// A request's place among its client's requests in flight, given back on drop
+struct InFlight {
+    limits: Arc<ClientLimits>,
+    ip: IpAddr,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut clients = self.limits.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(client) = clients.get_mut(&self.ip) {
+            client.in_flight = client.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// A response body that keeps its request counted as in flight until it's done
+struct HeldBody {
+    body: Body,
+    _in_flight: InFlight,
+}
+
+impl HttpBody for HeldBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Why a request was turned away, and when to try again
+enum Refusal {
+    TooManyConnections,
+    RateLimited(Duration),
+}
+
+impl ClientLimits {
+    pub fn new(max_in_flight: Option<usize>, rate: Option<RateLimit>) -> Self {
+        Self { max_in_flight, rate, clients: Mutex::new(HashMap::new()) }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.max_in_flight.is_some() || self.rate.is_some()
+    }
+
+    /// The limits as reported by `/api/capabilities`
+    pub fn info(&self) -> (Option<usize>, Option<RateLimitInfo>) {
+        let rate = self.rate.map(|rate| RateLimitInfo {
+            requests: rate.requests,
+            window_secs: rate.window.as_secs(),
+        });
+        (self.max_in_flight, rate)
+    }
+
+    /// Count a new request from `ip`, unless it goes over a limit
+    fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<InFlight, Refusal> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let client = clients.entry(ip).or_default();
+        if self.max_in_flight.is_some_and(|max| client.in_flight >= max) {
+            return Err(Refusal::TooManyConnections);
+        }
+        if let Some(rate) = self.rate {
+            while client.recent.front().is_some_and(|&at| now.duration_since(at) >= rate.window) {
+                client.recent.pop_front();
+            }
+            if client.recent.len() >= rate.requests {
+                let oldest = client.recent.front().copied().unwrap_or(now);
+                return Err(Refusal::RateLimited(rate.window.saturating_sub(now.duration_since(oldest))));
+            }
+            client.recent.push_back(now);
+        }
+        client.in_flight += 1;
+        Ok(InFlight { limits: self.clone(), ip })
+    }
+
+    /// Forget clients with nothing in flight and no request in the current window
+    fn prune(&self) {
+        let now = Instant::now();
+        let window = self.rate.map_or(Duration::ZERO, |rate| rate.window);
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.retain(|_, client| {
+            client.in_flight > 0 || client.recent.back().is_some_and(|&at| now.duration_since(at) < window)
+        });
+    }
+}
+
+/// Prune idle clients every `PRUNE_INTERVAL` until shutdown
+pub fn start_pruning(limits: Arc<ClientLimits>, shutdown: CancellationToken) {
+    if !limits.is_enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => limits.prune(),
+            }
+        }
+    });
+}
+
+/// Middleware applying `ClientLimits`; over-limit requests get a 429 with `Retry-After`
+pub async fn limit_clients(State(limits): State<Arc<ClientLimits>>, request: Request, next: Next) -> Response {
+    if !limits.is_enabled() {
+        return next.run(request).await;
+    }
+    let (code, message, retry_after) = match limits.admit(client_ip(&request)) {
+        Ok(in_flight) => {
+            let response = next.run(request).await;
+            return response.map(|body| Body::new(HeldBody { body, _in_flight: in_flight }));
+        }
+        Err(Refusal::TooManyConnections) => ("TOO_MANY_CONNECTIONS", Msg::TooManyConnections.into(), 1),
+        Err(Refusal::RateLimited(wait)) => {
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            ("RATE_LIMITED", Msg::RateLimited.with(&[&secs]), secs.max(1))
+        }
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(ApiResponse::<()>::error_with_code(code, message)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const B: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    fn client_limits(max_in_flight: Option<usize>, rate: Option<&str>) -> Arc<ClientLimits> {
+        Arc::new(ClientLimits::new(max_in_flight, rate.map(|rate| parse_rate(rate).unwrap())))
+    }
+
+    #[test]
+    fn rates_parse_with_units() {
+        let rate = |requests, secs| Ok(RateLimit { requests, window: Duration::from_secs(secs) });
+        assert_eq!(parse_rate("100/10s"), rate(100, 10));
+        assert_eq!(parse_rate("600/m"), rate(600, 60));
+        assert_eq!(parse_rate(" 5000 / 1h "), rate(5000, 3600));
+        for text in ["", "100", "0/s", "10/0s", "10/5d", "x/s", "10/s5", "-1/s"] {
+            assert!(parse_rate(text).is_err(), "{text}");
+        }
+        assert_eq!(parse_rate("600/m").unwrap().to_string(), "600/60s");
+    }
+
+    #[test]
+    fn in_flight_requests_are_limited_per_ip() {
+        let limits = client_limits(Some(2), None);
+        let first = limits.admit(A).ok().unwrap();
+        let _second = limits.admit(A).ok().unwrap();
+        assert!(matches!(limits.admit(A), Err(Refusal::TooManyConnections)));
+        // Other clients are counted on their own
+        assert!(limits.admit(B).is_ok());
+        // A finished request gives its place back
+        drop(first);
+        assert!(limits.admit(A).is_ok());
+    }
+
+    #[test]
+    fn rates_are_limited_per_ip() {
+        let limits = client_limits(None, Some("2/1h"));
+        // Finished requests still count towards the rate
+        drop(limits.admit(A).ok().unwrap());
+        drop(limits.admit(A).ok().unwrap());
+        match limits.admit(A) {
+            Err(Refusal::RateLimited(wait)) => {
+                assert!(wait <= Duration::from_secs(3600) && wait > Duration::from_secs(3590), "{wait:?}")
+            }
+            _ => panic!("third request admitted"),
+        }
+        assert!(limits.admit(B).is_ok());
+    }
+
+    #[test]
+    fn refused_requests_do_not_count() {
+        let limits = client_limits(Some(1), Some("2/1h"));
+        let held = limits.admit(A).ok().unwrap();
+        assert!(matches!(limits.admit(A), Err(Refusal::TooManyConnections)));
+        drop(held);
+        assert!(limits.admit(A).is_ok());
+    }
+
+    #[test]
+    fn idle_clients_are_forgotten() {
+        let limits = client_limits(Some(1), None);
+        let held = limits.admit(A).ok().unwrap();
+        drop(limits.admit(B).ok().unwrap());
+        limits.prune();
+        let clients = limits.clients.lock().unwrap().keys().copied().collect::<Vec<_>>();
+        assert_eq!(clients, [A]);
+        drop(held);
+        limits.prune();
+        assert!(limits.clients.lock().unwrap().is_empty());
+
+        // Clients with requests in the current window are kept for the rate
+        let limits = client_limits(None, Some("5/1h"));
+        drop(limits.admit(A).ok().unwrap());
+        limits.prune();
+        assert_eq!(limits.clients.lock().unwrap().len(), 1);
+    }
+}
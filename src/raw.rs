@@ -0,0 +1,108 @@
+use axum::{
+    extract::{Extension, Path as AxumPath, State},
+    http::{header, HeaderMap},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, dir_entries, file_response, mount_entries, safe_path};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::{ApiResponse, FileInfo, FilesResponse};
+use crate::paths::url_path;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Where files are served by path, below `--base-path`
+pub(crate) const RAW_PREFIX: &str = "/raw";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// A folder index: JSON like `/api/files` when asked for, else a minimal HTML page
+///
+/// Links are absolute and percent-encoded per name, so names with `#`, `?` or `%`
+/// lead back to the same file.
+fn index(state: &AppState, headers: &HeaderMap, path: &str, files: Vec<FileInfo>) -> Response {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    if wants_json {
+        return Json(ApiResponse::success(FilesResponse { path: path.to_string(), files })).into_response();
+    }
+
+    let prefix = format!("{}{}", state.base_path, RAW_PREFIX);
+    let title = escape(&format!("Index of {}", path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body><h1>{title}</h1>\n<pre>\n"
+    );
+    if let Some((parent, _)) = path.trim_end_matches('/').rsplit_once('/') {
+        html.push_str(&format!("<a href=\"{}\">../</a>\n", escape(&url_path(&prefix, parent, true))));
+    }
+    for file in &files {
+        let is_dir = file.file_type == "folder";
+        let href = url_path(&prefix, &file.path, is_dir);
+        let name = if is_dir { format!("{}/", file.name) } else { file.name.clone() };
+        html.push_str(&format!(
+            "<a href=\"{}\">{}</a>  {}  {}\n",
+            escape(&href),
+            escape(&name),
+            file.modified,
+            if is_dir { "-" } else { &file.size_formatted },
+        ));
+    }
+    html.push_str("</pre>\n</body></html>\n");
+    Html(html).into_response()
+}
+
+/// Files by URL path, such as `/raw/reports/2024/q1.pdf`
+///
+/// The router percent-decodes the path once and `safe_path` checks the result like any
+/// other user path, so an encoded `..` can't leave the root. Files are served inline with
+/// `/api/download`'s range and conditional request support. Folders are refused unless
+/// `--raw-autoindex` is set.
+pub async fn raw(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    path: Option<AxumPath<String>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let user_path = format!("/{}", path.map(|AxumPath(p)| p).unwrap_or_default());
+
+    if state.mounts.is_virtual_root(&user_path) {
+        if !state.raw_autoindex {
+            return Err(ApiError::AccessDenied(Msg::RawIndexDisabled.into()));
+        }
+        return Ok(index(&state, &headers, "/", mount_entries(&state, &user, None).await));
+    }
+
+    let paths = safe_path(&state.mounts, &user_path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+
+    if paths.actual.is_dir() {
+        user.check_visible(&logical).map_err(ApiError::AccessDenied)?;
+        check_patterns(&state, &logical, false)?;
+        check_not_internal(&logical, &paths.actual)?;
+        if !state.raw_autoindex {
+            return Err(ApiError::AccessDenied(Msg::RawIndexDisabled.into()));
+        }
+        let files = dir_entries(&state, &user, &paths, None).await?;
+        return Ok(index(&state, &headers, &logical, files));
+    }
+
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+    let response = file_response(&paths.actual, &headers, "inline").await;
+    Ok(crate::throttle::limit(response, state.max_download_rate))
+}
@@ -0,0 +1,175 @@
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use crate::models::{FileInfo, ListingFormat};
+
+/// The format named by `format=`, else the one `Accept` prefers
+///
+/// JSON unless `text/plain` or `text/csv` ranks above it, so browsers and `*/*` clients
+/// (curl's default) keep getting JSON. Equal quality goes to the first listed.
+pub fn negotiate(format: Option<ListingFormat>, headers: &HeaderMap) -> ListingFormat {
+    if let Some(format) = format {
+        return format;
+    }
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return ListingFormat::Json;
+    };
+    let (mut best, mut best_q) = (ListingFormat::Json, 0.0);
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let format = match params.next().unwrap_or_default().trim().to_ascii_lowercase().as_str() {
+            "text/plain" => ListingFormat::Text,
+            "text/csv" => ListingFormat::Csv,
+            "application/json" | "application/*" | "*/*" => ListingFormat::Json,
+            _ => continue,
+        };
+        let q = params
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if q > best_q {
+            (best, best_q) = (format, q);
+        }
+    }
+    best
+}
+
+/// `files` as text or CSV, or `None` for JSON, which the handler serializes itself
+///
+/// Text lists search results by their full path, since they come from many folders.
+pub fn listing(format: ListingFormat, files: &[FileInfo], full_paths: bool) -> Option<Response> {
+    let (content_type, body) = match format {
+        ListingFormat::Json => return None,
+        ListingFormat::Text => ("text/plain; charset=utf-8", text(files, full_paths)),
+        ListingFormat::Csv => ("text/csv; charset=utf-8", csv(files)),
+    };
+    Some(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+/// One line per file: type, size, modified and name in aligned columns
+fn text(files: &[FileInfo], full_paths: bool) -> String {
+    let width = |column: fn(&FileInfo) -> &str| files.iter().map(|f| column(f).chars().count()).max().unwrap_or(0);
+    let type_width = width(|f| &f.file_type);
+    let size_width = width(|f| &f.size_formatted);
+    let modified_width = width(|f| &f.modified);
+    let mut out = String::new();
+    for file in files {
+        let name = if full_paths { &file.path } else { &file.name };
+        out.push_str(&format!(
+            "{:<type_width$}  {:>size_width$}  {:<modified_width$}  {}\n",
+            file.file_type,
+            file.size_formatted,
+            file.modified,
+            printable(name),
+        ));
+    }
+    out
+}
+
+/// A name with control characters (such as newlines) shown as `?`, as `ls` does
+fn printable(name: &str) -> String {
+    name.chars().map(|c| if c.is_control() { '?' } else { c }).collect()
+}
+
+/// RFC 4180 CSV with a header row; times are RFC 3339 UTC
+fn csv(files: &[FileInfo]) -> String {
+    let mut out = String::from("type,name,path,size,modified\r\n");
+    for file in files {
+        let fields = [
+            csv_field(&file.file_type),
+            csv_field(&file.name),
+            csv_field(&file.path),
+            file.size.to_string(),
+            csv_field(file.modified_at.as_deref().unwrap_or_default()),
+        ];
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Quote a field containing a separator, quote or line break, doubling its quotes
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn file(name: &str, path: &str) -> FileInfo {
+        FileInfo {
+            name: name.to_string(),
+            path: path.to_string(),
+            file_type: "file".to_string(),
+            size: 1536,
+            size_formatted: "1.50 KB".to_string(),
+            modified: "2024-06-01 12:00".to_string(),
+            created: "2024-06-01 12:00".to_string(),
+            modified_at: Some("2024-06-01T12:00:00.000Z".to_string()),
+            created_at: None,
+            category: None,
+            dir_size: None,
+            dir_size_is_estimate: None,
+            tags: None,
+            symlink: None,
+        }
+    }
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("plain name.txt"), "plain name.txt");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\rhere"), "\"cr\rhere\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_has_a_header_and_crlf_rows() {
+        let out = csv(&[file("a,b.txt", "/docs/a,b.txt")]);
+        assert_eq!(
+            out,
+            "type,name,path,size,modified\r\nfile,\"a,b.txt\",\"/docs/a,b.txt\",1536,2024-06-01T12:00:00.000Z\r\n"
+        );
+        let mut untimed = file("x", "/x");
+        untimed.modified_at = None;
+        assert!(csv(&[untimed]).ends_with("file,x,/x,1536,\r\n"));
+    }
+
+    #[test]
+    fn text_shows_control_characters_as_question_marks() {
+        assert_eq!(printable("bad\nname\t.txt"), "bad?name?.txt");
+        let out = text(&[file("evil\nname", "/evil\nname")], false);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with("evil?name\n"));
+        assert!(text(&[file("a", "/dir/a")], true).ends_with("/dir/a\n"));
+    }
+
+    #[test]
+    fn format_parameter_overrides_accept() {
+        assert_eq!(negotiate(Some(ListingFormat::Csv), &accept("text/plain")), ListingFormat::Csv);
+    }
+
+    #[test]
+    fn json_wins_unless_text_ranks_higher() {
+        assert_eq!(negotiate(None, &HeaderMap::new()), ListingFormat::Json);
+        assert_eq!(negotiate(None, &accept("*/*")), ListingFormat::Json);
+        assert_eq!(negotiate(None, &accept("text/html,application/xhtml+xml,*/*;q=0.8")), ListingFormat::Json);
+        assert_eq!(negotiate(None, &accept("text/plain")), ListingFormat::Text);
+        assert_eq!(negotiate(None, &accept("Text/CSV; charset=utf-8")), ListingFormat::Csv);
+        assert_eq!(negotiate(None, &accept("application/json, text/plain")), ListingFormat::Json);
+        assert_eq!(negotiate(None, &accept("application/json;q=0.5, text/csv")), ListingFormat::Csv);
+    }
+}
@@ -0,0 +1,871 @@
+use axum::{
+    body::Body,
+    extract::{multipart::Field, ConnectInfo, Extension, Form, Multipart, Path as AxumPath, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+use crate::audit::AuditEntry;
+use crate::disks;
+use crate::error::ApiError;
+use crate::handlers::{
+    check_not_internal, check_patterns, file_response, format_size, relative_path, safe_path, sizes_changed, stream_field_to_file, TempPath,
+};
+use crate::hooks;
+use crate::i18n::Msg;
+use crate::jsonfile::JsonFile;
+use crate::models::*;
+use crate::paths::{existing_name, is_internal_artifact, INTERNAL_FILE_PREFIX};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// How long an unlocked password-protected share stays unlocked (seconds)
+const SHARE_GRANT_SECS: i64 = 3600;
+/// Upload requests allowed per client IP within `DROP_RATE_WINDOW_SECS` on upload-only shares
+const DROP_RATE_LIMIT: u32 = 30;
+const DROP_RATE_WINDOW_SECS: i64 = 60;
+
+/// Share links persisted as JSON in the data directory
+pub struct ShareStore {
+    file: JsonFile,
+    shares: Mutex<HashMap<String, ShareRecord>>,
+    /// Unlock grants for password-protected shares (grant id -> (token, expires_at))
+    grants: Mutex<HashMap<String, (String, i64)>>,
+    /// Upload-only request counters per client IP (count, window start)
+    drop_hits: Mutex<HashMap<IpAddr, (u32, i64)>>,
+}
+
+impl ShareStore {
+    /// Load the share store from `<data_dir>/shares.json`, starting empty if it doesn't exist
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let file = JsonFile::new(data_dir, "shares.json", "share store");
+        let records: Vec<ShareRecord> = file.load()?;
+        let shares = records.into_iter().map(|r| (r.token.clone(), r)).collect();
+        Ok(Self {
+            file,
+            shares: Mutex::new(shares),
+            grants: Mutex::new(HashMap::new()),
+            drop_hits: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Write the shares to disk, as a list
+    async fn save(&self, shares: &HashMap<String, ShareRecord>) -> Result<(), String> {
+        let records: Vec<&ShareRecord> = shares.values().collect();
+        self.file.save(&records).await.map_err(|e| e.to_string())
+    }
+
+    /// Get a share that exists and hasn't expired
+    async fn get_active(&self, token: &str) -> Option<ShareRecord> {
+        let shares = self.shares.lock().await;
+        let share = shares.get(token)?;
+        match share.expires_at {
+            Some(expires_at) if expires_at <= Utc::now().timestamp() => None,
+            _ => Some(share.clone()),
+        }
+    }
+
+    /// Count an upload-only request from this IP; returns the seconds to wait when over the limit
+    async fn hit_drop_limit(&self, ip: IpAddr) -> Option<u64> {
+        let now = Utc::now().timestamp();
+        let mut hits = self.drop_hits.lock().await;
+        hits.retain(|_, (_, start)| now - *start < DROP_RATE_WINDOW_SECS);
+        let entry = hits.entry(ip).or_insert((0, now));
+        entry.0 += 1;
+        (entry.0 > DROP_RATE_LIMIT).then(|| (entry.1 + DROP_RATE_WINDOW_SECS - now).max(1) as u64)
+    }
+
+    /// Add received bytes to an upload-only share and persist it
+    async fn add_received(&self, token: &str, bytes: u64) -> Result<(), String> {
+        let mut shares = self.shares.lock().await;
+        let Some(share) = shares.get_mut(token) else {
+            // Revoked while the upload was running; nothing left to account
+            return Ok(());
+        };
+        share.received_bytes += bytes;
+        self.save(&shares).await
+    }
+}
+
+/// Build the API view of a share record
+fn share_info(state: &AppState, share: &ShareRecord) -> ShareInfo {
+    ShareInfo {
+        token: share.token.clone(),
+        path: share.path.clone(),
+        url: format!("{}/s/{}", state.base_path, share.token),
+        created_at: share.created_at,
+        expires_at: share.expires_at,
+        has_password: share.password_hash.is_some(),
+        allow_listing: share.allow_listing,
+        kind: share.kind,
+        max_file_size: share.max_file_size,
+        max_total_bytes: share.max_total_bytes,
+        received_bytes: share.received_bytes,
+    }
+}
+
+// ========== Authenticated share management ==========
+
+/// Create a share link
+#[utoipa::path(
+    post, path = "/api/share", tag = "share", request_body = CreateShareRequest,
+    responses((status = 200, description = "Share link created", body = ApiResponse<ShareResponse>)),
+)]
+pub async fn create_share(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(req): Json<CreateShareRequest>,
+) -> impl IntoResponse {
+    let paths = match safe_path(&state.mounts, &req.path) {
+        Ok(p) => p,
+        Err(e) => return e.into_response(),
+    };
+    let logical = state.mounts.logical_path(&paths.logical);
+
+    if let Err(e) = user.check(&logical) {
+        return crate::auth::forbidden(e);
+    }
+    if let Err(e) = check_patterns(&state, &logical, true) {
+        return e.into_response();
+    }
+    if let Err(e) = check_not_internal(&logical, &paths.actual) {
+        return e.into_response();
+    }
+
+    if !paths.actual.exists() {
+        return ApiError::NotFound(Msg::FileNotFound.into()).into_response();
+    }
+
+    let upload_only = req.kind == ShareKind::UploadOnly;
+    if upload_only && !paths.actual.is_dir() {
+        return ApiError::BadRequest(Msg::UploadShareNeedsFolder.into()).into_response();
+    }
+
+    let password_hash = match req.password.as_deref().filter(|p| !p.is_empty()) {
+        Some(password) => match crate::auth::hash_password(password) {
+            Ok(h) => Some(h),
+            Err(e) => return ApiError::Io(e).into_response(),
+        },
+        None => None,
+    };
+
+    let now = Utc::now().timestamp();
+    let share = ShareRecord {
+        token: Uuid::new_v4().simple().to_string(),
+        path: logical,
+        owner: user.username.clone(),
+        created_at: now,
+        expires_at: req.expires_in.map(|secs| now + secs as i64),
+        password_hash,
+        allow_listing: req.allow_listing && !upload_only,
+        kind: req.kind,
+        max_file_size: req.max_file_size.filter(|_| upload_only),
+        max_total_bytes: req.max_total_bytes.filter(|_| upload_only),
+        received_bytes: 0,
+    };
+
+    {
+        let mut shares = state.shares.shares.lock().await;
+        // Drop expired shares while we hold the lock
+        shares.retain(|_, s| s.expires_at.is_none_or(|t| t > now));
+        shares.insert(share.token.clone(), share.clone());
+        if let Err(e) = state.shares.save(&shares).await {
+            shares.remove(&share.token);
+            return ApiError::Io(e).into_response();
+        }
+    }
+
+    Json(ApiResponse::success(ShareResponse {
+        share: share_info(&state, &share),
+    })).into_response()
+}
+
+/// List the current user's active share links
+#[utoipa::path(
+    get, path = "/api/share", tag = "share",
+    responses((status = 200, description = "Active share links", body = ApiResponse<SharesResponse>)),
+)]
+pub async fn list_shares(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    let now = Utc::now().timestamp();
+    let shares = state.shares.shares.lock().await;
+    let mut list: Vec<ShareInfo> = shares
+        .values()
+        .filter(|s| s.owner == user.username && s.expires_at.is_none_or(|t| t > now))
+        .map(|s| share_info(&state, s))
+        .collect();
+    list.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+
+    Json(ApiResponse::success(SharesResponse { shares: list }))
+}
+
+/// Revoke a share link (owner, or the primary user)
+#[utoipa::path(
+    delete, path = "/api/share/{token}", tag = "share", params(("token" = String, Path, description = "Share token")),
+    responses((status = 200, description = "Share link revoked", body = ApiResponse<OperationResponse>)),
+)]
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    AxumPath(token): AxumPath<String>,
+) -> impl IntoResponse {
+    let mut shares = state.shares.shares.lock().await;
+    match shares.get(&token) {
+        Some(s) if s.owner == user.username || user.username == state.username => {}
+        _ => return ApiError::NotFound(Msg::ShareNotFound.into()).into_response(),
+    }
+
+    let removed = shares.remove(&token);
+    if let Err(e) = state.shares.save(&shares).await {
+        if let Some(share) = removed {
+            shares.insert(token, share);
+        }
+        return ApiError::Io(e).into_response();
+    }
+
+    Json(ApiResponse::success(OperationResponse {
+        message: Msg::ShareRevoked.into(),
+        new_path: None,
+    })).into_response()
+}
+
+// ========== Public share access ==========
+
+fn not_found() -> Response {
+    (StatusCode::NOT_FOUND, Msg::ShareUnavailable.text()).into_response()
+}
+
+/// Name of the cookie holding an unlock grant for one share
+fn grant_cookie_name(token: &str) -> String {
+    format!("filest_share_{}", token)
+}
+
+/// Whether the request carries a valid unlock grant for the share
+async fn has_grant(state: &AppState, token: &str, headers: &HeaderMap) -> bool {
+    let cookie_name = grant_cookie_name(token);
+    let grant = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == cookie_name)
+        .map(|(_, value)| value.to_string());
+    let Some(grant) = grant else {
+        return false;
+    };
+
+    let grants = state.shares.grants.lock().await;
+    grants
+        .get(&grant)
+        .is_some_and(|(t, expires_at)| t == token && *expires_at > Utc::now().timestamp())
+}
+
+/// Access a share: download the shared file, or list/download inside a shared folder
+pub async fn access_share(
+    State(state): State<AppState>,
+    AxumPath(token): AxumPath<String>,
+    Query(query): Query<ShareAccessQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(share) = state.shares.get_active(&token).await else {
+        return not_found();
+    };
+
+    if share.password_hash.is_some() && !has_grant(&state, &token, &headers).await {
+        return password_page(&state, &token, false);
+    }
+
+    // The owner may have lost access to the path since the share was created
+    let Some(owner) = crate::auth::lookup_user(&state, &share.owner).filter(|owner| owner.check(&share.path).is_ok())
+    else {
+        return not_found();
+    };
+
+    // Re-validate the share target on every access in case it was moved or deleted
+    let share_paths = match safe_path(&state.mounts, &share.path) {
+        Ok(p) if check_patterns(&state, &share.path, true).is_ok() => p,
+        _ => return not_found(),
+    };
+    if !share_paths.actual.exists() {
+        return not_found();
+    }
+
+    // Upload-only shares never reveal what's already in the folder
+    if share.kind == ShareKind::UploadOnly {
+        return upload_page(&state, &share);
+    }
+
+    if !share_paths.actual.is_dir() {
+        let response = file_response(&share_paths.actual, &headers, "attachment").await;
+        return crate::throttle::limit(response, state.max_download_rate);
+    }
+
+    // Resolve the requested entry inside the shared folder
+    let sub = query.path.unwrap_or_default();
+    // Whatever is inside is still subject to the owner's own rules, as it would be for them
+    let paths = match safe_path(&state.mounts, &format!("{}/{}", share.path, sub)) {
+        Ok(p) if p.logical.starts_with(&share_paths.logical) && {
+            let logical = state.mounts.logical_path(&p.logical);
+            // The trash and upload temporaries below a shared folder aren't part of it
+            check_not_internal(&logical, &p.actual).is_ok()
+                && owner.check(&logical).is_ok()
+                && check_patterns(&state, &logical, true).is_ok()
+        } => p,
+        _ => return not_found(),
+    };
+    if !paths.actual.exists() {
+        return not_found();
+    }
+
+    if paths.actual.is_dir() {
+        if !share.allow_listing {
+            return not_found();
+        }
+        return listing_page(&state, &share, &owner, &share_paths.logical, &paths.logical, &paths.actual).await;
+    }
+
+    if !share.allow_listing && paths.logical != share_paths.logical {
+        return not_found();
+    }
+    let response = file_response(&paths.actual, &headers, "attachment").await;
+    crate::throttle::limit(response, state.max_download_rate)
+}
+
+/// Unlock a password-protected share and remember it in a cookie
+///
+/// Wrong passwords count towards the same per-IP lockout as failed logins, so share
+/// passwords can't be guessed any faster than user passwords.
+pub async fn unlock_share(
+    State(state): State<AppState>,
+    AxumPath(token): AxumPath<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Form(form): Form<ShareUnlockForm>,
+) -> Response {
+    if let Some(retry_after) = crate::auth::lockout_remaining(&state, addr.ip()).await {
+        return crate::auth::too_many_attempts(retry_after);
+    }
+    let Some(share) = state.shares.get_active(&token).await else {
+        return not_found();
+    };
+    let Some(hash) = &share.password_hash else {
+        return redirect_to_share(&state, &token, None);
+    };
+
+    if !crate::auth::verify_password_hash(hash, &form.password) {
+        warn!("Wrong password for share {}", token);
+        crate::auth::record_failure(&state, addr.ip(), &format!("share:{}", token)).await;
+        return password_page(&state, &token, true);
+    }
+
+    let now = Utc::now().timestamp();
+    let expires_at = share
+        .expires_at
+        .map_or(now + SHARE_GRANT_SECS, |t| t.min(now + SHARE_GRANT_SECS));
+    let grant = Uuid::new_v4().simple().to_string();
+    {
+        let mut grants = state.shares.grants.lock().await;
+        grants.retain(|_, (_, t)| *t > now);
+        grants.insert(grant.clone(), (token.clone(), expires_at));
+    }
+
+    let cookie = format!(
+        "{}={}; Path={}/s/{}; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        grant_cookie_name(&token),
+        grant,
+        state.base_path,
+        token,
+        expires_at - now,
+        if state.tls_enabled { "; Secure" } else { "" }
+    );
+    redirect_to_share(&state, &token, Some(cookie))
+}
+
+/// Upload files into an upload-only share
+///
+/// Existing files are never reported: conflicting names are stored under a new name.
+pub async fn drop_upload(
+    State(state): State<AppState>,
+    AxumPath(token): AxumPath<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    if let Some(retry_after) = state.shares.hit_drop_limit(addr.ip()).await {
+        warn!("Upload rate limit hit for share {} from {}", token, addr.ip());
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(ApiResponse::<()>::error_with_code(
+                "RATE_LIMITED",
+                Msg::RateLimited.with(&[&retry_after]),
+            )),
+        )
+            .into_response();
+    }
+
+    let Some(share) = state.shares.get_active(&token).await else {
+        return not_found();
+    };
+    if share.kind != ShareKind::UploadOnly {
+        return not_found();
+    }
+    if share.password_hash.is_some() && !has_grant(&state, &token, &headers).await {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::<()>::error_with_code("PASSWORD_REQUIRED", Msg::SharePasswordRequired)),
+        )
+            .into_response();
+    }
+    if crate::auth::lookup_user(&state, &share.owner).is_none_or(|owner| owner.check(&share.path).is_err()) {
+        return not_found();
+    }
+    let (share_root, dest) = match safe_path(&state.mounts, &share.path) {
+        Ok(p) if p.actual.is_dir() && check_patterns(&state, &share.path, true).is_ok() => (p.logical, p.actual),
+        _ => return not_found(),
+    };
+
+    let _slot = match state.upload_slots.stream().await {
+        Ok(slot) => slot,
+        Err(e) => return e.into_response(),
+    };
+
+    let mut received = share.received_bytes;
+    let mut dropped = Vec::new();
+    if let Some(length) = crate::limits::declared_length(&headers)
+        && let Err(e) = disks::ensure_space(&dest, length, state.min_free_space).await
+    {
+        return drop_result(&headers, Err(e), dropped);
+    }
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                let e = crate::limits::too_large(state.upload_limits.max_request_body);
+                return drop_result(&headers, Err(e), dropped);
+            }
+            Err(e) => {
+                let e = ApiError::BadRequest(format!("{}: {}", Msg::ReadUploadFailed, e));
+                return drop_result(&headers, Err(e), dropped);
+            }
+        };
+        if field.name() != Some("files") {
+            continue;
+        }
+        // Keep only the final path component of the client-supplied name
+        let filename = field
+            .file_name()
+            .and_then(|n| Path::new(n).file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "upload".to_string());
+        let filename = match state.name_rules.prepare(&filename) {
+            Ok(name) if check_patterns(&state, &format!("{}/{}", share.path.trim_end_matches('/'), name), true).is_ok() => name,
+            _ => return drop_result(&headers, Err(ApiError::AccessDenied(Msg::FileNotAllowed.with(&[&filename]))), dropped),
+        };
+
+        let remaining = share.max_total_bytes.map(|max| max.saturating_sub(received));
+        if remaining == Some(0) {
+            return drop_result(&headers, Err(ApiError::QuotaExceeded(Msg::ShareQuotaReached.into())), dropped);
+        }
+        let limit = match (share.max_file_size, remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let (path, file) = match create_unique(&dest, &filename).await {
+            Ok(f) => f,
+            Err(e) => return drop_result(&headers, Err(ApiError::Io(e)), dropped),
+        };
+        let uploader = format!("share:{}", token);
+        let logical = state.mounts.logical_path(&share_root.join(path.file_name().unwrap_or_default()));
+        let result = match state.upload_hook {
+            Some(_) => {
+                drop(file);
+                receive_checked(&state, &mut field, &path, limit, &logical, &uploader, addr.ip()).await
+            }
+            None => {
+                let idle = state.upload_limits.idle_timeout;
+                stream_field_to_file(&mut field, file, &path, limit, idle, state.min_free_space).await
+            }
+        };
+        let mut entry = AuditEntry::new("upload", &uploader, addr.ip(), logical).result(&result);
+        if let Ok(size) = result {
+            entry = entry.size(size);
+        }
+        state.audit.record(entry);
+        sizes_changed(&state, &path).await;
+        let size = match result {
+            Ok(size) => size,
+            Err(e) => return drop_result(&headers, Err(e), dropped),
+        };
+        received += size;
+        if let Err(e) = state.shares.add_received(&token, size).await {
+            warn!("Failed to record upload for share {}: {}", token, e);
+        }
+        dropped.push(DroppedFile {
+            name: path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(filename),
+            size,
+        });
+    }
+
+    drop_result(&headers, Ok(()), dropped)
+}
+
+/// Receive a file into the empty file reserved for it at `path`, through `--upload-hook`
+///
+/// The data goes to a temporary file next to it, which replaces the reserved file once the
+/// hook passes it. A file that fails gives up its reserved name too. Warnings aren't shown
+/// to the uploader; the audit log has them.
+async fn receive_checked(
+    state: &AppState,
+    field: &mut Field<'_>,
+    path: &Path,
+    limit: Option<u64>,
+    logical: &str,
+    uploader: &str,
+    ip: IpAddr,
+) -> Result<u64, ApiError> {
+    let reserved = TempPath::file(path);
+    let dir = path.parent().unwrap_or(path);
+    let staging = dir.join(format!("{}upload_{}.tmp", INTERNAL_FILE_PREFIX, Uuid::new_v4().simple()));
+    let _staged = TempPath::file(&staging);
+    let file = fs::File::create(&staging)
+        .await
+        .map_err(|e| ApiError::io(Msg::CreateFileFailed, e))?;
+    let idle = state.upload_limits.idle_timeout;
+    let size = stream_field_to_file(field, file, &staging, limit, idle, state.min_free_space).await?;
+    hooks::check_upload(state, &staging, logical, uploader, ip).await?;
+    fs::rename(&staging, path).await.map_err(|e| ApiError::io(Msg::WriteFileFailed, e))?;
+    reserved.keep();
+    Ok(size)
+}
+
+/// Create a new file in `dir`, appending " (n)" to the name until it doesn't collide
+async fn create_unique(dir: &Path, filename: &str) -> Result<(PathBuf, fs::File), String> {
+    let name = Path::new(filename);
+    let stem = name.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = name.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+
+    for n in 0..1000 {
+        let candidate = if n == 0 {
+            filename.to_string()
+        } else {
+            format!("{} ({}){}", stem, n, ext)
+        };
+        // A name differing only in Unicode normalization would look like a duplicate
+        if existing_name(dir, &candidate).await.is_some() {
+            continue;
+        }
+        let candidate = dir.join(candidate);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&candidate).await {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("{}: {}", Msg::CreateFileFailed, e)),
+        }
+    }
+    Err(Msg::CreateFileFailed.into())
+}
+
+/// Respond to an upload-only request as HTML for browser forms, JSON otherwise
+fn drop_result(headers: &HeaderMap, result: Result<(), ApiError>, files: Vec<DroppedFile>) -> Response {
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.contains("text/html"));
+
+    if wants_html {
+        let mut body = String::new();
+        if !files.is_empty() {
+            body.push_str(&format!("<h2>{}</h2><ul>", html_escape(&Msg::DropUploaded.with(&[&files.len()]))));
+            for f in &files {
+                body.push_str(&format!("<li>{} <small>({})</small></li>", html_escape(&f.name), format_size(f.size)));
+            }
+            body.push_str("</ul>");
+        }
+        if let Err(e) = &result {
+            body.push_str(&format!("<p style=\"color:#c00\">{}</p>", html_escape(e.message())));
+        }
+        body.push_str(&format!("<p><a href=\"javascript:history.back()\">{}</a></p>", html_escape(Msg::DropMore.text())));
+        let status = result.as_ref().err().map_or(StatusCode::OK, ApiError::status);
+        return (status, html_page(Msg::DropSubmit.text(), &body)).into_response();
+    }
+
+    match result {
+        Ok(()) => Json(ApiResponse::success(DropResponse { files })).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+fn redirect_to_share(state: &AppState, token: &str, cookie: Option<String>) -> Response {
+    let mut builder = Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, format!("{}/s/{}", state.base_path, token));
+    if let Some(cookie) = cookie {
+        builder = builder.header(header::SET_COOKIE, cookie);
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
+/// Escape text for inclusion in HTML
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn html_page(title: &str, body: &str) -> Response {
+    let html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title><style>body{{font-family:sans-serif;max-width:720px;margin:2em auto;padding:0 1em}}\
+         li{{margin:.3em 0}}input,button{{padding:.4em}}</style></head><body>{}</body></html>",
+        html_escape(title),
+        body
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+fn password_page(state: &AppState, token: &str, wrong: bool) -> Response {
+    let error = if wrong {
+        format!("<p style=\"color:#c00\">{}</p>", html_escape(Msg::SharePasswordWrong.text()))
+    } else {
+        String::new()
+    };
+    let body = format!(
+        "<h2>{}</h2>{}<form method=\"post\" action=\"{}/s/{}\">\
+         <input type=\"password\" name=\"password\" autofocus placeholder=\"{}\"> \
+         <button type=\"submit\">{}</button></form>",
+        html_escape(Msg::SharePasswordPrompt.text()),
+        error,
+        html_escape(&state.base_path),
+        html_escape(token),
+        html_escape(Msg::SharePasswordField.text()),
+        html_escape(Msg::ShareOpen.text())
+    );
+    let mut response = html_page(Msg::SharePageTitle.text(), &body);
+    if wrong {
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+    }
+    response
+}
+
+fn upload_page(state: &AppState, share: &ShareRecord) -> Response {
+    let mut limits = Vec::new();
+    if let Some(max) = share.max_file_size {
+        limits.push(Msg::DropMaxFileSize.with(&[&format_size(max)]));
+    }
+    if let Some(max) = share.max_total_bytes {
+        limits.push(Msg::DropRemaining.with(&[&format_size(max.saturating_sub(share.received_bytes))]));
+    }
+    let body = format!(
+        "<h2>{}</h2>{}<form method=\"post\" action=\"{}/s/{}/upload\" enctype=\"multipart/form-data\">\
+         <input type=\"file\" name=\"files\" multiple required> \
+         <button type=\"submit\">{}</button></form>",
+        html_escape(Msg::DropPageTitle.text()),
+        if limits.is_empty() {
+            String::new()
+        } else {
+            format!("<p>{}</p>", html_escape(&limits.join(Msg::DropLimitSeparator.text())))
+        },
+        html_escape(&state.base_path),
+        html_escape(&share.token),
+        html_escape(Msg::DropSubmit.text())
+    );
+    html_page(Msg::DropPageTitle.text(), &body)
+}
+
+/// Render a minimal listing of a directory inside a shared folder
+async fn listing_page(
+    state: &AppState,
+    share: &ShareRecord,
+    owner: &AuthUser,
+    share_root: &Path,
+    logical_dir: &Path,
+    actual_dir: &Path,
+) -> Response {
+    let mut entries = Vec::new();
+    if let Ok(mut dir) = fs::read_dir(actual_dir).await {
+        while let Ok(Some(entry)) = dir.next_entry().await {
+            let rel = state.mounts.logical_path(&logical_dir.join(entry.file_name()));
+            // Listed as the owner would see it in `/api/files`
+            if !owner.can_see(&rel)
+                || state.hidden.matches(&rel)
+                || is_internal_artifact(&rel, &entry.path())
+                || state.mounts.refuses_link(&entry.path())
+            {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(entry.path()).await else {
+                continue;
+            };
+            entries.push((entry.file_name().to_string_lossy().to_string(), metadata.is_dir(), metadata.len()));
+        }
+    }
+    // Folders first, then by name
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let rel_dir = relative_path(share_root, logical_dir);
+    let link = |rel: &str| {
+        format!(
+            "{}/s/{}?path={}",
+            state.base_path,
+            share.token,
+            utf8_percent_encode(rel, NON_ALPHANUMERIC)
+        )
+    };
+
+    let mut items = String::new();
+    if logical_dir != share_root {
+        let parent = Path::new(&rel_dir).parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+        items.push_str(&format!("<li><a href=\"{}\">..</a></li>", html_escape(&link(&parent))));
+    }
+    for (name, is_dir, size) in &entries {
+        let rel = format!("{}/{}", rel_dir.trim_end_matches('/'), name);
+        let label = if *is_dir {
+            format!("📁 {}/", html_escape(name))
+        } else {
+            format!("📄 {} <small>({})</small>", html_escape(name), format_size(*size))
+        };
+        items.push_str(&format!("<li><a href=\"{}\">{}</a></li>", html_escape(&link(&rel)), label));
+    }
+
+    let title = share_root
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| share.path.clone());
+    let body = format!(
+        "<h2>{}{}</h2><ul>{}</ul>",
+        html_escape(&title),
+        html_escape(if rel_dir == "/" { "" } else { &rel_dir }),
+        items
+    );
+    html_page(&title, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{admin, TestApp};
+    use axum::http::{Method, Request};
+
+    /// Share `path` with listing allowed, returning the token
+    async fn share(app: &TestApp, path: &str) -> String {
+        share_with(app, serde_json::json!({ "path": path, "allowListing": true })).await
+    }
+
+    async fn share_with(app: &TestApp, body: serde_json::Value) -> String {
+        let request = admin(Method::POST, "/api/share")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, body) = app.json(request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["share"]["token"].as_str().unwrap().to_string()
+    }
+
+    async fn get(app: &TestApp, uri: &str) -> StatusCode {
+        app.send(Request::get(uri).body(Body::empty()).unwrap()).await.status()
+    }
+
+    #[tokio::test]
+    async fn a_shared_folder_does_not_serve_the_trash() {
+        let app = TestApp::with(|_| {});
+        app.write("pub/a.txt", "shared");
+        app.write(".filest_trash/0123/secret.txt", "deleted");
+        app.write("pub/.filest_upload_1.tmp", "half");
+        let token = share(&app, "/").await;
+
+        assert_eq!(get(&app, &format!("/s/{token}?path=pub/a.txt")).await, StatusCode::OK);
+        for sub in [".filest_trash/0123/secret.txt", ".filest_trash", "pub/.filest_upload_1.tmp"] {
+            assert_eq!(get(&app, &format!("/s/{token}?path={sub}")).await, StatusCode::NOT_FOUND, "{sub}");
+        }
+    }
+
+    #[tokio::test]
+    async fn guessing_a_share_password_locks_the_client_out() {
+        let app = TestApp::with(|c| c.auth_max_failures = 3);
+        app.write("a.txt", "shared");
+        let token = share_with(&app, serde_json::json!({ "path": "/a.txt", "password": "right" })).await;
+        let unlock = |password: &str| {
+            Request::post(format!("/s/{token}"))
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(format!("password={password}")))
+                .unwrap()
+        };
+
+        for _ in 0..3 {
+            assert_eq!(app.send(unlock("wrong")).await.status(), StatusCode::UNAUTHORIZED);
+        }
+        // Locked out now, even with the right password
+        let response = app.send(unlock("right")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((1..=300).contains(&retry_after), "{retry_after}");
+    }
+
+    #[tokio::test]
+    async fn share_pages_follow_accept_language() {
+        let app = TestApp::with(|_| {});
+        app.write("a.txt", "shared");
+        let token = share_with(&app, serde_json::json!({ "path": "/a.txt", "password": "right" })).await;
+        let page = async |uri: &str, lang: &str| {
+            let request = Request::get(uri).header(header::ACCEPT_LANGUAGE, lang).body(Body::empty()).unwrap();
+            let response = app.send(request).await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let uri = format!("/s/{token}");
+        let zh = page(&uri, "zh-CN,zh;q=0.9").await;
+        assert!(zh.contains("此分享需要密码") && zh.contains("placeholder=\"密码\""), "{zh}");
+        let en = page(&uri, "en-US,en;q=0.9").await;
+        assert!(en.contains("This share needs a password") && en.contains("placeholder=\"Password\""), "{en}");
+        assert!(!en.contains("密码"), "{en}");
+
+        assert_eq!(page("/s/missing", "zh-CN").await, "分享不存在或已过期");
+        assert_eq!(page("/s/missing", "en").await, "Share not found or expired");
+    }
+
+    #[tokio::test]
+    async fn truncated_drops_are_refused() {
+        let app = TestApp::with(|_| {});
+        std::fs::create_dir(app.path("inbox")).unwrap();
+        let token = share_with(&app, serde_json::json!({ "path": "/inbox", "kind": "upload_only" })).await;
+        let drop = |body: String| {
+            Request::post(format!("/s/{token}/upload"))
+                .header(header::CONTENT_TYPE, "multipart/form-data; boundary=B")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let part = |name: &str| format!("--B\r\nContent-Disposition: form-data; name=\"files\"; filename=\"{name}\"\r\n\r\ndata\r\n");
+
+        let whole = format!("{}--B--\r\n", part("a.txt"));
+        let (status, body) = app.json(drop(whole)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        // The body ends inside the second part's headers
+        let cut = format!("{}--B\r\nContent-Disp", part("b.txt"));
+        let (status, body) = app.json(drop(cut)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["success"], false);
+    }
+}
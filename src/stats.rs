@@ -0,0 +1,158 @@
+use axum::{
+    extract::{Extension, Query, State},
+    Json,
+};
+use chrono::{SecondsFormat, Utc};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::category::FileCategory;
+use crate::error::ApiError;
+use crate::handlers::{format_size, walk_tree, TreeStats, WalkCaps};
+use crate::jobs::{report_roots, Outcome, ReportRequest, WalkProgress};
+use crate::models::{
+    ApiResponse, JobStatus, StatsGroup, StatsGroupBy, StatsQuery, StatsReport, StatsResponse, UsageEntry,
+};
+use crate::usage::link_id;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Running totals of one group
+#[derive(Default)]
+struct Group {
+    files: u64,
+    bytes: u64,
+    /// Largest file so far, by logical path
+    largest: Option<(u64, PathBuf)>,
+}
+
+/// The group a file name falls in
+fn group_key(name: &str, group_by: StatsGroupBy) -> String {
+    match group_by {
+        StatsGroupBy::Extension => name.rsplit_once('.').map_or(String::new(), |(_, ext)| ext.to_lowercase()),
+        StatsGroupBy::Category => FileCategory::of(name).as_str().to_string(),
+    }
+}
+
+async fn build_report(
+    state: AppState,
+    user: AuthUser,
+    path: String,
+    roots: Vec<(PathBuf, PathBuf)>,
+    group_by: StatsGroupBy,
+    depth: Option<u32>,
+    progress: Arc<WalkProgress>,
+) -> StatsReport {
+    let caps = WalkCaps {
+        max_entries: state.report_max_entries,
+        max_depth: depth.unwrap_or(u32::MAX),
+        ..WalkCaps::NONE
+    };
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    let mut seen_links = HashSet::new();
+    let mut stats = TreeStats::default();
+    for (logical, actual) in &roots {
+        walk_tree(&state, logical, actual, caps, &mut stats, &mut |entry, metadata| {
+            if metadata.is_dir() || link_id(metadata).is_some_and(|id| !seen_links.insert(id)) {
+                progress.add(1, 0);
+                return;
+            }
+            let size = metadata.len();
+            progress.add(1, size);
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            let group = groups.entry(group_key(&name, group_by)).or_default();
+            group.files += 1;
+            group.bytes += size;
+            if group.largest.as_ref().is_none_or(|(max, _)| size > *max) {
+                // Only candidates are checked against the user's rules
+                if user.can_see(&state.mounts.logical_path(entry)) {
+                    group.largest = Some((size, entry.to_path_buf()));
+                }
+            }
+        })
+        .await;
+        if stats.truncated {
+            break;
+        }
+    }
+
+    let (mut total_files, mut total_bytes) = (0, 0);
+    let mut groups: Vec<StatsGroup> = groups
+        .into_iter()
+        .map(|(key, group)| {
+            total_files += group.files;
+            total_bytes += group.bytes;
+            StatsGroup {
+                key,
+                files: group.files,
+                bytes: group.bytes,
+                bytes_formatted: format_size(group.bytes),
+                largest: group.largest.map(|(size, logical)| UsageEntry {
+                    path: state.mounts.logical_path(&logical),
+                    size,
+                    size_formatted: format_size(size),
+                    files: None,
+                }),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.key.cmp(&b.key)));
+    StatsReport {
+        path,
+        group_by,
+        groups,
+        total_files,
+        total_bytes,
+        total_formatted: format_size(total_bytes),
+        truncated: stats.truncated,
+        max_entries: state.report_max_entries,
+        max_depth: depth,
+        generated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    }
+}
+
+/// File counts and sizes below a path, by extension or category
+///
+/// Runs like `/api/usage`: big trees answer `computing` with a `job` token for
+/// `/api/jobs`, and reports are cached until something below the folder changes.
+#[utoipa::path(
+    get, path = "/api/stats", tag = "files", params(StatsQuery),
+    responses((status = 200, description = "The report, or the job computing it", body = ApiResponse<StatsResponse>)),
+)]
+pub async fn file_stats(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<ApiResponse<StatsResponse>>, ApiError> {
+    let user_path = query.path.unwrap_or_else(|| "/".to_string());
+    let group_by = query.group_by.unwrap_or_default();
+    // Depth 0 would count nothing
+    let depth = query.depth.map(|d| d.max(1));
+
+    let (path, roots) = report_roots(&state, &user, &user_path)?;
+    let options = format!("{:?}\0{:?}", group_by, depth);
+    let request = ReportRequest::new("stats", &options, &user.username, path.clone(), &roots).await;
+
+    let walk = {
+        let (state, user) = (state.clone(), user.clone());
+        move |progress| async move {
+            let report = build_report(state, user, path, roots, group_by, depth, progress).await;
+            serde_json::to_value(report).unwrap_or_default()
+        }
+    };
+    let response = match state.reports.run(&user.username, request, walk).await {
+        Outcome::Done(report) => StatsResponse {
+            status: JobStatus::Done,
+            job: None,
+            entries: None,
+            report: Some((*report).clone()),
+        },
+        Outcome::Running { token, progress } => StatsResponse {
+            status: JobStatus::Computing,
+            job: Some(token),
+            entries: Some(progress.entries()),
+            report: None,
+        },
+    };
+    Ok(Json(ApiResponse::success(response)))
+}
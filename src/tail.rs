@@ -0,0 +1,242 @@
+use axum::{
+    extract::{Extension, Query, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Json,
+};
+use futures::{stream, StreamExt};
+use std::convert::Infallible;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use crate::error::ApiError;
+use crate::handlers::{check_not_internal, check_patterns, safe_path};
+use crate::i18n::Msg;
+use crate::logging::record_path;
+use crate::models::{ApiResponse, TailQuery, TailReset, TailResponse};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Bytes read at a time while looking back for the start of the wanted lines
+const BLOCK_BYTES: u64 = 64 * 1024;
+/// Lines returned when the request names no number
+const DEFAULT_LINES: usize = 100;
+const MAX_LINES: usize = 10_000;
+/// Bytes at the start of a file checked for NUL, as `git` does, to refuse binary files
+const SNIFF_BYTES: u64 = 8000;
+/// How often a followed file is checked for new data, truncation and rotation
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Identity of the file behind a name, which changes when rotation puts a new file there
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Whether there is a NUL byte in the first `SNIFF_BYTES` of `bytes`, which text doesn't have
+pub(crate) fn looks_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(SNIFF_BYTES as usize)].contains(&0)
+}
+
+/// Whether the file looks binary by its first bytes
+async fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut head = Vec::new();
+    fs::File::open(path).await?.take(SNIFF_BYTES).read_to_end(&mut head).await?;
+    Ok(looks_binary(&head))
+}
+
+/// Text as lines without their endings; a last line without a newline still counts
+fn split_lines(bytes: &[u8]) -> Vec<String> {
+    if bytes.is_empty() {
+        return Vec::new();
+    }
+    let text = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    text.split(|&b| b == b'\n')
+        .map(|line| String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned())
+        .collect()
+}
+
+/// The last `lines` lines of the file's first `end` bytes, and whether `max_bytes` cut them short
+///
+/// Reads back from `end` one block at a time until enough line starts are found or the
+/// start of the file is reached. Past `max_bytes` it stops and drops the partial line it
+/// ended in.
+async fn last_lines(path: &Path, end: u64, lines: usize, max_bytes: u64) -> io::Result<(Vec<String>, bool)> {
+    let mut file = fs::File::open(path).await?;
+    let mut start = end;
+    let mut tail: Vec<u8> = Vec::new();
+    let capped = loop {
+        // A newline ending the file doesn't start another line
+        let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if start == 0 || body.iter().filter(|&&b| b == b'\n').count() >= lines {
+            break false;
+        }
+        let read = end - start;
+        if read >= max_bytes {
+            break true;
+        }
+        let len = BLOCK_BYTES.min(start).min(max_bytes - read);
+        start -= len;
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut block = vec![0; len as usize];
+        file.read_exact(&mut block).await?;
+        block.extend_from_slice(&tail);
+        tail = block;
+    };
+    let mut found = split_lines(&tail);
+    let whole = found.len() > lines;
+    if capped && !whole && !found.is_empty() {
+        found.remove(0);
+    }
+    let skip = found.len().saturating_sub(lines);
+    Ok((found.split_off(skip), capped && !whole))
+}
+
+/// Up to `len` bytes of the file from `start`
+async fn read_from(path: &Path, start: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut bytes = Vec::new();
+    file.take(len).read_to_end(&mut bytes).await?;
+    Ok(bytes)
+}
+
+/// A file being followed, polled every `POLL_INTERVAL`
+struct Follow {
+    path: PathBuf,
+    /// Where the next read starts
+    offset: u64,
+    id: Option<u64>,
+    /// The start of a line whose newline hasn't been written yet
+    pending: Vec<u8>,
+    max_bytes: u64,
+    interval: tokio::time::Interval,
+}
+
+impl Follow {
+    fn reset(&mut self, reason: TailReset, size: u64) -> Event {
+        self.offset = size;
+        self.pending.clear();
+        Event::default().event("reset").json_data(reason).unwrap_or_default()
+    }
+
+    /// Wait for the next thing to tell the client: appended lines or a reset
+    ///
+    /// A file that is missing for a while, as in the middle of a rotation, is waited for.
+    async fn next_event(&mut self) -> Event {
+        loop {
+            self.interval.tick().await;
+            let Ok(metadata) = fs::metadata(&self.path).await else {
+                continue;
+            };
+            let (id, size) = (file_id(&metadata), metadata.len());
+            if id != self.id {
+                self.id = id;
+                return self.reset(TailReset::Rotated, size);
+            }
+            if size < self.offset {
+                return self.reset(TailReset::Truncated, size);
+            }
+            if size == self.offset {
+                continue;
+            }
+            // Huge bursts are passed on `max_bytes` at a time, one poll after another
+            let Ok(bytes) = read_from(&self.path, self.offset, (size - self.offset).min(self.max_bytes)).await else {
+                continue;
+            };
+            self.offset += bytes.len() as u64;
+            self.pending.extend_from_slice(&bytes);
+            let complete = match self.pending.iter().rposition(|&b| b == b'\n') {
+                Some(end) => self.pending.drain(..=end).collect(),
+                // A line longer than `max_bytes` is sent in pieces
+                None if self.pending.len() as u64 >= self.max_bytes => std::mem::take(&mut self.pending),
+                None => continue,
+            };
+            let lines = split_lines(&complete);
+            return Event::default().event("lines").json_data(lines).unwrap_or_default();
+        }
+    }
+}
+
+/// The last lines of a text file such as a log, optionally followed as it grows
+///
+/// Reads back from the end, so the file's size doesn't matter; at most `--tail-max-bytes`
+/// are read, and `truncated` says when that cut the lines short. With `follow=true` the
+/// answer is a `text/event-stream` instead: a `lines` event with the last lines, then a
+/// `lines` event (a JSON array of strings) whenever complete lines are appended, checked
+/// every second, until the client disconnects. When the file gets shorter or is replaced
+/// (log rotation), a `reset` event (`"truncated"` or `"rotated"`) is sent and following
+/// continues from the new end.
+#[utoipa::path(
+    get, path = "/api/tail", tag = "files", params(TailQuery),
+    responses(
+        (status = 200, description = "The last lines, or with `follow=true` a `text/event-stream` of `lines` and `reset` events", body = ApiResponse<TailResponse>),
+        (status = 400, description = "`lines` over 10000, or not a file", body = crate::openapi::ErrorBody),
+        (status = 415, description = "`UNSUPPORTED_TYPE`: a binary file", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn tail(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<TailQuery>,
+) -> Result<Response, ApiError> {
+    let paths = safe_path(&state.mounts, &query.path)?;
+    let logical = state.mounts.logical_path(&paths.logical);
+    record_path(&logical);
+    user.check(&logical).map_err(ApiError::AccessDenied)?;
+    check_patterns(&state, &logical, true)?;
+    check_not_internal(&logical, &paths.actual)?;
+
+    let lines = query.lines.unwrap_or(DEFAULT_LINES);
+    if lines > MAX_LINES {
+        return Err(ApiError::BadRequest(Msg::TooManyTailLines.with(&[&MAX_LINES])));
+    }
+    let metadata = fs::metadata(&paths.actual)
+        .await
+        .map_err(|e| ApiError::io(Msg::FileNotFound, e))?;
+    if !metadata.is_file() {
+        return Err(ApiError::InvalidPath(Msg::NotAFile.into()));
+    }
+    let read_failed = |e| ApiError::io(Msg::ReadFileFailed, e);
+    if is_binary(&paths.actual).await.map_err(read_failed)? {
+        return Err(ApiError::UnsupportedType(Msg::NotATextFile.into()));
+    }
+    let size = metadata.len();
+    let (found, truncated) = last_lines(&paths.actual, size, lines, state.tail_max_bytes)
+        .await
+        .map_err(read_failed)?;
+
+    if !query.follow {
+        return Ok(Json(ApiResponse::success(TailResponse { lines: found, size, truncated })).into_response());
+    }
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let follow = Follow {
+        path: paths.actual,
+        offset: size,
+        id: file_id(&metadata),
+        pending: Vec::new(),
+        max_bytes: state.tail_max_bytes,
+        interval,
+    };
+    let first = Event::default().event("lines").json_data(found).unwrap_or_default();
+    // Ends the stream at shutdown instead of letting it hold up the grace period
+    let shutdown = state.shutdown.clone().cancelled_owned();
+    let events = stream::once(async move { Ok::<_, Infallible>(first) })
+        .chain(stream::unfold(follow, |mut follow| async move {
+            let event = follow.next_event().await;
+            Some((Ok(event), follow))
+        }))
+        .take_until(shutdown);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
+}
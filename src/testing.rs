@@ -0,0 +1,102 @@
+//! The whole app over a temporary root and data directory, for tests that go through
+//! the router as a client would
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::ConnectInfo,
+    http::{header, request, Method, Request, Response, StatusCode},
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use crate::config::Config;
+
+/// Address requests appear to come from
+pub const CLIENT: ([u8; 4], u16) = ([127, 0, 0, 1], 40000);
+
+pub struct TestApp {
+    pub app: Router,
+    /// The state behind `app`, for driving background tasks the server would run
+    pub state: crate::AppState,
+    root: tempfile::TempDir,
+    _data: tempfile::TempDir,
+}
+
+impl TestApp {
+    /// An app on a fresh root, its default config changed by `configure`
+    pub fn with(configure: impl FnOnce(&mut Config)) -> Self {
+        let (root, data) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut config = Config {
+            root: root.path().to_path_buf(),
+            data_dir: data.path().to_path_buf(),
+            min_free_space: 0,
+            ..Default::default()
+        };
+        configure(&mut config);
+        config.validate().unwrap();
+        let state = crate::load_state(&config, CancellationToken::new()).unwrap();
+        let app = crate::build_app(&config, state.clone());
+        Self { app, state, root, _data: data }
+    }
+
+    pub fn root(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// `path` below the root
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.root.path().join(path.trim_start_matches('/'))
+    }
+
+    /// Write a file below the root, creating its folders
+    pub fn write(&self, path: &str, bytes: impl AsRef<[u8]>) {
+        let path = self.path(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> Vec<u8> {
+        std::fs::read(self.path(path)).unwrap()
+    }
+
+    /// Send `request` as a client connecting from `CLIENT` would
+    pub async fn send(&self, mut request: Request<Body>) -> Response<Body> {
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(CLIENT)));
+        self.app.clone().oneshot(request).await.unwrap()
+    }
+
+    /// Send `request` and read its JSON answer
+    pub async fn json(&self, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = self.send(request).await;
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+    }
+}
+
+/// A request signed in as the configured administrator
+pub fn admin(method: Method, uri: &str) -> request::Builder {
+    as_user(method, uri, "admin", "admin123")
+}
+
+/// A request with Basic credentials
+pub fn as_user(method: Method, uri: &str, user: &str, password: &str) -> request::Builder {
+    let credentials = STANDARD.encode(format!("{}:{}", user, password));
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+}
+
+/// A hook script refusing files that contain `EICAR`, as a virus scanner would
+#[cfg(unix)]
+pub fn scanner(dir: &Path) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let script = dir.join("scan.sh");
+    std::fs::write(&script, "#!/bin/sh\nif grep -q EICAR \"$1\"; then echo infected >&2; exit 1; fi\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    format!("{} {{file}}", script.display())
+}
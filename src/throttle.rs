@@ -0,0 +1,78 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    response::Response,
+};
+use http_body::{Frame, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+use crate::error::ApiError;
+use crate::handlers::parse_size;
+use crate::i18n::Msg;
+
+/// A response body sent no faster than `rate` bytes per second
+///
+/// Each frame is passed on as it comes; the one after it waits until the bytes sent so
+/// far are due at `rate` since the start, so the average holds whatever size the frames
+/// are.
+struct ThrottledBody {
+    body: Body,
+    rate: u64,
+    start: Instant,
+    sent: u64,
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl HttpBody for ThrottledBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        if let Some(wait) = &mut self.wait {
+            ready!(wait.as_mut().poll(cx));
+            self.wait = None;
+        }
+        let frame = ready!(Pin::new(&mut self.body).poll_frame(cx));
+        if let Some(Ok(frame)) = &frame
+            && let Some(data) = frame.data_ref()
+        {
+            self.sent += data.len() as u64;
+            let due = self.start + Duration::from_secs_f64(self.sent as f64 / self.rate as f64);
+            if due > Instant::now() {
+                self.wait = Some(Box::pin(tokio::time::sleep_until(due)));
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.wait.is_none() && self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Send `response`'s body at no more than `rate` bytes per second, if a rate is set
+pub fn limit(response: Response, rate: Option<u64>) -> Response {
+    let Some(rate) = rate else {
+        return response;
+    };
+    response.map(|body| Body::new(ThrottledBody { body, rate, start: Instant::now(), sent: 0, wait: None }))
+}
+
+/// The rate a download is sent at: the `rate` asked for, such as "500KB", but never above
+/// `--max-download-rate`
+pub fn download_rate(max: Option<u64>, asked: Option<&str>) -> Result<Option<u64>, ApiError> {
+    let Some(asked) = asked else {
+        return Ok(max);
+    };
+    let rate = parse_size(asked)
+        .ok()
+        .filter(|&rate| rate > 0)
+        .ok_or_else(|| ApiError::BadRequest(Msg::InvalidDownloadRate.with(&[&asked])))?;
+    Ok(Some(max.map_or(rate, |max| rate.min(max))))
+}
@@ -0,0 +1,100 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Load a PEM certificate chain and private key into a rustls config
+///
+/// Fails with a readable message when either file is missing, doesn't parse,
+/// or the key doesn't belong to the certificate.
+pub async fn load_rustls_config(cert: &Path, key: &Path) -> Result<RustlsConfig, String> {
+    // ring is the only compiled-in provider; ignore the error if one is already installed
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let cert_pem = tokio::fs::read(cert)
+        .await
+        .map_err(|e| format!("Failed to read TLS certificate {:?}: {}", cert, e))?;
+    let key_pem = tokio::fs::read(key)
+        .await
+        .map_err(|e| format!("Failed to read TLS key {:?}: {}", key, e))?;
+
+    RustlsConfig::from_pem(cert_pem, key_pem)
+        .await
+        .map_err(|e| format!("Invalid TLS certificate/key pair: {}", e))
+}
+
+/// Run a plain HTTP listener that redirects every request to the HTTPS port until shutdown
+pub async fn redirect_http_to_https(bind: String, http_port: u16, https_port: u16, shutdown: CancellationToken) {
+    let addr: SocketAddr = match format!("{}:{}", bind, http_port).parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            warn!("Invalid TLS redirect address: {}", e);
+            return;
+        }
+    };
+
+    let app = Router::new().fallback(move |request: Request| async move {
+        redirect_to_https(&request, https_port)
+    });
+
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            warn!("Failed to bind TLS redirect listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("HTTP -> HTTPS 重定向监听: {}", addr);
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+    {
+        warn!("TLS redirect listener stopped: {}", e);
+    }
+}
+
+/// Build the redirect response for a single plain HTTP request
+fn redirect_to_https(request: &Request, https_port: u16) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(strip_port);
+    let Some(host) = host else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let authority = if https_port == 443 {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, https_port)
+    };
+
+    match format!("https://{}{}", authority, path_and_query).parse::<Uri>() {
+        Ok(uri) => Redirect::permanent(&uri.to_string()).into_response(),
+        Err(_) => (StatusCode::BAD_REQUEST, "Invalid Host header").into_response(),
+    }
+}
+
+/// Strip the port from a Host header value, keeping IPv6 brackets intact
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        };
+    }
+    host.split(':').next().unwrap_or(host)
+}
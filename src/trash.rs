@@ -0,0 +1,378 @@
+use axum::{
+    extract::{ConnectInfo, Extension, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::{Mutex, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+use crate::audit::AuditEntry;
+use crate::error::ApiError;
+use crate::handlers::{
+    check_patterns, format_size, move_path, remove_tree, safe_path, sizes_changed, source_size, RemoveOutcome,
+    SafePathResult,
+};
+use crate::i18n::Msg;
+use crate::jsonfile::JsonFile;
+use crate::jobs::WalkProgress;
+use crate::models::*;
+use crate::paths::{existing_name, INTERNAL_FILE_PREFIX};
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// How often items older than `--trash-retention-days` are purged
+const SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Folder in each mount root holding its trashed items, named by id; the `.filest_`
+/// prefix keeps it out of listings, walks and the file operations
+fn trash_dir(root: &Path) -> PathBuf {
+    root.join(format!("{}trash", INTERNAL_FILE_PREFIX))
+}
+
+/// Deleted files and folders kept for restoring, indexed in `<data_dir>/trash.json`
+///
+/// Items are moved into the trash folder of their mount's root, so deleting is a rename
+/// on the same disk and restoring one renames it back. The lock is held across every
+/// load-modify-save cycle of the index.
+pub struct TrashStore {
+    file: JsonFile,
+    items: Mutex<HashMap<String, TrashRecord>>,
+    /// Deletes go to the trash; off with `--no-trash`, when the index is still served so
+    /// what is already there can be restored or purged
+    enabled: bool,
+    /// How long items are kept before they are purged; `None` keeps them until purged
+    retention: Option<Duration>,
+    /// Bytes the trash may hold before the oldest items are purged (`--trash-max-size`)
+    max_size: Option<u64>,
+    /// Wakes the sweep when a delete takes the trash over `max_size`
+    over_size: Notify,
+}
+
+impl TrashStore {
+    /// Load the index from `<data_dir>/trash.json`, starting empty if it doesn't exist
+    pub fn load(data_dir: &Path, enabled: bool, retention_days: u64, max_size: Option<u64>) -> Result<Self, String> {
+        let file = JsonFile::new(data_dir, "trash.json", "trash index");
+        let items = file.load()?;
+        Ok(Self {
+            file,
+            items: Mutex::new(items),
+            enabled,
+            retention: (retention_days > 0).then(|| Duration::from_secs(retention_days * 86400)),
+            max_size,
+            over_size: Notify::new(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Move a checked path into the trash, returning its id
+    ///
+    /// Across filesystems, such as a disk mounted inside the root, the item is copied, and
+    /// that copy is counted in `progress` and can be cancelled. If the index can't be
+    /// saved the item is moved back.
+    pub(crate) async fn put(
+        &self,
+        state: &AppState,
+        user: &AuthUser,
+        paths: &SafePathResult,
+        progress: Option<&WalkProgress>,
+    ) -> Result<String, ApiError> {
+        let dir = trash_dir(&paths.root);
+        fs::create_dir_all(&dir).await.map_err(|e| ApiError::io(Msg::DeleteFailed, e))?;
+        let record = TrashRecord {
+            path: state.mounts.logical_path(&paths.logical),
+            root: paths.root.clone(),
+            deleted_by: user.username.clone(),
+            deleted_at: Utc::now().timestamp(),
+            size: source_size(state, &paths.logical, &paths.actual).await,
+            is_dir: paths.actual.is_dir(),
+        };
+        let id = Uuid::new_v4().simple().to_string();
+        let item = dir.join(&id);
+        if let Err(e) = move_path(&paths.actual, &item, progress).await {
+            if progress.is_some_and(WalkProgress::is_cancelled) {
+                return Err(ApiError::Cancelled(Msg::JobCancelled.into()));
+            }
+            return Err(ApiError::io(Msg::DeleteFailed, e));
+        }
+        let mut items = self.items.lock().await;
+        items.insert(id.clone(), record);
+        if let Err(e) = self.file.save(&*items).await {
+            items.remove(&id);
+            if let Err(e) = move_path(&item, &paths.actual, None).await {
+                warn!("Failed to move {:?} back out of the trash: {}", item, e);
+            }
+            return Err(e);
+        }
+        if self.max_size.is_some_and(|max| total_size(&items) > max) {
+            self.over_size.notify_one();
+        }
+        Ok(id)
+    }
+
+    /// Delete an item for good, dropping it from the index once nothing of it is left
+    async fn purge(&self, id: &str, record: &TrashRecord) -> Result<(), ApiError> {
+        let item = trash_dir(&record.root).join(id);
+        if fs::symlink_metadata(&item).await.is_ok() {
+            let mut outcome = RemoveOutcome::default();
+            remove_tree(&item, &CancellationToken::new(), &mut outcome).await;
+            if let Some((_, e)) = outcome.failures.into_iter().next() {
+                return Err(ApiError::io(Msg::DeleteFailed, e));
+            }
+        }
+        self.forget(id).await
+    }
+
+    /// Drop an item from the index
+    async fn forget(&self, id: &str) -> Result<(), ApiError> {
+        let mut items = self.items.lock().await;
+        if let Some(record) = items.remove(id)
+            && let Err(e) = self.file.save(&*items).await
+        {
+            items.insert(id.to_string(), record);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Purge the items kept longer than `--trash-retention-days`, then the oldest ones
+    /// until the trash fits in `--trash-max-size`
+    async fn sweep(&self) {
+        let mut items: Vec<(String, TrashRecord)> = self
+            .items
+            .lock()
+            .await
+            .iter()
+            .map(|(id, record)| (id.clone(), record.clone()))
+            .collect();
+        items.sort_by_key(|(_, record)| record.deleted_at);
+        let cutoff = self.retention.map(|retention| Utc::now().timestamp() - retention.as_secs() as i64);
+        let expired = items
+            .iter()
+            .take_while(|(_, record)| cutoff.is_some_and(|cutoff| record.deleted_at < cutoff))
+            .count();
+        // Oldest first; anything past the cutoff goes whatever the size
+        let mut size: u64 = items[expired..].iter().map(|(_, record)| record.size).sum();
+        let mut evict = expired;
+        while let Some(max) = self.max_size
+            && size > max
+            && evict < items.len()
+        {
+            size -= items[evict].1.size;
+            evict += 1;
+        }
+        if evict == 0 {
+            return;
+        }
+        let mut purged = 0;
+        for (id, record) in &items[..evict] {
+            match self.purge(id, record).await {
+                Ok(()) => purged += 1,
+                Err(e) => warn!("Failed to purge {} from the trash: {}", record.path, e),
+            }
+        }
+        info!(
+            "Purged {} item(s) from the trash: {} expired, {} over the size limit",
+            purged,
+            expired,
+            evict - expired
+        );
+    }
+}
+
+/// Bytes held by the items in the index, as measured when they were trashed
+fn total_size(items: &HashMap<String, TrashRecord>) -> u64 {
+    items.values().map(|record| record.size).sum()
+}
+
+/// Purge expired trash every `SWEEP_INTERVAL`, and evict the oldest items as soon as a
+/// delete takes the trash over `--trash-max-size`, until shutdown
+pub fn start_sweeping(state: AppState, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => state.trash.sweep().await,
+                _ = state.trash.over_size.notified() => state.trash.sweep().await,
+            }
+        }
+    });
+}
+
+/// Whether the user may see and act on an item deleted from `path`
+fn allowed(state: &AppState, user: &AuthUser, path: &str) -> bool {
+    user.check(path).is_ok() && !state.hidden.matches(path)
+}
+
+/// A trashed item the user may act on, by id
+async fn find(state: &AppState, user: &AuthUser, id: &str) -> Result<TrashRecord, ApiError> {
+    state
+        .trash
+        .items
+        .lock()
+        .await
+        .get(id)
+        .filter(|record| allowed(state, user, &record.path))
+        .cloned()
+        .ok_or_else(|| ApiError::NotFound(Msg::TrashItemNotFound.into()))
+}
+
+/// Trashed items the user may restore, most recently deleted first
+///
+/// Users see what was deleted from paths they can access, whoever deleted it.
+#[utoipa::path(
+    get, path = "/api/trash", tag = "files",
+    responses((status = 200, description = "Items in the trash", body = ApiResponse<TrashResponse>)),
+)]
+pub async fn list_trash(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> Json<ApiResponse<TrashResponse>> {
+    let mut items: Vec<TrashItem> = state
+        .trash
+        .items
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, record)| allowed(&state, &user, &record.path))
+        .map(|(id, record)| TrashItem {
+            id: id.clone(),
+            name: record.path.rsplit('/').next().unwrap_or_default().to_string(),
+            original_path: record.path.clone(),
+            file_type: if record.is_dir { "folder" } else { "file" }.to_string(),
+            size: record.size,
+            size_formatted: format_size(record.size),
+            deleted_by: record.deleted_by.clone(),
+            deleted_at: record.deleted_at,
+        })
+        .collect();
+    items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then_with(|| a.original_path.cmp(&b.original_path)));
+    let retention_days = state.trash.retention.map(|retention| retention.as_secs() / 86400);
+    let size = total_size(&*state.trash.items.lock().await);
+    Json(ApiResponse::success(TrashResponse {
+        items,
+        size,
+        retention_days,
+        max_size: state.trash.max_size,
+    }))
+}
+
+/// Put a trashed item back where it was deleted from
+///
+/// Folders missing along the way are recreated. Nothing is overwritten: when something
+/// with the same name is there now, the restore is refused with 409.
+#[utoipa::path(
+    post, path = "/api/trash/restore", tag = "files", request_body = RestoreTrashRequest,
+    responses(
+        (status = 200, description = "Restored to `newPath`", body = ApiResponse<OperationResponse>),
+        (status = 404, description = "No such item in the trash", body = crate::openapi::ErrorBody),
+        (status = 409, description = "The original location is taken", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn restore_trash(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<RestoreTrashRequest>,
+) -> Result<Json<ApiResponse<OperationResponse>>, ApiError> {
+    let path = restore(&state, &user, addr, &headers, &req.id).await?;
+    Ok(Json(ApiResponse::success(OperationResponse {
+        message: Msg::Restored.into(),
+        new_path: Some(path),
+    })))
+}
+
+/// Put a trashed item back where it was deleted from, returning that logical path
+pub(crate) async fn restore(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    id: &str,
+) -> Result<String, ApiError> {
+    let record = find(state, user, id).await?;
+    let paths = safe_path(&state.mounts, &record.path)?;
+    check_patterns(state, &record.path, true)?;
+    state.locks.check(&record.path, false, user, headers)?;
+
+    let (Some(parent), Some(name)) = (paths.actual.parent(), paths.actual.file_name()) else {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    };
+    if existing_name(parent, name).await.is_some() {
+        return Err(ApiError::AlreadyExists(Msg::RestoreTargetExists.with(&[&record.path])));
+    }
+    fs::create_dir_all(parent).await.map_err(|e| ApiError::io(Msg::RestoreFailed, e))?;
+
+    let item = trash_dir(&record.root).join(id);
+    let result = move_path(&item, &paths.actual, None).await;
+    state.audit.record(AuditEntry::new("restore", &user.username, addr.ip(), &record.path).result(&result));
+    sizes_changed(state, &paths.actual).await;
+    result.map_err(|e| ApiError::io(Msg::RestoreFailed, e))?;
+    state.trash.forget(id).await?;
+    Ok(record.path)
+}
+
+/// Delete trashed items for good: those listed in `ids`, or without it all the caller
+/// can see in the trash
+///
+/// An item that can't be removed completely stays in the trash with what is left of it.
+#[utoipa::path(
+    delete, path = "/api/trash/purge", tag = "files", request_body = PurgeTrashRequest,
+    responses(
+        (status = 200, description = "How many items were purged", body = ApiResponse<PurgeTrashResult>),
+        (status = 404, description = "An id that isn't in the trash", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn purge_trash(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<PurgeTrashRequest>,
+) -> Result<Json<ApiResponse<PurgeTrashResult>>, ApiError> {
+    let targets: Vec<(String, TrashRecord)> = match req.ids {
+        Some(ids) => {
+            let mut targets = Vec::with_capacity(ids.len());
+            for id in ids {
+                let record = find(&state, &user, &id).await?;
+                targets.push((id, record));
+            }
+            targets
+        }
+        None => state
+            .trash
+            .items
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, record)| allowed(&state, &user, &record.path))
+            .map(|(id, record)| (id.clone(), record.clone()))
+            .collect(),
+    };
+
+    let (mut purged, mut failed) = (0, 0);
+    for (id, record) in &targets {
+        let result = state.trash.purge(id, record).await;
+        state.audit.record(AuditEntry::new("purge", &user.username, addr.ip(), &record.path).result(&result));
+        match result {
+            Ok(()) => purged += 1,
+            Err(_) => failed += 1,
+        }
+    }
+    let message = if failed == 0 {
+        Msg::TrashPurged.with(&[&purged])
+    } else {
+        Msg::TrashPurgePartial.with(&[&purged, &failed])
+    };
+    Ok(Json(ApiResponse::success(PurgeTrashResult { message, purged, failed })))
+}
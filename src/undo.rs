@@ -0,0 +1,236 @@
+use axum::{
+    extract::{ConnectInfo, Extension, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use crate::audit::AuditEntry;
+use crate::error::ApiError;
+use crate::handlers::{
+    check_not_internal, check_patterns, check_patterns_tree, check_transfer_space, move_path, remove_tree, safe_path,
+    sizes_changed, RemoveOutcome,
+};
+use crate::i18n::Msg;
+use crate::models::{ApiResponse, UndoEntry, UndoListResponse, UndoOp, UndoResult};
+use crate::paths::existing_name;
+use crate::trash;
+use crate::users::AuthUser;
+use crate::AppState;
+
+/// Operations remembered per user; older ones can no longer be undone
+const MAX_UNDO: usize = 20;
+
+struct Undoable {
+    entry: UndoEntry,
+    /// The trash item a delete went to
+    trash_id: Option<String>,
+}
+
+/// Each user's last renames, moves, copies and deletes, newest last, held in memory
+///
+/// Handlers record an operation once it has succeeded; `POST /api/undo` takes the newest
+/// back off. Undoing records nothing, so it can't be undone in turn.
+#[derive(Default)]
+pub struct UndoStore {
+    users: Mutex<HashMap<String, VecDeque<Undoable>>>,
+}
+
+impl UndoStore {
+    fn push(&self, username: &str, op: UndoOp, path: String, from: Option<String>, trash_id: Option<String>) {
+        let mut users = self.users.lock().unwrap_or_else(|e| e.into_inner());
+        let stack = users.entry(username.to_string()).or_default();
+        if stack.len() == MAX_UNDO {
+            stack.pop_front();
+        }
+        let entry = UndoEntry { op, path, from, at: Utc::now().timestamp() };
+        stack.push_back(Undoable { entry, trash_id });
+    }
+
+    /// Record a rename or move of `from` to `path`
+    pub fn moved(&self, username: &str, op: UndoOp, from: String, path: String) {
+        self.push(username, op, path, Some(from), None);
+    }
+
+    /// Record a copy made at `path`
+    pub fn copied(&self, username: &str, path: String) {
+        self.push(username, UndoOp::Copy, path, None, None);
+    }
+
+    /// Record the delete of `path` into the trash as item `trash_id`
+    pub fn trashed(&self, username: &str, path: String, trash_id: String) {
+        self.push(username, UndoOp::Delete, path, None, Some(trash_id));
+    }
+
+    fn pop(&self, username: &str) -> Option<Undoable> {
+        self.users.lock().unwrap_or_else(|e| e.into_inner()).get_mut(username)?.pop_back()
+    }
+
+    /// Put back an operation whose undo may work later
+    fn restack(&self, username: &str, undoable: Undoable) {
+        let mut users = self.users.lock().unwrap_or_else(|e| e.into_inner());
+        users.entry(username.to_string()).or_default().push_back(undoable);
+    }
+
+    fn list(&self, username: &str) -> Vec<UndoEntry> {
+        let users = self.users.lock().unwrap_or_else(|e| e.into_inner());
+        users
+            .get(username)
+            .map(|stack| stack.iter().rev().map(|undoable| undoable.entry.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Put a renamed or moved item at `path` back at `from`, with the checks a move makes
+async fn move_back(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    op: UndoOp,
+    path: &str,
+    from: &str,
+) -> Result<(), ApiError> {
+    let current = safe_path(&state.mounts, path)?;
+    let original = safe_path(&state.mounts, from)?;
+    for logical in [path, from] {
+        user.check(logical).map_err(ApiError::AccessDenied)?;
+    }
+    if !current.actual.exists() {
+        return Err(ApiError::NotFound(Msg::SourceNotFound.into()));
+    }
+    check_patterns_tree(state, &current.logical, &current.actual).await?;
+    check_patterns(state, from, true)?;
+    check_not_internal(path, &current.actual)?;
+    check_not_internal(from, &original.actual)?;
+    state.locks.check(path, true, user, headers)?;
+    state.locks.check(from, false, user, headers)?;
+
+    let (Some(parent), Some(name)) = (original.actual.parent(), original.actual.file_name()) else {
+        return Err(ApiError::InvalidPath(Msg::InvalidPath.into()));
+    };
+    if existing_name(parent, name).await.is_some() {
+        return Err(ApiError::AlreadyExists(Msg::UndoTargetExists.with(&[&from])));
+    }
+    // The folder it came from may have been removed since
+    fs::create_dir_all(parent).await.map_err(|e| ApiError::io(Msg::MoveFailed, e))?;
+    check_transfer_space(state, &current.logical, &current.actual, parent, true).await?;
+
+    let result = move_path(&current.actual, &original.actual, None).await;
+    let audit_op = if op == UndoOp::Rename { "rename" } else { "move" };
+    state.audit.record(AuditEntry::new(audit_op, &user.username, addr.ip(), path).dest(from).result(&result));
+    sizes_changed(state, &current.actual).await;
+    sizes_changed(state, &original.actual).await;
+    let failed = if op == UndoOp::Rename { Msg::RenameFailed } else { Msg::MoveFailed };
+    result.map_err(|e| ApiError::io(failed, e))?;
+    state.meta.moved(path, from).await;
+    Ok(())
+}
+
+/// Remove the copy at `path`, to the trash unless it is off; returns whether it went there
+async fn remove_copy(
+    state: &AppState,
+    user: &AuthUser,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    path: &str,
+) -> Result<bool, ApiError> {
+    let paths = safe_path(&state.mounts, path)?;
+    user.check(path).map_err(ApiError::AccessDenied)?;
+    if !paths.actual.exists() {
+        return Err(ApiError::NotFound(Msg::FileNotFound.into()));
+    }
+    check_patterns_tree(state, &paths.logical, &paths.actual).await?;
+    check_not_internal(path, &paths.actual)?;
+    state.locks.check(path, true, user, headers)?;
+
+    let entry = AuditEntry::new("delete", &user.username, addr.ip(), path);
+    let result = if state.trash.enabled() {
+        state.trash.put(state, user, &paths, None).await.map(|_| ())
+    } else {
+        let mut outcome = RemoveOutcome::default();
+        remove_tree(&paths.actual, &CancellationToken::new(), &mut outcome).await;
+        match outcome.failures.into_iter().next() {
+            Some((_, e)) => Err(ApiError::io(Msg::DeleteFailed, e)),
+            None => Ok(()),
+        }
+    };
+    state.audit.record(entry.result(&result));
+    sizes_changed(state, &paths.actual).await;
+    result?;
+    state.meta.removed(path).await;
+    Ok(state.trash.enabled())
+}
+
+/// The caller's operations that can be undone, newest first
+///
+/// Renames, moves, copies and deletes into the trash are remembered, the last 20 per
+/// user, until the server restarts.
+#[utoipa::path(
+    get, path = "/api/undo", tag = "files",
+    responses((status = 200, description = "Undoable operations, the next one to undo first", body = ApiResponse<UndoListResponse>)),
+)]
+pub async fn list_undo(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> Json<ApiResponse<UndoListResponse>> {
+    Json(ApiResponse::success(UndoListResponse { entries: state.undo.list(&user.username) }))
+}
+
+/// Reverse the caller's most recent rename, move, copy or delete
+///
+/// A renamed or moved item is put back where it was, a copy is moved to the trash (or
+/// deleted with `--no-trash`), and a deleted item is restored from the trash. Nothing is
+/// overwritten: when the original location is taken, or the item is locked, the answer
+/// is 409 or 423 and the operation stays to be undone once that is cleared. Any other
+/// failure, such as the item having been deleted since, drops it.
+#[utoipa::path(
+    post, path = "/api/undo", tag = "files",
+    responses(
+        (status = 200, description = "Undone; `newPath` is where the item is back at", body = ApiResponse<UndoResult>),
+        (status = 400, description = "Nothing to undo", body = crate::openapi::ErrorBody),
+        (status = 409, description = "The original location is taken", body = crate::openapi::ErrorBody),
+    ),
+)]
+pub async fn undo(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Json<ApiResponse<UndoResult>>, ApiError> {
+    let undoable = state
+        .undo
+        .pop(&user.username)
+        .ok_or_else(|| ApiError::BadRequest(Msg::NothingToUndo.into()))?;
+    let UndoEntry { op, path, from, .. } = undoable.entry.clone();
+    let result = match (op, from, &undoable.trash_id) {
+        (UndoOp::Rename | UndoOp::Move, Some(from), _) => move_back(&state, &user, addr, &headers, op, &path, &from)
+            .await
+            .map(|()| {
+                let message = if op == UndoOp::Rename { Msg::UndoneRename } else { Msg::UndoneMove };
+                (message.into(), Some(from))
+            }),
+        (UndoOp::Copy, _, _) => remove_copy(&state, &user, addr, &headers, &path).await.map(|trashed| {
+            let message = if trashed { Msg::UndoneCopy } else { Msg::UndoneCopyDeleted };
+            (message.into(), None)
+        }),
+        (UndoOp::Delete, _, Some(id)) => trash::restore(&state, &user, addr, &headers, id)
+            .await
+            .map(|path| (Msg::UndoneDelete.into(), Some(path))),
+        _ => Err(ApiError::BadRequest(Msg::NothingToUndo.into())),
+    };
+    match result {
+        Ok((message, new_path)) => Ok(Json(ApiResponse::success(UndoResult { message, op, new_path }))),
+        Err(e) => {
+            if matches!(e, ApiError::AlreadyExists(_) | ApiError::Locked(_)) {
+                state.undo.restack(&user.username, undoable);
+            }
+            Err(e)
+        }
+    }
+}
+
@@ -0,0 +1,207 @@
+use axum::{
+    extract::{Extension, Query, State},
+    Json,
+};
+use chrono::{SecondsFormat, Utc};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::error::ApiError;
+use crate::handlers::{format_size, walk_tree, TreeStats, WalkCaps};
+use crate::jobs::{report_roots, Outcome, ReportRequest, WalkProgress};
+use crate::models::{ApiResponse, JobStatus, UsageEntry, UsageQuery, UsageReport, UsageResponse};
+use crate::users::AuthUser;
+use crate::AppState;
+
+const DEFAULT_TOP: usize = 20;
+const MAX_TOP: usize = 1000;
+
+/// Identity of a file with several hard links, so its size is only counted once
+#[cfg(unix)]
+pub(crate) fn link_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then_some((metadata.dev(), metadata.ino()))
+}
+#[cfg(not(unix))]
+pub(crate) fn link_id(_: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Totals gathered by the visitor of one usage walk
+struct Tally<'a> {
+    state: &'a AppState,
+    user: &'a AuthUser,
+    top: usize,
+    progress: &'a WalkProgress,
+    files: u64,
+    bytes: u64,
+    /// Smallest of the largest files on top, so it is the one dropped
+    largest: BinaryHeap<Reverse<(u64, String)>>,
+    /// Size and file count per immediate subfolder, by logical path
+    folders: HashMap<PathBuf, (u64, u64)>,
+    seen_links: HashSet<(u64, u64)>,
+}
+
+impl Tally<'_> {
+    /// Count a file or folder found below `folder` (the immediate subfolder it is in, if any)
+    fn visit(&mut self, folder: Option<&Path>, logical: &Path, metadata: &Metadata) {
+        if metadata.is_dir() {
+            if folder == Some(logical) {
+                self.folders.entry(logical.to_path_buf()).or_default();
+            }
+            self.progress.add(1, 0);
+            return;
+        }
+        if link_id(metadata).is_some_and(|id| !self.seen_links.insert(id)) {
+            self.progress.add(1, 0);
+            return;
+        }
+        let size = metadata.len();
+        self.files += 1;
+        self.bytes += size;
+        self.progress.add(1, size);
+        if let Some(folder) = folder {
+            let totals = self.folders.entry(folder.to_path_buf()).or_default();
+            totals.0 += size;
+            totals.1 += 1;
+        }
+        // Only files that would make the list are checked against the user's rules
+        if self.largest.len() < self.top || self.largest.peek().is_some_and(|Reverse((min, _))| size > *min) {
+            let path = self.state.mounts.logical_path(logical);
+            if self.user.can_see(&path) {
+                self.largest.push(Reverse((size, path)));
+                if self.largest.len() > self.top {
+                    self.largest.pop();
+                }
+            }
+        }
+    }
+
+    fn into_report(self, path: String, stats: &TreeStats, max_entries: u64) -> UsageReport {
+        let largest_files = self
+            .largest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((size, path))| UsageEntry { path, size, size_formatted: format_size(size), files: None })
+            .collect();
+        let mut folders: Vec<UsageEntry> = self
+            .folders
+            .into_iter()
+            .map(|(logical, (size, files))| UsageEntry {
+                path: self.state.mounts.logical_path(&logical),
+                size,
+                size_formatted: format_size(size),
+                files: Some(files),
+            })
+            .filter(|f| self.user.can_see(&f.path))
+            .collect();
+        folders.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        folders.truncate(self.top);
+        UsageReport {
+            path,
+            largest_files,
+            largest_folders: folders,
+            total_files: self.files,
+            total_bytes: self.bytes,
+            total_formatted: format_size(self.bytes),
+            truncated: stats.truncated,
+            max_entries,
+            generated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+}
+
+/// Walk `roots` (logical, actual) and build the report
+///
+/// With a single root its immediate subfolders are ranked; from the virtual root of
+/// several mounts, the mounts themselves are.
+async fn build_report(
+    state: AppState,
+    user: AuthUser,
+    path: String,
+    roots: Vec<(PathBuf, PathBuf)>,
+    top: usize,
+    progress: Arc<WalkProgress>,
+) -> UsageReport {
+    let caps = WalkCaps { max_entries: state.report_max_entries, ..WalkCaps::NONE };
+    let mut tally = Tally {
+        state: &state,
+        user: &user,
+        top,
+        progress: &progress,
+        files: 0,
+        bytes: 0,
+        largest: BinaryHeap::new(),
+        folders: HashMap::new(),
+        seen_links: HashSet::new(),
+    };
+    let mut stats = TreeStats::default();
+    let by_mount = state.mounts.is_virtual_root(&path);
+    for (logical, actual) in &roots {
+        if by_mount {
+            tally.folders.entry(logical.clone()).or_default();
+        }
+        walk_tree(&state, logical, actual, caps, &mut stats, &mut |entry, metadata| {
+            let folder = if by_mount {
+                Some(logical.clone())
+            } else {
+                entry.strip_prefix(logical).ok().and_then(|rest| rest.components().next()).map(|c| logical.join(c))
+            };
+            // Files directly in the root belong to no subfolder
+            let folder = folder.filter(|f| f != entry || metadata.is_dir());
+            tally.visit(folder.as_deref(), entry, metadata);
+        })
+        .await;
+        if stats.truncated {
+            break;
+        }
+    }
+    let max_entries = state.report_max_entries;
+    tally.into_report(path, &stats, max_entries)
+}
+
+/// Largest files and folders below a path
+///
+/// Big trees are walked in the background: the response is then `computing` with a `job`
+/// token for `/api/jobs`, whose `result` is the report once done. Reports are cached
+/// until something below the folder changes.
+#[utoipa::path(
+    get, path = "/api/usage", tag = "files", params(UsageQuery),
+    responses((status = 200, description = "The report, or the job computing it", body = ApiResponse<UsageResponse>)),
+)]
+pub async fn usage_report(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<ApiResponse<UsageResponse>>, ApiError> {
+    let user_path = query.path.unwrap_or_else(|| "/".to_string());
+    let top = query.top.unwrap_or(DEFAULT_TOP).clamp(1, MAX_TOP);
+
+    let (path, roots) = report_roots(&state, &user, &user_path)?;
+    let request = ReportRequest::new("usage", &top.to_string(), &user.username, path.clone(), &roots).await;
+
+    let walk = {
+        let (state, user, path) = (state.clone(), user.clone(), path.clone());
+        move |progress| async move {
+            let report = build_report(state, user, path, roots, top, progress).await;
+            serde_json::to_value(report).unwrap_or_default()
+        }
+    };
+    let response = match state.reports.run(&user.username, request, walk).await {
+        Outcome::Done(report) => UsageResponse {
+            status: JobStatus::Done,
+            job: None,
+            entries: None,
+            report: Some((*report).clone()),
+        },
+        Outcome::Running { token, progress } => UsageResponse {
+            status: JobStatus::Computing,
+            job: Some(token),
+            entries: Some(progress.entries()),
+            report: None,
+        },
+    };
+    Ok(Json(ApiResponse::success(response)))
+}
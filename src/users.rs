@@ -0,0 +1,239 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use crate::i18n::Msg;
+
+/// Additional user declared in the users file
+///
+/// ```json
+/// { "users": [
+///     { "username": "alice", "password_hash": "$argon2id$...", "allow": ["/projects/alpha"] },
+///     { "username": "bob", "password": "secret", "deny": ["/private"] }
+/// ] }
+/// ```
+#[derive(Deserialize, Clone)]
+pub struct UserEntry {
+    pub username: String,
+    /// Plaintext password, only used when no hash is set
+    #[serde(default)]
+    pub password: Option<String>,
+    /// bcrypt or argon2 PHC hash
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    /// Logical path prefixes the user may access (empty = everything)
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Logical path prefixes the user may never access (takes precedence over allow)
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Compiled rules; `None` when the user is unrestricted
+    #[serde(skip)]
+    pub rules: Option<Arc<PathRules>>,
+}
+
+#[derive(Deserialize)]
+struct UsersFile {
+    users: Vec<UserEntry>,
+}
+
+/// Users loaded from the users file, keyed by username
+pub type Users = Arc<HashMap<String, UserEntry>>;
+
+/// Load and validate the users file
+pub fn load_users_file(path: &Path) -> Result<Users, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read users file {:?}: {}", path, e))?;
+    let file: UsersFile = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid users file {:?}: {}", path, e))?;
+
+    let mut users = HashMap::new();
+    for mut user in file.users {
+        if user.password.is_none() && user.password_hash.is_none() {
+            return Err(format!("User {:?} has neither password nor password_hash", user.username));
+        }
+        if let Some(hash) = &user.password_hash {
+            crate::auth::validate_password_hash(hash)
+                .map_err(|e| format!("User {:?}: {}", user.username, e))?;
+        }
+        if !user.allow.is_empty() || !user.deny.is_empty() {
+            user.rules = Some(Arc::new(PathRules::new(&user.allow, &user.deny)));
+        }
+        if users.insert(user.username.clone(), user).is_some() {
+            return Err("Duplicate username in users file".to_string());
+        }
+    }
+    Ok(Arc::new(users))
+}
+
+/// Allow/deny path prefix rules for one user
+pub struct PathRules {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl PathRules {
+    pub fn new(allow: &[String], deny: &[String]) -> Self {
+        Self {
+            allow: allow.iter().map(|p| normalize_prefix(p)).collect(),
+            deny: deny.iter().map(|p| normalize_prefix(p)).collect(),
+        }
+    }
+
+    /// Whether the logical path (and everything under it) may be accessed
+    pub fn can_access(&self, path: &str) -> bool {
+        if self.deny.iter().any(|d| is_within(path, d)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|a| is_within(path, a))
+    }
+
+    /// Whether the directory may be listed only to reach an allowed prefix below it
+    pub fn can_traverse(&self, path: &str) -> bool {
+        self.allow
+            .iter()
+            .any(|a| is_within(a, path) && !self.deny.iter().any(|d| is_within(a, d)))
+    }
+
+    /// Whether the path is visible at all (accessible or on the way to something accessible)
+    pub fn can_see(&self, path: &str) -> bool {
+        self.can_access(path) || self.can_traverse(path)
+    }
+}
+
+/// Normalize a rule prefix to "/a/b" form without a trailing slash
+fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim().trim_matches('/');
+    format!("/{}", trimmed)
+}
+
+/// Whether `path` equals `prefix` or lies below it (component-wise)
+fn is_within(path: &str, prefix: &str) -> bool {
+    prefix == "/"
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Authenticated user, inserted into request extensions by the auth middleware
+#[derive(Clone)]
+pub struct AuthUser {
+    pub username: String,
+    /// Path rules; `None` means unrestricted
+    pub rules: Option<Arc<PathRules>>,
+    /// Authenticated with a read-only API key, whose writes never reach a handler
+    pub readonly: bool,
+}
+
+impl AuthUser {
+    /// Fail with an access-denied message if the logical path is not accessible
+    pub fn check(&self, path: &str) -> Result<(), String> {
+        match &self.rules {
+            Some(rules) if !rules.can_access(path) => Err(Msg::AccessDenied.with(&[&path])),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail unless the logical directory may be accessed or traversed
+    pub fn check_visible(&self, path: &str) -> Result<(), String> {
+        match &self.rules {
+            Some(rules) if !rules.can_see(path) => Err(Msg::AccessDenied.with(&[&path])),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether a listing/search entry should be shown to this user
+    pub fn can_see(&self, path: &str) -> bool {
+        self.rules.as_ref().is_none_or(|rules| rules.can_see(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str]) -> PathRules {
+        let strings = |list: &[&str]| list.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        PathRules::new(&strings(allow), &strings(deny))
+    }
+
+    fn user(rules: Option<PathRules>) -> AuthUser {
+        AuthUser { username: "bob".to_string(), rules: rules.map(Arc::new), readonly: false }
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let rules = rules(&["/projects"], &["/projects/secret"]);
+        assert!(rules.can_access("/projects"));
+        assert!(rules.can_access("/projects/alpha/notes.txt"));
+        assert!(!rules.can_access("/projects/secret"));
+        assert!(!rules.can_access("/projects/secret/plan.txt"));
+        assert!(!rules.can_access("/other"));
+    }
+
+    #[test]
+    fn prefixes_match_whole_components() {
+        let rules = rules(&["/proj"], &["/proj/a"]);
+        assert!(!rules.can_access("/project"));
+        assert!(rules.can_access("/proj/ab"));
+        assert!(!rules.can_access("/proj/a/b"));
+    }
+
+    #[test]
+    fn prefixes_are_normalized() {
+        let rules = rules(&["projects/ "], &[]);
+        assert!(rules.can_access("/projects/x"));
+        assert!(!rules.can_access("/"));
+    }
+
+    #[test]
+    fn deny_only_allows_everything_else() {
+        let rules = rules(&[], &["/private"]);
+        assert!(rules.can_access("/"));
+        assert!(rules.can_access("/public/a"));
+        assert!(!rules.can_access("/private"));
+        assert!(!rules.can_traverse("/private"));
+    }
+
+    #[test]
+    fn folders_above_an_allowed_prefix_are_visible_but_not_accessible() {
+        let rules = rules(&["/a/b/c"], &[]);
+        for path in ["/", "/a", "/a/b"] {
+            assert!(!rules.can_access(path), "{path}");
+            assert!(rules.can_traverse(path), "{path}");
+            assert!(rules.can_see(path), "{path}");
+        }
+        assert!(rules.can_see("/a/b/c/d"));
+        assert!(!rules.can_see("/a/x"));
+        assert!(!rules.can_see("/a/bc"));
+    }
+
+    #[test]
+    fn a_denied_folder_hides_allowed_prefixes_below_it() {
+        let rules = rules(&["/a/b"], &["/a"]);
+        assert!(!rules.can_see("/"));
+        assert!(!rules.can_see("/a"));
+        assert!(!rules.can_see("/a/b"));
+    }
+
+    #[test]
+    fn check_visible_admits_traversal_where_check_does_not() {
+        let user = user(Some(rules(&["/team/alice"], &["/team/alice/private"])));
+        assert!(user.check("/team").is_err());
+        assert!(user.check_visible("/team").is_ok());
+        assert!(user.check("/team/alice/doc.txt").is_ok());
+        assert!(user.check_visible("/team/alice/private").is_err());
+        assert!(user.check_visible("/team/bob").is_err());
+        assert!(user.can_see("/team"));
+        assert!(!user.can_see("/team/bob"));
+    }
+
+    #[test]
+    fn users_without_rules_are_unrestricted() {
+        let user = user(None);
+        assert!(user.check("/anything").is_ok());
+        assert!(user.check_visible("/anything").is_ok());
+        assert!(user.can_see("/anything"));
+    }
+}